@@ -3,18 +3,104 @@ import ReactDom from "react-dom";
 
 const append = (oldArray, newItem) => [...oldArray, newItem];
 
+// Commands for the backend, see `FrontendCommand` in
+// src/types/frontend_communication.rs for the other side.
+const send = (type, payload) =>
+  window.external.invoke(JSON.stringify({ type, payload }));
+
+const formatChaos = value => `${value.toFixed(1)}c`;
+
+// How the loot filter showed a drop, in its colours. See `FilterTier` in
+// src/types/loot_filter.rs.
+const FilterTierLabel = ({ tier }) => (
+  <span
+    className={`f7 ph1 ${tier.hidden ? "strike" : ""}`}
+    style={{
+      color: tier.text_colour || undefined,
+      backgroundColor: tier.background_colour || undefined,
+      border: tier.border_colour ? `1px solid ${tier.border_colour}` : undefined
+    }}
+  >
+    {tier.tier}
+  </span>
+);
+
+// Drops show up as soon as they're parsed, the price fills in later. Tagged
+// ones stand out.
 const Item = props => (
-  <div className="fl w-75 bg-light-gray shadow-4 ma2 pa2">
-    <div className="fl w-75 i pa2">{props.name}</div>
-    <div className="fr w-25 b pa2 tr">{props.value}</div>
+  <div
+    className={`fl w-75 shadow-4 ma2 pa2 ${
+      props.tagged ? "bg-washed-yellow" : "bg-light-gray"
+    }`}
+  >
+    <div className="fl w-75 i pa2">
+      {props.name}
+      {props.filterTier && (
+        <div>
+          <FilterTierLabel tier={props.filterTier} />
+        </div>
+      )}
+    </div>
+    <div className="fr w-25 b pa2 tr">
+      {props.value === null ? (
+        <span className="gray">pricing…</span>
+      ) : (
+        formatChaos(props.value)
+      )}
+    </div>
   </div>
 );
 
+const renderItem = item => (
+  <Item
+    key={item.id}
+    name={item.name}
+    value={item.value}
+    tagged={item.tagged}
+    filterTier={item.filterTier}
+  />
+);
+
+// Drops by loot filter tier, in the order the tiers first dropped.
+const groupByTier = items =>
+  items.reduce((groups, item) => {
+    const tier = item.filterTier === null ? "Unsorted" : item.filterTier.tier;
+    const group = groups.find(g => g.tier === tier);
+    if (group) {
+      group.items.push(item);
+    } else {
+      groups.push({ tier, items: [item] });
+    }
+    return groups;
+  }, []);
+
 const EventPipe = props => {
   return (
     <div className="fl w-20 ma2 pa1">
       <h1 className="h1">Item Log</h1>
-      <div className="pt3 item-center">{props.items}</div>
+      <label>
+        <input
+          type="checkbox"
+          checked={props.grouped}
+          onChange={event => props.onGroup(event.target.checked)}
+        />{" "}
+        Group by loot filter tier
+      </label>
+      <div className="pt3 item-center">
+        {props.grouped
+          ? groupByTier(props.items).map(group => (
+              <div key={group.tier} className="fl w-100">
+                <div className="b pt2">
+                  {group.tier}: {group.items.length},{" "}
+                  {formatChaos(
+                    group.items.reduce((sum, item) => sum + (item.value || 0), 0)
+                  )}
+                </div>
+                {group.items.map(renderItem)}
+              </div>
+            ))
+          : props.items.map(renderItem)}
+      </div>
     </div>
   );
 };
@@ -25,15 +111,625 @@ const MapDisplay = props => {
       <div>
         Current Map:
         <div className="b f1">{props.currentMap}</div>
+        {props.character && (
+          <div className="gray">
+            {props.character.name}
+            {props.character.level !== null &&
+              `, level ${props.character.level} ${props.character.class}`}
+          </div>
+        )}
+        {props.paused && <div className="b orange">Paused</div>}
+        {props.mapDeaths > 0 && (
+          <div className="dark-red">Died {props.mapDeaths} times in here</div>
+        )}
       </div>
+      {props.queuedMap && (
+        <div className="pt2 gray">
+          Next {props.queuedMap.name} run: tier {props.queuedMap.tier}
+        </div>
+      )}
       <div className="pt3">
         Previous Map:
         <div className="b f3 pt1"> {props.previousMap} </div>
       </div>
+      <div className="pt3">
+        <button className="mr2" onClick={() => send("StartMap", { name: null })}>
+          Start Map
+        </button>
+        <button className="mr2" onClick={() => send("EndMap")}>
+          End Map
+        </button>
+        <button className="mr2" onClick={() => send("TogglePause")}>
+          {props.paused ? "Resume" : "Pause"}
+        </button>
+        <button onClick={() => send("RefreshPrices")}>Refresh Prices</button>
+      </div>
     </div>
   );
 };
 
+// Trades of this session, whispered about or done.
+const Trades = props =>
+  props.trades.length === 0 ? null : (
+    <div className="fl w-25 pa2">
+      Trades:
+      {props.trades.map(trade => (
+        <div
+          key={trade.id}
+          className={trade.completed_at === null ? "gray" : "dark-green"}
+        >
+          {trade.kind === "Sale" ? "Selling" : "Buying"} {trade.request.item}{" "}
+          {trade.kind === "Sale" ? "to" : "from"} {trade.player} for{" "}
+          {formatTradeValue(trade)}
+        </div>
+      ))}
+    </div>
+  );
+
+const RunningTotal = props => {
+  if (props.total === null) {
+    return null;
+  }
+
+  const previous = props.total.previous_map_chaos_value;
+  const difference = previous === null ? null : props.total.map_chaos_value - previous;
+  return (
+    <div className="fl w-25 pa2">
+      <div>
+        This Map:
+        <div className="b f2">{formatChaos(props.total.map_chaos_value)}</div>
+      </div>
+      <div className="pt2">
+        Per Hour:
+        <div className="b f3">{formatChaos(props.total.map_chaos_per_hour)}</div>
+      </div>
+      {difference === null ? null : (
+        <div className="pt2">
+          Versus Previous Map:
+          <div className={`b f3 ${difference < 0 ? "dark-red" : "dark-green"}`}>
+            {difference < 0 ? "" : "+"}
+            {formatChaos(difference)}
+          </div>
+        </div>
+      )}
+      {props.session === null ? null : (
+        <div className="pt2">
+          Session: {props.session.maps_run} maps,{" "}
+          {formatChaos(props.session.chaos_value)} (
+          {formatChaos(props.session.chaos_per_hour)}/h,{" "}
+          {formatChaos(props.session.active_chaos_per_hour)}/h while playing)
+          <div className="gray">
+            Mapping {formatDuration(props.session.time.map_secs)}, hideout{" "}
+            {formatDuration(props.session.time.hideout_secs)}, loading{" "}
+            {formatDuration(props.session.time.loading_secs)}, away{" "}
+            {formatDuration(props.session.time.afk_secs)}
+          </div>
+          {props.session.deaths > 0 && (
+            <div className="dark-red">
+              {props.session.deaths} deaths, up to{" "}
+              {props.session.experience_lost.toFixed(1)} levels of experience
+            </div>
+          )}
+        </div>
+      )}
+    </div>
+  );
+};
+
+const formatDuration = seconds =>
+  `${Math.floor(seconds / 60)}m ${Math.round(seconds % 60)}s`;
+
+const formatDate = timestamp => new Date(timestamp * 1000).toLocaleString();
+
+// Turns a date input's value into seconds since the epoch, or null if empty.
+const parseDate = value =>
+  value === "" ? null : Math.floor(new Date(value).getTime() / 1000);
+
+const parseTier = value => (value === "" ? null : parseInt(value, 10));
+
+const HISTORY_VIEWS = [
+  { label: "Runs", query: { type: "MapRuns" } },
+  { label: "Maps", query: { type: "MapKindAverages" } },
+  { label: "Best Drops", query: { type: "BestDrops", payload: { limit: 25 } } },
+  { label: "Over Time", query: { type: "ChaosPerHourOverTime" } },
+  { label: "Deaths", query: { type: "DeathsPerMod" } },
+  { label: "Trades", query: { type: "Trades" } },
+  { label: "Profit", query: { type: "Profit" } },
+  { label: "Filter Tiers", query: { type: "FilterTiers" } }
+];
+
+// See `ExportTable` in src/types/export.rs. Events come from Client.txt.
+const EXPORT_TABLES = ["Runs", "Drops", "Trades", "Events", "Sessions"];
+
+const formatTradeValue = trade =>
+  trade.chaos_value === null
+    ? `${trade.request.price} ${trade.request.currency}`
+    : formatChaos(trade.chaos_value);
+
+const Table = props => (
+  <table className="w-100 collapse f6">
+    <thead>
+      <tr>
+        {props.columns.map(column => (
+          <th key={column} className="tl pa1 bb">
+            {column}
+          </th>
+        ))}
+      </tr>
+    </thead>
+    <tbody>
+      {props.rows.map((row, i) => (
+        <tr key={i} className="striped--light-gray">
+          {row.map((cell, j) => (
+            <td key={j} className="pa1">
+              {cell}
+            </td>
+          ))}
+        </tr>
+      ))}
+    </tbody>
+  </table>
+);
+
+// Renders whatever `HistoryResult` came back last.
+const HistoryResult = props => {
+  const rows = props.result.payload;
+  switch (props.result.type) {
+    case "MapRuns":
+      return (
+        <Table
+          columns={[
+            "Started",
+            "Character",
+            "Map",
+            "Tier",
+            "Quantity",
+            "Duration",
+            "Deaths",
+            "Profit"
+          ]}
+          rows={rows.map(run => [
+            formatDate(run.started_at),
+            run.character === null ? "-" : run.character,
+            run.name,
+            run.tier === null ? "-" : run.tier,
+            run.item_quantity === null ? "-" : `${run.item_quantity}%`,
+            formatDuration(run.duration_secs),
+            run.deaths,
+            formatChaos(run.chaos_value)
+          ])}
+        />
+      );
+    case "MapKindAverages":
+      return (
+        <Table
+          columns={["Map", "Runs", "Avg. Profit", "Avg. Duration", "Per Hour"]}
+          rows={rows.map(map => [
+            map.name,
+            map.runs,
+            formatChaos(map.average_chaos_value),
+            formatDuration(map.average_duration_secs),
+            formatChaos(map.chaos_per_hour)
+          ])}
+        />
+      );
+    case "BestDrops":
+      return (
+        <Table
+          columns={["Item", "Count", "Value", "Map", "Dropped"]}
+          rows={rows.map(drop => [
+            drop.name,
+            drop.count,
+            formatChaos(drop.chaos_value),
+            drop.map_name,
+            formatDate(drop.dropped_at)
+          ])}
+        />
+      );
+    case "ChaosPerHourOverTime":
+      return (
+        <Table
+          columns={["Day", "Maps", "Per Hour"]}
+          rows={rows.map(point => [
+            point.day,
+            point.maps_run,
+            formatChaos(point.chaos_per_hour)
+          ])}
+        />
+      );
+    case "DeathsPerMod":
+      return (
+        <Table
+          columns={["Mod", "Runs", "Deaths", "Per Run"]}
+          rows={rows.map(row => [
+            row.map_mod,
+            row.runs,
+            row.deaths,
+            row.deaths_per_run.toFixed(2)
+          ])}
+        />
+      );
+    case "Trades":
+      return (
+        <Table
+          columns={["Traded", "Kind", "With", "Item", "Count", "Price", "Value"]}
+          rows={rows.map(trade => [
+            formatDate(trade.completed_at),
+            trade.kind,
+            trade.player,
+            trade.request.item,
+            trade.request.quantity,
+            `${trade.request.price} ${trade.request.currency}`,
+            trade.chaos_value === null ? "-" : formatChaos(trade.chaos_value)
+          ])}
+        />
+      );
+    case "Profit":
+      return (
+        <Table
+          columns={["Day", "Loot", "Sales", "Purchases", "Realised"]}
+          rows={rows.map(point => [
+            point.day,
+            formatChaos(point.loot),
+            formatChaos(point.sales),
+            formatChaos(point.purchases),
+            formatChaos(point.sales - point.purchases)
+          ])}
+        />
+      );
+    case "FilterTiers":
+      return (
+        <Table
+          columns={["Loot Filter Tier", "Drops", "Value"]}
+          rows={rows.map(row => [
+            row.tier === null ? "Unsorted" : row.tier,
+            row.drops,
+            formatChaos(row.chaos_value)
+          ])}
+        />
+      );
+    default:
+      return null;
+  }
+};
+
+// The history screens. Filters are kept here and sent along with every query,
+// see `HistoryFilter` in src/types/history.rs.
+class History extends React.Component {
+  constructor(props) {
+    super(props);
+    this.state = {
+      view: 0,
+      league: "",
+      from: "",
+      to: "",
+      minTier: "",
+      maxTier: "",
+      character: "",
+      mods: "",
+      exportTable: "Runs"
+    };
+    this.query = this.query.bind(this);
+  }
+
+  filter() {
+    return {
+      league: this.state.league === "" ? null : this.state.league,
+      from: parseDate(this.state.from),
+      to: parseDate(this.state.to),
+      min_tier: parseTier(this.state.minTier),
+      max_tier: parseTier(this.state.maxTier),
+      character: this.state.character === "" ? null : this.state.character,
+      mods: this.state.mods
+        .split(",")
+        .map(mod => mod.trim())
+        .filter(mod => mod !== "")
+    };
+  }
+
+  // Written to a file by the backend, which answers with where.
+  export(format) {
+    send("Export", {
+      table: this.state.exportTable,
+      format,
+      filter: this.filter()
+    });
+  }
+
+  componentDidMount() {
+    this.query(this.state.view);
+  }
+
+  query(view) {
+    this.setState({ view });
+    send("QueryHistory", {
+      query: HISTORY_VIEWS[view].query,
+      filter: this.filter()
+    });
+  }
+
+  input(label, field, type) {
+    return (
+      <label className="mr2">
+        {label}{" "}
+        <input
+          type={type || "text"}
+          className="w4"
+          value={this.state[field]}
+          onChange={event => this.setState({ [field]: event.target.value })}
+        />
+      </label>
+    );
+  }
+
+  render() {
+    return (
+      <div className="fl w-100 pa2">
+        <h1 className="h1">History</h1>
+        <div>
+          {HISTORY_VIEWS.map((view, i) => (
+            <button
+              key={view.label}
+              className={`mr2 ${i === this.state.view ? "b" : ""}`}
+              onClick={() => this.query(i)}
+            >
+              {view.label}
+            </button>
+          ))}
+        </div>
+        <div className="pt2">
+          {this.input("League", "league")}
+          {this.input("Character", "character")}
+          {this.input("From", "from", "date")}
+          {this.input("To", "to", "date")}
+          {this.input("Min Tier", "minTier", "number")}
+          {this.input("Max Tier", "maxTier", "number")}
+          {this.input("Mods", "mods")}
+          <button onClick={() => this.query(this.state.view)}>Filter</button>
+        </div>
+        <div className="pt2">
+          Export{" "}
+          <select
+            className="mr2"
+            value={this.state.exportTable}
+            onChange={event => this.setState({ exportTable: event.target.value })}
+          >
+            {EXPORT_TABLES.map(table => (
+              <option key={table}>{table}</option>
+            ))}
+          </select>
+          <button className="mr2" onClick={() => this.export("Csv")}>
+            as CSV
+          </button>
+          <button className="mr2" onClick={() => this.export("Ndjson")}>
+            as JSON lines
+          </button>
+          {this.props.exported && (
+            <span className="gray">
+              Wrote {this.props.exported.records} to {this.props.exported.path}
+            </span>
+          )}
+        </div>
+        <div className="pt2">
+          {this.props.result === null ? null : (
+            <HistoryResult result={this.props.result} />
+          )}
+        </div>
+      </div>
+    );
+  }
+}
+
+// Fields of `Config` in src/types/config.rs the settings screen edits, with
+// how to turn the input's text back into a value.
+const SETTINGS_FIELDS = [
+  { key: "league", label: "League", parse: value => value },
+  {
+    key: "character_name",
+    label: "Character (empty to go by the log)",
+    parse: value => value
+  },
+  {
+    key: "clipboard_poll_interval_ms",
+    label: "Clipboard poll interval (ms)",
+    parse: value => parseInt(value, 10)
+  },
+  {
+    key: "clipboard_backend",
+    label: "Clipboard backend: auto, x11, wayland or polling (needs restart)",
+    parse: value => value
+  },
+  {
+    key: "price_cache_ttl_minutes",
+    label: "Keep prices for (minutes)",
+    parse: value => parseInt(value, 10)
+  },
+  {
+    key: "price_refresh_lead_minutes",
+    label: "Refresh prices early by (minutes)",
+    parse: value => parseInt(value, 10)
+  },
+  {
+    key: "duplicate_window_minutes",
+    label: "Count copies of the same item once within (minutes)",
+    parse: value => parseInt(value, 10)
+  },
+  {
+    key: "endpoints_file",
+    label: "poe.ninja endpoint list",
+    parse: value => (value === "" ? null : value)
+  },
+  {
+    key: "database_file",
+    label: "History database (needs restart)",
+    parse: value => (value === "" ? null : value)
+  },
+  {
+    key: "loot_filter_file",
+    label: "Loot filter to sort drops by (.filter file)",
+    parse: value => (value === "" ? null : value)
+  },
+  {
+    key: "log_level",
+    label: "Log level (error, warn, info, debug or trace)",
+    parse: value => value
+  }
+];
+
+// Edits a copy of the config, nothing changes until it's saved. The backend
+// answers a save with `Settings` or `SettingsInvalid`.
+class Settings extends React.Component {
+  constructor(props) {
+    super(props);
+    this.state = { draft: props.config };
+  }
+
+  componentDidUpdate(previous) {
+    if (previous.config !== this.props.config) {
+      this.setState({ draft: this.props.config });
+    }
+  }
+
+  update(field, value) {
+    this.setState({ draft: { ...this.state.draft, [field]: value } });
+  }
+
+  render() {
+    const draft = this.state.draft;
+    if (draft === null) {
+      return <div className="fl w-100 pa2">Loading settings…</div>;
+    }
+    return (
+      <div className="fl w-100 pa2">
+        <h1 className="h1">Settings</h1>
+        {SETTINGS_FIELDS.map(field => (
+          <div key={field.key} className="pt2">
+            <label>
+              <div>{field.label}</div>
+              <input
+                className="w5"
+                value={draft[field.key] === null ? "" : draft[field.key]}
+                onChange={event =>
+                  this.update(field.key, field.parse(event.target.value))
+                }
+              />
+            </label>
+          </div>
+        ))}
+        <div className="pt2">
+          <label>
+            <input
+              type="checkbox"
+              checked={draft.debug}
+              onChange={event => this.update("debug", event.target.checked)}
+            />{" "}
+            Web inspector (needs restart)
+          </label>
+        </div>
+        <div className="pt2 dark-red">
+          {this.props.problems.map((problem, i) => (
+            <div key={i}>{problem}</div>
+          ))}
+        </div>
+        <div className="pt2">
+          <button
+            className="mr2"
+            onClick={() => send("SaveSettings", { config: draft })}
+          >
+            Save
+          </button>
+          <button onClick={this.props.onClose}>Close</button>
+        </div>
+      </div>
+    );
+  }
+}
+
+const Errors = props => (
+  <div className="fl w-100 dark-red">
+    {props.errors.map((error, i) => (
+      <div key={i}>{error}</div>
+    ))}
+  </div>
+);
+
+// Workers in the backend that aren't running right now, see `WorkerStatus`
+// in src/types/supervisor.rs. Nothing to show while everything is fine.
+const Health = ({ workers }) => (
+  <div className="fl w-100">
+    {Object.keys(workers)
+      .filter(name => workers[name].state !== "Running")
+      .map(name => {
+        const status = workers[name];
+        switch (status.state) {
+          case "Restarting":
+            return (
+              <p key={name} className="orange ma1">
+                {name} crashed ({status.reason}), restarting in{" "}
+                {Math.ceil(status.retry_in_ms / 1000)}s
+              </p>
+            );
+          case "Failed":
+            return (
+              <p key={name} className="red ma1">
+                {name} keeps crashing ({status.reason}), restart the app to try
+                again
+              </p>
+            );
+          default:
+            return (
+              <p key={name} className="gray ma1">
+                {name} stopped
+              </p>
+            );
+        }
+      })}
+  </div>
+);
+
+// Only the newest log entries are kept, the log file has all of them.
+const LOG_ENTRIES_KEPT = 500;
+const LOG_LEVELS = ["ERROR", "WARN", "INFO", "DEBUG", "TRACE"];
+const LOG_LEVEL_COLOURS = { ERROR: "red", WARN: "orange", INFO: "black" };
+
+// What the backend logged since the window opened, see `LogEntry` in
+// src/types/logging.rs. Showing less than that is up to `level`.
+class LogViewer extends React.Component {
+  constructor(props) {
+    super(props);
+    this.state = { level: "INFO" };
+  }
+
+  render() {
+    const shown = LOG_LEVELS.slice(0, LOG_LEVELS.indexOf(this.state.level) + 1);
+    return (
+      <div className="fl w-100 pa2">
+        <h1 className="h1">Log</h1>
+        <select
+          value={this.state.level}
+          onChange={event => this.setState({ level: event.target.value })}
+        >
+          {LOG_LEVELS.map(level => (
+            <option key={level} value={level}>
+              {level}
+            </option>
+          ))}
+        </select>
+        <div className="pt2 code f6">
+          {this.props.entries
+            .filter(entry => shown.includes(entry.level))
+            .map((entry, i) => (
+              <div key={i} className={LOG_LEVEL_COLOURS[entry.level] || "gray"}>
+                {new Date(entry.timestamp * 1000).toLocaleTimeString()}{" "}
+                {entry.level} {entry.target}: {entry.message}
+              </div>
+            ))}
+        </div>
+      </div>
+    );
+  }
+}
+
 class Display extends React.Component {
   constructor(props) {
     super(props);
@@ -42,30 +738,240 @@ class Display extends React.Component {
     window.dispatch = this.dispatch;
 
     this.state = {
-      currentMap: "Shaped Cage",
+      currentMap: "",
       previousMap: "",
-      droppedItems: []
+      droppedItems: [],
+      groupByTier: false,
+      runningTotal: null,
+      queuedMap: null,
+      character: null,
+      mapDeaths: 0,
+      trades: [],
+      paused: false,
+      session: null,
+      history: null,
+      exported: null,
+      settings: null,
+      settingsProblems: [],
+      screen: "session",
+      workers: {},
+      logs: [],
+      errors: []
     };
+    this.onKeyDown = this.onKeyDown.bind(this);
+  }
+
+  componentDidMount() {
+    document.addEventListener("keydown", this.onKeyDown);
+  }
+
+  componentWillUnmount() {
+    document.removeEventListener("keydown", this.onKeyDown);
+  }
+
+  // C-, opens the settings, like everywhere else. C-z takes back the last
+  // drop.
+  onKeyDown(event) {
+    if (event.ctrlKey && event.key === ",") {
+      event.preventDefault();
+      this.openSettings();
+    } else if (event.ctrlKey && event.key === "z") {
+      event.preventDefault();
+      send("UndoLastDrop");
+    }
+  }
+
+  openSettings() {
+    this.setState({ screen: "settings", settingsProblems: [] });
+    send("OpenSettings");
+  }
+
+  renderScreen() {
+    switch (this.state.screen) {
+      case "history":
+        return (
+          <History result={this.state.history} exported={this.state.exported} />
+        );
+      case "log":
+        return <LogViewer entries={this.state.logs} />;
+      case "settings":
+        return (
+          <Settings
+            config={this.state.settings}
+            problems={this.state.settingsProblems}
+            onClose={() => this.setState({ screen: "session" })}
+          />
+        );
+      default:
+        return (
+          <div>
+            <EventPipe
+              items={this.state.droppedItems}
+              grouped={this.state.groupByTier}
+              onGroup={grouped => this.setState({ groupByTier: grouped })}
+            />
+            <button className="ma2" onClick={() => send("UndoLastDrop")}>
+              Undo last drop
+            </button>
+            <button className="ma2" onClick={() => send("TagLastDrop")}>
+              Tag last drop
+            </button>
+            <MapDisplay
+              currentMap={this.state.currentMap}
+              previousMap={this.state.previousMap}
+              queuedMap={this.state.queuedMap}
+              mapDeaths={this.state.mapDeaths}
+              character={this.state.character}
+              paused={this.state.paused}
+            />
+            <RunningTotal
+              total={this.state.runningTotal}
+              session={this.state.session}
+            />
+            <Trades trades={this.state.trades} />
+          </div>
+        );
+    }
   }
 
   render() {
     return (
       <div className="fl w-100 helvetica">
-        <EventPipe items={this.state.droppedItems} />
-        <MapDisplay
-          currentMap={this.state.currentMap}
-          previousMap={this.state.previousMap}
-        />
+        <Errors errors={this.state.errors} />
+        <Health workers={this.state.workers} />
+        <div className="fl w-100 pa2">
+          <button
+            className="mr2"
+            onClick={() => this.setState({ screen: "session" })}
+          >
+            Session
+          </button>
+          <button
+            className="mr2"
+            onClick={() => this.setState({ screen: "history" })}
+          >
+            History
+          </button>
+          <button
+            className="mr2"
+            onClick={() => this.setState({ screen: "log" })}
+          >
+            Log
+          </button>
+          <button onClick={() => this.openSettings()}>Settings</button>
+        </div>
+        {this.renderScreen()}
       </div>
     );
   }
 
-  dispatch(string) {
-    this.setState({
-      currentMap: string,
-      previousMap: this.state.currentMap,
-      droppedItems: append(this.state.droppedItems, <Item name={string} />)
-    });
+  // Messages from the backend, see `FrontendMessage` in
+  // src/types/frontend_communication.rs.
+  dispatch(message) {
+    const payload = message.payload;
+    switch (message.type) {
+      case "ItemDropped":
+        this.setState({
+          droppedItems: append(this.state.droppedItems, {
+            id: payload.id,
+            name: payload.name,
+            value: null,
+            tagged: false,
+            filterTier: payload.filter_tier
+          })
+        });
+        break;
+      case "PriceUpdated":
+        this.setState({
+          droppedItems: this.state.droppedItems.map(item =>
+            item.id === payload.id
+              ? { ...item, value: payload.chaos_equivalent }
+              : item
+          )
+        });
+        break;
+      case "DropRemoved":
+        this.setState({
+          droppedItems: this.state.droppedItems.filter(
+            item => item.id !== payload.id
+          )
+        });
+        break;
+      case "DropTagged":
+        this.setState({
+          droppedItems: this.state.droppedItems.map(item =>
+            item.id === payload.id ? { ...item, tagged: payload.tagged } : item
+          )
+        });
+        break;
+      case "Paused":
+        this.setState({ paused: payload.paused });
+        break;
+      case "MapQueued":
+        this.setState({ queuedMap: payload });
+        break;
+      case "MapStarted": {
+        // Same as the backend, the queued map goes to its area's next run.
+        const queued = this.state.queuedMap;
+        this.setState({
+          currentMap: payload.name,
+          previousMap: this.state.currentMap,
+          queuedMap: queued && queued.name === payload.name ? null : queued,
+          mapDeaths: 0
+        });
+        break;
+      }
+      case "MapEnded":
+        this.setState({ currentMap: "", previousMap: payload.name });
+        break;
+      case "CharacterChanged":
+        this.setState({ character: payload.character });
+        break;
+      case "Died":
+        this.setState({ mapDeaths: payload.map_deaths });
+        break;
+      case "TradeUpdated":
+        this.setState({
+          trades: [
+            ...this.state.trades.filter(trade => trade.id !== payload.trade.id),
+            payload.trade
+          ]
+        });
+        break;
+      case "RunningTotal":
+        this.setState({ runningTotal: payload });
+        break;
+      case "SessionSummary":
+        this.setState({ session: payload });
+        break;
+      case "Settings":
+        this.setState({ settings: payload.config, settingsProblems: [] });
+        break;
+      case "SettingsInvalid":
+        this.setState({ settingsProblems: payload.problems });
+        break;
+      case "History":
+        this.setState({ history: payload.result });
+        break;
+      case "Exported":
+        this.setState({ exported: payload });
+        break;
+      case "Health":
+        this.setState({
+          workers: { ...this.state.workers, [payload.worker]: payload.status }
+        });
+        break;
+      case "Log":
+        this.setState({
+          logs: append(this.state.logs, payload.entry).slice(-LOG_ENTRIES_KEPT)
+        });
+        break;
+      case "Error":
+        this.setState({ errors: append(this.state.errors, payload.message) });
+        break;
+      default:
+        console.log("Unknown message from backend", message);
+    }
   }
 }
 
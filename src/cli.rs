@@ -0,0 +1,477 @@
+//! The command line. Without a subcommand, the tool runs with its window, as
+//! it always has. Options given here override the config file.
+use chrono::prelude::*;
+use chrono::Duration;
+use clap::{App, AppSettings, Arg, ArgMatches, Error, SubCommand};
+use std::path::PathBuf;
+
+use atlas_of_beancounting::config::default_config_path;
+use atlas_of_beancounting::types::config::ConfigOverrides;
+use atlas_of_beancounting::types::export::{ExportFormat, ExportTable};
+use atlas_of_beancounting::types::history::HistoryFilter;
+
+pub fn app() -> App<'static, 'static> {
+    App::new("atlas-of-beancounting")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Keeps track of what your maps are worth.")
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("FILE")
+                .help("Read settings from FILE instead of the default location")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("league")
+                .long("league")
+                .value_name("LEAGUE")
+                .help("Price items for, and record maps under, LEAGUE")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("debug")
+                .long("debug")
+                .help("Enable the web inspector")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .value_name("LEVEL")
+                .possible_values(&["off", "error", "warn", "info", "debug", "trace"])
+                .help("Log everything at LEVEL and above")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("poll-interval")
+                .long("poll-interval")
+                .value_name("MS")
+                .help("Check the clipboard every MS milliseconds")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("price-ttl")
+                .long("price-ttl")
+                .value_name("MINUTES")
+                .help("Keep prices for MINUTES before refreshing them")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("endpoints")
+                .long("endpoints")
+                .value_name("FILE")
+                .help("Fetch the poe.ninja categories listed in FILE")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("database")
+                .long("database")
+                .value_name("FILE")
+                .help("Keep map history in FILE")
+                .global(true),
+        )
+        .setting(AppSettings::VersionlessSubcommands)
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Track the session (default)")
+                .arg(Arg::with_name("headless").long("headless").help(
+                    "Don't open a window. Events are printed to stdout as JSON, one per \
+                     line, and commands are read from stdin the same way",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("parse")
+                .about("Parse copied tooltips and print the items as JSON, one per line")
+                .arg(
+                    Arg::with_name("FILE")
+                        .multiple(true)
+                        .help("Tooltip to parse, stdin if none or -"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("price")
+                .about("Look up the price of an item on poe.ninja")
+                .arg(Arg::with_name("NAME").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("replay")
+                .about("Rebuild map history from an old Client.txt")
+                .arg(Arg::with_name("LOG").required(true)),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about(
+                    "Merge in the history of another machine, from its database or what \
+                     export wrote there. Runs as CSV or JSON lines need their drops export \
+                     too. --league only takes that league",
+                )
+                .arg(Arg::with_name("FILE").required(true).multiple(true))
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("List what would be imported, without importing it"),
+                )
+                .arg(
+                    Arg::with_name("character")
+                        .long("character")
+                        .value_name("NAME")
+                        .help("Only take the runs of the character NAME"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about(
+                    "Dump history as JSON, CSV or JSON lines. --league picks the league, \
+                     without it they're all in",
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .value_name("FILE")
+                        .help("Write to FILE instead of stdout"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .possible_values(&["json", "csv", "ndjson"])
+                        .default_value("json"),
+                )
+                .arg(
+                    Arg::with_name("table")
+                        .long("table")
+                        .value_name("TABLE")
+                        .possible_values(&["runs", "drops", "trades", "events", "sessions"])
+                        .default_value("runs"),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .value_name("DATE")
+                        .help("Only what started on DATE (YYYY-MM-DD) or later"),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .value_name("DATE")
+                        .help("Only what started on DATE (YYYY-MM-DD) or earlier"),
+                )
+                .arg(
+                    Arg::with_name("character")
+                        .long("character")
+                        .value_name("NAME")
+                        .help("Only runs and drops of the character NAME"),
+                )
+                .arg(
+                    Arg::with_name("log")
+                        .long("log")
+                        .value_name("FILE")
+                        .help("Read events from FILE instead of the game's Client.txt"),
+                )
+                .arg(
+                    Arg::with_name("columns")
+                        .long("columns")
+                        .help("List the columns of TABLE instead"),
+                ),
+        )
+}
+
+/// What the user asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Run { headless: bool },
+    Parse { files: Vec<PathBuf> },
+    Price { name: String },
+    Replay { log: PathBuf },
+    Import {
+        files: Vec<PathBuf>,
+        filter: HistoryFilter,
+        dry_run: bool,
+    },
+    Export {
+        output: Option<PathBuf>,
+        table: ExportTable,
+        format: ExportFormat,
+        filter: HistoryFilter,
+        log: Option<PathBuf>,
+        columns: bool,
+    },
+}
+
+pub fn command(matches: &ArgMatches) -> Command {
+    match matches.subcommand() {
+        ("run", Some(run)) => Command::Run {
+            headless: run.is_present("headless"),
+        },
+        ("parse", Some(parse)) => Command::Parse {
+            files: parse
+                .values_of("FILE")
+                .map_or(vec![], |files| files.map(PathBuf::from).collect()),
+        },
+        ("price", Some(price)) => Command::Price {
+            name: price
+                .value_of("NAME")
+                .expect("NAME is required")
+                .to_string(),
+        },
+        ("replay", Some(replay)) => Command::Replay {
+            log: PathBuf::from(replay.value_of("LOG").expect("LOG is required")),
+        },
+        ("import", Some(import)) => Command::Import {
+            files: import
+                .values_of("FILE")
+                .expect("FILE is required")
+                .map(PathBuf::from)
+                .collect(),
+            filter: HistoryFilter {
+                league: value_of(matches, "league").map(|l| l.to_string()),
+                character: import.value_of("character").map(|c| c.to_string()),
+                ..HistoryFilter::default()
+            },
+            dry_run: import.is_present("dry-run"),
+        },
+        ("export", Some(export)) => Command::Export {
+            output: export.value_of("output").map(PathBuf::from),
+            table: export
+                .value_of("table")
+                .and_then(ExportTable::from_name)
+                .expect("table has a default"),
+            format: export
+                .value_of("format")
+                .and_then(ExportFormat::from_name)
+                .expect("format has a default"),
+            filter: HistoryFilter {
+                league: value_of(matches, "league").map(|l| l.to_string()),
+                from: export.value_of("from").map(|day| start_of_day(day, 0)),
+                to: export.value_of("to").map(|day| start_of_day(day, 1)),
+                character: export.value_of("character").map(|c| c.to_string()),
+                ..HistoryFilter::default()
+            },
+            log: export.value_of("log").map(PathBuf::from),
+            columns: export.is_present("columns"),
+        },
+        _ => Command::Run { headless: false },
+    }
+}
+
+/// Midnight `days_later` days after `day`, local time, in seconds since the
+/// epoch. Exits with a usage message if `day` isn't a date.
+fn start_of_day(day: &str, days_later: i64) -> i64 {
+    let date = NaiveDate::parse_from_str(day, "%Y-%m-%d").unwrap_or_else(|_| {
+        Error::value_validation_auto(format!("{} isn't a date like 2018-11-22", day)).exit()
+    });
+    let midnight = (date + Duration::days(days_later)).and_hms(0, 0, 0);
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .map_or_else(|| midnight.timestamp(), |midnight| midnight.timestamp())
+}
+
+/// Global options can come before or after the subcommand, so look in both.
+fn value_of<'a>(matches: &'a ArgMatches, name: &str) -> Option<&'a str> {
+    matches.value_of(name).or_else(|| {
+        matches
+            .subcommand()
+            .1
+            .and_then(|subcommand| subcommand.value_of(name))
+    })
+}
+
+fn is_present(matches: &ArgMatches, name: &str) -> bool {
+    matches.is_present(name)
+        || matches
+            .subcommand()
+            .1
+            .map_or(false, |subcommand| subcommand.is_present(name))
+}
+
+pub fn config_path(matches: &ArgMatches) -> PathBuf {
+    value_of(matches, "config")
+        .map(PathBuf::from)
+        .unwrap_or_else(default_config_path)
+}
+
+/// Pick the config overrides out of the command line. Exits with a usage
+/// message if a number isn't one.
+pub fn overrides(matches: &ArgMatches) -> ConfigOverrides {
+    let number = |name| {
+        value_of(matches, name).map(|value| {
+            value.parse::<u64>().unwrap_or_else(|_| {
+                Error::value_validation_auto(format!("{} isn't a number: {}", name, value)).exit()
+            })
+        })
+    };
+
+    ConfigOverrides {
+        league: value_of(matches, "league").map(|l| l.to_string()),
+        debug: if is_present(matches, "debug") {
+            Some(true)
+        } else {
+            None
+        },
+        clipboard_poll_interval_ms: number("poll-interval"),
+        price_cache_ttl_minutes: number("price-ttl").map(|ttl| ttl as i64),
+        endpoints_file: value_of(matches, "endpoints").map(PathBuf::from),
+        database_file: value_of(matches, "database").map(PathBuf::from),
+        log_level: value_of(matches, "log-level").map(|l| l.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_override_nothing_by_default() {
+        let matches = app().get_matches_from(vec!["atlas-of-beancounting"]);
+        assert_eq!(overrides(&matches), ConfigOverrides::default());
+        assert_eq!(config_path(&matches), default_config_path());
+    }
+
+    #[test]
+    fn should_pick_up_overrides() {
+        let matches = app().get_matches_from(vec![
+            "atlas-of-beancounting",
+            "--config",
+            "/tmp/config.toml",
+            "--league",
+            "Betrayal",
+            "--debug",
+            "--poll-interval",
+            "500",
+            "--price-ttl",
+            "30",
+            "--log-level",
+            "debug",
+        ]);
+        assert_eq!(config_path(&matches), PathBuf::from("/tmp/config.toml"));
+        assert_eq!(
+            overrides(&matches),
+            ConfigOverrides {
+                league: Some("Betrayal".to_string()),
+                debug: Some(true),
+                clipboard_poll_interval_ms: Some(500),
+                price_cache_ttl_minutes: Some(30),
+                endpoints_file: None,
+                database_file: None,
+                log_level: Some("debug".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn should_run_with_window_by_default() {
+        let matches = app().get_matches_from(vec!["atlas-of-beancounting"]);
+        assert_eq!(command(&matches), Command::Run { headless: false });
+    }
+
+    #[test]
+    fn should_pick_subcommands() {
+        let command_of = |args: Vec<&str>| command(&app().get_matches_from(args));
+        assert_eq!(
+            command_of(vec!["atlas-of-beancounting", "run", "--headless"]),
+            Command::Run { headless: true }
+        );
+        assert_eq!(
+            command_of(vec![
+                "atlas-of-beancounting",
+                "parse",
+                "resources/chaos-orb"
+            ]),
+            Command::Parse {
+                files: vec![PathBuf::from("resources/chaos-orb")]
+            }
+        );
+        assert_eq!(
+            command_of(vec!["atlas-of-beancounting", "parse"]),
+            Command::Parse { files: vec![] }
+        );
+        assert_eq!(
+            command_of(vec!["atlas-of-beancounting", "price", "Exalted Orb"]),
+            Command::Price {
+                name: "Exalted Orb".to_string()
+            }
+        );
+        assert_eq!(
+            command_of(vec!["atlas-of-beancounting", "replay", "Client.txt"]),
+            Command::Replay {
+                log: PathBuf::from("Client.txt")
+            }
+        );
+        assert_eq!(
+            command_of(vec!["atlas-of-beancounting", "export", "-o", "runs.json"]),
+            Command::Export {
+                output: Some(PathBuf::from("runs.json")),
+                table: ExportTable::Runs,
+                format: ExportFormat::Json,
+                filter: HistoryFilter::default(),
+                log: None,
+                columns: false,
+            }
+        );
+        assert_eq!(
+            command_of(vec![
+                "atlas-of-beancounting",
+                "import",
+                "laptop.sqlite",
+                "--dry-run",
+                "--character",
+                "Nightblade",
+            ]),
+            Command::Import {
+                files: vec![PathBuf::from("laptop.sqlite")],
+                filter: HistoryFilter {
+                    character: Some("Nightblade".to_string()),
+                    ..HistoryFilter::default()
+                },
+                dry_run: true,
+            }
+        );
+        let day = |d| Local.ymd(2018, 11, d).and_hms(0, 0, 0).timestamp();
+        assert_eq!(
+            command_of(vec![
+                "atlas-of-beancounting",
+                "export",
+                "--league",
+                "Betrayal",
+                "--table",
+                "drops",
+                "--format",
+                "csv",
+                "--from",
+                "2018-11-22",
+                "--to",
+                "2018-11-22",
+                "--character",
+                "Nightblade",
+            ]),
+            Command::Export {
+                output: None,
+                table: ExportTable::Drops,
+                format: ExportFormat::Csv,
+                filter: HistoryFilter {
+                    league: Some("Betrayal".to_string()),
+                    from: Some(day(22)),
+                    to: Some(day(23)),
+                    character: Some("Nightblade".to_string()),
+                    ..HistoryFilter::default()
+                },
+                log: None,
+                columns: false,
+            }
+        );
+    }
+
+    #[test]
+    fn should_take_options_after_subcommand() {
+        let matches = app().get_matches_from(vec![
+            "atlas-of-beancounting",
+            "price",
+            "Exalted Orb",
+            "--league",
+            "Betrayal",
+        ]);
+        assert_eq!(overrides(&matches).league, Some("Betrayal".to_string()));
+    }
+}
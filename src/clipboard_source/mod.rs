@@ -0,0 +1,276 @@
+//! Where tooltips come from. The game puts them on the clipboard on Ctrl+C, so
+//! something has to notice the clipboard changing. How depends on the desktop:
+//! X11 says so through XFixes, Wayland through `wl-paste --watch`, and
+//! everywhere else the clipboard is still polled. `Scripted` plays back a list
+//! of contents instead, for tests.
+use std::env;
+use std::fmt;
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
+
+use constants::CLIPBOARD_RETRY_MS;
+use supervisor::Shutdown;
+use types::clipboard_event::{ClipboardEvent, ClipboardRequest};
+
+mod polling;
+mod scripted;
+#[cfg(target_os = "linux")]
+mod wayland;
+#[cfg(target_os = "linux")]
+mod x11;
+
+pub use self::polling::Polling;
+pub use self::scripted::Scripted;
+#[cfg(target_os = "linux")]
+pub use self::wayland::Wayland;
+#[cfg(target_os = "linux")]
+pub use self::x11::X11;
+
+/// What `clipboard_backend` in the config can be.
+pub const BACKENDS: &[&str] = &["auto", "x11", "wayland", "polling"];
+
+#[derive(Debug, PartialEq)]
+pub enum ClipboardError {
+    /// Can't get at the clipboard right now. It's tried again in a bit.
+    Unavailable(String),
+    /// Nothing more is coming. Only scripted sources run out.
+    Closed,
+}
+
+impl fmt::Display for ClipboardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClipboardError::Unavailable(ref reason) => write!(f, "{}", reason),
+            ClipboardError::Closed => write!(f, "clipboard source closed"),
+        }
+    }
+}
+
+pub trait ClipboardSource {
+    /// Wait for the clipboard to change, and return what's on it then. `None`
+    /// if that isn't text, or if nothing happened for a while; sources come
+    /// back every so often so `shutdown` gets noticed.
+    fn next_change(&mut self, shutdown: &Shutdown) -> Result<Option<String>, ClipboardError>;
+
+    /// Only matters to sources that poll.
+    fn set_poll_interval(&mut self, _interval: Duration) -> () {}
+
+    /// Sources that poll can't tell a copy of the same thing from nothing
+    /// happening, the others only come back with something after a copy.
+    fn polls(&self) -> bool {
+        false
+    }
+}
+
+/// Open the source called `backend`, one of `BACKENDS`. `auto` goes by what
+/// the session looks like. Whatever can't be opened falls back to polling.
+pub fn open(backend: &str) -> Box<dyn ClipboardSource> {
+    let backend = if backend == "auto" { detect() } else { backend };
+    let opened: Result<Box<dyn ClipboardSource>, ClipboardError> = match backend {
+        #[cfg(target_os = "linux")]
+        "x11" => X11::open().map(|source| Box::new(source) as Box<dyn ClipboardSource>),
+        #[cfg(target_os = "linux")]
+        "wayland" => Wayland::open().map(|source| Box::new(source) as Box<dyn ClipboardSource>),
+        "polling" => Ok(Box::new(Polling::new())),
+        _ => Err(ClipboardError::Unavailable(format!(
+            "{} isn't supported here",
+            backend
+        ))),
+    };
+    match opened {
+        Ok(source) => {
+            info!("Watching the clipboard through {}", backend);
+            source
+        }
+        Err(e) => {
+            warn!(
+                "Can't watch the clipboard through {}, polling instead: {}",
+                backend, e
+            );
+            Box::new(Polling::new())
+        }
+    }
+}
+
+/// Wayland sessions usually have an X server for older programs too, but
+/// only the Wayland clipboard sees everything.
+fn detect() -> &'static str {
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        "wayland"
+    } else if env::var_os("DISPLAY").is_some() {
+        "x11"
+    } else {
+        "polling"
+    }
+}
+
+/// Pass on whatever text `source` finds on the clipboard, unless it's the
+/// same as last time and no copy was expected. A new interval sent on
+/// `requests` goes to the source, and stays in `interval` for when the
+/// watcher is restarted. Errors are logged and waited out. Returns on
+/// shutdown, when the source closes, or once nobody listens to `s` anymore.
+pub fn watch_clipboard(
+    source: &mut dyn ClipboardSource,
+    s: &Sender<ClipboardEvent>,
+    interval: &mut Duration,
+    requests: &Receiver<ClipboardRequest>,
+    shutdown: &Shutdown,
+) -> () {
+    source.set_poll_interval(*interval);
+    let mut current_content = String::new();
+    let mut failing = false;
+    while !shutdown.is_requested() {
+        for request in requests.try_iter() {
+            match request {
+                ClipboardRequest::PollInterval(new_interval) => {
+                    *interval = new_interval;
+                    source.set_poll_interval(new_interval);
+                }
+                // Whatever comes next is new then, unless it's only the
+                // clipboard being polled again.
+                ClipboardRequest::ExpectCopy if !source.polls() => current_content.clear(),
+                ClipboardRequest::ExpectCopy => {}
+            }
+        }
+
+        let content = match source.next_change(shutdown) {
+            Ok(content) => content,
+            Err(ClipboardError::Closed) => return,
+            Err(ClipboardError::Unavailable(reason)) => {
+                // Only once, it's likely to stay that way for a while.
+                if !failing {
+                    warn!("Can't read the clipboard: {}", reason);
+                    failing = true;
+                }
+                if !shutdown.sleep(Duration::from_millis(CLIPBOARD_RETRY_MS)) {
+                    return;
+                }
+                continue;
+            }
+        };
+        if failing {
+            info!("Can read the clipboard again");
+            failing = false;
+        }
+
+        if let Some(content) = content {
+            if content != current_content {
+                current_content = content.clone();
+                if s.send(ClipboardEvent { content }).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    fn watch(source: &mut dyn ClipboardSource) -> Vec<String> {
+        let (sender, receiver) = channel();
+        let (_request_sender, requests) = channel();
+        watch_clipboard(
+            source,
+            &sender,
+            &mut Duration::from_millis(200),
+            &requests,
+            &Shutdown::new(),
+        );
+        receiver.try_iter().map(|event| event.content).collect()
+    }
+
+    #[test]
+    fn should_pass_on_changes_once() {
+        let mut source = Scripted::new(vec![
+            Ok(Some("Rarity: Currency".to_string())),
+            Ok(Some("Rarity: Currency".to_string())),
+            Ok(None),
+            Ok(Some("Rarity: Normal".to_string())),
+            Ok(Some("Rarity: Currency".to_string())),
+        ]);
+
+        assert_eq!(
+            watch(&mut source),
+            vec!["Rarity: Currency", "Rarity: Normal", "Rarity: Currency"]
+        );
+    }
+
+    #[test]
+    fn should_keep_going_after_errors() {
+        let mut source = Scripted::new(vec![
+            Err(ClipboardError::Unavailable("no display".to_string())),
+            Err(ClipboardError::Unavailable("no display".to_string())),
+            Ok(Some("Rarity: Rare".to_string())),
+        ]);
+
+        assert_eq!(watch(&mut source), vec!["Rarity: Rare"]);
+    }
+
+    #[test]
+    fn should_pass_interval_updates_on() {
+        let (sender, _receiver) = channel();
+        let (request_sender, requests) = channel();
+        request_sender
+            .send(ClipboardRequest::PollInterval(Duration::from_millis(500)))
+            .unwrap();
+        let mut source = Scripted::new(vec![Ok(None)]);
+        let mut interval = Duration::from_millis(200);
+
+        watch_clipboard(
+            &mut source,
+            &sender,
+            &mut interval,
+            &requests,
+            &Shutdown::new(),
+        );
+
+        assert_eq!(interval, Duration::from_millis(500));
+        assert_eq!(
+            source.poll_intervals,
+            vec![Duration::from_millis(200), Duration::from_millis(500)]
+        );
+    }
+
+    /// Copies the same map twice, expecting the second copy in between.
+    struct CopyingTwice {
+        copies: u32,
+        requests: Sender<ClipboardRequest>,
+    }
+
+    impl ClipboardSource for CopyingTwice {
+        fn next_change(&mut self, _shutdown: &Shutdown) -> Result<Option<String>, ClipboardError> {
+            self.copies += 1;
+            match self.copies {
+                1 | 3 => Ok(Some("Rarity: Normal".to_string())),
+                2 => {
+                    self.requests.send(ClipboardRequest::ExpectCopy).unwrap();
+                    Ok(None)
+                }
+                _ => Err(ClipboardError::Closed),
+            }
+        }
+    }
+
+    #[test]
+    fn should_pass_on_expected_copies_of_the_same_thing() {
+        let (sender, receiver) = channel();
+        let (request_sender, requests) = channel();
+        let mut source = CopyingTwice {
+            copies: 0,
+            requests: request_sender,
+        };
+
+        watch_clipboard(
+            &mut source,
+            &sender,
+            &mut Duration::from_millis(200),
+            &requests,
+            &Shutdown::new(),
+        );
+
+        assert_eq!(receiver.try_iter().count(), 2);
+    }
+}
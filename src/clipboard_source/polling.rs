@@ -0,0 +1,56 @@
+use clipboard::ClipboardContext;
+use clipboard::ClipboardProvider;
+use std::time::Duration;
+
+use super::{ClipboardError, ClipboardSource};
+use supervisor::Shutdown;
+
+/// Asks for the clipboard every `interval`, whether it changed or not. Works
+/// wherever the `clipboard` crate does, so it's what's left when nothing
+/// better is.
+pub struct Polling {
+    context: Option<ClipboardContext>,
+    interval: Duration,
+}
+
+impl Default for Polling {
+    fn default() -> Polling {
+        Polling {
+            context: None,
+            interval: Duration::from_millis(200),
+        }
+    }
+}
+
+impl Polling {
+    pub fn new() -> Polling {
+        Polling::default()
+    }
+}
+
+impl ClipboardSource for Polling {
+    fn next_change(&mut self, shutdown: &Shutdown) -> Result<Option<String>, ClipboardError> {
+        if !shutdown.sleep(self.interval) {
+            return Ok(None);
+        }
+        if self.context.is_none() {
+            let context: ClipboardContext =
+                ClipboardProvider::new().map_err(|e| ClipboardError::Unavailable(e.to_string()))?;
+            self.context = Some(context);
+        }
+        // Fails whenever there's something other than text on the clipboard,
+        // which is nothing we'd care about anyway.
+        Ok(self
+            .context
+            .as_mut()
+            .and_then(|context| context.get_contents().ok()))
+    }
+
+    fn set_poll_interval(&mut self, interval: Duration) -> () {
+        self.interval = interval;
+    }
+
+    fn polls(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,34 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use super::{ClipboardError, ClipboardSource};
+use supervisor::Shutdown;
+
+/// Hands out `steps` one at a time, then closes. For tests, so they don't
+/// need a clipboard.
+pub struct Scripted {
+    steps: VecDeque<Result<Option<String>, ClipboardError>>,
+    /// Every interval it was told to poll at, oldest first.
+    pub poll_intervals: Vec<Duration>,
+}
+
+impl Scripted {
+    pub fn new(steps: Vec<Result<Option<String>, ClipboardError>>) -> Scripted {
+        Scripted {
+            steps: steps.into_iter().collect(),
+            poll_intervals: Vec::new(),
+        }
+    }
+}
+
+impl ClipboardSource for Scripted {
+    fn next_change(&mut self, _shutdown: &Shutdown) -> Result<Option<String>, ClipboardError> {
+        self.steps
+            .pop_front()
+            .unwrap_or(Err(ClipboardError::Closed))
+    }
+
+    fn set_poll_interval(&mut self, interval: Duration) -> () {
+        self.poll_intervals.push(interval);
+    }
+}
@@ -0,0 +1,92 @@
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use super::{ClipboardError, ClipboardSource};
+use constants::CLIPBOARD_WAIT_MS;
+use supervisor::Shutdown;
+
+/// Leaves the waiting to `wl-paste --watch`, from wl-clipboard. That runs a
+/// command with the new contents on stdin every time the clipboard changes;
+/// ours hands them back with a NUL after, so they can be told apart.
+pub struct Wayland {
+    /// Gone after `wl-paste` quit, until it's started again.
+    watch: Option<Watch>,
+}
+
+struct Watch {
+    child: Child,
+    /// `None` for contents that weren't UTF-8.
+    changes: Receiver<Option<String>>,
+}
+
+impl Wayland {
+    pub fn open() -> Result<Wayland, ClipboardError> {
+        Ok(Wayland {
+            watch: Some(Watch::start()?),
+        })
+    }
+}
+
+impl ClipboardSource for Wayland {
+    fn next_change(&mut self, _shutdown: &Shutdown) -> Result<Option<String>, ClipboardError> {
+        if self.watch.is_none() {
+            self.watch = Some(Watch::start()?);
+        }
+        let received = match self.watch {
+            Some(ref watch) => watch
+                .changes
+                .recv_timeout(Duration::from_millis(CLIPBOARD_WAIT_MS)),
+            None => return Ok(None),
+        };
+        match received {
+            Ok(contents) => Ok(contents),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => {
+                self.watch = None;
+                Err(ClipboardError::Unavailable("wl-paste quit".to_string()))
+            }
+        }
+    }
+}
+
+impl Watch {
+    fn start() -> Result<Watch, ClipboardError> {
+        let mut child = Command::new("wl-paste")
+            .args(["--no-newline", "--type", "text", "--watch"])
+            .args(["sh", "-c", "cat; printf '\\000'"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| ClipboardError::Unavailable(format!("Can't run wl-paste: {}", e)))?;
+
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (sender, changes) = channel();
+        thread::Builder::new()
+            .name("wl-paste reader".to_string())
+            .spawn(move || {
+                for contents in BufReader::new(stdout).split(0) {
+                    let contents = match contents {
+                        Ok(contents) => String::from_utf8(contents).ok(),
+                        Err(_) => return,
+                    };
+                    if sender.send(contents).is_err() {
+                        return;
+                    }
+                }
+            })
+            .map_err(|e| ClipboardError::Unavailable(e.to_string()))?;
+        Ok(Watch { child, changes })
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) -> () {
+        // Also ends the reader, which sees stdout close.
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,173 @@
+use std::time::Duration;
+use xcb;
+use xcb::xfixes;
+
+use super::{ClipboardError, ClipboardSource};
+use constants::CLIPBOARD_WAIT_MS;
+use supervisor::Shutdown;
+
+/// Waits for XFixes to say the clipboard has a new owner, and only then asks
+/// that owner for its contents. Between changes, nothing but our own event
+/// queue gets looked at.
+pub struct X11 {
+    /// Gone after the connection broke, until it's made again.
+    session: Option<Session>,
+}
+
+struct Session {
+    connection: xcb::Connection,
+    /// Ours, invisible. The contents get put on one of its properties.
+    window: xcb::Window,
+    clipboard: xcb::Atom,
+    utf8_string: xcb::Atom,
+    property: xcb::Atom,
+    incr: xcb::Atom,
+    /// XFixes events are numbered from here on.
+    first_xfixes_event: u8,
+}
+
+impl X11 {
+    pub fn open() -> Result<X11, ClipboardError> {
+        Ok(X11 {
+            session: Some(Session::open()?),
+        })
+    }
+}
+
+impl ClipboardSource for X11 {
+    fn next_change(&mut self, shutdown: &Shutdown) -> Result<Option<String>, ClipboardError> {
+        if self.session.is_none() {
+            self.session = Some(Session::open()?);
+        }
+        let result = match self.session {
+            Some(ref session) => session.next_change(shutdown),
+            None => Ok(None),
+        };
+        if result.is_err() {
+            self.session = None;
+        }
+        result
+    }
+}
+
+fn unavailable<E: ToString>(e: E) -> ClipboardError {
+    ClipboardError::Unavailable(e.to_string())
+}
+
+fn intern(connection: &xcb::Connection, name: &str) -> Result<xcb::Atom, ClipboardError> {
+    xcb::intern_atom(connection, false, name)
+        .get_reply()
+        .map(|reply| reply.atom())
+        .map_err(unavailable)
+}
+
+impl Session {
+    fn open() -> Result<Session, ClipboardError> {
+        let (connection, screen) = xcb::Connection::connect(None).map_err(unavailable)?;
+        let window = connection.generate_id();
+        {
+            let screen = connection
+                .get_setup()
+                .roots()
+                .nth(screen as usize)
+                .ok_or_else(|| unavailable("X server has no such screen"))?;
+            xcb::create_window(
+                &connection,
+                xcb::COPY_FROM_PARENT as u8,
+                window,
+                screen.root(),
+                0,
+                0,
+                1,
+                1,
+                0,
+                xcb::WINDOW_CLASS_INPUT_OUTPUT as u16,
+                screen.root_visual(),
+                &[],
+            );
+        }
+
+        let first_xfixes_event = match connection.get_extension_data(xfixes::id()) {
+            Some(ref extension) if extension.present() => extension.first_event(),
+            _ => return Err(unavailable("X server doesn't have XFixes")),
+        };
+        // XFixes doesn't report anything before it's been asked its version.
+        xfixes::query_version(&connection, 5, 0)
+            .get_reply()
+            .map_err(unavailable)?;
+
+        let session = Session {
+            clipboard: intern(&connection, "CLIPBOARD")?,
+            utf8_string: intern(&connection, "UTF8_STRING")?,
+            property: intern(&connection, "ATLAS_OF_BEANCOUNTING")?,
+            incr: intern(&connection, "INCR")?,
+            connection,
+            window,
+            first_xfixes_event,
+        };
+        xfixes::select_selection_input(
+            &session.connection,
+            session.window,
+            session.clipboard,
+            xfixes::SELECTION_EVENT_MASK_SET_SELECTION_OWNER,
+        );
+        session.connection.flush();
+        Ok(session)
+    }
+
+    /// A new owner gets asked for text, and the answer is what's returned.
+    fn next_change(&self, shutdown: &Shutdown) -> Result<Option<String>, ClipboardError> {
+        loop {
+            let event = match self.connection.poll_for_event() {
+                Some(event) => event,
+                None => {
+                    self.connection.has_error().map_err(unavailable)?;
+                    shutdown.sleep(Duration::from_millis(CLIPBOARD_WAIT_MS));
+                    return Ok(None);
+                }
+            };
+
+            let kind = event.response_type() & !0x80;
+            if kind == self.first_xfixes_event + xfixes::SELECTION_NOTIFY {
+                xcb::convert_selection(
+                    &self.connection,
+                    self.window,
+                    self.clipboard,
+                    self.utf8_string,
+                    self.property,
+                    xcb::CURRENT_TIME,
+                );
+                self.connection.flush();
+            } else if kind == xcb::SELECTION_NOTIFY {
+                let event: &xcb::SelectionNotifyEvent = unsafe { xcb::cast_event(&event) };
+                return self.read(event);
+            }
+        }
+    }
+
+    fn read(&self, event: &xcb::SelectionNotifyEvent) -> Result<Option<String>, ClipboardError> {
+        // The owner doesn't have it as text.
+        if event.property() == xcb::NONE {
+            return Ok(None);
+        }
+        let reply = xcb::get_property(
+            &self.connection,
+            true,
+            self.window,
+            self.property,
+            xcb::ATOM_ANY,
+            0,
+            u32::MAX / 4,
+        )
+        .get_reply()
+        .map_err(unavailable)?;
+        // Sent in pieces, which only happens for far more than a tooltip.
+        if reply.type_() == self.incr {
+            debug!("Skipping a clipboard too big to be a tooltip");
+            return Ok(None);
+        }
+        Ok(Some(
+            String::from_utf8_lossy(reply.value::<u8>()).into_owned(),
+        ))
+    }
+}
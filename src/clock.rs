@@ -0,0 +1,136 @@
+//! Where the time goes. Wall-clock chaos per hour punishes every coffee
+//! break, so the mastermind runs zone changes, loading screens and AFK mode
+//! past a `Clock`, which books the time in between to whatever was going on.
+use chrono::prelude::*;
+
+use types::session::TimeSpent;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Place {
+    Map,
+    Hideout,
+    Loading,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clock {
+    /// Nothing is booked until the log says where the player is.
+    place: Option<Place>,
+    afk: bool,
+    paused: bool,
+    /// When the time not yet booked started.
+    since: Option<DateTime<Local>>,
+    spent: TimeSpent,
+}
+
+impl Clock {
+    pub fn new() -> Clock {
+        Clock {
+            place: None,
+            afk: false,
+            paused: false,
+            since: None,
+            spent: TimeSpent::default(),
+        }
+    }
+
+    pub fn loading(&mut self, at: DateTime<Local>) -> () {
+        self.book(at);
+        self.place = Some(Place::Loading);
+    }
+
+    /// Entering a zone ends the loading screen. `map` is whether it's a map,
+    /// anything else counts as hideout.
+    pub fn entered(&mut self, map: bool, at: DateTime<Local>) -> () {
+        self.book(at);
+        self.place = Some(if map { Place::Map } else { Place::Hideout });
+    }
+
+    pub fn set_afk(&mut self, afk: bool, at: DateTime<Local>) -> () {
+        self.book(at);
+        self.afk = afk;
+    }
+
+    pub fn set_paused(&mut self, paused: bool, at: DateTime<Local>) -> () {
+        self.book(at);
+        self.paused = paused;
+    }
+
+    /// All time booked so far, and what's gone by since, up to `now`.
+    pub fn spent(&self, now: DateTime<Local>) -> TimeSpent {
+        let mut clock = self.clone();
+        clock.book(now);
+        clock.spent
+    }
+
+    fn book(&mut self, at: DateTime<Local>) -> () {
+        if let Some(since) = self.since {
+            // The log only has seconds, so events can seem to go backwards
+            // against the mastermind's own timestamps.
+            if at <= since {
+                return;
+            }
+            let seconds = (at - since).num_seconds();
+            match (self.afk || self.paused, self.place) {
+                (true, Some(_)) => self.spent.afk_secs += seconds,
+                (false, Some(Place::Map)) => self.spent.map_secs += seconds,
+                (false, Some(Place::Hideout)) => self.spent.hideout_secs += seconds,
+                (false, Some(Place::Loading)) => self.spent.loading_secs += seconds,
+                (_, None) => {}
+            }
+        }
+        self.since = Some(at);
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn should_book_time_to_what_was_going_on() {
+        let mut clock = Clock::new();
+        let at = |minute| Local.ymd(2018, 11, 22).and_hms(5, minute, 0);
+
+        clock.loading(at(0));
+        clock.entered(true, at(1));
+        clock.loading(at(10));
+        clock.entered(false, at(11));
+        clock.set_afk(true, at(15));
+        clock.set_afk(false, at(25));
+        clock.loading(at(30));
+
+        assert_eq!(
+            clock.spent(at(32)),
+            TimeSpent {
+                map_secs: 9 * 60,
+                hideout_secs: 9 * 60,
+                loading_secs: 4 * 60,
+                afk_secs: 10 * 60,
+            }
+        );
+        assert_eq!(clock.spent(at(32)).active(), Duration::minutes(22));
+    }
+
+    #[test]
+    fn should_not_book_anything_before_knowing_where_we_are() {
+        let mut clock = Clock::new();
+        let at = |minute| Local.ymd(2018, 11, 22).and_hms(5, minute, 0);
+
+        clock.set_paused(true, at(0));
+        clock.set_paused(false, at(5));
+        clock.entered(true, at(10));
+        // Out of order, by a second.
+        clock.set_afk(true, at(9));
+
+        assert_eq!(clock.spent(at(10)), TimeSpent::default());
+        assert_eq!(clock.spent(at(12)).afk_secs, 120);
+    }
+}
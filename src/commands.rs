@@ -0,0 +1,355 @@
+//! The one-shot subcommands of the command line. Each of them returns the
+//! exit code for the process, and reports problems on stderr.
+use serde_json;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use atlas_of_beancounting::config::price_bot_config;
+use atlas_of_beancounting::types::config::Config;
+use atlas_of_beancounting::log_watcher::guess_event_path;
+use atlas_of_beancounting::types::export::{ExportFormat, ExportTable};
+use atlas_of_beancounting::types::history::HistoryFilter;
+use atlas_of_beancounting::web_client::NinjaClient;
+use atlas_of_beancounting::import::{self, ImportReport};
+use atlas_of_beancounting::types::session::MapRun;
+use atlas_of_beancounting::{database, export, mastermind};
+use atlas_of_beancounting::log_watcher::parse_log;
+use atlas_of_beancounting::{parse_tooltip, Item, PriceKey};
+
+/// Where a tooltip came from, and what parsing it gave.
+#[derive(Serialize)]
+struct ParseReport {
+    source: String,
+    #[serde(flatten)]
+    outcome: ParseOutcome,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ParseOutcome {
+    Item(Item),
+    Error(ParseError),
+}
+
+#[derive(Serialize)]
+struct ParseError {
+    /// One of `unreadable`, `malformed` or `unsupported`, so other tools don't
+    /// have to match on the message.
+    kind: &'static str,
+    message: String,
+}
+
+impl ParseError {
+    fn unreadable(e: &io::Error) -> ParseError {
+        ParseError {
+            kind: "unreadable",
+            message: e.to_string(),
+        }
+    }
+
+    fn from_parser(e: &io::Error) -> ParseError {
+        ParseError {
+            kind: match e.kind() {
+                io::ErrorKind::InvalidInput => "unsupported",
+                _ => "malformed",
+            },
+            message: e.to_string(),
+        }
+    }
+}
+
+/// Parse copied tooltips and print one line of JSON for each. Reads stdin
+/// when there are no files, or for `-`. Fails if any of them didn't parse.
+pub fn parse(files: &[PathBuf]) -> i32 {
+    let stdin = [PathBuf::from("-")];
+    let files = if files.is_empty() { &stdin[..] } else { files };
+
+    let mut failed = false;
+    for file in files {
+        let report = parse_file(file);
+        if let ParseOutcome::Error(_) = report.outcome {
+            failed = true;
+        }
+        match serde_json::to_string(&report) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Can't serialize {}: {}", report.source, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        1
+    } else {
+        0
+    }
+}
+
+fn parse_file(file: &Path) -> ParseReport {
+    let content = if file == Path::new("-") {
+        let mut content = String::new();
+        io::stdin().read_to_string(&mut content).map(|_| content)
+    } else {
+        fs::read_to_string(file)
+    };
+    let outcome = match content {
+        Ok(content) => match parse_tooltip(&content) {
+            Ok(item) => ParseOutcome::Item(item),
+            Err(e) => ParseOutcome::Error(ParseError::from_parser(&e)),
+        },
+        Err(e) => ParseOutcome::Error(ParseError::unreadable(&e)),
+    };
+    ParseReport {
+        source: file.display().to_string(),
+        outcome,
+    }
+}
+
+/// Fetch current prices and look up `name` in them.
+pub fn price(name: &str, config: &Config) -> i32 {
+    let price_config = price_bot_config(config);
+    let cache =
+        match NinjaClient::new().refresh_price_cache(&price_config.endpoints, &config.league) {
+            Ok(cache) => cache,
+            Err(e) => {
+                eprintln!("Can't fetch prices: {}", e);
+                return 1;
+            }
+        };
+    match cache.lookup(&PriceKey::named(name)) {
+        Some((price, quality)) => {
+            println!(
+                "{}: {} chaos ({:?})",
+                price.name, price.chaos_equivalent, quality
+            );
+            0
+        }
+        None => {
+            eprintln!("No price for {} in {}", name, config.league);
+            1
+        }
+    }
+}
+
+/// Read a whole Client.txt and record the map runs in it that aren't in the
+/// database yet.
+pub fn replay(log: &Path, config: &Config) -> i32 {
+    let file = match File::open(log) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Can't open {}: {}", log.display(), e);
+            return 1;
+        }
+    };
+    let runs = mastermind::replay(parse_log(BufReader::new(file)), config.clone());
+
+    let mut connection = match open_database(config) {
+        Some(connection) => connection,
+        None => return 1,
+    };
+    let mut recorded = 0;
+    for run in &runs {
+        let saved = database::has_map_run(&connection, &config.league, run).and_then(|known| {
+            if known {
+                Ok(false)
+            } else {
+                database::save_map_run(&mut connection, &config.league, run).map(|_| true)
+            }
+        });
+        match saved {
+            Ok(true) => recorded += 1,
+            Ok(false) => {}
+            Err(e) => {
+                eprintln!("Can't save map run {}: {}", run.name, e);
+                return 1;
+            }
+        }
+    }
+    println!(
+        "Found {} map runs, recorded {} new ones.",
+        runs.len(),
+        recorded
+    );
+    0
+}
+
+/// Merge the history in `files` into ours, or only say what that would do.
+pub fn import(files: &[PathBuf], filter: &HistoryFilter, dry_run: bool, config: &Config) -> i32 {
+    let history = match import::read_history(files, filter) {
+        Ok(history) => history,
+        Err(e) => {
+            eprintln!("Can't read history to import: {}", e);
+            return 1;
+        }
+    };
+    let mut connection = match open_database(config) {
+        Some(connection) => connection,
+        None => return 1,
+    };
+    let report = match import::merge(&mut connection, history, dry_run) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Can't import: {}", e);
+            return 1;
+        }
+    };
+
+    if dry_run {
+        print_import_report(&report);
+    }
+    println!(
+        "{} {} new map runs, {} new trades and {} new sessions, {} runs, {} trades and {} \
+         sessions were here already.",
+        if dry_run { "Would import" } else { "Imported" },
+        report.new_runs.len(),
+        report.new_trades.len(),
+        report.new_sessions.len(),
+        report.known_runs,
+        report.known_trades,
+        report.known_sessions
+    );
+    0
+}
+
+/// What's new, by league and character.
+fn print_import_report(report: &ImportReport) -> () {
+    let mut runs: BTreeMap<(&str, &str), Vec<&MapRun>> = BTreeMap::new();
+    for (league, run) in &report.new_runs {
+        let character = run.character.as_ref().map_or("nobody", |c| c.name.as_str());
+        runs.entry((league, character)).or_default().push(run);
+    }
+    for ((league, character), runs) in runs {
+        println!("{}, {}:", league, character);
+        for run in runs {
+            let drops = match run.drops.len() {
+                0 => "no drops".to_string(),
+                count => format!("{} drops worth {:.1}c", count, run.chaos_value()),
+            };
+            println!(
+                "  {} {}, {}",
+                run.started_at.format("%Y-%m-%d %H:%M"),
+                run.name,
+                drops
+            );
+        }
+    }
+    for trade in &report.new_trades {
+        println!(
+            "{}, {} of {} with {} for {} {}",
+            trade.request.league,
+            trade.kind.name(),
+            trade.request.item,
+            trade.player,
+            trade.request.price,
+            trade.request.currency
+        );
+    }
+    for (league, session) in &report.new_sessions {
+        let characters: Vec<&str> = session.characters.iter().map(|c| c.name.as_str()).collect();
+        println!(
+            "{}, session of {} by {}, {} maps",
+            league,
+            session.started_at.format("%Y-%m-%d %H:%M"),
+            characters.join(", "),
+            session.maps_run
+        );
+    }
+}
+
+/// Write history matching `filter` to `output`, or stdout. Events are read
+/// from `log`, or the game's own Client.txt if there is one.
+pub fn export(
+    output: Option<&Path>,
+    table: ExportTable,
+    format: ExportFormat,
+    filter: &HistoryFilter,
+    log: Option<&Path>,
+    config: &Config,
+) -> i32 {
+    let connection = match open_database(config) {
+        Some(connection) => connection,
+        None => return 1,
+    };
+    let client_txt = guess_event_path().ok();
+    let log = log.or(client_txt.as_deref());
+
+    let written = match output {
+        Some(path) => export::export_to_file(&connection, table, format, filter, log, path),
+        None => export::export(&connection, table, format, filter, log, io::stdout()),
+    };
+    match written {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("Can't export {}: {}", table.name(), e);
+            1
+        }
+    }
+}
+
+/// Print what the columns of `table` are, for whoever is writing a script.
+pub fn columns(table: ExportTable) -> i32 {
+    for column in export::columns(table) {
+        println!("{}\t{}", column.name, column.description);
+    }
+    0
+}
+
+fn open_database(config: &Config) -> Option<::rusqlite::Connection> {
+    let path = database::database_path(config);
+    match database::open(&path) {
+        Ok(connection) => Some(connection),
+        Err(e) => {
+            eprintln!("Can't open {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    const FIXTURES: &[&str] = &[
+        "chaos-orb",
+        "essence-of-spite",
+        "fossil",
+        "heterochromia-card",
+        "honour-arch",
+        "inpulsas-broken-heart",
+        "shaped-cage",
+        "shaped-underground-sea",
+        "the-wise-oak",
+    ];
+
+    /// Each tooltip in resources/ against what it parsed to last time, kept in
+    /// resources/golden/. Run with UPDATE_GOLDEN=1 to write them again after
+    /// a deliberate change to the parser, and check the diff.
+    #[test]
+    fn should_match_golden_files() {
+        let update = env::var_os("UPDATE_GOLDEN").is_some();
+        let resources = Path::new("resources");
+        for fixture in FIXTURES {
+            let outcome = parse_file(&resources.join(fixture)).outcome;
+            let actual = serde_json::to_string_pretty(&outcome).unwrap() + "\n";
+            let golden = resources.join("golden").join(format!("{}.json", fixture));
+            if update {
+                fs::write(&golden, &actual).unwrap();
+            }
+            let expected = fs::read_to_string(&golden)
+                .unwrap_or_else(|e| panic!("Can't read {}: {}", golden.display(), e));
+            assert_eq!(actual, expected, "{} changed", fixture);
+        }
+    }
+
+    #[test]
+    fn should_report_unreadable_files() {
+        let report = parse_file(Path::new("resources/does-not-exist"));
+        match report.outcome {
+            ParseOutcome::Error(e) => assert_eq!(e.kind, "unreadable"),
+            ParseOutcome::Item(item) => panic!("expected an error, got {:?}", item),
+        }
+    }
+}
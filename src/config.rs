@@ -0,0 +1,607 @@
+//! The config actor owns the settings file. It reads it on start, watches it
+//! for changes, writes it when the settings screen saves, and hands every new
+//! valid config to its subscribers. Subscribers are plain closures, usually
+//! wrapping the config into whatever message their actor understands, and are
+//! dropped once they return `false`.
+use chrono::Duration;
+use dirs;
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time;
+use log::LevelFilter;
+use serde_json;
+use toml;
+
+use clipboard_source;
+use hotkeys;
+use loot_filter::LootFilter;
+use constants::{
+    APP_DIR_NAME, CONFIG_FILE, DEFAULT_POE_NINJA_ENDPOINTS, NINJA_MAX_BACKOFF_SECS,
+    NINJA_MAX_CACHE_TTL_MINUTES, NINJA_MIN_REFRESH_INTERVAL_SECS,
+};
+use supervisor::Supervisor;
+use types::config::{Config, ConfigMessage, ConfigOverrides};
+use types::poe_ninja::NinjaEndpoint;
+use types::pricing::PriceBotConfig;
+
+/// Bounds for the clipboard poll interval. Faster burns CPU for nothing,
+/// slower misses tooltips copied in quick succession.
+const MIN_CLIPBOARD_POLL_INTERVAL_MS: u64 = 50;
+const MAX_CLIPBOARD_POLL_INTERVAL_MS: u64 = 5000;
+
+/// How long the file has to be quiet before a change is picked up. Editors
+/// like to write files in several steps.
+const RELOAD_DEBOUNCE_MS: u64 = 500;
+
+pub type Subscriber = Box<dyn Fn(&Config) -> bool + Send>;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+    /// The config parsed, but makes no sense. One entry per problem.
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "Can't access config file: {}", e),
+            ConfigError::Parse(e) => write!(f, "Can't parse config file: {}", e),
+            ConfigError::Serialize(e) => write!(f, "Can't write config: {}", e),
+            ConfigError::Invalid(problems) => write!(f, "Invalid config: {}", problems.join(", ")),
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> ConfigError {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> ConfigError {
+        ConfigError::Parse(e)
+    }
+}
+
+impl From<toml::ser::Error> for ConfigError {
+    fn from(e: toml::ser::Error) -> ConfigError {
+        ConfigError::Serialize(e)
+    }
+}
+
+pub struct ConfigBot {
+    inbox: Receiver<ConfigMessage>,
+    path: PathBuf,
+    overrides: ConfigOverrides,
+    /// As in the file, without the overrides.
+    file: Config,
+    /// With the overrides, what everybody else gets.
+    current: Config,
+    subscribers: Vec<Subscriber>,
+}
+
+impl ConfigBot {
+    pub fn new(
+        inbox: Receiver<ConfigMessage>,
+        path: PathBuf,
+        overrides: ConfigOverrides,
+        current: Config,
+        subscribers: Vec<Subscriber>,
+    ) -> ConfigBot {
+        let file = load(&path).unwrap_or_else(|_| current.clone());
+        ConfigBot {
+            inbox,
+            path,
+            overrides,
+            file,
+            current,
+            subscribers,
+        }
+    }
+
+    /// Run the bot under `supervisor`, along with a watcher that sends it a
+    /// `Reload` whenever the file at `path` changes. `sender` has to feed into
+    /// `inbox`.
+    pub fn supervise(
+        supervisor: &mut Supervisor,
+        inbox: Receiver<ConfigMessage>,
+        sender: Sender<ConfigMessage>,
+        path: PathBuf,
+        overrides: ConfigOverrides,
+        current: Config,
+        subscribers: Vec<Subscriber>,
+    ) -> () {
+        watch_config_file(path.clone(), sender.clone());
+        let mut config_bot = ConfigBot::new(inbox, path, overrides, current, subscribers);
+        supervisor.spawn_actor(
+            "ConfigBot",
+            move || config_bot.run(),
+            move || {
+                let _ = sender.send(ConfigMessage::ShutDown);
+            },
+        );
+    }
+
+    pub fn run(&mut self) -> () {
+        loop {
+            match self.inbox.recv() {
+                Ok(ConfigMessage::Reload) => self.reload(),
+                Ok(ConfigMessage::Save(config)) => self.save(config),
+                Ok(ConfigMessage::ShutDown) | Err(_) => {
+                    info!("Shutting down...");
+                    break;
+                }
+            }
+        }
+    }
+
+    fn reload(&mut self) -> () {
+        let file = match load(&self.path) {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("Keeping the old config. {}", e);
+                return;
+            }
+        };
+        let mut config = file.clone();
+        self.overrides.apply(&mut config);
+        if let Err(e) = validate(&config) {
+            warn!("Keeping the old config. {}", e);
+            return;
+        }
+        self.file = file;
+        self.publish(config);
+    }
+
+    /// `config` is the current one, edited. The overrides in it stay out of
+    /// the file.
+    fn save(&mut self, config: Config) -> () {
+        let mut overrides = self.overrides.clone();
+        let file = overrides.unapply(&config, &self.file);
+        if let Err(e) = save(&self.path, &file) {
+            error!("Can't save config. {}", e);
+            return;
+        }
+        self.overrides = overrides;
+        let mut config = file.clone();
+        self.overrides.apply(&mut config);
+        self.file = file;
+        self.publish(config);
+    }
+
+    fn publish(&mut self, config: Config) -> () {
+        // Saving triggers the watcher too, no need to tell everybody twice.
+        if config == self.current {
+            return;
+        }
+        info!("Config changed: {:?}", config);
+        self.subscribers.retain(|subscriber| subscriber(&config));
+        self.current = config;
+    }
+}
+
+pub fn default_config_path() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(APP_DIR_NAME)
+        .join(CONFIG_FILE)
+}
+
+/// Read the config at `path`. A missing file is the default config, so a
+/// fresh install works without any setup.
+pub fn load(path: &Path) -> Result<Config, ConfigError> {
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+    let text = fs::read_to_string(path)?;
+    Ok(toml::from_str(&text)?)
+}
+
+/// The config to start with: what's in the file, with `overrides` on top. If
+/// there's no file yet, the defaults are written out, so there's something to
+/// edit.
+pub fn startup_config(path: &Path, overrides: &ConfigOverrides) -> Result<Config, ConfigError> {
+    let mut config = load(path)?;
+    if !path.exists() {
+        if let Err(e) = save(path, &config) {
+            warn!("Can't write default config: {}", e);
+        }
+    }
+    overrides.apply(&mut config);
+    validate(&config)?;
+    Ok(config)
+}
+
+/// Write `config` to `path`, creating the directory if needed. Refuses to
+/// write a config that wouldn't load again.
+pub fn save(path: &Path, config: &Config) -> Result<(), ConfigError> {
+    validate(config)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, toml::to_string_pretty(config)?)?;
+    Ok(())
+}
+
+pub fn validate(config: &Config) -> Result<(), ConfigError> {
+    let mut problems = Vec::new();
+
+    if config.league.trim().is_empty() {
+        problems.push("league can't be empty".to_string());
+    }
+    if config.clipboard_poll_interval_ms < MIN_CLIPBOARD_POLL_INTERVAL_MS
+        || config.clipboard_poll_interval_ms > MAX_CLIPBOARD_POLL_INTERVAL_MS
+    {
+        problems.push(format!(
+            "clipboard_poll_interval_ms has to be between {} and {}",
+            MIN_CLIPBOARD_POLL_INTERVAL_MS, MAX_CLIPBOARD_POLL_INTERVAL_MS
+        ));
+    }
+    if !clipboard_source::BACKENDS.contains(&config.clipboard_backend.as_str()) {
+        problems.push(format!(
+            "clipboard_backend has to be one of {}",
+            clipboard_source::BACKENDS.join(", ")
+        ));
+    }
+    // poe.ninja won't be asked more often than this anyway.
+    let min_ttl = (NINJA_MIN_REFRESH_INTERVAL_SECS / 60) as i64;
+    if config.price_cache_ttl_minutes < min_ttl
+        || config.price_cache_ttl_minutes > NINJA_MAX_CACHE_TTL_MINUTES
+    {
+        problems.push(format!(
+            "price_cache_ttl_minutes has to be between {} and {}",
+            min_ttl, NINJA_MAX_CACHE_TTL_MINUTES
+        ));
+    }
+    if config.price_refresh_lead_minutes < 0
+        || config.price_refresh_lead_minutes >= config.price_cache_ttl_minutes
+    {
+        problems.push(
+            "price_refresh_lead_minutes has to be between 0 and price_cache_ttl_minutes"
+                .to_string(),
+        );
+    }
+    if config.duplicate_window_minutes < 0 {
+        problems.push("duplicate_window_minutes can't be negative".to_string());
+    }
+    if let Some(ref file) = config.endpoints_file {
+        if let Err(e) = read_endpoints(file) {
+            problems.push(format!("endpoints_file: {}", e));
+        }
+    }
+    if let Some(ref file) = config.loot_filter_file {
+        if let Err(e) = LootFilter::load(file) {
+            problems.push(format!("loot_filter_file: {}", e));
+        }
+    }
+    if config.log_level.parse::<LevelFilter>().is_err() {
+        problems.push(format!("log_level: {} isn't a log level", config.log_level));
+    }
+    for (module, level) in &config.log_filters {
+        if level.parse::<LevelFilter>().is_err() {
+            problems.push(format!("log_filters: {} isn't a log level for {}", level, module));
+        }
+    }
+    if let Err(e) = hotkeys::bindings(&config.hotkeys) {
+        problems.push(format!("hotkeys: {}", e));
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(ConfigError::Invalid(problems))
+    }
+}
+
+/// The part of the config the price bot cares about.
+pub fn price_bot_config(config: &Config) -> PriceBotConfig {
+    PriceBotConfig {
+        league: config.league.clone(),
+        endpoints: load_endpoints(config.endpoints_file.as_ref().map(PathBuf::as_path)),
+        ttl: Duration::minutes(config.price_cache_ttl_minutes),
+        refresh_lead: Duration::minutes(config.price_refresh_lead_minutes),
+        initial_backoff: Duration::seconds(30),
+        max_backoff: Duration::seconds(NINJA_MAX_BACKOFF_SECS as i64),
+    }
+}
+
+/// Load the list of poe.ninja categories to fetch. A list at `override_file`
+/// replaces the built-in one, unless it can't be read, in which case we
+/// complain and carry on with the defaults.
+pub fn load_endpoints(override_file: Option<&Path>) -> Vec<NinjaEndpoint> {
+    let default = || {
+        serde_json::from_str(DEFAULT_POE_NINJA_ENDPOINTS)
+            .expect("Built-in poe.ninja endpoint list is malformed")
+    };
+
+    let override_file = match override_file {
+        Some(file) => file,
+        None => return default(),
+    };
+    match read_endpoints(override_file) {
+        Ok(endpoints) => {
+            info!(
+                "Using poe.ninja endpoints from {}",
+                override_file.display()
+            );
+            endpoints
+        }
+        Err(e) => {
+            warn!("{}, using built-in endpoints.", e);
+            default()
+        }
+    }
+}
+
+/// Read an endpoint list in the format of `DEFAULT_POE_NINJA_ENDPOINTS`.
+pub fn read_endpoints(file: &Path) -> Result<Vec<NinjaEndpoint>, String> {
+    let reader =
+        fs::File::open(file).map_err(|e| format!("Can't open {}: {}", file.display(), e))?;
+    serde_json::from_reader(reader).map_err(|e| format!("Can't parse {}: {}", file.display(), e))
+}
+
+/// Send a `Reload` whenever the file at `path` is written. This watches the
+/// directory rather than the file, since editors tend to replace the file
+/// instead of writing to it, which a watch on the file itself doesn't survive.
+fn watch_config_file(path: PathBuf, sender: Sender<ConfigMessage>) -> () {
+    thread::spawn(move || {
+        let directory = match path.parent() {
+            Some(directory) if directory.exists() => directory.to_path_buf(),
+            _ => {
+                warn!(
+                    "Not watching {}, its directory doesn't exist",
+                    path.display()
+                );
+                return;
+            }
+        };
+        let (watcher_sender, watcher_receiver) = channel();
+        let mut watcher = match watcher(
+            watcher_sender,
+            time::Duration::from_millis(RELOAD_DEBOUNCE_MS),
+        ) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                warn!("Can't watch config file: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = watcher.watch(&directory, RecursiveMode::NonRecursive) {
+            warn!("Can't watch {}: {}", directory.display(), e);
+            return;
+        }
+
+        for event in watcher_receiver.iter() {
+            let changed = match event {
+                DebouncedEvent::Create(ref p)
+                | DebouncedEvent::Write(ref p)
+                | DebouncedEvent::Rename(_, ref p) => p.file_name() == path.file_name(),
+                _ => false,
+            };
+            if changed && sender.send(ConfigMessage::Reload).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::sync::mpsc;
+    use types::poe_ninja::NinjaEndpointType;
+    use types::pricing::ItemKind;
+
+    fn temp_config_path(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("atlas-of-beancounting-test-{}", name));
+        let _ = fs::remove_dir_all(&directory);
+        directory.join(CONFIG_FILE)
+    }
+
+    #[test]
+    fn should_default_when_file_is_missing() {
+        let config = load(Path::new("does/not/exist.toml")).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn should_fill_in_missing_fields() {
+        let config: Config = toml::from_str("league = \"Betrayal\"").unwrap();
+        assert_eq!(config.league, "Betrayal".to_string());
+        assert_eq!(
+            config.clipboard_poll_interval_ms,
+            Config::default().clipboard_poll_interval_ms
+        );
+    }
+
+    #[test]
+    fn should_round_trip_through_file() {
+        let path = temp_config_path("round-trip");
+        let config = Config {
+            league: "Betrayal".to_string(),
+            price_cache_ttl_minutes: 30,
+            database_file: Some(PathBuf::from("/tmp/history.sqlite")),
+            log_filters: vec![("web_client".to_string(), "debug".to_string())]
+                .into_iter()
+                .collect(),
+            ..Config::default()
+        };
+        save(&path, &config).unwrap();
+        assert_eq!(load(&path).unwrap(), config);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn should_reject_nonsense() {
+        let config = Config {
+            league: " ".to_string(),
+            clipboard_poll_interval_ms: 1,
+            clipboard_backend: "carrier pigeon".to_string(),
+            price_cache_ttl_minutes: 1,
+            price_refresh_lead_minutes: 5,
+            endpoints_file: Some(PathBuf::from("does/not/exist.json")),
+            loot_filter_file: Some(PathBuf::from("does/not/exist.filter")),
+            duplicate_window_minutes: -1,
+            log_level: "loud".to_string(),
+            log_filters: vec![("reqwest".to_string(), "quiet".to_string())]
+                .into_iter()
+                .collect(),
+            hotkeys: vec![("start_map".to_string(), "ctrl+mouse4".to_string())]
+                .into_iter()
+                .collect(),
+            ..Config::default()
+        };
+        match validate(&config) {
+            Err(ConfigError::Invalid(problems)) => assert_eq!(problems.len(), 11),
+            other => panic!("Expected problems, got {:?}", other),
+        }
+        assert!(validate(&Config::default()).is_ok());
+
+        let forever = Config {
+            price_cache_ttl_minutes: i64::max_value(),
+            ..Config::default()
+        };
+        match validate(&forever) {
+            Err(ConfigError::Invalid(problems)) => assert_eq!(
+                problems,
+                vec![format!(
+                    "price_cache_ttl_minutes has to be between 5 and {}",
+                    NINJA_MAX_CACHE_TTL_MINUTES
+                )]
+            ),
+            other => panic!("Expected problems, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_not_save_invalid_config() {
+        let path = temp_config_path("invalid");
+        let config = Config {
+            league: String::new(),
+            ..Config::default()
+        };
+        assert!(save(&path, &config).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn should_let_overrides_win() {
+        let mut config = Config::default();
+        let overrides = ConfigOverrides {
+            league: Some("Hardcore".to_string()),
+            clipboard_poll_interval_ms: Some(500),
+            ..ConfigOverrides::default()
+        };
+        overrides.apply(&mut config);
+        assert_eq!(config.league, "Hardcore".to_string());
+        assert_eq!(config.clipboard_poll_interval_ms, 500);
+        assert_eq!(config.price_cache_ttl_minutes, 60);
+    }
+
+    #[test]
+    fn should_save_overridden_fields_once_they_are_changed() {
+        let path = temp_config_path("changed-override");
+        let (_sender, inbox) = mpsc::channel();
+        let overrides = ConfigOverrides {
+            league: Some("Hardcore".to_string()),
+            ..ConfigOverrides::default()
+        };
+        let mut current = Config::default();
+        overrides.apply(&mut current);
+        let mut bot = ConfigBot::new(inbox, path.clone(), overrides, current, Vec::new());
+
+        // Like picking another league in the window.
+        let mut changed = bot.current.clone();
+        changed.league = "Betrayal".to_string();
+        bot.save(changed);
+        assert_eq!(bot.current.league, "Betrayal".to_string());
+        assert_eq!(load(&path).unwrap().league, "Betrayal".to_string());
+        // And it stays that way when the file is read again.
+        bot.reload();
+        assert_eq!(bot.current.league, "Betrayal".to_string());
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn should_publish_saved_config_with_overrides() {
+        let path = temp_config_path("publish");
+        let (_sender, inbox) = mpsc::channel();
+        let (published_sender, published) = mpsc::channel();
+        let subscriber: Subscriber =
+            Box::new(move |config: &Config| published_sender.send(config.clone()).is_ok());
+        let overrides = ConfigOverrides {
+            price_cache_ttl_minutes: Some(15),
+            ..ConfigOverrides::default()
+        };
+        let mut bot = ConfigBot::new(
+            inbox,
+            path.clone(),
+            overrides,
+            Config::default(),
+            vec![subscriber],
+        );
+
+        // What the settings screen saves has the overrides in it.
+        bot.save(Config {
+            league: "Betrayal".to_string(),
+            price_cache_ttl_minutes: 15,
+            ..Config::default()
+        });
+        let config = published.try_recv().unwrap();
+        assert_eq!(config.league, "Betrayal".to_string());
+        assert_eq!(config.price_cache_ttl_minutes, 15);
+        // The file has what was saved, not the overrides.
+        assert_eq!(
+            load(&path).unwrap(),
+            Config {
+                league: "Betrayal".to_string(),
+                ..Config::default()
+            }
+        );
+
+        // Reloading the same thing isn't news.
+        bot.reload();
+        assert!(published.try_recv().is_err());
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn should_load_built_in_endpoints() {
+        let endpoints = load_endpoints(Some(Path::new("does/not/exist.json")));
+        let currency = endpoints
+            .iter()
+            .find(|e| e.category == "Currency")
+            .expect("Currency endpoint missing");
+
+        assert_eq!(currency.endpoint, NinjaEndpointType::CurrencyOverview);
+        assert_eq!(currency.prices, ItemKind::Currency);
+        assert_eq!(
+            currency.url("Standard"),
+            "https://poe.ninja/api/data/currencyoverview?league=Standard&type=Currency"
+        );
+        assert!(endpoints.iter().any(|e| e.prices == ItemKind::BlightedMap));
+        // Nothing copied is priced as those, fetching them is a waste.
+        assert!(!endpoints
+            .iter()
+            .any(|e| e.prices == ItemKind::ClusterJewel || e.prices == ItemKind::Beast));
+    }
+
+    #[test]
+    fn should_not_list_categories_twice() {
+        let endpoints = load_endpoints(Some(Path::new("does/not/exist.json")));
+        let mut categories: Vec<_> = endpoints.iter().map(|e| e.category.clone()).collect();
+        categories.sort();
+        categories.dedup();
+        assert_eq!(categories.len(), endpoints.len());
+    }
+}
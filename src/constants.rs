@@ -1,29 +1,94 @@
-/// TODO: Get the list of this from the POE API:
-/// https://www.pathofexile.com/developer/docs/api-resource-leagues
-pub const CURRENT_LEAGUE: &str = "Standard";
-
-pub const IS_DEBUG: bool = true;
-
-/// URLs for poe.ninja. These need to have the `{}` `replace`d with the league
-/// name before use.  before use. These are overspecific because the API is
-/// locked down pretty tightly -- These are the endpoints that work and I
-/// haven't gotten any others to work.
-pub const POE_NINJA_ENDPOINT_TEMPLATES: &[&str] = &[
-    "https://poe.ninja/api/data/currencyoverview?league={}&type=Currency",
-    "https://poe.ninja/api/data/currencyoverview?league={}&type=Fragment",
-    "https://poe.ninja/api/data/itemoverview?league={}&type=Fossil",
-    "https://poe.ninja/api/data/itemoverview?league={}&type=Resonator",
-    "https://poe.ninja/api/data/itemoverview?league={}&type=Essence",
-    "https://poe.ninja/api/data/itemoverview?league={}&type=DivinationCard",
-    "https://poe.ninja/api/data/itemoverview?league={}&type=Prophecy",
-    "https://poe.ninja/api/data/itemoverview?league={}&type=SkillGem",
-    "https://poe.ninja/api/data/itemoverview?league={}&type=BaseType",
-    "https://poe.ninja/api/data/itemoverview?league={}&type=HelmetEnchant",
-    "https://poe.ninja/api/data/itemoverview?league={}&type=UniqueMap",
-    "https://poe.ninja/api/data/itemoverview?league={}&type=Map",
-    "https://poe.ninja/api/data/itemoverview?league={}&type=UniqueJewel",
-    "https://poe.ninja/api/data/itemoverview?league={}&type=UniqueFlask",
-    "https://poe.ninja/api/data/itemoverview?league={}&type=UniqueWeapon",
-    "https://poe.ninja/api/data/itemoverview?league={}&type=UniqueArmour",
-    "https://poe.ninja/api/data/itemoverview?league={}&type=UniqueAccessory",
+/// Zones that don't end a map when you port into them. Hideouts are
+/// recognised by name, so they aren't listed here.
+pub const TOWN_ZONES: &[&str] = &[
+    "Lioneye's Watch",
+    "The Forest Encampment",
+    "The Sarn Encampment",
+    "Highgate",
+    "Overseer's Tower",
+    "The Bridge Encampment",
+    "Oriath Docks",
+    "Oriath",
+    "The Rogue Harbour",
+    "The Templar Laboratory",
 ];
+
+/// Base URL for the poe.ninja API. Endpoints hang off of this as
+/// `{base}/{endpoint}?league={league}&type={category}`.
+pub const POE_NINJA_API_BASE: &str = "https://poe.ninja/api/data";
+
+/// The poe.ninja categories we fetch prices for, as shipped with the binary.
+/// The API is locked down pretty tightly, so these are the ones known to work.
+/// Users can swap in their own list with `endpoints_file` in the config.
+pub const DEFAULT_POE_NINJA_ENDPOINTS: &str = include_str!("../resources/poe-ninja-endpoints.json");
+
+/// How we introduce ourselves to poe.ninja, so they know who to talk to if we
+/// misbehave.
+pub const NINJA_USER_AGENT: &str = concat!(
+    "atlas-of-beancounting/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/MordecaiMalignatus/atlas-of-beancounting)"
+);
+
+/// The shortest time between two refreshes of the price cache. Everybody
+/// running the tool hits the same API, so be nice.
+pub const NINJA_MIN_REFRESH_INTERVAL_SECS: u64 = 5 * 60;
+
+/// The longest prices are kept before they're refreshed, in minutes. They're
+/// stale long before then.
+pub const NINJA_MAX_CACHE_TTL_MINUTES: i64 = 7 * 24 * 60;
+
+/// How long to back off after a 429 that didn't say how long to wait.
+pub const NINJA_DEFAULT_RETRY_AFTER_SECS: u64 = 60;
+
+/// The longest to back off after failed refreshes. A 429 asking for longer
+/// than that isn't taken at its word.
+pub const NINJA_MAX_BACKOFF_SECS: u64 = 30 * 60;
+
+/// Name of our directory inside the platform's config and data directories.
+pub const APP_DIR_NAME: &str = "atlas-of-beancounting";
+
+/// The SQLite file map runs are kept in, inside `APP_DIR_NAME`.
+pub const DATABASE_FILE: &str = "history.sqlite";
+
+/// Where exports from the frontend are written, inside `APP_DIR_NAME` in the
+/// data directory.
+pub const EXPORT_DIR: &str = "exports";
+
+/// The settings file, inside `APP_DIR_NAME` in the config directory.
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// How long the supervisor waits before restarting a crashed worker the first
+/// time. Doubles with every crash in a row, up to the maximum.
+pub const SUPERVISOR_INITIAL_BACKOFF_MS: u64 = 500;
+pub const SUPERVISOR_MAX_BACKOFF_MS: u64 = 60 * 1000;
+
+/// How many crashes in a row a worker gets before the supervisor gives up on
+/// it. A worker that ran for `SUPERVISOR_STABLE_AFTER_SECS` starts over.
+pub const SUPERVISOR_MAX_RESTARTS: u32 = 5;
+pub const SUPERVISOR_STABLE_AFTER_SECS: u64 = 60;
+
+/// The log file, inside `APP_DIR_NAME` in the data directory.
+pub const LOG_FILE: &str = "atlas-of-beancounting.log";
+
+/// When the log file is moved aside for a fresh one, and how many of the old
+/// ones are kept, as `.1` (newest) to `.3`.
+pub const LOG_FILE_MAX_BYTES: u64 = 1024 * 1024;
+pub const LOG_FILES_KEPT: u32 = 3;
+
+/// How long a clipboard source waits for a change before checking whether it
+/// should shut down instead.
+pub const CLIPBOARD_WAIT_MS: u64 = 50;
+
+/// How long to leave the clipboard alone after it couldn't be read.
+pub const CLIPBOARD_RETRY_MS: u64 = 500;
+
+/// How long after the `start_map` hotkey a copied map still starts a run.
+/// Copying is up to the game, which usually takes a few milliseconds.
+pub const START_MAP_COPY_TIMEOUT_SECS: i64 = 5;
+
+/// Dying in the endgame costs 10% of the experience the current level needs.
+pub const DEATH_EXPERIENCE_PENALTY: f32 = 0.1;
+
+/// How long after the whisper a trade can still be accepted.
+pub const TRADE_REQUEST_TIMEOUT_MINUTES: i64 = 30;
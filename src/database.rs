@@ -1,39 +1,1455 @@
 //! This is the module for the database actor. You can interact with it by
 //! sending it messages, usually a request to persist something, and looking for
 //! the return value.
+//!
+//! Map runs and their drops are kept in a SQLite file in the platform data
+//! directory, so the history screens have something to show across launches.
+use chrono::prelude::*;
+use dirs;
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, Result, Row, NO_PARAMS};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+
+use constants::{APP_DIR_NAME, DATABASE_FILE};
+use export::{export_events_to_file, export_path, export_to_file};
+use log_watcher::guess_event_path;
+use supervisor::Supervisor;
+use types::config::Config;
 use types::database::DatabaseMessage;
+use types::export::ExportTable;
+use types::history::{
+    BestDrop, ChaosPerHourPoint, DropSummary, FilterTierTotal, HistoryFilter, HistoryQuery,
+    HistoryResult, MapKindAverage, MapRunExport, MapRunSummary, ModDeaths, ProfitPoint,
+};
+use types::session::{Character, MapRun, SessionExport, SessionRecord};
+use types::trade::{Trade, TradeKind, TradeRequest};
+
+/// SQL for a random UUID, as SQLite doesn't have a function for them.
+macro_rules! new_uuid {
+    () => {
+        "lower(hex(randomblob(4))) || '-' || lower(hex(randomblob(2))) || '-4' ||
+         substr(lower(hex(randomblob(2))), 2) || '-' ||
+         substr('89ab', abs(random()) % 4 + 1, 1) || substr(lower(hex(randomblob(2))), 2) ||
+         '-' || lower(hex(randomblob(6)))"
+    };
+}
+
+/// Schema changes, in order. `PRAGMA user_version` records how many of these
+/// a database has seen, so only append to this list, never edit it.
+const MIGRATIONS: &[&str] = &[r"
+    CREATE TABLE map_runs (
+        id          INTEGER PRIMARY KEY,
+        league      TEXT    NOT NULL,
+        name        TEXT    NOT NULL,
+        tier        INTEGER,
+        mods        TEXT    NOT NULL DEFAULT '',
+        started_at  INTEGER NOT NULL,
+        ended_at    INTEGER NOT NULL,
+        deaths      INTEGER NOT NULL DEFAULT 0,
+        chaos_value REAL    NOT NULL
+    );
+    CREATE INDEX map_runs_started_at ON map_runs (started_at);
+    CREATE TABLE drops (
+        id          INTEGER PRIMARY KEY,
+        map_run_id  INTEGER NOT NULL REFERENCES map_runs (id) ON DELETE CASCADE,
+        name        TEXT    NOT NULL,
+        count       INTEGER NOT NULL,
+        chaos_value REAL,
+        dropped_at  INTEGER NOT NULL
+    );
+    CREATE INDEX drops_map_run_id ON drops (map_run_id);
+", r"
+    ALTER TABLE drops ADD COLUMN tagged INTEGER NOT NULL DEFAULT 0;
+", r"
+    ALTER TABLE map_runs ADD COLUMN item_quantity INTEGER;
+    ALTER TABLE map_runs ADD COLUMN item_rarity INTEGER;
+    ALTER TABLE map_runs ADD COLUMN pack_size INTEGER;
+", r"
+    ALTER TABLE map_runs ADD COLUMN character TEXT;
+    ALTER TABLE map_runs ADD COLUMN character_class TEXT;
+    ALTER TABLE map_runs ADD COLUMN character_level INTEGER;
+", r"
+    CREATE TABLE trades (
+        id           INTEGER PRIMARY KEY,
+        kind         TEXT    NOT NULL,
+        player       TEXT    NOT NULL,
+        item         TEXT    NOT NULL,
+        quantity     INTEGER NOT NULL,
+        price        REAL    NOT NULL,
+        currency     TEXT    NOT NULL,
+        league       TEXT    NOT NULL,
+        chaos_value  REAL,
+        requested_at INTEGER NOT NULL,
+        completed_at INTEGER
+    );
+    CREATE UNIQUE INDEX trades_request ON trades (player, item, requested_at);
+", r"
+    ALTER TABLE drops ADD COLUMN filter_tier TEXT;
+", r"
+    CREATE TABLE sessions (
+        id          INTEGER PRIMARY KEY,
+        league      TEXT    NOT NULL,
+        started_at  INTEGER NOT NULL,
+        ended_at    INTEGER NOT NULL,
+        maps_run    INTEGER NOT NULL,
+        chaos_value REAL    NOT NULL,
+        deaths      INTEGER NOT NULL
+    );
+    CREATE TABLE session_characters (
+        session_id INTEGER NOT NULL REFERENCES sessions (id) ON DELETE CASCADE,
+        name       TEXT    NOT NULL,
+        class      TEXT,
+        level      INTEGER
+    );
+    CREATE INDEX session_characters_session_id ON session_characters (session_id);
+", concat!(r"
+    ALTER TABLE map_runs ADD COLUMN uuid TEXT;
+    UPDATE map_runs SET uuid = ",
+        new_uuid!(),
+        r";
+    CREATE UNIQUE INDEX map_runs_uuid ON map_runs (uuid);
+"
+    ),
+];
+
+/// Map mods are stored in a single column, one per line.
+const MOD_SEPARATOR: &str = "\n";
 
 pub struct DatabaseBot {
     pub receiver: Receiver<DatabaseMessage>,
     pub sender: Sender<DatabaseMessage>,
-    // connections, other config things.
+    connection: Connection,
 }
 
 impl DatabaseBot {
-    pub fn spawn(
+    pub fn new(
         receiver: Receiver<DatabaseMessage>,
         sender: Sender<DatabaseMessage>,
-    ) -> JoinHandle<()> {
-        thread::spawn(move || {
-            let databaseBot = DatabaseBot { receiver, sender };
-            databaseBot.run()
-        })
+        connection: Connection,
+    ) -> DatabaseBot {
+        DatabaseBot {
+            receiver,
+            sender,
+            connection,
+        }
+    }
+
+    /// Run the bot under `supervisor`, which shuts it down through
+    /// `requests`. The database is opened in the bot's thread, and again on
+    /// every restart until that works.
+    pub fn supervise(
+        supervisor: &mut Supervisor,
+        receiver: Receiver<DatabaseMessage>,
+        sender: Sender<DatabaseMessage>,
+        requests: Sender<DatabaseMessage>,
+        path: PathBuf,
+    ) -> () {
+        let mut channels = Some((receiver, sender));
+        let mut database_bot = None;
+        supervisor.spawn_actor(
+            "DatabaseBot",
+            move || {
+                if let Some((receiver, sender)) = channels.take() {
+                    match open(&path) {
+                        Ok(connection) => {
+                            database_bot = Some(DatabaseBot::new(receiver, sender, connection))
+                        }
+                        Err(e) => {
+                            let reason = format!("Can't open {}: {}", path.display(), e);
+                            let _ = sender.send(DatabaseMessage::Panic {
+                                reason: reason.clone(),
+                            });
+                            channels = Some((receiver, sender));
+                            panic!("{}", reason);
+                        }
+                    }
+                }
+                if let Some(ref mut database_bot) = database_bot {
+                    database_bot.run()
+                }
+            },
+            move || {
+                let _ = requests.send(DatabaseMessage::ShutDown);
+            },
+        );
     }
 
     pub fn run(&mut self) -> () {
-        unimplemented!();
         loop {
             match self.receiver.recv() {
-                Ok(o) => match o {
-                    _ => unimplemented!()
+                Ok(DatabaseMessage::ShutDown) => {
+                    info!("Shutting down...");
+                    break;
+                }
+                Ok(message) => self.handle(message),
+                Err(_) => {
+                    // Nobody left to ask us for anything.
+                    info!("Input channel closed, exiting");
+                    break;
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, message: DatabaseMessage) -> () {
+        match message {
+            DatabaseMessage::SaveMapRun { league, run } => {
+                if let Err(e) = save_map_run(&mut self.connection, &league, &run) {
+                    self.report(format!("Can't save map run {}: {}", run.name, e));
+                }
+            }
+            DatabaseMessage::SaveSession { league, session } => {
+                if let Err(e) = save_session(&mut self.connection, &league, &session) {
+                    self.report(format!("Can't save session: {}", e));
+                }
+            }
+            DatabaseMessage::SaveTrade { trade } => {
+                if let Err(e) = save_trade(&self.connection, &trade) {
+                    self.report(format!("Can't save trade with {}: {}", trade.player, e));
+                }
+            }
+            DatabaseMessage::Query { query, filter } => {
+                match run_query(&self.connection, &query, &filter) {
+                    Ok(result) => self.respond(DatabaseMessage::History(result)),
+                    Err(e) => self.report(format!("Can't load history: {}", e)),
                 }
-                Err(e) => {
-                    println!("[DatabaseBot] Can't read from input channel, exiting");
-                    self.sender.send(DatabaseMessage::Panic{reason: String::from("[DatabaseBot] Can't read from input channel, exiting.")});
-                    painic!("Can't read from input channel")
+            }
+            DatabaseMessage::Export {
+                table: ExportTable::Events,
+                format,
+                filter,
+            } => {
+                // Client.txt can be huge, and the database has better things
+                // to do than wait for it.
+                let path = export_path(ExportTable::Events, format, Local::now());
+                let sender = self.sender.clone();
+                thread::spawn(move || {
+                    let log = guess_event_path().ok();
+                    let message =
+                        match export_events_to_file(format, &filter, log.as_deref(), &path) {
+                            Ok(records) => DatabaseMessage::Exported { path, records },
+                            Err(e) => {
+                                let reason = format!("Can't export events: {}", e);
+                                error!("{}", reason);
+                                DatabaseMessage::Error { reason }
+                            }
+                        };
+                    if let Err(e) = sender.send(message) {
+                        warn!("Nobody is listening for responses: {}", e);
+                    }
+                });
+            }
+            DatabaseMessage::Export {
+                table,
+                format,
+                filter,
+            } => {
+                let path = export_path(table, format, Local::now());
+                let log = guess_event_path().ok();
+                match export_to_file(
+                    &self.connection,
+                    table,
+                    format,
+                    &filter,
+                    log.as_deref(),
+                    &path,
+                ) {
+                    Ok(records) => self.respond(DatabaseMessage::Exported { path, records }),
+                    Err(e) => self.report(format!("Can't export {}: {}", table.name(), e)),
                 }
             }
+            other => warn!("Unexpected message: {:?}", other),
+        }
+    }
+
+    fn report(&self, reason: String) -> () {
+        error!("{}", reason);
+        self.respond(DatabaseMessage::Error { reason });
+    }
+
+    fn respond(&self, message: DatabaseMessage) -> () {
+        if let Err(e) = self.sender.send(message) {
+            warn!("Nobody is listening for responses: {}", e);
+        }
+    }
+}
+
+/// Where the history lives, according to `config`.
+pub fn database_path(config: &Config) -> PathBuf {
+    config
+        .database_file
+        .clone()
+        .unwrap_or_else(default_database_path)
+}
+
+/// Where the history lives unless told otherwise.
+pub fn default_database_path() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(APP_DIR_NAME)
+        .join(DATABASE_FILE)
+}
+
+/// Open (or create) the database at `path` and bring its schema up to date.
+pub fn open(path: &Path) -> Result<Connection> {
+    if let Some(parent) = path.parent() {
+        // If this fails, opening the file will too, with a better error.
+        let _ = fs::create_dir_all(parent);
+    }
+    let mut connection = Connection::open(path)?;
+    migrate(&mut connection)?;
+    Ok(connection)
+}
+
+/// Apply every migration the database hasn't seen yet.
+pub fn migrate(connection: &mut Connection) -> Result<()> {
+    connection.execute_batch("PRAGMA foreign_keys = ON;")?;
+    let version: i64 = connection.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+
+    let transaction = connection.transaction()?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        transaction.execute_batch(migration)?;
+        transaction.execute_batch(&format!("PRAGMA user_version = {}", index + 1))?;
+    }
+    transaction.commit()
+}
+
+/// Persist a finished run along with all of its drops. Returns the id of the
+/// new run.
+pub fn save_map_run(connection: &mut Connection, league: &str, run: &MapRun) -> Result<i64> {
+    let transaction = connection.transaction()?;
+    let map_run_id = insert_map_run(&transaction, league, run)?;
+    transaction.commit()?;
+    Ok(map_run_id)
+}
+
+/// `save_map_run` inside a transaction that's already going. A run without a
+/// UUID gets a new one.
+pub fn insert_map_run(transaction: &Connection, league: &str, run: &MapRun) -> Result<i64> {
+    let ended_at = run.ended_at.unwrap_or_else(Local::now);
+    let character = run.character.as_ref();
+    let uuid = match run.uuid {
+        Some(ref uuid) => uuid.clone(),
+        None => {
+            transaction.query_row(concat!("SELECT ", new_uuid!()), NO_PARAMS, |row| row.get(0))?
+        }
+    };
+    transaction.execute(
+        "INSERT INTO map_runs (league, name, tier, mods, item_quantity, item_rarity, pack_size,
+                               character, character_class, character_level,
+                               started_at, ended_at, deaths, chaos_value, uuid)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        &[
+            &league as &dyn ToSql,
+            &run.name,
+            &run.tier,
+            &run.mods.join(MOD_SEPARATOR),
+            &run.item_quantity,
+            &run.item_rarity,
+            &run.pack_size,
+            &character.map(|c| &c.name),
+            &character.and_then(|c| c.class.as_ref()),
+            &character.and_then(|c| c.level),
+            &run.started_at.timestamp(),
+            &ended_at.timestamp(),
+            &run.deaths,
+            &(run.chaos_value() as f64),
+            &uuid,
+        ],
+    )?;
+    let map_run_id = transaction.last_insert_rowid();
+
+    for drop in &run.drops {
+        transaction.execute(
+            "INSERT INTO drops (map_run_id, name, count, chaos_value, dropped_at, tagged,
+                                filter_tier)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            &[
+                &map_run_id as &dyn ToSql,
+                &drop.name,
+                &drop.count,
+                &drop.chaos_value.map(|v| v as f64),
+                &drop.dropped_at.timestamp(),
+                &drop.tagged,
+                &drop.filter_tier,
+            ],
+        )?;
+    }
+    Ok(map_run_id)
+}
+
+/// Whether the run was saved before, so replaying the same log twice, or
+/// importing the same history, doesn't count everything twice. That's a run
+/// with the same UUID, or failing that, of `name` starting at `started_at`.
+/// Only a run known to be by someone else is a different one then, the
+/// character isn't always in the log.
+pub fn has_map_run(connection: &Connection, league: &str, run: &MapRun) -> Result<bool> {
+    let character = run.character.as_ref().map(|c| &c.name);
+    let count: i64 = connection.query_row(
+        "SELECT COUNT(*) FROM map_runs
+         WHERE uuid = ?
+            OR league = ? AND name = ? AND started_at = ?
+               AND (character IS NULL OR ? IS NULL OR character = ?)",
+        &[
+            &run.uuid as &dyn ToSql,
+            &league,
+            &run.name,
+            &run.started_at.timestamp(),
+            &character,
+            &character,
+        ],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// Whether a trade for the same request was saved before.
+pub fn has_trade(connection: &Connection, trade: &Trade) -> Result<bool> {
+    let count: i64 = connection.query_row(
+        "SELECT COUNT(*) FROM trades WHERE player = ? AND item = ? AND requested_at = ?",
+        &[
+            &trade.player as &dyn ToSql,
+            &trade.request.item,
+            &trade.requested_at,
+        ],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// Persist a session that's over, along with everybody who played in it.
+/// Returns the id of the new session.
+pub fn save_session(
+    connection: &mut Connection,
+    league: &str,
+    session: &SessionRecord,
+) -> Result<i64> {
+    let transaction = connection.transaction()?;
+    let session_id = insert_session(&transaction, league, session)?;
+    transaction.commit()?;
+    Ok(session_id)
+}
+
+/// `save_session` inside a transaction that's already going.
+pub fn insert_session(
+    transaction: &Connection,
+    league: &str,
+    session: &SessionRecord,
+) -> Result<i64> {
+    transaction.execute(
+        "INSERT INTO sessions (league, started_at, ended_at, maps_run, chaos_value, deaths)
+         VALUES (?, ?, ?, ?, ?, ?)",
+        &[
+            &league as &dyn ToSql,
+            &session.started_at.timestamp(),
+            &session.ended_at.timestamp(),
+            &session.maps_run,
+            &(session.chaos_value as f64),
+            &session.deaths,
+        ],
+    )?;
+    let session_id = transaction.last_insert_rowid();
+    for character in &session.characters {
+        transaction.execute(
+            "INSERT INTO session_characters (session_id, name, class, level)
+             VALUES (?, ?, ?, ?)",
+            &[
+                &session_id as &dyn ToSql,
+                &character.name,
+                &character.class,
+                &character.level,
+            ],
+        )?;
+    }
+    Ok(session_id)
+}
+
+/// Whether a session of `league` that started at the same second was saved
+/// before. Only one can run at a time.
+pub fn has_session(connection: &Connection, league: &str, session: &SessionRecord) -> Result<bool> {
+    let count: i64 = connection.query_row(
+        "SELECT COUNT(*) FROM sessions WHERE league = ? AND started_at = ?",
+        &[&league as &dyn ToSql, &session.started_at.timestamp()],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// Trades are saved again whenever there's news about them, so the same
+/// request updates what was saved before. It keeps its id that way.
+pub fn save_trade(connection: &Connection, trade: &Trade) -> Result<()> {
+    connection.execute(
+        "INSERT INTO trades (kind, player, item, quantity, price, currency, league,
+                             chaos_value, requested_at, completed_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT (player, item, requested_at) DO UPDATE
+         SET kind = excluded.kind, quantity = excluded.quantity, price = excluded.price,
+             currency = excluded.currency, league = excluded.league,
+             chaos_value = excluded.chaos_value, completed_at = excluded.completed_at",
+        &[
+            &trade.kind.name() as &dyn ToSql,
+            &trade.player,
+            &trade.request.item,
+            &trade.request.quantity,
+            &(trade.request.price as f64),
+            &trade.request.currency,
+            &trade.request.league,
+            &trade.chaos_value.map(|v| v as f64),
+            &trade.requested_at,
+            &trade.completed_at,
+        ],
+    )?;
+    Ok(())
+}
+
+pub fn run_query(
+    connection: &Connection,
+    query: &HistoryQuery,
+    filter: &HistoryFilter,
+) -> Result<HistoryResult> {
+    Ok(match *query {
+        HistoryQuery::MapRuns => HistoryResult::MapRuns(map_runs(connection, filter)?),
+        HistoryQuery::MapKindAverages => {
+            HistoryResult::MapKindAverages(map_kind_averages(connection, filter)?)
+        }
+        HistoryQuery::BestDrops { limit } => {
+            HistoryResult::BestDrops(best_drops(connection, filter, limit)?)
+        }
+        HistoryQuery::ChaosPerHourOverTime => {
+            HistoryResult::ChaosPerHourOverTime(chaos_per_hour_over_time(connection, filter)?)
+        }
+        HistoryQuery::DeathsPerMod => {
+            HistoryResult::DeathsPerMod(deaths_per_mod(connection, filter)?)
+        }
+        HistoryQuery::Trades => HistoryResult::Trades(trades(connection, filter)?),
+        HistoryQuery::Profit => HistoryResult::Profit(profit(connection, filter)?),
+        HistoryQuery::FilterTiers => HistoryResult::FilterTiers(filter_tiers(connection, filter)?),
+    })
+}
+
+pub fn map_runs(connection: &Connection, filter: &HistoryFilter) -> Result<Vec<MapRunSummary>> {
+    let (conditions, params) = filter_conditions(filter);
+    let sql = format!(
+        "SELECT r.id, r.league, r.name, r.tier, r.mods, r.started_at,
+                r.ended_at - r.started_at, r.deaths, r.chaos_value,
+                r.item_quantity, r.item_rarity, r.pack_size,
+                r.character, r.character_class, r.character_level, r.uuid
+         FROM map_runs r {}
+         ORDER BY r.started_at DESC",
+        conditions
+    );
+    query_all(connection, &sql, &params, |row| {
+        let mods: String = row.get(4)?;
+        Ok(MapRunSummary {
+            id: row.get(0)?,
+            league: row.get(1)?,
+            name: row.get(2)?,
+            tier: row.get(3)?,
+            mods: split_mods(&mods),
+            item_quantity: row.get(9)?,
+            item_rarity: row.get(10)?,
+            pack_size: row.get(11)?,
+            character: row.get(12)?,
+            character_class: row.get(13)?,
+            character_level: row.get(14)?,
+            started_at: row.get(5)?,
+            duration_secs: row.get(6)?,
+            deaths: row.get(7)?,
+            chaos_value: row.get::<_, f64>(8)? as f32,
+            uuid: row.get(15)?,
+        })
+    })
+}
+
+pub fn map_kind_averages(
+    connection: &Connection,
+    filter: &HistoryFilter,
+) -> Result<Vec<MapKindAverage>> {
+    let (conditions, params) = filter_conditions(filter);
+    let sql = format!(
+        "SELECT r.name, COUNT(*), AVG(r.chaos_value), AVG(r.ended_at - r.started_at),
+                SUM(r.chaos_value), SUM(r.ended_at - r.started_at)
+         FROM map_runs r {}
+         GROUP BY r.name",
+        conditions
+    );
+    let mut averages = query_all(connection, &sql, &params, |row| {
+        Ok(MapKindAverage {
+            name: row.get(0)?,
+            runs: row.get(1)?,
+            average_chaos_value: row.get::<_, f64>(2)? as f32,
+            average_duration_secs: row.get::<_, f64>(3)? as f32,
+            chaos_per_hour: chaos_per_hour(row.get(4)?, row.get(5)?),
+        })
+    })?;
+    averages.sort_by(|a, b| {
+        b.chaos_per_hour
+            .partial_cmp(&a.chaos_per_hour)
+            .unwrap_or(::std::cmp::Ordering::Equal)
+    });
+    Ok(averages)
+}
+
+pub fn best_drops(
+    connection: &Connection,
+    filter: &HistoryFilter,
+    limit: u32,
+) -> Result<Vec<BestDrop>> {
+    let (conditions, mut params) = filter_conditions(filter);
+    let conditions = if conditions.is_empty() {
+        "WHERE d.chaos_value IS NOT NULL".to_string()
+    } else {
+        format!("{} AND d.chaos_value IS NOT NULL", conditions)
+    };
+    let sql = format!(
+        "SELECT d.name, d.count, d.chaos_value, r.name, d.dropped_at
+         FROM drops d JOIN map_runs r ON d.map_run_id = r.id {}
+         ORDER BY d.chaos_value DESC
+         LIMIT ?",
+        conditions
+    );
+    params.push(Box::new(limit));
+    query_all(connection, &sql, &params, |row| {
+        Ok(BestDrop {
+            name: row.get(0)?,
+            count: row.get(1)?,
+            chaos_value: row.get::<_, f64>(2)? as f32,
+            map_name: row.get(3)?,
+            dropped_at: row.get(4)?,
+        })
+    })
+}
+
+pub fn filter_tiers(
+    connection: &Connection,
+    filter: &HistoryFilter,
+) -> Result<Vec<FilterTierTotal>> {
+    let (conditions, params) = filter_conditions(filter);
+    let sql = format!(
+        "SELECT d.filter_tier, SUM(d.count), SUM(COALESCE(d.chaos_value, 0))
+         FROM drops d JOIN map_runs r ON d.map_run_id = r.id {}
+         GROUP BY d.filter_tier
+         ORDER BY SUM(COALESCE(d.chaos_value, 0)) DESC",
+        conditions
+    );
+    query_all(connection, &sql, &params, |row| {
+        Ok(FilterTierTotal {
+            tier: row.get(0)?,
+            drops: row.get(1)?,
+            chaos_value: row.get::<_, f64>(2)? as f32,
+        })
+    })
+}
+
+pub fn chaos_per_hour_over_time(
+    connection: &Connection,
+    filter: &HistoryFilter,
+) -> Result<Vec<ChaosPerHourPoint>> {
+    let (conditions, params) = filter_conditions(filter);
+    let sql = format!(
+        "SELECT date(r.started_at, 'unixepoch', 'localtime') AS day, COUNT(*),
+                SUM(r.chaos_value), SUM(r.ended_at - r.started_at)
+         FROM map_runs r {}
+         GROUP BY day
+         ORDER BY day",
+        conditions
+    );
+    query_all(connection, &sql, &params, |row| {
+        Ok(ChaosPerHourPoint {
+            day: row.get(0)?,
+            maps_run: row.get(1)?,
+            chaos_per_hour: chaos_per_hour(row.get(2)?, row.get(3)?),
+        })
+    })
+}
+
+/// Mods live in one column, so they're counted up here rather than in SQL.
+pub fn deaths_per_mod(connection: &Connection, filter: &HistoryFilter) -> Result<Vec<ModDeaths>> {
+    let mut per_mod: BTreeMap<String, (u32, u32)> = BTreeMap::new();
+    for run in map_runs(connection, filter)? {
+        for map_mod in run.mods {
+            let counts = per_mod.entry(map_mod).or_insert((0, 0));
+            counts.0 += 1;
+            counts.1 += run.deaths;
+        }
+    }
+    let mut deaths: Vec<ModDeaths> = per_mod
+        .into_iter()
+        .map(|(map_mod, (runs, deaths))| ModDeaths {
+            map_mod,
+            runs,
+            deaths,
+            deaths_per_run: deaths as f32 / runs as f32,
+        })
+        .collect();
+    deaths.sort_by(|a, b| {
+        b.deaths_per_run
+            .partial_cmp(&a.deaths_per_run)
+            .unwrap_or(::std::cmp::Ordering::Equal)
+    });
+    Ok(deaths)
+}
+
+pub fn trades(connection: &Connection, filter: &HistoryFilter) -> Result<Vec<Trade>> {
+    let (conditions, params) = trade_conditions(filter);
+    let sql = format!(
+        "SELECT t.id, t.kind, t.player, t.item, t.quantity, t.price, t.currency, t.league,
+                t.chaos_value, t.requested_at, t.completed_at
+         FROM trades t {}
+         ORDER BY t.completed_at DESC, t.id DESC",
+        conditions
+    );
+    query_all(connection, &sql, &params, |row| {
+        let kind: String = row.get(1)?;
+        Ok(Trade {
+            id: row.get::<_, i64>(0)? as u64,
+            kind: TradeKind::from_name(&kind).unwrap_or(TradeKind::Sale),
+            player: row.get(2)?,
+            request: TradeRequest {
+                item: row.get(3)?,
+                quantity: row.get(4)?,
+                price: row.get::<_, f64>(5)? as f32,
+                currency: row.get(6)?,
+                league: row.get(7)?,
+            },
+            chaos_value: row.get::<_, Option<f64>>(8)?.map(|v| v as f32),
+            requested_at: row.get(9)?,
+            completed_at: row.get(10)?,
+        })
+    })
+}
+
+/// Loot goes by the day maps were started, trades by the day they went
+/// through. Days with neither are left out.
+pub fn profit(connection: &Connection, filter: &HistoryFilter) -> Result<Vec<ProfitPoint>> {
+    let (conditions, params) = filter_conditions(filter);
+    let sql = format!(
+        "SELECT date(r.started_at, 'unixepoch', 'localtime') AS day, SUM(r.chaos_value)
+         FROM map_runs r {}
+         GROUP BY day",
+        conditions
+    );
+    let loot = query_all(connection, &sql, &params, |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, f64>(1)?))
+    })?;
+    let (conditions, params) = trade_conditions(filter);
+    let sql = format!(
+        "SELECT date(t.completed_at, 'unixepoch', 'localtime') AS day, t.kind,
+                TOTAL(t.chaos_value)
+         FROM trades t {}
+         GROUP BY day, t.kind",
+        conditions
+    );
+    let trades = query_all(connection, &sql, &params, |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, f64>(2)?,
+        ))
+    })?;
+
+    let mut days: BTreeMap<String, ProfitPoint> = BTreeMap::new();
+    fn point(days: &mut BTreeMap<String, ProfitPoint>, day: String) -> &mut ProfitPoint {
+        days.entry(day.clone()).or_insert_with(|| ProfitPoint {
+            day,
+            loot: 0.0,
+            sales: 0.0,
+            purchases: 0.0,
+        })
+    }
+    for (day, chaos_value) in loot {
+        point(&mut days, day).loot = chaos_value as f32;
+    }
+    for (day, kind, chaos_value) in trades {
+        let point = point(&mut days, day);
+        match TradeKind::from_name(&kind) {
+            Some(TradeKind::Sale) => point.sales = chaos_value as f32,
+            Some(TradeKind::Purchase) => point.purchases = chaos_value as f32,
+            None => warn!("Trade of unknown kind {}", kind),
         }
     }
+    Ok(days.into_values().collect())
+}
+
+/// Every run matching `filter`, newest first, with its drops.
+pub fn export_map_runs(
+    connection: &Connection,
+    filter: &HistoryFilter,
+) -> Result<Vec<MapRunExport>> {
+    let mut statement = connection.prepare(
+        "SELECT name, count, chaos_value, dropped_at, tagged, filter_tier FROM drops
+         WHERE map_run_id = ?
+         ORDER BY dropped_at, id",
+    )?;
+    let mut exports = Vec::new();
+    for run in map_runs(connection, filter)? {
+        let drops = statement
+            .query_map(&[run.id], |row| {
+                Ok(DropSummary {
+                    name: row.get(0)?,
+                    count: row.get(1)?,
+                    chaos_value: row.get::<_, Option<f64>>(2)?.map(|v| v as f32),
+                    dropped_at: row.get(3)?,
+                    tagged: row.get(4)?,
+                    filter_tier: row.get(5)?,
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        exports.push(MapRunExport { run, drops });
+    }
+    Ok(exports)
+}
+
+/// Every session matching `filter`, newest first, with everybody who played in
+/// it.
+pub fn sessions(connection: &Connection, filter: &HistoryFilter) -> Result<Vec<SessionExport>> {
+    let (conditions, params) = session_conditions(filter);
+    let sql = format!(
+        "SELECT s.id, s.league, s.started_at, s.ended_at, s.maps_run, s.chaos_value, s.deaths
+         FROM sessions s {}
+         ORDER BY s.started_at DESC, s.id DESC",
+        conditions
+    );
+    let mut sessions = query_all(connection, &sql, &params, |row| {
+        Ok(SessionExport {
+            id: row.get(0)?,
+            league: row.get(1)?,
+            session: SessionRecord {
+                started_at: Local.timestamp(row.get(2)?, 0),
+                ended_at: Local.timestamp(row.get(3)?, 0),
+                maps_run: row.get(4)?,
+                chaos_value: row.get::<_, f64>(5)? as f32,
+                deaths: row.get(6)?,
+                characters: Vec::new(),
+            },
+        })
+    })?;
+    let mut statement = connection.prepare(
+        "SELECT name, class, level FROM session_characters
+         WHERE session_id = ?
+         ORDER BY rowid",
+    )?;
+    for export in &mut sessions {
+        export.session.characters = statement
+            .query_map(&[export.id], |row| {
+                Ok(Character {
+                    name: row.get(0)?,
+                    class: row.get(1)?,
+                    level: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+    }
+    Ok(sessions)
+}
+
+/// Turn a filter into a `WHERE` clause on `map_runs r`, and the parameters to
+/// go with it. The clause is empty if the filter doesn't filter anything.
+fn filter_conditions(filter: &HistoryFilter) -> (String, Vec<Box<dyn ToSql>>) {
+    let mut conditions: Vec<&str> = Vec::new();
+    let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+
+    if let Some(ref league) = filter.league {
+        conditions.push("r.league = ?");
+        params.push(Box::new(league.clone()));
+    }
+    if let Some(from) = filter.from {
+        conditions.push("r.started_at >= ?");
+        params.push(Box::new(from));
+    }
+    if let Some(to) = filter.to {
+        conditions.push("r.started_at < ?");
+        params.push(Box::new(to));
+    }
+    if let Some(min_tier) = filter.min_tier {
+        conditions.push("r.tier >= ?");
+        params.push(Box::new(min_tier));
+    }
+    if let Some(max_tier) = filter.max_tier {
+        conditions.push("r.tier <= ?");
+        params.push(Box::new(max_tier));
+    }
+    if let Some(ref character) = filter.character {
+        conditions.push("r.character = ?");
+        params.push(Box::new(character.clone()));
+    }
+    for map_mod in &filter.mods {
+        // Mods like "+20% Monster Damage" are looked for as they are.
+        conditions.push("r.mods LIKE '%' || ? || '%' ESCAPE '\\'");
+        let escaped = map_mod
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        params.push(Box::new(escaped));
+    }
+
+    if conditions.is_empty() {
+        (String::new(), params)
+    } else {
+        (format!("WHERE {}", conditions.join(" AND ")), params)
+    }
+}
+
+/// Like `filter_conditions`, for `trades t`. Trades are saved when they're
+/// whispered about, but only count once they've gone through.
+fn trade_conditions(filter: &HistoryFilter) -> (String, Vec<Box<dyn ToSql>>) {
+    let mut conditions = vec!["t.completed_at IS NOT NULL"];
+    let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+
+    if let Some(ref league) = filter.league {
+        conditions.push("t.league = ?");
+        params.push(Box::new(league.clone()));
+    }
+    if let Some(from) = filter.from {
+        conditions.push("t.completed_at >= ?");
+        params.push(Box::new(from));
+    }
+    if let Some(to) = filter.to {
+        conditions.push("t.completed_at < ?");
+        params.push(Box::new(to));
+    }
+    (format!("WHERE {}", conditions.join(" AND ")), params)
+}
+
+/// Like `filter_conditions`, for `sessions s`. A character filter finds the
+/// sessions they played in, with or without others.
+fn session_conditions(filter: &HistoryFilter) -> (String, Vec<Box<dyn ToSql>>) {
+    let mut conditions: Vec<&str> = Vec::new();
+    let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+
+    if let Some(ref league) = filter.league {
+        conditions.push("s.league = ?");
+        params.push(Box::new(league.clone()));
+    }
+    if let Some(from) = filter.from {
+        conditions.push("s.started_at >= ?");
+        params.push(Box::new(from));
+    }
+    if let Some(to) = filter.to {
+        conditions.push("s.started_at < ?");
+        params.push(Box::new(to));
+    }
+    if let Some(ref character) = filter.character {
+        conditions.push(
+            "EXISTS (SELECT 1 FROM session_characters c WHERE c.session_id = s.id AND c.name = ?)",
+        );
+        params.push(Box::new(character.clone()));
+    }
+
+    if conditions.is_empty() {
+        (String::new(), params)
+    } else {
+        (format!("WHERE {}", conditions.join(" AND ")), params)
+    }
+}
+
+fn query_all<T, F>(
+    connection: &Connection,
+    sql: &str,
+    params: &[Box<dyn ToSql>],
+    f: F,
+) -> Result<Vec<T>>
+where
+    F: FnMut(&Row) -> Result<T>,
+{
+    let mut statement = connection.prepare(sql)?;
+    let params: Vec<&dyn ToSql> = params.iter().map(|p| p.as_ref()).collect();
+    let rows = statement.query_map(&params, f)?;
+    rows.collect()
+}
+
+fn split_mods(mods: &str) -> Vec<String> {
+    mods.split(MOD_SEPARATOR)
+        .filter(|m| !m.is_empty())
+        .map(|m| m.to_string())
+        .collect()
+}
+
+fn chaos_per_hour(chaos_value: f64, seconds: i64) -> f32 {
+    if seconds <= 0 {
+        return 0.0;
+    }
+    (chaos_value * 3600.0 / seconds as f64) as f32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use types::session::{Character, Drop};
+
+    fn database() -> Connection {
+        let mut connection = Connection::open_in_memory().unwrap();
+        migrate(&mut connection).unwrap();
+        connection
+    }
+
+    fn run(name: &str, start: DateTime<Local>, minutes: i64, drops: &[(&str, f32)]) -> MapRun {
+        let mut run = MapRun::new(name.to_string(), start);
+        run.ended_at = Some(start + ::chrono::Duration::minutes(minutes));
+        run.drops = drops
+            .iter()
+            .enumerate()
+            .map(|(i, &(name, value))| Drop {
+                id: i as u64,
+                name: name.to_string(),
+                count: 1,
+                chaos_value: Some(value),
+                dropped_at: start,
+                tagged: false,
+                filter_tier: None,
+            })
+            .collect();
+        run
+    }
+
+    fn noon(day: u32) -> DateTime<Local> {
+        Local.ymd(2018, 11, day).and_hms(12, 0, 0)
+    }
+
+    #[test]
+    fn should_migrate_twice_without_complaint() {
+        let mut connection = database();
+        migrate(&mut connection).unwrap();
+        let version: i64 = connection
+            .query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len() as i64);
+    }
+
+    #[test]
+    fn should_save_sessions_with_their_characters() {
+        let mut connection = database();
+        let session = SessionRecord {
+            started_at: noon(22),
+            ended_at: noon(22) + ::chrono::Duration::hours(2),
+            maps_run: 9,
+            chaos_value: 250.0,
+            deaths: 1,
+            characters: vec![
+                Character {
+                    name: "Nightblade".to_string(),
+                    class: Some("Assassin".to_string()),
+                    level: Some(91),
+                },
+                Character {
+                    name: "Daybreak".to_string(),
+                    class: None,
+                    level: None,
+                },
+            ],
+        };
+        let id = save_session(&mut connection, "Betrayal", &session).unwrap();
+
+        let names: Vec<(String, Option<u32>)> = query_all(
+            &connection,
+            "SELECT name, level FROM session_characters WHERE session_id = ? ORDER BY rowid",
+            &[Box::new(id) as Box<dyn ToSql>],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+        assert_eq!(
+            names,
+            vec![
+                ("Nightblade".to_string(), Some(91)),
+                ("Daybreak".to_string(), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn should_list_saved_runs_newest_first() {
+        let mut connection = database();
+        let mut cage = run("Shaped Cage", noon(22), 10, &[("Exalted Orb", 100.0)]);
+        cage.tier = Some(11);
+        cage.mods = vec!["Area is inhabited by Goatmen".to_string()];
+        cage.item_quantity = Some(64);
+        cage.deaths = 2;
+        save_map_run(&mut connection, "Betrayal", &cage).unwrap();
+        save_map_run(&mut connection, "Betrayal", &run("Dunes", noon(23), 5, &[])).unwrap();
+
+        let runs = map_runs(&connection, &HistoryFilter::default()).unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].name, "Dunes".to_string());
+        assert_eq!(runs[1].name, "Shaped Cage".to_string());
+        assert_eq!(runs[1].tier, Some(11));
+        assert_eq!(runs[1].mods, cage.mods);
+        assert_eq!(runs[1].item_quantity, Some(64));
+        assert_eq!(runs[0].item_quantity, None);
+        assert_eq!(runs[1].deaths, 2);
+        assert_eq!(runs[1].duration_secs, 600);
+        assert_eq!(runs[1].chaos_value, 100.0);
+    }
+
+    #[test]
+    fn should_filter_runs() {
+        let mut connection = database();
+        let mut cage = run("Shaped Cage", noon(22), 10, &[]);
+        cage.tier = Some(11);
+        cage.mods = vec!["Players are Cursed with Elemental Weakness".to_string()];
+        let mut dunes = run("Dunes", noon(23), 10, &[]);
+        dunes.tier = Some(1);
+        dunes.mods = vec!["+20% Monster Damage".to_string()];
+        dunes.character = Some(Character {
+            name: "Nightblade".to_string(),
+            class: Some("Assassin".to_string()),
+            level: Some(91),
+        });
+        save_map_run(&mut connection, "Betrayal", &cage).unwrap();
+        save_map_run(&mut connection, "Standard", &dunes).unwrap();
+
+        let names = |filter: HistoryFilter| -> Vec<String> {
+            map_runs(&connection, &filter)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.name)
+                .collect()
+        };
+        assert_eq!(
+            names(HistoryFilter {
+                league: Some("Standard".to_string()),
+                ..HistoryFilter::default()
+            }),
+            vec!["Dunes".to_string()]
+        );
+        assert_eq!(
+            names(HistoryFilter {
+                from: Some(noon(23).timestamp()),
+                ..HistoryFilter::default()
+            }),
+            vec!["Dunes".to_string()]
+        );
+        assert_eq!(
+            names(HistoryFilter {
+                min_tier: Some(6),
+                max_tier: Some(16),
+                ..HistoryFilter::default()
+            }),
+            vec!["Shaped Cage".to_string()]
+        );
+        assert_eq!(
+            names(HistoryFilter {
+                mods: vec!["elemental weakness".to_string()],
+                ..HistoryFilter::default()
+            }),
+            vec!["Shaped Cage".to_string()]
+        );
+        assert_eq!(
+            names(HistoryFilter {
+                mods: vec!["%".to_string()],
+                ..HistoryFilter::default()
+            }),
+            vec!["Dunes".to_string()]
+        );
+        assert!(names(HistoryFilter {
+            mods: vec!["elemental_weakness".to_string()],
+            ..HistoryFilter::default()
+        })
+        .is_empty());
+        assert_eq!(
+            names(HistoryFilter {
+                character: Some("Nightblade".to_string()),
+                ..HistoryFilter::default()
+            }),
+            vec!["Dunes".to_string()]
+        );
+    }
+
+    #[test]
+    fn should_average_per_map_kind() {
+        let mut connection = database();
+        save_map_run(
+            &mut connection,
+            "Standard",
+            &run("Dunes", noon(22), 10, &[("Chaos Orb", 10.0)]),
+        )
+        .unwrap();
+        save_map_run(
+            &mut connection,
+            "Standard",
+            &run("Dunes", noon(23), 20, &[("Chaos Orb", 20.0)]),
+        )
+        .unwrap();
+        save_map_run(
+            &mut connection,
+            "Standard",
+            &run("Shaped Cage", noon(23), 6, &[("Chaos Orb", 50.0)]),
+        )
+        .unwrap();
+
+        let averages = map_kind_averages(&connection, &HistoryFilter::default()).unwrap();
+        assert_eq!(
+            averages,
+            vec![
+                MapKindAverage {
+                    name: "Shaped Cage".to_string(),
+                    runs: 1,
+                    average_chaos_value: 50.0,
+                    average_duration_secs: 360.0,
+                    chaos_per_hour: 500.0,
+                },
+                MapKindAverage {
+                    name: "Dunes".to_string(),
+                    runs: 2,
+                    average_chaos_value: 15.0,
+                    average_duration_secs: 900.0,
+                    chaos_per_hour: 60.0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_find_best_drops() {
+        let mut connection = database();
+        let mut dunes = run(
+            "Dunes",
+            noon(22),
+            10,
+            &[("Chaos Orb", 1.0), ("Exalted Orb", 100.0)],
+        );
+        dunes.drops.push(Drop {
+            id: 2,
+            name: "Unpriced Thing".to_string(),
+            count: 1,
+            chaos_value: None,
+            dropped_at: noon(22),
+            tagged: false,
+            filter_tier: None,
+        });
+        save_map_run(&mut connection, "Standard", &dunes).unwrap();
+
+        let drops = best_drops(&connection, &HistoryFilter::default(), 5).unwrap();
+        assert_eq!(drops.len(), 2);
+        assert_eq!(drops[0].name, "Exalted Orb".to_string());
+        assert_eq!(drops[0].map_name, "Dunes".to_string());
+
+        let drops = best_drops(&connection, &HistoryFilter::default(), 1).unwrap();
+        assert_eq!(drops.len(), 1);
+    }
+
+    #[test]
+    fn should_add_up_drops_per_filter_tier() {
+        let mut connection = database();
+        let mut dunes = run(
+            "Dunes",
+            noon(22),
+            10,
+            &[("Chaos Orb", 1.0), ("Exalted Orb", 100.0), ("Divine Orb", 15.0)],
+        );
+        dunes.drops[1].filter_tier = Some("currency t1".to_string());
+        dunes.drops[2].filter_tier = Some("currency t1".to_string());
+        save_map_run(&mut connection, "Standard", &dunes).unwrap();
+
+        let tiers = filter_tiers(&connection, &HistoryFilter::default()).unwrap();
+        assert_eq!(
+            tiers,
+            vec![
+                FilterTierTotal {
+                    tier: Some("currency t1".to_string()),
+                    drops: 2,
+                    chaos_value: 115.0,
+                },
+                FilterTierTotal {
+                    tier: None,
+                    drops: 1,
+                    chaos_value: 1.0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_count_deaths_per_mod() {
+        let mut connection = database();
+        let goatmen = "Area is inhabited by Goatmen".to_string();
+        let weakness = "Players are Cursed with Elemental Weakness".to_string();
+        let mut cage = run("Shaped Cage", noon(22), 10, &[]);
+        cage.mods = vec![goatmen.clone(), weakness.clone()];
+        cage.deaths = 3;
+        save_map_run(&mut connection, "Standard", &cage).unwrap();
+        let mut dunes = run("Dunes", noon(23), 10, &[]);
+        dunes.mods = vec![goatmen.clone()];
+        save_map_run(&mut connection, "Standard", &dunes).unwrap();
+
+        let deaths = deaths_per_mod(&connection, &HistoryFilter::default()).unwrap();
+        assert_eq!(
+            deaths,
+            vec![
+                ModDeaths {
+                    map_mod: weakness,
+                    runs: 1,
+                    deaths: 3,
+                    deaths_per_run: 3.0,
+                },
+                ModDeaths {
+                    map_mod: goatmen,
+                    runs: 2,
+                    deaths: 3,
+                    deaths_per_run: 1.5,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_track_chaos_per_hour_by_day() {
+        let mut connection = database();
+        save_map_run(
+            &mut connection,
+            "Standard",
+            &run("Dunes", noon(22), 30, &[("Chaos Orb", 10.0)]),
+        )
+        .unwrap();
+        save_map_run(
+            &mut connection,
+            "Standard",
+            &run(
+                "Dunes",
+                noon(22) + ::chrono::Duration::hours(1),
+                30,
+                &[("Chaos Orb", 20.0)],
+            ),
+        )
+        .unwrap();
+        save_map_run(
+            &mut connection,
+            "Standard",
+            &run("Dunes", noon(23), 60, &[("Chaos Orb", 5.0)]),
+        )
+        .unwrap();
+
+        let points = chaos_per_hour_over_time(&connection, &HistoryFilter::default()).unwrap();
+        assert_eq!(
+            points,
+            vec![
+                ChaosPerHourPoint {
+                    day: "2018-11-22".to_string(),
+                    maps_run: 2,
+                    chaos_per_hour: 30.0,
+                },
+                ChaosPerHourPoint {
+                    day: "2018-11-23".to_string(),
+                    maps_run: 1,
+                    chaos_per_hour: 5.0,
+                },
+            ]
+        );
+    }
+
+    fn trade(kind: TradeKind, item: &str, at: DateTime<Local>, chaos_value: f32) -> Trade {
+        Trade {
+            id: 0,
+            kind,
+            player: "Trader".to_string(),
+            request: TradeRequest {
+                item: item.to_string(),
+                quantity: 1,
+                price: chaos_value,
+                currency: "chaos".to_string(),
+                league: "Standard".to_string(),
+            },
+            requested_at: at.timestamp(),
+            completed_at: Some(at.timestamp()),
+            chaos_value: None,
+        }
+    }
+
+    #[test]
+    fn should_add_up_trades_and_loot_per_day() {
+        let mut connection = database();
+        save_map_run(
+            &mut connection,
+            "Standard",
+            &run("Dunes", noon(22), 10, &[("Chaos Orb", 10.0)]),
+        )
+        .unwrap();
+        let mut sale = trade(TradeKind::Sale, "Tabula Rasa", noon(22), 8.0);
+        save_trade(&connection, &sale).unwrap();
+        let sale_id = trades(&connection, &HistoryFilter::default()).unwrap()[0].id;
+        // The price came in, which updates what was saved.
+        sale.chaos_value = Some(8.0);
+        save_trade(&connection, &sale).unwrap();
+        let mut purchase = trade(TradeKind::Purchase, "Kaom's Heart", noon(23), 100.0);
+        purchase.chaos_value = Some(100.0);
+        save_trade(&connection, &purchase).unwrap();
+
+        let saved = trades(&connection, &HistoryFilter::default()).unwrap();
+        assert_eq!(saved.len(), 2);
+        assert_eq!(saved[0].request.item, "Kaom's Heart");
+        assert_eq!(saved[1].chaos_value, Some(8.0));
+        assert_eq!(saved[1].id, sale_id);
+
+        let days = profit(&connection, &HistoryFilter::default()).unwrap();
+        assert_eq!(
+            days,
+            vec![
+                ProfitPoint {
+                    day: noon(22).format("%Y-%m-%d").to_string(),
+                    loot: 10.0,
+                    sales: 8.0,
+                    purchases: 0.0,
+                },
+                ProfitPoint {
+                    day: noon(23).format("%Y-%m-%d").to_string(),
+                    loot: 0.0,
+                    sales: 0.0,
+                    purchases: 100.0,
+                },
+            ]
+        );
+        let elsewhere = HistoryFilter {
+            league: Some("Betrayal".to_string()),
+            ..HistoryFilter::default()
+        };
+        assert!(profit(&connection, &elsewhere).unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_recognise_saved_runs() {
+        let mut connection = database();
+        let dunes = run("Dunes", noon(22), 10, &[]);
+        assert!(!has_map_run(&connection, "Standard", &dunes).unwrap());
+        save_map_run(&mut connection, "Standard", &dunes).unwrap();
+        assert!(has_map_run(&connection, "Standard", &dunes).unwrap());
+        assert!(!has_map_run(&connection, "Betrayal", &dunes).unwrap());
+
+        let by = |name: &str| {
+            let mut run = run("Strand", noon(23), 10, &[]);
+            run.character = Some(Character {
+                name: name.to_string(),
+                class: None,
+                level: None,
+            });
+            run
+        };
+        save_map_run(&mut connection, "Standard", &by("Nightblade")).unwrap();
+        assert!(has_map_run(&connection, "Standard", &by("Nightblade")).unwrap());
+        // Replayed from before the character was known.
+        let mut replayed = by("Nightblade");
+        replayed.character = None;
+        assert!(has_map_run(&connection, "Standard", &replayed).unwrap());
+        // Somebody else in the same map at the same time, on another machine.
+        assert!(!has_map_run(&connection, "Standard", &by("Mirelle")).unwrap());
+    }
+
+    #[test]
+    fn should_export_runs_with_drops() {
+        let mut connection = database();
+        let mut dunes = run("Dunes", noon(22), 10, &[("Chaos Orb", 1.0), ("Exalted Orb", 100.0)]);
+        dunes.drops[1].tagged = true;
+        save_map_run(&mut connection, "Standard", &dunes).unwrap();
+        save_map_run(&mut connection, "Standard", &run("Strand", noon(23), 10, &[])).unwrap();
+
+        let exports = export_map_runs(&connection, &HistoryFilter::default()).unwrap();
+        assert_eq!(exports.len(), 2);
+        assert_eq!(exports[0].run.name, "Strand".to_string());
+        assert!(exports[0].drops.is_empty());
+        let names: Vec<_> = exports[1].drops.iter().map(|d| d.name.clone()).collect();
+        assert_eq!(names, vec!["Chaos Orb".to_string(), "Exalted Orb".to_string()]);
+        assert!(exports[1].drops[1].tagged);
+    }
 }
@@ -0,0 +1,148 @@
+//! Copying an item isn't the same as it dropping. Sorting loot means copying
+//! the same items over and over, and whatever's copied out of the stash
+//! dropped a long time ago. The mastermind runs every parsed item past a
+//! `DropFilter` before counting it.
+use chrono::prelude::*;
+use chrono::Duration;
+use std::collections::HashMap;
+
+use types::item::Fingerprint;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    /// Not seen lately, so it's a drop.
+    New,
+    /// Copied again within the window.
+    Duplicate,
+    /// Seen within the window, and first before the current map started, so
+    /// it didn't drop there.
+    SeenBeforeMap,
+}
+
+/// When each item was first and last seen, for as long as the window says
+/// copying it again isn't a new drop.
+pub struct DropFilter {
+    seen: HashMap<Fingerprint, (DateTime<Local>, DateTime<Local>)>,
+    window: Duration,
+}
+
+impl DropFilter {
+    /// Copies within `window` of each other are the same drop.
+    pub fn new(window: Duration) -> DropFilter {
+        DropFilter {
+            seen: HashMap::new(),
+            window,
+        }
+    }
+
+    pub fn set_window(&mut self, window: Duration) -> () {
+        self.window = window;
+    }
+
+    /// Whether an item copied `now` is a drop. `map_started_at` is when the
+    /// current map started, if there is one. Either way it's been seen now.
+    /// Items not seen for longer than the window are forgotten, so another one
+    /// of them is a drop again.
+    pub fn check(
+        &mut self,
+        fingerprint: Fingerprint,
+        map_started_at: Option<DateTime<Local>>,
+        now: DateTime<Local>,
+    ) -> Verdict {
+        let window = self.window;
+        self.seen
+            .retain(|_, &mut (_, last_seen)| now - last_seen <= window);
+        let verdict = match self.seen.get(&fingerprint) {
+            None => Verdict::New,
+            // `None` sorts first, so without a map nothing was before it.
+            Some(&(first_seen, _)) if Some(first_seen) < map_started_at => Verdict::SeenBeforeMap,
+            Some(_) => Verdict::Duplicate,
+        };
+        let first_seen = self.seen.get(&fingerprint).map_or(now, |&(first, _)| first);
+        self.seen.insert(fingerprint, (first_seen, now));
+        verdict
+    }
+
+    /// Act like the item was never seen, so copying it again is a drop.
+    pub fn forget(&mut self, fingerprint: &Fingerprint) -> () {
+        self.seen.remove(fingerprint);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn fingerprint(name: &str, stack_size: u32) -> Fingerprint {
+        Fingerprint {
+            name: name.to_string(),
+            mods: Vec::new(),
+            stack_size,
+            item_level: None,
+        }
+    }
+
+    #[test]
+    fn should_catch_copies_in_between_others() {
+        let mut filter = DropFilter::new(Duration::minutes(10));
+        let at = |minute| Local.ymd(2018, 11, 22).and_hms(5, minute, 0);
+        let map = Some(at(0));
+
+        let verdicts: Vec<_> = vec![
+            (fingerprint("Chaos Orb", 3), at(1)),
+            (fingerprint("Exalted Orb", 1), at(2)),
+            (fingerprint("Chaos Orb", 3), at(3)),
+            // A bigger stack is a different item.
+            (fingerprint("Chaos Orb", 4), at(4)),
+            // And the same one is new again once the window's over.
+            (fingerprint("Exalted Orb", 1), at(20)),
+        ]
+        .into_iter()
+        .map(|(item, now)| filter.check(item, map, now))
+        .collect();
+
+        assert_eq!(
+            verdicts,
+            vec![
+                Verdict::New,
+                Verdict::New,
+                Verdict::Duplicate,
+                Verdict::New,
+                Verdict::New
+            ]
+        );
+    }
+
+    #[test]
+    fn should_ignore_items_seen_before_the_map() {
+        let mut filter = DropFilter::new(Duration::minutes(10));
+        let at = |minute| Local.ymd(2018, 11, 22).and_hms(5, minute, 0);
+
+        assert_eq!(
+            filter.check(fingerprint("The Doctor", 1), None, at(0)),
+            Verdict::New
+        );
+        assert_eq!(
+            filter.check(fingerprint("The Doctor", 1), Some(at(5)), at(6)),
+            Verdict::SeenBeforeMap
+        );
+        // Long enough after the last copy, another one is a drop.
+        assert_eq!(
+            filter.check(fingerprint("The Doctor", 1), Some(at(30)), at(31)),
+            Verdict::New
+        );
+    }
+
+    #[test]
+    fn should_count_forgotten_items_again() {
+        let mut filter = DropFilter::new(Duration::minutes(10));
+        let at = |minute| Local.ymd(2018, 11, 22).and_hms(5, minute, 0);
+
+        filter.check(fingerprint("Chaos Orb", 3), Some(at(0)), at(1));
+        filter.forget(&fingerprint("Chaos Orb", 3));
+        assert_eq!(
+            filter.check(fingerprint("Chaos Orb", 3), Some(at(0)), at(2)),
+            Verdict::New
+        );
+    }
+}
@@ -0,0 +1,635 @@
+//! Getting the history out, for spreadsheets and notebooks. Every table has a
+//! fixed list of columns that scripts can go by: new columns only ever get
+//! added at the end, and none are renamed or dropped. Times are RFC 3339 in
+//! local time, chaos values are rounded to hundredths.
+use chrono::prelude::*;
+use dirs;
+use rusqlite::{self, Connection};
+use serde_json::{self, Map, Value};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use constants::{APP_DIR_NAME, EXPORT_DIR};
+use database;
+use log_watcher::parse_log;
+use types::export::{ExportFormat, ExportTable};
+use types::history::HistoryFilter;
+use types::trade::Direction;
+use types::zone_event::ZoneEvent;
+
+pub struct Column {
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+const fn column(name: &'static str, description: &'static str) -> Column {
+    Column { name, description }
+}
+
+const RUN_COLUMNS: &[Column] = &[
+    column("run_id", "The same for the same run in every export"),
+    column("league", "What the run was recorded under"),
+    column("map", "The area, like Shaped Cage"),
+    column("tier", "Empty unless the map item was copied"),
+    column("mods", "The map's mods, separated by \"; \" in CSV"),
+    column("item_quantity", "In percent"),
+    column("item_rarity", "In percent"),
+    column("pack_size", "In percent"),
+    column("character", "Who ran it, empty if nobody was known"),
+    column(
+        "character_class",
+        "Like Assassin, empty until they levelled",
+    ),
+    column("character_level", "When the run started"),
+    column("started_at", "When the map was entered"),
+    column("duration_secs", "From entering to leaving for good"),
+    column("deaths", "Of the character, in this run"),
+    column("chaos_value", "Of all drops"),
+    column("run_uuid", "The same for the same run on every machine"),
+];
+
+const DROP_COLUMNS: &[Column] = &[
+    column("run_id", "The run it dropped in"),
+    column("league", "Of the run"),
+    column("map", "Of the run"),
+    column("character", "Of the run"),
+    column("item", "As copied, with the stack size left out"),
+    column("count", "The stack size, 1 for anything that doesn't stack"),
+    column(
+        "chaos_value",
+        "Of the whole stack, empty if it never got a price",
+    ),
+    column("chaos_per_item", "The price the stack was valued at"),
+    column("dropped_at", "When it was copied"),
+    column("tagged", "true or false"),
+    column(
+        "filter_tier",
+        "What the loot filter showed it as, empty without one",
+    ),
+    column("run_uuid", "Of the run"),
+];
+
+const TRADE_COLUMNS: &[Column] = &[
+    column("trade_id", "The same for the same trade in every export"),
+    column("kind", "sale or purchase"),
+    column("player", "Who it was with"),
+    column("item", "What changed hands"),
+    column("quantity", "More than 1 only for bulk trades"),
+    column("price", "For all of them, in currency"),
+    column("currency", "As whispered, like chaos or exalted"),
+    column("league", "As whispered"),
+    column(
+        "chaos_value",
+        "What the price was worth when it went through",
+    ),
+    column("requested_at", "The first whisper"),
+    column("completed_at", "When the trade was accepted"),
+];
+
+const EVENT_COLUMNS: &[Column] = &[
+    column("at", "When Client.txt says it happened"),
+    column(
+        "event",
+        "zone_change, death, level_up, loading_started, afk, whisper_from, whisper_to, \
+         joined_area or trade_accepted",
+    ),
+    column(
+        "name",
+        "The zone for zone_change, the character for death and level_up, the player for \
+         whispers and joined_area",
+    ),
+    column("level", "For level_up"),
+    column(
+        "detail",
+        "The class for level_up, on or off for afk, the message for whispers, the \
+         instance seed for loading_started",
+    ),
+];
+
+const SESSION_COLUMNS: &[Column] = &[
+    column(
+        "session_id",
+        "The same for the same session in every export",
+    ),
+    column("league", "What the session was recorded under"),
+    column("started_at", "When the app was started"),
+    column("ended_at", "When it was closed"),
+    column("duration_secs", "From start to close"),
+    column("maps_run", "Maps entered, each instance once"),
+    column("deaths", "Of everybody who played"),
+    column("chaos_value", "Of all drops"),
+    column(
+        "characters",
+        "Everybody who played, separated by \"; \" in CSV",
+    ),
+];
+
+/// What spreadsheets take as the start of a formula.
+pub const FORMULA_STARTS: &[char] = &['=', '+', '-', '@'];
+
+pub fn columns(table: ExportTable) -> &'static [Column] {
+    match table {
+        ExportTable::Runs => RUN_COLUMNS,
+        ExportTable::Drops => DROP_COLUMNS,
+        ExportTable::Trades => TRADE_COLUMNS,
+        ExportTable::Events => EVENT_COLUMNS,
+        ExportTable::Sessions => SESSION_COLUMNS,
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Database(rusqlite::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExportError::Database(ref e) => write!(f, "Can't read history: {}", e),
+            ExportError::Io(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ExportError {
+    fn from(e: rusqlite::Error) -> ExportError {
+        ExportError::Database(e)
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(e: io::Error) -> ExportError {
+        ExportError::Io(e)
+    }
+}
+
+/// Write what of `table` matches `filter`. Trades only go by `league`, `from`
+/// and `to`, sessions by those and `character`, events only by `from` and
+/// `to`, and come from `log`. Returns how many records were written.
+pub fn export<W: Write>(
+    connection: &Connection,
+    table: ExportTable,
+    format: ExportFormat,
+    filter: &HistoryFilter,
+    log: Option<&Path>,
+    mut writer: W,
+) -> Result<usize, ExportError> {
+    if let (ExportTable::Runs, ExportFormat::Json) = (table, format) {
+        let runs = database::export_map_runs(connection, filter)?;
+        write_json(&mut writer, &runs)?;
+        return Ok(runs.len());
+    }
+
+    let rows = match table {
+        ExportTable::Runs => run_rows(connection, filter)?,
+        ExportTable::Drops => drop_rows(connection, filter)?,
+        ExportTable::Trades => trade_rows(connection, filter)?,
+        ExportTable::Sessions => session_rows(connection, filter)?,
+        ExportTable::Events => return export_events(format, filter, log, writer),
+    };
+    write_rows(columns(table), format, &rows, writer)
+}
+
+/// Events come from the log, not the database, so they don't need it.
+fn export_events<W: Write>(
+    format: ExportFormat,
+    filter: &HistoryFilter,
+    log: Option<&Path>,
+    writer: W,
+) -> Result<usize, ExportError> {
+    let rows = event_rows(log, filter)?;
+    write_rows(EVENT_COLUMNS, format, &rows, writer)
+}
+
+fn write_rows<W: Write>(
+    columns: &[Column],
+    format: ExportFormat,
+    rows: &[Vec<Value>],
+    mut writer: W,
+) -> Result<usize, ExportError> {
+    match format {
+        ExportFormat::Json => {
+            let records: Vec<Value> = rows.iter().map(|row| record(columns, row)).collect();
+            write_json(&mut writer, &records)?;
+        }
+        ExportFormat::Csv => write_csv(&mut writer, columns, rows)?,
+        ExportFormat::Ndjson => {
+            for row in rows {
+                serde_json::to_writer(&mut writer, &record(columns, row))
+                    .map_err(io::Error::from)?;
+                writeln!(writer)?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(rows.len())
+}
+
+/// Where the frontend's exports go, named after what's in them and when.
+pub fn export_path(table: ExportTable, format: ExportFormat, now: DateTime<Local>) -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(APP_DIR_NAME)
+        .join(EXPORT_DIR)
+        .join(format!(
+            "{}-{}.{}",
+            table.name(),
+            now.format("%Y%m%d-%H%M%S"),
+            format.name()
+        ))
+}
+
+pub fn export_to_file(
+    connection: &Connection,
+    table: ExportTable,
+    format: ExportFormat,
+    filter: &HistoryFilter,
+    log: Option<&Path>,
+    path: &Path,
+) -> Result<usize, ExportError> {
+    export(connection, table, format, filter, log, create(path)?)
+}
+
+/// Like `export_to_file` for events, which can take a while with a big log.
+/// It needs no connection, so it can run away from the database.
+pub fn export_events_to_file(
+    format: ExportFormat,
+    filter: &HistoryFilter,
+    log: Option<&Path>,
+    path: &Path,
+) -> Result<usize, ExportError> {
+    export_events(format, filter, log, create(path)?)
+}
+
+fn create(path: &Path) -> io::Result<BufWriter<File>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(BufWriter::new(File::create(path)?))
+}
+
+fn run_rows(
+    connection: &Connection,
+    filter: &HistoryFilter,
+) -> Result<Vec<Vec<Value>>, ExportError> {
+    Ok(database::map_runs(connection, filter)?
+        .into_iter()
+        .map(|run| {
+            vec![
+                Value::from(run.id),
+                Value::from(run.league),
+                Value::from(run.name),
+                Value::from(run.tier),
+                Value::from(run.mods),
+                Value::from(run.item_quantity),
+                Value::from(run.item_rarity),
+                Value::from(run.pack_size),
+                Value::from(run.character),
+                Value::from(run.character_class),
+                Value::from(run.character_level),
+                time(run.started_at),
+                Value::from(run.duration_secs),
+                Value::from(run.deaths),
+                chaos(run.chaos_value),
+                Value::from(run.uuid),
+            ]
+        })
+        .collect())
+}
+
+fn drop_rows(
+    connection: &Connection,
+    filter: &HistoryFilter,
+) -> Result<Vec<Vec<Value>>, ExportError> {
+    let mut rows = Vec::new();
+    for export in database::export_map_runs(connection, filter)? {
+        for drop in export.drops {
+            let per_item = drop
+                .chaos_value
+                .map(|value| value / drop.count.max(1) as f32);
+            rows.push(vec![
+                Value::from(export.run.id),
+                Value::from(export.run.league.clone()),
+                Value::from(export.run.name.clone()),
+                Value::from(export.run.character.clone()),
+                Value::from(drop.name),
+                Value::from(drop.count),
+                drop.chaos_value.map_or(Value::Null, chaos),
+                per_item.map_or(Value::Null, chaos),
+                time(drop.dropped_at),
+                Value::from(drop.tagged),
+                Value::from(drop.filter_tier),
+                Value::from(export.run.uuid.clone()),
+            ]);
+        }
+    }
+    Ok(rows)
+}
+
+fn trade_rows(
+    connection: &Connection,
+    filter: &HistoryFilter,
+) -> Result<Vec<Vec<Value>>, ExportError> {
+    Ok(database::trades(connection, filter)?
+        .into_iter()
+        .map(|trade| {
+            vec![
+                Value::from(trade.id),
+                Value::from(trade.kind.name()),
+                Value::from(trade.player),
+                Value::from(trade.request.item),
+                Value::from(trade.request.quantity),
+                Value::from(round(trade.request.price)),
+                Value::from(trade.request.currency),
+                Value::from(trade.request.league),
+                trade.chaos_value.map_or(Value::Null, chaos),
+                time(trade.requested_at),
+                trade.completed_at.map_or(Value::Null, time),
+            ]
+        })
+        .collect())
+}
+
+fn session_rows(
+    connection: &Connection,
+    filter: &HistoryFilter,
+) -> Result<Vec<Vec<Value>>, ExportError> {
+    Ok(database::sessions(connection, filter)?
+        .into_iter()
+        .map(|export| {
+            let session = export.session;
+            let characters: Vec<String> = session.characters.into_iter().map(|c| c.name).collect();
+            vec![
+                Value::from(export.id),
+                Value::from(export.league),
+                time(session.started_at.timestamp()),
+                time(session.ended_at.timestamp()),
+                Value::from((session.ended_at - session.started_at).num_seconds()),
+                Value::from(session.maps_run),
+                Value::from(session.deaths),
+                chaos(session.chaos_value),
+                Value::from(characters),
+            ]
+        })
+        .collect())
+}
+
+fn event_rows(log: Option<&Path>, filter: &HistoryFilter) -> Result<Vec<Vec<Value>>, ExportError> {
+    let log = log.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "No Client.txt to read events from")
+    })?;
+    let file = File::open(log)?;
+    Ok(parse_log(BufReader::new(file))
+        .filter(|event| {
+            let at = event.timestamp().timestamp();
+            filter.from.iter().all(|&from| at >= from) && filter.to.iter().all(|&to| at < to)
+        })
+        .map(event_row)
+        .collect())
+}
+
+fn event_row(event: ZoneEvent) -> Vec<Value> {
+    let at = time(event.timestamp().timestamp());
+    let (kind, name, level, detail) = match event {
+        ZoneEvent::ZoneChange(zone, _) => ("zone_change", Some(zone), None, None),
+        ZoneEvent::Death(character, _) => ("death", Some(character), None, None),
+        ZoneEvent::LevelUp(character, class, level, _) => {
+            ("level_up", Some(character), Some(level), Some(class))
+        }
+        ZoneEvent::LoadingStarted(seed, _) => {
+            ("loading_started", None, None, seed.map(|seed| seed.to_string()))
+        }
+        ZoneEvent::Afk(afk, _) => {
+            let detail = if afk { "on" } else { "off" };
+            ("afk", None, None, Some(detail.to_string()))
+        }
+        ZoneEvent::Whisper(whisper, _) => {
+            let kind = match whisper.direction {
+                Direction::Incoming => "whisper_from",
+                Direction::Outgoing => "whisper_to",
+            };
+            (kind, Some(whisper.player), None, Some(whisper.message))
+        }
+        ZoneEvent::JoinedArea(player, _) => ("joined_area", Some(player), None, None),
+        ZoneEvent::TradeAccepted(_) => ("trade_accepted", None, None, None),
+    };
+    vec![
+        at,
+        Value::from(kind),
+        Value::from(name),
+        Value::from(level),
+        Value::from(detail),
+    ]
+}
+
+fn time(timestamp: i64) -> Value {
+    Value::from(Local.timestamp(timestamp, 0).to_rfc3339())
+}
+
+fn round(value: f32) -> f64 {
+    (f64::from(value) * 100.0).round() / 100.0
+}
+
+fn chaos(value: f32) -> Value {
+    Value::from(round(value))
+}
+
+fn record(columns: &[Column], row: &[Value]) -> Value {
+    let record: Map<String, Value> = columns
+        .iter()
+        .zip(row)
+        .map(|(column, value)| (column.name.to_string(), value.clone()))
+        .collect();
+    Value::Object(record)
+}
+
+fn write_json<W: Write, T: ::serde::Serialize>(writer: &mut W, value: &T) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut *writer, value)?;
+    writeln!(writer)
+}
+
+fn write_csv<W: Write>(writer: &mut W, columns: &[Column], rows: &[Vec<Value>]) -> io::Result<()> {
+    let header: Vec<&str> = columns.iter().map(|column| column.name).collect();
+    writeln!(writer, "{}", header.join(","))?;
+    for row in rows {
+        let fields: Vec<String> = row.iter().map(|value| csv_field(&text(value))).collect();
+        writeln!(writer, "{}", fields.join(","))?;
+    }
+    Ok(())
+}
+
+fn text(value: &Value) -> String {
+    match *value {
+        Value::Null => String::new(),
+        Value::String(ref text) => text.clone(),
+        Value::Array(ref values) => values.iter().map(text).collect::<Vec<_>>().join("; "),
+        ref other => other.to_string(),
+    }
+}
+
+/// Quoted if it has to be, the way spreadsheets expect. Text that starts like
+/// a formula gets a `'` in front, so spreadsheets show it instead of running
+/// it. Negative numbers are just numbers.
+fn csv_field(text: &str) -> String {
+    let text = if text.starts_with(FORMULA_STARTS) && text.parse::<f64>().is_err() {
+        format!("'{}", text)
+    } else {
+        text.to_string()
+    };
+    if text.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+    use types::session::{Character, Drop, MapRun, SessionRecord};
+
+    fn history() -> Connection {
+        let mut connection = Connection::open_in_memory().unwrap();
+        database::migrate(&mut connection).unwrap();
+        let started_at = Local.ymd(2018, 11, 22).and_hms(5, 0, 0);
+        let mut run = MapRun::new("Shaped Cage".to_string(), started_at);
+        run.ended_at = Some(started_at + Duration::minutes(10));
+        run.mods = vec!["Players are Cursed with Elemental Weakness".to_string()];
+        run.drops.push(Drop {
+            id: 0,
+            name: "Chaos Orb, \"shiny\"".to_string(),
+            count: 3,
+            chaos_value: Some(3.0),
+            dropped_at: started_at,
+            tagged: false,
+            filter_tier: Some("currency t2".to_string()),
+        });
+        database::save_map_run(&mut connection, "Standard", &run).unwrap();
+        let character = |name: &str| Character {
+            name: name.to_string(),
+            class: None,
+            level: None,
+        };
+        for &(hours, ref characters) in &[
+            (0, vec![character("Nightblade"), character("Daybreak")]),
+            (3, vec![character("Daybreak")]),
+        ] {
+            let session = SessionRecord {
+                started_at: started_at + Duration::hours(hours),
+                ended_at: started_at + Duration::hours(hours + 2),
+                maps_run: 9,
+                chaos_value: 250.0,
+                deaths: 1,
+                characters: characters.clone(),
+            };
+            database::save_session(&mut connection, "Standard", &session).unwrap();
+        }
+        connection
+    }
+
+    fn exported(table: ExportTable, format: ExportFormat) -> String {
+        let mut out = Vec::new();
+        export(
+            &history(),
+            table,
+            format,
+            &HistoryFilter::default(),
+            None,
+            &mut out,
+        )
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn should_write_drops_as_csv() {
+        let csv = exported(ExportTable::Drops, ExportFormat::Csv);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "run_id,league,map,character,item,count,chaos_value,chaos_per_item,dropped_at,tagged,filter_tier,run_uuid"
+        );
+        assert_eq!(lines.len(), 2);
+        assert!(
+            lines[1].starts_with("1,Standard,Shaped Cage,,\"Chaos Orb, \"\"shiny\"\"\",3,3.0,1.0,")
+        );
+        assert!(lines[1].contains(",false,currency t2,"));
+    }
+
+    #[test]
+    fn should_keep_spreadsheets_from_running_fields() {
+        assert_eq!(csv_field("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(csv_field("+20% Monster Damage"), "'+20% Monster Damage");
+        assert_eq!(csv_field("@me"), "'@me");
+        assert_eq!(csv_field("-12.5"), "-12.5");
+        assert_eq!(csv_field("Chaos Orb"), "Chaos Orb");
+    }
+
+    #[test]
+    fn should_write_runs_as_one_object_per_line() {
+        let ndjson = exported(ExportTable::Runs, ExportFormat::Ndjson);
+        let records: Vec<Value> = ndjson
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 1);
+        let record = records[0].as_object().unwrap();
+        let keys: Vec<&str> = record.keys().map(String::as_str).collect();
+        let mut names: Vec<&str> = RUN_COLUMNS.iter().map(|c| c.name).collect();
+        names.sort();
+        assert_eq!(keys, names);
+        assert_eq!(record["duration_secs"], Value::from(600));
+        assert_eq!(
+            record["started_at"],
+            Value::from(Local.ymd(2018, 11, 22).and_hms(5, 0, 0).to_rfc3339())
+        );
+    }
+
+    #[test]
+    fn should_write_sessions_with_who_played() {
+        let mut out = Vec::new();
+        let filter = HistoryFilter {
+            character: Some("Nightblade".to_string()),
+            ..HistoryFilter::default()
+        };
+        export(
+            &history(),
+            ExportTable::Sessions,
+            ExportFormat::Csv,
+            &filter,
+            None,
+            &mut out,
+        )
+        .unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "session_id,league,started_at,ended_at,duration_secs,maps_run,deaths,chaos_value,characters"
+        );
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("1,Standard,"));
+        assert!(lines[1].ends_with(",7200,9,1,250.0,Nightblade; Daybreak"));
+    }
+
+    #[test]
+    fn should_need_a_log_for_events() {
+        let mut out = Vec::new();
+        let result = export(
+            &history(),
+            ExportTable::Events,
+            ExportFormat::Csv,
+            &HistoryFilter::default(),
+            None,
+            &mut out,
+        );
+        match result {
+            Err(ExportError::Io(ref e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+            other => panic!("Expected no log, got {:?}", other),
+        }
+    }
+}
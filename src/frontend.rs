@@ -1,9 +1,18 @@
-use constants::IS_DEBUG;
-use std::sync::mpsc::Receiver;
-use types::frontend_communication::FrontendMessage;
-use web_view::Content;
+use serde_json;
+use std::io::{self, BufRead};
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+use types::frontend_communication::{FrontendCommand, FrontendMessage};
+use web_view::{Content, WebView};
 
-pub fn spawn_frontend(_receiver: Receiver<FrontendMessage>) -> () {
+/// Open the window and block until it's closed. Messages sent on `receiver`
+/// are pushed into the page, and commands coming from the page are parsed and
+/// passed on through `commands`. `debug` enables the web inspector.
+pub fn spawn_frontend(
+    receiver: Receiver<FrontendMessage>,
+    commands: Sender<FrontendCommand>,
+    debug: bool,
+) -> () {
     let html = include_str!("../frontend/index.html");
 
     let view = web_view::builder()
@@ -11,11 +20,85 @@ pub fn spawn_frontend(_receiver: Receiver<FrontendMessage>) -> () {
         .content(Content::Html(html))
         .size(800, 600)
         .resizable(true)
-        .debug(IS_DEBUG)
+        .debug(debug)
         .user_data(()) // data is kept in react
-        .invoke_handler(|_webview, arg| {
-            println!("[frontend] Caught external invokation: {}", arg);
+        .invoke_handler(move |webview, arg| {
+            match serde_json::from_str::<FrontendCommand>(arg) {
+                Ok(command) => {
+                    if let Err(e) = commands.send(command) {
+                        warn!("Nobody is listening for commands: {}", e);
+                    }
+                }
+                Err(e) => {
+                    warn!("Can't parse command {}: {}", arg, e);
+                    dispatch(
+                        webview,
+                        &FrontendMessage::Error {
+                            message: format!("Backend didn't understand {}: {}", arg, e),
+                        },
+                    )?;
+                }
+            }
             Ok(())
-        }).build()
+        })
+        .build()
         .expect("Can't create front-end window");
+
+    let handle = view.handle();
+    thread::spawn(move || {
+        for message in receiver.iter() {
+            let sent = handle.dispatch(move |webview| dispatch(webview, &message));
+            if sent.is_err() {
+                // The window is gone, nothing left to push to.
+                break;
+            }
+        }
+    });
+
+    view.run().expect("Front-end window crashed");
+}
+
+/// Hand a message to `window.dispatch` in the page.
+fn dispatch(webview: &mut WebView<()>, message: &FrontendMessage) -> web_view::WVResult {
+    let json = serde_json::to_string(message).expect("Frontend messages always serialize");
+    webview.eval(&format!("window.dispatch({})", json))
+}
+
+/// The frontend without a window, for scripts and boxes without a display.
+/// Every message is printed to stdout as a line of JSON, and every line on
+/// stdin is parsed as a command, in the same format the window uses. Blocks
+/// until stdin is closed. Messages keep being printed until the backend hangs
+/// up, join the returned handle to wait for that.
+pub fn run_headless(
+    receiver: Receiver<FrontendMessage>,
+    commands: Sender<FrontendCommand>,
+) -> thread::JoinHandle<()> {
+    let printer = thread::spawn(move || {
+        for message in receiver.iter() {
+            println!(
+                "{}",
+                serde_json::to_string(&message).expect("Frontend messages always serialize")
+            );
+        }
+    });
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<FrontendCommand>(&line) {
+            Ok(command) => {
+                if commands.send(command).is_err() {
+                    break;
+                }
+            }
+            Err(e) => warn!("Can't parse command {}: {}", line, e),
+        }
+    }
+    printer
 }
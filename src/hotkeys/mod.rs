@@ -0,0 +1,301 @@
+//! Global hotkeys, so nobody has to alt-tab out of the game to start a map or
+//! take back a drop. The listener waits for the keys bound in the config and
+//! sends the same commands the frontend's buttons do. Grabbing keys is up to
+//! the desktop, only X11 is done so far. `Stub` presses keys for tests.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::mpsc::{Receiver, Sender};
+
+use supervisor::Shutdown;
+use types::frontend_communication::FrontendCommand;
+use types::hotkeys::HotkeyAction;
+
+mod stub;
+#[cfg(target_os = "linux")]
+mod x11;
+
+pub use self::stub::Stub;
+#[cfg(target_os = "linux")]
+pub use self::x11::X11;
+
+/// A key and the modifiers that have to be held with it, like `ctrl+F5`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyCombo {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub super_key: bool,
+    /// As named in `KEYS`.
+    pub key: String,
+}
+
+impl KeyCombo {
+    pub fn parse(combo: &str) -> Result<KeyCombo, String> {
+        let mut parts: Vec<&str> = combo.split('+').map(str::trim).collect();
+        let key = parts.pop().unwrap_or("");
+        let mut parsed = KeyCombo {
+            ctrl: false,
+            shift: false,
+            alt: false,
+            super_key: false,
+            key: match keysym(key) {
+                Some(_) => canonical_key(key),
+                None => return Err(format!("{} isn't a key we know", key)),
+            },
+        };
+        for modifier in parts {
+            match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => parsed.ctrl = true,
+                "shift" => parsed.shift = true,
+                "alt" => parsed.alt = true,
+                "super" | "win" => parsed.super_key = true,
+                _ => return Err(format!("{} isn't a modifier", modifier)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+impl fmt::Display for KeyCombo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let modifiers = [
+            (self.ctrl, "ctrl+"),
+            (self.shift, "shift+"),
+            (self.alt, "alt+"),
+            (self.super_key, "super+"),
+        ];
+        for &(held, name) in &modifiers {
+            if held {
+                write!(f, "{}", name)?;
+            }
+        }
+        write!(f, "{}", self.key)
+    }
+}
+
+/// Keys that can be bound, besides letters, digits and F1 to F24, with their
+/// X keysyms. Nobody's going to bind Tab in a game that uses it.
+const KEYS: &[(&str, u32)] = &[
+    ("Insert", 0xff63),
+    ("Delete", 0xffff),
+    ("Home", 0xff50),
+    ("End", 0xff57),
+    ("PageUp", 0xff55),
+    ("PageDown", 0xff56),
+    ("Pause", 0xff13),
+    ("ScrollLock", 0xff14),
+];
+
+/// The X keysym of `key`, which is also how key names are checked.
+pub fn keysym(key: &str) -> Option<u32> {
+    let mut chars = key.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        // Lower case letters, digits, all ASCII.
+        if c.is_ascii_alphanumeric() {
+            return Some(c.to_ascii_lowercase() as u32);
+        }
+    }
+    if key.starts_with('F') || key.starts_with('f') {
+        if let Ok(n @ 1..=24) = key[1..].parse::<u32>() {
+            return Some(0xffbe + n - 1);
+        }
+    }
+    KEYS.iter()
+        .find(|&&(name, _)| name.eq_ignore_ascii_case(key))
+        .map(|&(_, keysym)| keysym)
+}
+
+fn canonical_key(key: &str) -> String {
+    match KEYS
+        .iter()
+        .find(|&&(name, _)| name.eq_ignore_ascii_case(key))
+    {
+        Some(&(name, _)) => name.to_string(),
+        None => key.to_uppercase(),
+    }
+}
+
+/// The bindings in the config's `hotkeys`, leaving out actions without a key.
+/// Complains about the first one that doesn't make sense.
+pub fn bindings(
+    hotkeys: &BTreeMap<String, String>,
+) -> Result<Vec<(KeyCombo, HotkeyAction)>, String> {
+    let mut bindings = Vec::new();
+    for (action, combo) in hotkeys {
+        let action = HotkeyAction::from_name(action)
+            .ok_or_else(|| format!("{} isn't something a hotkey can do", action))?;
+        if combo.trim().is_empty() {
+            continue;
+        }
+        let combo = KeyCombo::parse(combo).map_err(|e| format!("{}: {}", action.name(), e))?;
+        bindings.push((combo, action));
+    }
+    Ok(bindings)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HotkeyError {
+    /// Can't grab keys or press them, or lost the connection for it.
+    Unavailable(String),
+    /// Nothing more is coming. Only the stub runs out.
+    Closed,
+}
+
+impl fmt::Display for HotkeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HotkeyError::Unavailable(ref reason) => write!(f, "{}", reason),
+            HotkeyError::Closed => write!(f, "hotkeys closed"),
+        }
+    }
+}
+
+pub trait Hotkeys {
+    /// Listen for `bindings` from now on, and only those.
+    fn bind(&mut self, bindings: &[(KeyCombo, HotkeyAction)]) -> Result<(), HotkeyError>;
+
+    /// Wait a little for a bound key to be pressed.
+    fn next_press(&mut self, shutdown: &Shutdown) -> Result<Option<HotkeyAction>, HotkeyError>;
+
+    /// Press Ctrl+C, so the game copies whatever the mouse is over.
+    fn copy_hovered(&mut self) -> Result<(), HotkeyError>;
+}
+
+/// The hotkeys of this desktop, if there are any.
+pub fn open() -> Option<Box<dyn Hotkeys>> {
+    #[cfg(target_os = "linux")]
+    match X11::open() {
+        Ok(hotkeys) => return Some(Box::new(hotkeys)),
+        Err(e) => warn!("No hotkeys: {}", e),
+    }
+    None
+}
+
+/// Send the command of every hotkey pressed to `commands`. New bindings sent
+/// on `binding_updates` replace the old ones. Returns on shutdown, when
+/// `hotkeys` closes, or once nobody listens to `commands`, and with an error
+/// when `hotkeys` stops working.
+pub fn watch_hotkeys(
+    hotkeys: &mut dyn Hotkeys,
+    bindings: &mut Vec<(KeyCombo, HotkeyAction)>,
+    binding_updates: &Receiver<Vec<(KeyCombo, HotkeyAction)>>,
+    commands: &Sender<FrontendCommand>,
+    shutdown: &Shutdown,
+) -> Result<(), HotkeyError> {
+    if let Err(e) = hotkeys.bind(bindings) {
+        warn!("Can't bind hotkeys: {}", e);
+    }
+    while !shutdown.is_requested() {
+        if let Some(new_bindings) = binding_updates.try_iter().last() {
+            *bindings = new_bindings;
+            if let Err(e) = hotkeys.bind(bindings) {
+                warn!("Can't bind hotkeys: {}", e);
+            }
+        }
+
+        let action = match hotkeys.next_press(shutdown) {
+            Ok(Some(action)) => action,
+            Ok(None) => continue,
+            Err(HotkeyError::Closed) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        debug!("Hotkey for {} pressed", action.name());
+        // The mastermind has to know the copy is coming before it does.
+        if commands.send(action.command()).is_err() {
+            return Ok(());
+        }
+        if action == HotkeyAction::StartMap {
+            if let Err(e) = hotkeys.copy_hovered() {
+                warn!("Can't copy the map: {}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn should_parse_key_combos() {
+        let combo = KeyCombo::parse("Ctrl + shift+f5").unwrap();
+        assert!(combo.ctrl && combo.shift && !combo.alt && !combo.super_key);
+        assert_eq!(combo.to_string(), "ctrl+shift+F5");
+        assert_eq!(KeyCombo::parse("pageup").unwrap().key, "PageUp");
+        assert_eq!(keysym("F5"), Some(0xffc2));
+        assert_eq!(keysym("Z"), Some('z' as u32));
+
+        assert!(KeyCombo::parse("ctrl+").is_err());
+        assert!(KeyCombo::parse("hyper+F5").is_err());
+        assert!(KeyCombo::parse("F25").is_err());
+    }
+
+    #[test]
+    fn should_read_bindings_from_config() {
+        let hotkeys = vec![
+            ("end_map".to_string(), "ctrl+F6".to_string()),
+            ("undo_last_drop".to_string(), "".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let parsed = bindings(&hotkeys).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].1, HotkeyAction::EndMap);
+
+        let nonsense = vec![("make_coffee".to_string(), "F1".to_string())]
+            .into_iter()
+            .collect();
+        assert!(bindings(&nonsense).is_err());
+    }
+
+    #[test]
+    fn should_send_commands_for_presses() {
+        let mut hotkeys = Stub::new(vec![HotkeyAction::StartMap, HotkeyAction::UndoLastDrop]);
+        let mut bound = vec![(KeyCombo::parse("F5").unwrap(), HotkeyAction::StartMap)];
+        let (_updates_sender, updates) = channel();
+        let (commands_sender, commands) = channel();
+
+        watch_hotkeys(
+            &mut hotkeys,
+            &mut bound,
+            &updates,
+            &commands_sender,
+            &Shutdown::new(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            commands.try_iter().collect::<Vec<_>>(),
+            vec![
+                FrontendCommand::StartMapFromCopy,
+                FrontendCommand::UndoLastDrop
+            ]
+        );
+        assert_eq!(hotkeys.copies, 1);
+        assert_eq!(hotkeys.bound, vec![bound]);
+    }
+
+    #[test]
+    fn should_fail_when_hotkeys_stop_working() {
+        let mut hotkeys = Stub::new(vec![HotkeyAction::EndMap]);
+        hotkeys.failure = Some(HotkeyError::Unavailable("X server went away".to_string()));
+        let (_updates_sender, updates) = channel();
+        let (commands_sender, commands) = channel();
+
+        let result = watch_hotkeys(
+            &mut hotkeys,
+            &mut Vec::new(),
+            &updates,
+            &commands_sender,
+            &Shutdown::new(),
+        );
+
+        assert_eq!(
+            result,
+            Err(HotkeyError::Unavailable("X server went away".to_string()))
+        );
+        assert_eq!(commands.try_iter().count(), 1);
+    }
+}
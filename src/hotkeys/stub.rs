@@ -0,0 +1,46 @@
+use std::collections::VecDeque;
+
+use super::{HotkeyError, Hotkeys, KeyCombo};
+use supervisor::Shutdown;
+use types::hotkeys::HotkeyAction;
+
+/// "Presses" `presses` one after the other, then closes, or fails with
+/// `failure` if there is one. Remembers what it was asked to do, for tests to
+/// check.
+pub struct Stub {
+    presses: VecDeque<HotkeyAction>,
+    /// Every set of bindings it was given, oldest first.
+    pub bound: Vec<Vec<(KeyCombo, HotkeyAction)>>,
+    pub copies: u32,
+    pub failure: Option<HotkeyError>,
+}
+
+impl Stub {
+    pub fn new(presses: Vec<HotkeyAction>) -> Stub {
+        Stub {
+            presses: presses.into_iter().collect(),
+            bound: Vec::new(),
+            copies: 0,
+            failure: None,
+        }
+    }
+}
+
+impl Hotkeys for Stub {
+    fn bind(&mut self, bindings: &[(KeyCombo, HotkeyAction)]) -> Result<(), HotkeyError> {
+        self.bound.push(bindings.to_vec());
+        Ok(())
+    }
+
+    fn next_press(&mut self, _shutdown: &Shutdown) -> Result<Option<HotkeyAction>, HotkeyError> {
+        match self.presses.pop_front() {
+            Some(action) => Ok(Some(action)),
+            None => Err(self.failure.take().unwrap_or(HotkeyError::Closed)),
+        }
+    }
+
+    fn copy_hovered(&mut self) -> Result<(), HotkeyError> {
+        self.copies += 1;
+        Ok(())
+    }
+}
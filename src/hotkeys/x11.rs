@@ -0,0 +1,191 @@
+use std::time::Duration;
+use xcb;
+use xcb::test as xtest;
+
+use super::{keysym, HotkeyError, Hotkeys, KeyCombo};
+use constants::CLIPBOARD_WAIT_MS;
+use supervisor::Shutdown;
+use types::hotkeys::HotkeyAction;
+
+/// Modifiers that are nobody's business when matching a hotkey: Caps Lock
+/// and Num Lock. Keys are grabbed with every combination of them.
+const IGNORED_MODIFIERS: &[u16] = &[
+    0,
+    xcb::MOD_MASK_LOCK as u16,
+    xcb::MOD_MASK_2 as u16,
+    (xcb::MOD_MASK_LOCK | xcb::MOD_MASK_2) as u16,
+];
+
+/// Modifiers a hotkey can be bound with, see `modifiers`.
+const BOUND_MODIFIERS: u16 =
+    (xcb::MOD_MASK_CONTROL | xcb::MOD_MASK_SHIFT | xcb::MOD_MASK_1 | xcb::MOD_MASK_4) as u16;
+
+/// Grabs the bound keys on the root window, so they reach us whichever window
+/// has focus. Pressing Ctrl+C for the game goes through XTest, which looks to
+/// the game like the keyboard.
+pub struct X11 {
+    connection: xcb::Connection,
+    root: xcb::Window,
+    /// Keycode and modifiers of every grabbed key, and what it's for.
+    grabbed: Vec<(xcb::Keycode, u16, HotkeyAction)>,
+}
+
+fn unavailable<E: ToString>(e: E) -> HotkeyError {
+    HotkeyError::Unavailable(e.to_string())
+}
+
+impl X11 {
+    pub fn open() -> Result<X11, HotkeyError> {
+        let (connection, screen) = xcb::Connection::connect(None).map_err(unavailable)?;
+        let root = connection
+            .get_setup()
+            .roots()
+            .nth(screen as usize)
+            .map(|screen| screen.root())
+            .ok_or_else(|| unavailable("X server has no such screen"))?;
+        Ok(X11 {
+            connection,
+            root,
+            grabbed: Vec::new(),
+        })
+    }
+
+    /// The key that types `keysym` without any modifiers.
+    fn keycode(&self, keysym: u32) -> Result<xcb::Keycode, HotkeyError> {
+        let setup = self.connection.get_setup();
+        let (first, last) = (setup.min_keycode(), setup.max_keycode());
+        let mapping = xcb::get_keyboard_mapping(&self.connection, first, last - first + 1)
+            .get_reply()
+            .map_err(unavailable)?;
+        let per_keycode = mapping.keysyms_per_keycode() as usize;
+        mapping
+            .keysyms()
+            .chunks(per_keycode)
+            .position(|keysyms| keysyms.first() == Some(&keysym))
+            .map(|index| first + index as u8)
+            .ok_or_else(|| unavailable(format!("No key on this keyboard types {:#x}", keysym)))
+    }
+
+    fn press(&self, keycode: xcb::Keycode, down: bool) -> () {
+        let kind = if down {
+            xcb::KEY_PRESS
+        } else {
+            xcb::KEY_RELEASE
+        };
+        xtest::fake_input(
+            &self.connection,
+            kind,
+            keycode,
+            xcb::CURRENT_TIME,
+            xcb::NONE,
+            0,
+            0,
+            0,
+        );
+    }
+}
+
+fn modifiers(combo: &KeyCombo) -> u16 {
+    let mut mask = 0;
+    if combo.ctrl {
+        mask |= xcb::MOD_MASK_CONTROL;
+    }
+    if combo.shift {
+        mask |= xcb::MOD_MASK_SHIFT;
+    }
+    if combo.alt {
+        mask |= xcb::MOD_MASK_1;
+    }
+    if combo.super_key {
+        mask |= xcb::MOD_MASK_4;
+    }
+    mask as u16
+}
+
+impl Hotkeys for X11 {
+    fn bind(&mut self, bindings: &[(KeyCombo, HotkeyAction)]) -> Result<(), HotkeyError> {
+        for &(keycode, mask, _) in &self.grabbed {
+            for &ignored in IGNORED_MODIFIERS {
+                xcb::ungrab_key(&self.connection, keycode, self.root, mask | ignored);
+            }
+        }
+        self.grabbed.clear();
+
+        let mut problems = Vec::new();
+        for &(ref combo, action) in bindings {
+            let keycode = match keysym(&combo.key).map(|keysym| self.keycode(keysym)) {
+                Some(Ok(keycode)) => keycode,
+                Some(Err(e)) => {
+                    problems.push(format!("{}: {}", combo, e));
+                    continue;
+                }
+                None => continue,
+            };
+            let mask = modifiers(combo);
+            let taken = IGNORED_MODIFIERS.iter().any(|&ignored| {
+                xcb::grab_key_checked(
+                    &self.connection,
+                    true,
+                    self.root,
+                    mask | ignored,
+                    keycode,
+                    xcb::GRAB_MODE_ASYNC as u8,
+                    xcb::GRAB_MODE_ASYNC as u8,
+                )
+                .request_check()
+                .is_err()
+            });
+            if taken {
+                problems.push(format!("{} is taken by another program", combo));
+            }
+            self.grabbed.push((keycode, mask, action));
+        }
+        self.connection.flush();
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(HotkeyError::Unavailable(problems.join(", ")))
+        }
+    }
+
+    /// Goes by the key being let go. Until then the keyboard is ours, and the
+    /// game wouldn't see the Ctrl+C `start_map` presses.
+    fn next_press(&mut self, shutdown: &Shutdown) -> Result<Option<HotkeyAction>, HotkeyError> {
+        loop {
+            let event = match self.connection.poll_for_event() {
+                Some(event) => event,
+                None => {
+                    self.connection.has_error().map_err(unavailable)?;
+                    shutdown.sleep(Duration::from_millis(CLIPBOARD_WAIT_MS));
+                    return Ok(None);
+                }
+            };
+            if event.response_type() & !0x80 != xcb::KEY_RELEASE {
+                continue;
+            }
+
+            let event: &xcb::KeyReleaseEvent = unsafe { xcb::cast_event(&event) };
+            let held = event.state() & BOUND_MODIFIERS;
+            let pressed = self
+                .grabbed
+                .iter()
+                .find(|&&(keycode, mask, _)| keycode == event.detail() && mask == held);
+            if let Some(&(_, _, action)) = pressed {
+                return Ok(Some(action));
+            }
+        }
+    }
+
+    fn copy_hovered(&mut self) -> Result<(), HotkeyError> {
+        // Keysyms of Control_L and c.
+        let control = self.keycode(0xffe3)?;
+        let c = self.keycode('c' as u32)?;
+        self.press(control, true);
+        self.press(c, true);
+        self.press(c, false);
+        self.press(control, false);
+        self.connection.flush();
+        Ok(())
+    }
+}
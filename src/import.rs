@@ -0,0 +1,821 @@
+//! Bringing in the history of another machine, from its database file or from
+//! what `export` wrote there. Runs are the same run when they have the same
+//! UUID, or for runs from before those, when they're of the same map, started
+//! the same second in the same league and aren't known to be by somebody
+//! else; trades when they're for the same whisper; sessions when they started
+//! the same second in the same league. Anything already here is
+//! left alone, everything else keeps the league and character it had. Prices
+//! come along as what the drops and trades were valued at, there's no other
+//! price history to bring.
+//!
+//! Runs exported as CSV or JSON lines don't have their drops in them, so those
+//! need the drops export from the same database along with them. Sessions
+//! exported that way only have the names of who played.
+use chrono::prelude::*;
+use chrono::Duration;
+use rusqlite::{self, Connection};
+use serde_json::{self, Map, Value};
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+
+use database;
+use export::FORMULA_STARTS;
+use types::history::{HistoryFilter, MapRunExport};
+use types::session::{Character, Drop, MapRun, SessionRecord};
+use types::trade::{Trade, TradeKind, TradeRequest};
+
+/// What every SQLite file starts with.
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+/// A line of a CSV or JSON lines export, by column.
+type Record = Map<String, Value>;
+
+#[derive(Debug)]
+pub enum ImportError {
+    Database(rusqlite::Error),
+    Io(io::Error),
+    /// Neither a database nor an export of ours.
+    Unreadable(String),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImportError::Database(ref e) => write!(f, "{}", e),
+            ImportError::Io(ref e) => write!(f, "{}", e),
+            ImportError::Unreadable(ref reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for ImportError {
+    fn from(e: rusqlite::Error) -> ImportError {
+        ImportError::Database(e)
+    }
+}
+
+impl From<io::Error> for ImportError {
+    fn from(e: io::Error) -> ImportError {
+        ImportError::Io(e)
+    }
+}
+
+/// Everything that could be imported.
+pub struct History {
+    pub runs: Vec<(String, MapRun)>,
+    pub trades: Vec<Trade>,
+    /// With their league.
+    pub sessions: Vec<(String, SessionRecord)>,
+}
+
+/// What was imported, or would be on a dry run.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// With their league.
+    pub new_runs: Vec<(String, MapRun)>,
+    pub known_runs: usize,
+    pub new_trades: Vec<Trade>,
+    pub known_trades: usize,
+    /// With their league.
+    pub new_sessions: Vec<(String, SessionRecord)>,
+    pub known_sessions: usize,
+}
+
+/// Runs and drops from CSV and JSON lines exports, with the `run_id` they
+/// were exported with.
+#[derive(Default)]
+struct Tables {
+    runs: Vec<(i64, String, MapRun)>,
+    drops: Vec<(i64, Drop)>,
+    /// Whether there was a drops export, even one of runs where nothing
+    /// dropped.
+    has_drops: bool,
+}
+
+/// Read the history in `paths`, as far as `filter` wants it. Trades only go by
+/// league, `from` and `to`, sessions by those and who played in them.
+pub fn read_history(paths: &[PathBuf], filter: &HistoryFilter) -> Result<History, ImportError> {
+    let mut history = History {
+        runs: Vec::new(),
+        trades: Vec::new(),
+        sessions: Vec::new(),
+    };
+    let mut tables = Tables::default();
+    for path in paths {
+        let mut header = [0; 16];
+        let is_database = File::open(path)?
+            .read_exact(&mut header)
+            .map(|()| header == SQLITE_HEADER)
+            .unwrap_or(false);
+        if is_database {
+            let read = read_database(path, filter)?;
+            history.runs.extend(read.runs);
+            history.trades.extend(read.trades);
+            history.sessions.extend(read.sessions);
+        } else {
+            read_export(path, &mut history, &mut tables)?;
+        }
+    }
+    history.runs.extend(join_drops(tables)?);
+
+    history
+        .runs
+        .retain(|(league, run)| is_wanted(league, run, filter));
+    history.trades.retain(|trade| {
+        let completed_at = trade.completed_at.unwrap_or(trade.requested_at);
+        filter
+            .league
+            .iter()
+            .all(|wanted| wanted == &trade.request.league)
+            && filter.from.iter().all(|&from| completed_at >= from)
+            && filter.to.iter().all(|&to| completed_at < to)
+    });
+    history.sessions.retain(|(league, session)| {
+        let started_at = session.started_at.timestamp();
+        filter.league.iter().all(|wanted| wanted == league)
+            && filter
+                .character
+                .iter()
+                .all(|wanted| session.characters.iter().any(|c| &c.name == wanted))
+            && filter.from.iter().all(|&from| started_at >= from)
+            && filter.to.iter().all(|&to| started_at < to)
+    });
+    Ok(history)
+}
+
+/// The other database may be older than ours. It's brought up to date in a
+/// copy, so the original stays as it was.
+fn read_database(path: &Path, filter: &HistoryFilter) -> Result<History, ImportError> {
+    let copy = env::temp_dir().join(format!(
+        "atlas-of-beancounting-import-{}.sqlite",
+        process::id()
+    ));
+    fs::copy(path, &copy)?;
+    let read = database::open(&copy).and_then(|connection| {
+        let runs = database::export_map_runs(&connection, filter)?;
+        let trades = database::trades(&connection, filter)?;
+        let sessions = database::sessions(&connection, filter)?;
+        Ok(History {
+            runs: runs.into_iter().map(map_run).collect(),
+            trades,
+            sessions: sessions
+                .into_iter()
+                .map(|export| (export.league, export.session))
+                .collect(),
+        })
+    });
+    let _ = fs::remove_file(&copy);
+    read.map_err(ImportError::from)
+}
+
+/// JSON exports of runs come with their drops. Everything else is a table,
+/// told apart by its columns. CSV has those even without any records, JSON
+/// with nothing in it doesn't say what it's of, and has nothing to import
+/// anyway.
+fn read_export(path: &Path, history: &mut History, tables: &mut Tables) -> Result<(), ImportError> {
+    let unreadable = |reason: String| {
+        ImportError::Unreadable(format!(
+            "{} is neither a database nor an export: {}",
+            path.display(),
+            reason
+        ))
+    };
+    let text = fs::read_to_string(path)?;
+    let columns_of = |records: &[Record]| {
+        records
+            .first()
+            .map_or_else(Vec::new, |record| record.keys().cloned().collect())
+    };
+    let (columns, records) = match text.trim_start().chars().next() {
+        Some('[') => {
+            let values: Vec<Value> =
+                serde_json::from_str(&text).map_err(|e| unreadable(e.to_string()))?;
+            if values
+                .first()
+                .and_then(|value| value.get("drops"))
+                .is_some()
+            {
+                let runs: Vec<MapRunExport> = serde_json::from_value(Value::Array(values))
+                    .map_err(|e| unreadable(e.to_string()))?;
+                history.runs.extend(runs.into_iter().map(map_run));
+                return Ok(());
+            }
+            let records = values
+                .into_iter()
+                .map(|value| match value {
+                    Value::Object(record) => Ok(record),
+                    other => Err(unreadable(format!("{} isn't a record", other))),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            (columns_of(&records), records)
+        }
+        Some('{') => {
+            let records = text
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| serde_json::from_str(line).map_err(|e| unreadable(e.to_string())))
+                .collect::<Result<Vec<_>, _>>()?;
+            (columns_of(&records), records)
+        }
+        _ => csv_records(&text),
+    };
+    if columns.is_empty() {
+        return Ok(());
+    }
+    let has = |column: &str| columns.iter().any(|c| c == column);
+
+    let wrong = |number: usize, reason: String| {
+        ImportError::Unreadable(format!(
+            "{}, record {}: {}",
+            path.display(),
+            number + 1,
+            reason
+        ))
+    };
+    if has("trade_id") {
+        for (number, record) in records.iter().enumerate() {
+            history
+                .trades
+                .push(trade_record(record).map_err(|reason| wrong(number, reason))?);
+        }
+    } else if has("session_id") {
+        for (number, record) in records.iter().enumerate() {
+            history
+                .sessions
+                .push(session_record(record).map_err(|reason| wrong(number, reason))?);
+        }
+    } else if has("duration_secs") {
+        for (number, record) in records.iter().enumerate() {
+            tables
+                .runs
+                .push(run_record(record).map_err(|reason| wrong(number, reason))?);
+        }
+    } else if has("dropped_at") {
+        tables.has_drops = true;
+        for (number, record) in records.iter().enumerate() {
+            tables
+                .drops
+                .push(drop_record(record).map_err(|reason| wrong(number, reason))?);
+        }
+    } else if has("event") {
+        return Err(ImportError::Unreadable(format!(
+            "{} has events, replay the Client.txt they're from instead",
+            path.display()
+        )));
+    } else {
+        return Err(unreadable("it has none of the columns".to_string()));
+    }
+    Ok(())
+}
+
+/// Put the drops from a drops export into the runs they dropped in.
+fn join_drops(tables: Tables) -> Result<Vec<(String, MapRun)>, ImportError> {
+    let Tables {
+        mut runs,
+        drops,
+        has_drops,
+    } = tables;
+    if !runs.is_empty() && !has_drops {
+        return Err(ImportError::Unreadable(
+            "Runs exported as CSV or JSON lines need the drops export along with them".to_string(),
+        ));
+    }
+    for (run_id, mut drop) in drops {
+        let run = match runs.iter_mut().find(|&&mut (id, _, _)| id == run_id) {
+            Some(&mut (_, _, ref mut run)) => run,
+            None => {
+                return Err(ImportError::Unreadable(format!(
+                    "{} dropped in run {}, which isn't in the runs export",
+                    drop.name, run_id
+                )))
+            }
+        };
+        drop.id = run.drops.len() as u64;
+        run.drops.push(drop);
+    }
+    Ok(runs
+        .into_iter()
+        .map(|(_, league, run)| (league, run))
+        .collect())
+}
+
+fn is_wanted(league: &str, run: &MapRun, filter: &HistoryFilter) -> bool {
+    let started_at = run.started_at.timestamp();
+    filter.league.iter().all(|wanted| wanted == league)
+        && filter
+            .character
+            .iter()
+            .all(|wanted| run.character.as_ref().map(|c| &c.name) == Some(wanted))
+        && filter.from.iter().all(|&from| started_at >= from)
+        && filter.to.iter().all(|&to| started_at < to)
+}
+
+fn map_run(export: MapRunExport) -> (String, MapRun) {
+    let run = export.run;
+    let started_at = Local.timestamp(run.started_at, 0);
+    let (class, level) = (run.character_class, run.character_level);
+    let character = run.character.map(|name| Character { name, class, level });
+    let drops = export
+        .drops
+        .into_iter()
+        .enumerate()
+        .map(|(id, drop)| Drop {
+            id: id as u64,
+            name: drop.name,
+            count: drop.count,
+            chaos_value: drop.chaos_value,
+            dropped_at: Local.timestamp(drop.dropped_at, 0),
+            tagged: drop.tagged,
+            filter_tier: drop.filter_tier,
+        })
+        .collect();
+    let imported = MapRun {
+        name: run.name,
+        started_at,
+        ended_at: Some(started_at + Duration::seconds(run.duration_secs)),
+        tier: run.tier,
+        mods: run.mods,
+        item_quantity: run.item_quantity,
+        item_rarity: run.item_rarity,
+        pack_size: run.pack_size,
+        deaths: run.deaths,
+        character,
+        drops,
+        uuid: run.uuid,
+    };
+    (run.league, imported)
+}
+
+fn run_record(record: &Record) -> Result<(i64, String, MapRun), String> {
+    let started_at = Local.timestamp(required_time(record, "started_at")?, 0);
+    let mut run = MapRun::new(required(record, "map")?, started_at);
+    run.ended_at = Some(started_at + Duration::seconds(required(record, "duration_secs")?));
+    run.tier = optional(record, "tier")?;
+    run.mods = text(record, "mods").map_or_else(Vec::new, |mods| {
+        mods.split("; ").map(|m| m.to_string()).collect()
+    });
+    run.item_quantity = optional(record, "item_quantity")?;
+    run.item_rarity = optional(record, "item_rarity")?;
+    run.pack_size = optional(record, "pack_size")?;
+    run.deaths = optional(record, "deaths")?.unwrap_or(0);
+    let level = optional(record, "character_level")?;
+    run.character = text(record, "character").map(|name| Character {
+        name,
+        class: text(record, "character_class"),
+        level,
+    });
+    run.uuid = text(record, "run_uuid");
+    Ok((
+        required(record, "run_id")?,
+        required(record, "league")?,
+        run,
+    ))
+}
+
+fn drop_record(record: &Record) -> Result<(i64, Drop), String> {
+    let drop = Drop {
+        id: 0,
+        name: required(record, "item")?,
+        count: required(record, "count")?,
+        chaos_value: optional(record, "chaos_value")?,
+        dropped_at: Local.timestamp(required_time(record, "dropped_at")?, 0),
+        tagged: optional(record, "tagged")?.unwrap_or(false),
+        filter_tier: text(record, "filter_tier"),
+    };
+    Ok((required(record, "run_id")?, drop))
+}
+
+fn trade_record(record: &Record) -> Result<Trade, String> {
+    let kind: String = required(record, "kind")?;
+    Ok(Trade {
+        id: 0,
+        kind: TradeKind::from_name(&kind).ok_or_else(|| format!("{} isn't a kind", kind))?,
+        player: required(record, "player")?,
+        request: TradeRequest {
+            item: required(record, "item")?,
+            quantity: required(record, "quantity")?,
+            price: required(record, "price")?,
+            currency: required(record, "currency")?,
+            league: required(record, "league")?,
+        },
+        requested_at: required_time(record, "requested_at")?,
+        completed_at: time(record, "completed_at")?,
+        chaos_value: optional(record, "chaos_value")?,
+    })
+}
+
+fn session_record(record: &Record) -> Result<(String, SessionRecord), String> {
+    let characters = text(record, "characters").map_or_else(Vec::new, |names| {
+        names
+            .split("; ")
+            .map(|name| Character {
+                name: name.to_string(),
+                class: None,
+                level: None,
+            })
+            .collect()
+    });
+    let session = SessionRecord {
+        started_at: Local.timestamp(required_time(record, "started_at")?, 0),
+        ended_at: Local.timestamp(required_time(record, "ended_at")?, 0),
+        maps_run: required(record, "maps_run")?,
+        chaos_value: required(record, "chaos_value")?,
+        deaths: required(record, "deaths")?,
+        characters,
+    };
+    Ok((required(record, "league")?, session))
+}
+
+/// A field as text, `None` when it's empty. Lists are joined the way CSV
+/// exports have them.
+fn text(record: &Record, column: &str) -> Option<String> {
+    match *record.get(column)? {
+        Value::Null => None,
+        Value::String(ref text) if text.is_empty() => None,
+        Value::String(ref text) => Some(text.clone()),
+        Value::Array(ref values) => Some(
+            values
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join("; "),
+        ),
+        ref other => Some(other.to_string()),
+    }
+}
+
+fn optional<T: FromStr>(record: &Record, column: &str) -> Result<Option<T>, String> {
+    match text(record, column) {
+        Some(text) => text
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("{} isn't a valid {}", text, column)),
+        None => Ok(None),
+    }
+}
+
+fn required<T: FromStr>(record: &Record, column: &str) -> Result<T, String> {
+    optional(record, column)?.ok_or_else(|| format!("{} is missing", column))
+}
+
+/// Exports have times as RFC 3339.
+fn time(record: &Record, column: &str) -> Result<Option<i64>, String> {
+    match text(record, column) {
+        Some(text) => DateTime::parse_from_rfc3339(&text)
+            .map(|time| Some(time.timestamp()))
+            .map_err(|_| format!("{} isn't a valid {}", text, column)),
+        None => Ok(None),
+    }
+}
+
+fn required_time(record: &Record, column: &str) -> Result<i64, String> {
+    time(record, column)?.ok_or_else(|| format!("{} is missing", column))
+}
+
+/// The header of a CSV export and the rows after it, quoted the way `export`
+/// and spreadsheets do it, and without the `'` that keeps formulas from
+/// running.
+fn csv_records(text: &str) -> (Vec<String>, Vec<Record>) {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => row.push(mem::take(&mut field)),
+            '\n' if !quoted => {
+                row.push(mem::take(&mut field));
+                rows.push(mem::take(&mut row));
+            }
+            '\r' if !quoted => {}
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    let mut rows = rows.into_iter().filter(|row| row != &[""]);
+    let header = match rows.next() {
+        Some(header) => header,
+        None => return (Vec::new(), Vec::new()),
+    };
+    let records = rows
+        .map(|row| {
+            header
+                .iter()
+                .cloned()
+                .zip(
+                    row.into_iter()
+                        .map(|field| Value::String(unescape_formula(field))),
+                )
+                .collect()
+        })
+        .collect();
+    (header, records)
+}
+
+fn unescape_formula(field: String) -> String {
+    if field.starts_with('\'') && field[1..].starts_with(FORMULA_STARTS) {
+        field[1..].to_string()
+    } else {
+        field
+    }
+}
+
+/// Put what's new in `history` into `connection`. It all goes in or none of
+/// it does, and on a `dry_run` none of it does anyway.
+pub fn merge(
+    connection: &mut Connection,
+    history: History,
+    dry_run: bool,
+) -> Result<ImportReport, ImportError> {
+    let transaction = connection.transaction()?;
+    let mut report = ImportReport::default();
+    for (league, run) in history.runs {
+        if database::has_map_run(&transaction, &league, &run)? {
+            report.known_runs += 1;
+            continue;
+        }
+        database::insert_map_run(&transaction, &league, &run)?;
+        report.new_runs.push((league, run));
+    }
+    for trade in history.trades {
+        if database::has_trade(&transaction, &trade)? {
+            report.known_trades += 1;
+            continue;
+        }
+        database::save_trade(&transaction, &trade)?;
+        report.new_trades.push(trade);
+    }
+    for (league, session) in history.sessions {
+        if database::has_session(&transaction, &league, &session)? {
+            report.known_sessions += 1;
+            continue;
+        }
+        database::insert_session(&transaction, &league, &session)?;
+        report.new_sessions.push((league, session));
+    }
+    if !dry_run {
+        transaction.commit()?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use export::export_to_file;
+    use types::export::{ExportFormat, ExportTable};
+
+    fn database() -> Connection {
+        let mut connection = Connection::open_in_memory().unwrap();
+        database::migrate(&mut connection).unwrap();
+        connection
+    }
+
+    fn run(name: &str, character: &str, minute: u32) -> MapRun {
+        let started_at = Local.ymd(2018, 11, 22).and_hms(5, minute, 0);
+        let mut run = MapRun::new(name.to_string(), started_at);
+        run.ended_at = Some(started_at + Duration::minutes(5));
+        run.character = Some(Character {
+            name: character.to_string(),
+            class: Some("Assassin".to_string()),
+            level: Some(90),
+        });
+        run.drops.push(Drop {
+            id: 0,
+            name: "Exalted Orb".to_string(),
+            count: 1,
+            chaos_value: Some(150.0),
+            dropped_at: started_at,
+            tagged: true,
+            filter_tier: Some("currency t1exalted".to_string()),
+        });
+        run
+    }
+
+    fn session(character: &str) -> SessionRecord {
+        let started_at = Local.ymd(2018, 11, 22).and_hms(4, 55, 0);
+        SessionRecord {
+            started_at,
+            ended_at: started_at + Duration::hours(1),
+            maps_run: 3,
+            chaos_value: 450.0,
+            deaths: 0,
+            characters: vec![Character {
+                name: character.to_string(),
+                class: None,
+                level: None,
+            }],
+        }
+    }
+
+    fn laptop() -> History {
+        History {
+            runs: vec![
+                ("Betrayal".to_string(), run("Dunes", "Nightblade", 0)),
+                // Same time, somebody else.
+                ("Betrayal".to_string(), run("Dunes", "Boxer", 0)),
+                ("Betrayal".to_string(), run("Shaped Cage", "Nightblade", 10)),
+            ],
+            trades: vec![Trade {
+                id: 0,
+                kind: TradeKind::Sale,
+                player: "Buyer".to_string(),
+                request: TradeRequest {
+                    item: "Headhunter".to_string(),
+                    quantity: 1,
+                    price: 2.0,
+                    currency: "exa".to_string(),
+                    league: "Betrayal".to_string(),
+                },
+                requested_at: 1542864000,
+                completed_at: Some(1542864060),
+                chaos_value: Some(300.0),
+            }],
+            sessions: vec![("Betrayal".to_string(), session("Nightblade"))],
+        }
+    }
+
+    #[test]
+    fn should_only_import_what_is_new() {
+        let mut desktop = database();
+        database::save_map_run(&mut desktop, "Betrayal", &run("Dunes", "Nightblade", 0)).unwrap();
+
+        let report = merge(&mut desktop, laptop(), true).unwrap();
+        assert_eq!(report.known_runs, 1);
+        assert_eq!(report.new_runs.len(), 2);
+        assert_eq!(report.new_trades.len(), 1);
+        assert_eq!(report.new_sessions.len(), 1);
+        // A dry run doesn't change anything.
+        let all = HistoryFilter::default();
+        assert_eq!(database::map_runs(&desktop, &all).unwrap().len(), 1);
+
+        merge(&mut desktop, laptop(), false).unwrap();
+        let runs = database::map_runs(&desktop, &all).unwrap();
+        assert_eq!(runs.len(), 3);
+        assert!(runs
+            .iter()
+            .any(|r| r.character == Some("Boxer".to_string())));
+        assert_eq!(database::trades(&desktop, &all).unwrap().len(), 1);
+        assert_eq!(
+            database::sessions(&desktop, &all).unwrap()[0].session,
+            session("Nightblade")
+        );
+
+        let again = merge(&mut desktop, laptop(), false).unwrap();
+        assert!(again.new_runs.is_empty() && again.new_trades.is_empty());
+        assert!(again.new_sessions.is_empty());
+        assert_eq!(
+            (again.known_runs, again.known_trades, again.known_sessions),
+            (3, 1, 1)
+        );
+    }
+
+    #[test]
+    fn should_read_runs_back_from_an_export() {
+        let mut laptop = database();
+        database::save_map_run(&mut laptop, "Betrayal", &run("Dunes", "Nightblade", 0)).unwrap();
+        let export = database::export_map_runs(&laptop, &HistoryFilter::default()).unwrap();
+        let (league, mut imported) = map_run(export[0].clone());
+        assert_eq!(league, "Betrayal");
+        assert!(imported.uuid.is_some());
+        imported.uuid = None;
+        assert_eq!(imported, run("Dunes", "Nightblade", 0));
+    }
+
+    #[test]
+    fn should_read_tables_back_from_csv_and_json_lines() {
+        let mut other = database();
+        let mut dunes = run("Dunes", "Nightblade", 0);
+        dunes.mods = vec![
+            "+20% Monster Damage".to_string(),
+            "Area has patches of Shocked Ground".to_string(),
+        ];
+        database::save_map_run(&mut other, "Betrayal", &dunes).unwrap();
+        database::save_map_run(&mut other, "Betrayal", &run("Strand", "Boxer", 10)).unwrap();
+        database::save_trade(&other, &laptop().trades[0]).unwrap();
+        database::save_session(&mut other, "Betrayal", &session("Nightblade")).unwrap();
+        database::save_session(&mut other, "Standard", &session("Boxer")).unwrap();
+
+        let directory = env::temp_dir().join(format!(
+            "atlas-of-beancounting-import-test-{}",
+            process::id()
+        ));
+        let exports = vec![
+            (ExportTable::Runs, ExportFormat::Csv),
+            (ExportTable::Drops, ExportFormat::Ndjson),
+            (ExportTable::Trades, ExportFormat::Csv),
+            (ExportTable::Sessions, ExportFormat::Ndjson),
+        ];
+        let paths: Vec<PathBuf> = exports
+            .into_iter()
+            .map(|(table, format)| {
+                let path = directory.join(format!("{}.{}", table.name(), format.name()));
+                let all = HistoryFilter::default();
+                export_to_file(&other, table, format, &all, None, &path).unwrap();
+                path
+            })
+            .collect();
+        let nightblade = HistoryFilter {
+            character: Some("Nightblade".to_string()),
+            ..HistoryFilter::default()
+        };
+        let history = read_history(&paths, &nightblade).unwrap();
+        // Runs without their drops would lose what they're worth.
+        let without_drops = read_history(&paths[..1], &nightblade);
+        let _ = fs::remove_dir_all(&directory);
+
+        assert_eq!(history.runs.len(), 1);
+        let (ref league, ref imported) = history.runs[0];
+        assert_eq!(league, "Betrayal");
+        assert_eq!(imported.uuid, map_runs_of(&other)[1].uuid);
+        let mut imported = imported.clone();
+        imported.uuid = None;
+        assert_eq!(imported, dunes);
+        assert_eq!(history.trades, laptop().trades);
+        assert_eq!(history.sessions, laptop().sessions);
+        match without_drops {
+            Err(ImportError::Unreadable(_)) => {}
+            other => panic!("Expected the drops to be missing, got {:?}", other.is_ok()),
+        }
+    }
+
+    #[test]
+    fn should_know_an_export_of_no_drops_by_its_header() {
+        let mut other = database();
+        let mut dunes = run("Dunes", "Nightblade", 0);
+        dunes.drops.clear();
+        database::save_map_run(&mut other, "Betrayal", &dunes).unwrap();
+
+        let directory = env::temp_dir().join(format!(
+            "atlas-of-beancounting-import-empty-test-{}",
+            process::id()
+        ));
+        let paths: Vec<PathBuf> = [ExportTable::Runs, ExportTable::Drops]
+            .iter()
+            .map(|&table| {
+                let path = directory.join(format!("{}.csv", table.name()));
+                let all = HistoryFilter::default();
+                export_to_file(&other, table, ExportFormat::Csv, &all, None, &path).unwrap();
+                path
+            })
+            .collect();
+        let history = read_history(&paths, &HistoryFilter::default());
+        let _ = fs::remove_dir_all(&directory);
+
+        let history = history.unwrap();
+        assert_eq!(history.runs.len(), 1);
+        assert!(history.runs[0].1.drops.is_empty());
+    }
+
+    fn map_runs_of(connection: &Connection) -> Vec<::types::history::MapRunSummary> {
+        database::map_runs(connection, &HistoryFilter::default()).unwrap()
+    }
+
+    #[test]
+    fn should_import_all_or_nothing() {
+        let mut desktop = database();
+        let mut twice = laptop();
+        let dunes = twice.runs[0].clone();
+        twice.runs.push(dunes);
+
+        let report = merge(&mut desktop, twice, true).unwrap();
+        assert_eq!((report.new_runs.len(), report.known_runs), (3, 1));
+        assert!(map_runs_of(&desktop).is_empty());
+        assert!(database::trades(&desktop, &HistoryFilter::default())
+            .unwrap()
+            .is_empty());
+
+        merge(&mut desktop, laptop(), false).unwrap();
+        // Runs that came from elsewhere are still the same run here.
+        let mut moved = History {
+            runs: database::export_map_runs(&desktop, &HistoryFilter::default())
+                .unwrap()
+                .into_iter()
+                .map(map_run)
+                .collect(),
+            trades: Vec::new(),
+            sessions: Vec::new(),
+        };
+        for &mut (_, ref mut run) in &mut moved.runs {
+            run.started_at = run.started_at + Duration::seconds(1);
+        }
+        let again = merge(&mut desktop, moved, false).unwrap();
+        assert_eq!((again.new_runs.len(), again.known_runs), (0, 3));
+    }
+}
@@ -0,0 +1,114 @@
+//! The parts of Atlas of Beancounting that are useful outside of it: reading
+//! the tooltips the game puts on the clipboard, finding the poe.ninja price of
+//! what came out, and reading Client.txt.
+//!
+//! ```
+//! use atlas_of_beancounting::{parse_tooltip, resolve_price_key, Item};
+//!
+//! let tooltip = "Rarity: Currency\n\
+//!                Chaos Orb\n\
+//!                --------\n\
+//!                Stack Size: 7/10\n\
+//!                --------\n\
+//!                Reforges a rare item with new random modifiers\n\
+//!                --------\n\
+//!                Right click this item then left click a rare item to apply it.";
+//! let item = parse_tooltip(tooltip).unwrap();
+//! match item {
+//!     Item::Currency(ref orb) => assert_eq!(orb.stack_size.current, 7),
+//!     _ => panic!("Not a currency item"),
+//! }
+//! assert_eq!(resolve_price_key(&item).name, "Chaos Orb");
+//! ```
+//!
+//! Only what's re-exported here, and the `types` it uses, is meant to be
+//! relied on. The other modules are the application around it and change
+//! whenever it needs them to. They're behind the `app` feature, so with
+//! `default-features = false` there's only the library, and none of what the
+//! application needs.
+#![allow(dead_code)]
+extern crate chrono;
+#[cfg(feature = "app")]
+extern crate clipboard;
+#[cfg(feature = "app")]
+extern crate dirs;
+#[cfg(feature = "app")]
+extern crate fern;
+#[cfg(feature = "app")]
+extern crate notify;
+extern crate regex;
+#[cfg(feature = "app")]
+extern crate reqwest;
+#[cfg(feature = "app")]
+extern crate rusqlite;
+extern crate serde;
+extern crate serde_json;
+#[cfg(feature = "app")]
+extern crate toml;
+#[cfg(feature = "app")]
+extern crate web_view;
+#[cfg(all(feature = "app", target_os = "linux"))]
+extern crate xcb;
+#[macro_use] extern crate lazy_static;
+#[macro_use] extern crate log;
+#[macro_use] extern crate serde_derive;
+
+pub mod log_watcher;
+pub mod price_key;
+pub mod tooltip_parser;
+pub mod types;
+
+#[cfg(feature = "app")]
+#[doc(hidden)]
+pub mod clipboard_source;
+#[cfg(feature = "app")]
+#[doc(hidden)]
+pub mod clock;
+#[cfg(feature = "app")]
+#[doc(hidden)]
+pub mod config;
+#[doc(hidden)]
+pub mod constants;
+#[cfg(feature = "app")]
+#[doc(hidden)]
+pub mod database;
+#[cfg(feature = "app")]
+#[doc(hidden)]
+pub mod dedup;
+#[cfg(feature = "app")]
+#[doc(hidden)]
+pub mod export;
+#[cfg(feature = "app")]
+#[doc(hidden)]
+pub mod frontend;
+#[cfg(feature = "app")]
+#[doc(hidden)]
+pub mod hotkeys;
+#[cfg(feature = "app")]
+#[doc(hidden)]
+pub mod import;
+#[cfg(feature = "app")]
+#[doc(hidden)]
+pub mod logging;
+#[cfg(feature = "app")]
+#[doc(hidden)]
+pub mod loot_filter;
+#[cfg(feature = "app")]
+#[doc(hidden)]
+pub mod mastermind;
+#[cfg(feature = "app")]
+#[doc(hidden)]
+pub mod supervisor;
+#[cfg(feature = "app")]
+#[doc(hidden)]
+pub mod trade;
+#[cfg(feature = "app")]
+#[doc(hidden)]
+pub mod web_client;
+
+pub use log_watcher::parse_log_line;
+pub use price_key::{normalise_name, resolve_price_key, PriceCache};
+pub use tooltip_parser::parse_tooltip;
+pub use types::item::Item;
+pub use types::pricing::{ItemKind, MatchQuality, Price, PriceKey};
+pub use types::zone_event::ZoneEvent;
@@ -1,5 +1,10 @@
+use chrono::prelude::*;
+#[cfg(feature = "app")]
 use notify::{raw_watcher, RawEvent, RecursiveMode, Watcher};
+use regex::Regex;
+use std::env;
 use std::fs::File;
+use std::io::BufRead;
 use std::io::Error;
 use std::io::ErrorKind;
 use std::io::Read;
@@ -7,61 +12,324 @@ use std::io::Seek;
 use std::io::SeekFrom;
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::mpsc::channel;
-use std::sync::mpsc::Sender;
+#[cfg(feature = "app")]
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+#[cfg(feature = "app")]
+use std::time::Duration;
+#[cfg(feature = "app")]
+use supervisor::Shutdown;
+use types::trade::{Direction, Whisper};
 use types::zone_event::ZoneEvent;
 
-pub fn watch_zone_log(_s: Sender<ZoneEvent>) -> ! {
+/// How long to wait for the log to change before checking for shutdown.
+#[cfg(feature = "app")]
+const SHUTDOWN_CHECK_MS: u64 = 500;
+
+/// Send an event for everything in Client.txt we care about, from now on.
+/// Returns on shutdown, or once nobody listens to `s` anymore.
+#[cfg(feature = "app")]
+pub fn watch_zone_log(s: &Sender<ZoneEvent>, shutdown: &Shutdown) -> () {
     let (watcher_sender, watcher_receiver) = channel();
-    let mut watcher = raw_watcher(watcher_sender).unwrap();
-    let mut log_offset = 0 as u64;
+    let mut watcher = raw_watcher(watcher_sender).expect("Can't start file watcher");
     let filepath = guess_event_path().expect("Can't start watcher without event log.");
+    // Only what happens from now on is interesting, the history is not ours.
+    let mut log_offset = filepath
+        .metadata()
+        .expect("Can't read size of event log")
+        .len();
 
     watcher
         .watch(filepath.clone(), RecursiveMode::NonRecursive)
-        .unwrap();
+        .expect("Can't watch event log");
 
-    loop {
-        match watcher_receiver.recv() {
+    let tick = Duration::from_millis(SHUTDOWN_CHECK_MS);
+    while !shutdown.is_requested() {
+        match watcher_receiver.recv_timeout(tick) {
             Ok(RawEvent {
-                path: Some(path),
-                op: Ok(op),
-                cookie,
+                path: Some(_path),
+                op: Ok(_op),
+                ..
             }) => {
-                println!("{:?}, {:?}, {:?}: Raw event received", path, op, cookie);
-                let (last_line, new_offset) = get_last_line_of_log(filepath.as_path(), log_offset)
-                    .expect("Couldn't read from file");
+                // Whatever couldn't be read is still there the next time.
+                let (new_lines, new_offset) =
+                    match get_new_lines_of_log(filepath.as_path(), log_offset) {
+                        Ok(new) => new,
+                        Err(e) => {
+                            warn!("Can't read {}: {}", filepath.display(), e);
+                            continue;
+                        }
+                    };
                 log_offset = new_offset;
 
-                println!("New last line from log: {}", last_line);
+                for event in new_lines.iter().filter_map(|line| parse_log_line(line)) {
+                    if s.send(event).is_err() {
+                        return;
+                    }
+                }
             }
-            Ok(event) => println!("Got broken event: {:?}", event),
-            Err(e) => println!("Caught error {:?}", e),
+            Ok(event) => warn!("Got broken event: {:?}", event),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => panic!("File watcher went away"),
         }
     }
 }
 
+/// Turn a line of Client.txt into an event, if it's one we care about. Lines
+/// look like this:
+///
+/// `2018/11/22 05:30:12 123456789 9c4 [INFO Client 1234] : You have entered Shaped Cage.`
+pub fn parse_log_line(line: &str) -> Option<ZoneEvent> {
+    lazy_static! {
+        static ref LOG_LINE: Regex =
+            Regex::new(r"^(\d{4}/\d{2}/\d{2} \d{2}:\d{2}:\d{2}) \d+ \w+ \[\w+ Client \d+\] (.*)$")
+                .unwrap();
+        static ref ZONE_CHANGE: Regex = Regex::new(r"^: You have entered (.+)\.$").unwrap();
+        static ref DEATH: Regex = Regex::new(r"^: (.+) has been slain\.$").unwrap();
+        static ref GENERATING: Regex =
+            Regex::new(r#"^Generating level \d+ area "[^"]*"(?: with seed (\d+))?"#).unwrap();
+        static ref AFK: Regex = Regex::new(r"^: AFK mode is now (ON|OFF)\.").unwrap();
+        static ref LEVEL_UP: Regex = Regex::new(r"^: (.+) \((\w+)\) is now level (\d+)$").unwrap();
+        static ref WHISPER: Regex = Regex::new(r"^@(From|To) (?:<[^>]*> )?([^:]+): (.*)$").unwrap();
+        static ref JOINED: Regex = Regex::new(r"^: (.+) has joined the area\.$").unwrap();
+    }
+
+    let captures = LOG_LINE.captures(line.trim_end())?;
+    let timestamp = Local
+        .datetime_from_str(&captures[1], "%Y/%m/%d %H:%M:%S")
+        .ok()?;
+    let message = &captures[2];
+
+    if let Some(zone) = ZONE_CHANGE.captures(message) {
+        return Some(ZoneEvent::ZoneChange(zone[1].to_string(), timestamp));
+    }
+    if let Some(death) = DEATH.captures(message) {
+        return Some(ZoneEvent::Death(death[1].to_string(), timestamp));
+    }
+    if let Some(level_up) = LEVEL_UP.captures(message) {
+        return Some(ZoneEvent::LevelUp(
+            level_up[1].to_string(),
+            level_up[2].to_string(),
+            level_up[3].parse().ok()?,
+            timestamp,
+        ));
+    }
+    if let Some(whisper) = WHISPER.captures(message) {
+        let direction = if &whisper[1] == "From" {
+            Direction::Incoming
+        } else {
+            Direction::Outgoing
+        };
+        return Some(ZoneEvent::Whisper(
+            Whisper {
+                direction,
+                player: whisper[2].to_string(),
+                message: whisper[3].to_string(),
+            },
+            timestamp,
+        ));
+    }
+    if let Some(joined) = JOINED.captures(message) {
+        return Some(ZoneEvent::JoinedArea(joined[1].to_string(), timestamp));
+    }
+    if message == ": Trade accepted." {
+        return Some(ZoneEvent::TradeAccepted(timestamp));
+    }
+    if let Some(generating) = GENERATING.captures(message) {
+        let seed = generating
+            .get(1)
+            .and_then(|seed| seed.as_str().parse().ok());
+        return Some(ZoneEvent::LoadingStarted(seed, timestamp));
+    }
+    AFK.captures(message)
+        .map(|afk| ZoneEvent::Afk(&afk[1] == "ON", timestamp))
+}
+
+/// Every event in a whole log, like an old Client.txt.
+pub fn parse_log<R: BufRead>(log: R) -> impl Iterator<Item = ZoneEvent> {
+    // Client.txt isn't always valid UTF-8, chat messages get mangled.
+    log.split(b'\n')
+        .filter_map(|line| line.ok())
+        .filter_map(|line| parse_log_line(&String::from_utf8_lossy(&line)))
+}
+
 /// We don't actually know where on the system the log file is, so we're gonna
 /// take some educated guesses and give up if we're wrong.
 /// 1. Steam path on windows.
 /// 2. Standalone launcher on windows.
+/// 3. Steam path on Linux, for Proton.
+///
 /// If it's not there, I'm not sure where to find it, pull requests/issues appreciated.
-fn guess_event_path() -> Result<PathBuf, Error> {
-    Err(Error::new(ErrorKind::Other, "Not implemented"))
+pub fn guess_event_path() -> Result<PathBuf, Error> {
+    let mut candidates = vec![
+        PathBuf::from(
+            r"C:\Program Files (x86)\Steam\steamapps\common\Path of Exile\logs\Client.txt",
+        ),
+        PathBuf::from(r"C:\Program Files (x86)\Grinding Gear Games\Path of Exile\logs\Client.txt"),
+    ];
+    if let Some(home) = env::var_os("HOME") {
+        candidates.push(
+            PathBuf::from(home).join(".steam/steam/steamapps/common/Path of Exile/logs/Client.txt"),
+        );
+    }
+
+    candidates
+        .into_iter()
+        .find(|path| path.exists())
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "Can't find Client.txt"))
 }
 
-/// Get the last line of a file efficiently.  This returns an offset that is
-/// intended to be fed back into the function after getting a WRITE event from
-/// our watcher.
-/// TODO: This assumes the last line gotten will always be a single one, but that's
-/// not always the case. It needs to be the last line of the new lines we haven't seen yet.
-fn get_last_line_of_log(file: &Path, offset: u64) -> Result<(String, u64), Error> {
+/// Get the lines written to a file since `offset`. This returns an offset that
+/// is intended to be fed back into the function after getting a WRITE event
+/// from our watcher. A line that is still being written is left for next time.
+/// Like in `parse_log`, mangled chat messages don't stop the rest.
+fn get_new_lines_of_log(file: &Path, offset: u64) -> Result<(Vec<String>, u64), Error> {
     let mut file = File::open(&file)?;
     file.seek(SeekFrom::Start(offset))?;
 
-    let mut last_line = String::new();
-    let bytes_read = file.read_to_string(&mut last_line)?;
-    let new_offset = offset + bytes_read as u64;
+    let mut new_bytes = Vec::new();
+    file.read_to_end(&mut new_bytes)?;
+
+    let complete = match new_bytes.iter().rposition(|&b| b == b'\n') {
+        Some(index) => &new_bytes[..index + 1],
+        None => &[],
+    };
+    let new_offset = offset + complete.len() as u64;
+
+    Ok((
+        String::from_utf8_lossy(complete)
+            .lines()
+            .map(|l| l.to_string())
+            .collect(),
+        new_offset,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_parse_zone_changes() {
+        let line =
+            "2018/11/22 05:30:12 123456789 9c4 [INFO Client 1234] : You have entered Shaped Cage.";
+        match parse_log_line(line) {
+            Some(ZoneEvent::ZoneChange(zone, timestamp)) => {
+                assert_eq!(zone, "Shaped Cage".to_string());
+                assert_eq!(timestamp, Local.ymd(2018, 11, 22).and_hms(5, 30, 12));
+            }
+            other => panic!("Expected zone change, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_parse_deaths() {
+        let line =
+            "2018/11/22 05:31:40 123456789 9c4 [INFO Client 1234] : Nightblade has been slain.";
+        match parse_log_line(line) {
+            Some(ZoneEvent::Death(character, _)) => assert_eq!(character, "Nightblade"),
+            other => panic!("Expected death, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_parse_loading_screens_and_afk() {
+        let line = "2018/11/22 05:30:10 123456789 9c4 [DEBUG Client 1234] Generating level 73 area \"MapWorldsShapedCage\" with seed 2591735637";
+        match parse_log_line(line) {
+            Some(ZoneEvent::LoadingStarted(seed, timestamp)) => {
+                assert_eq!(seed, Some(2591735637));
+                assert_eq!(timestamp, Local.ymd(2018, 11, 22).and_hms(5, 30, 10))
+            }
+            other => panic!("Expected loading screen, got {:?}", other),
+        }
+        let line = "2018/11/22 05:40:00 123456789 9c4 [INFO Client 1234] : AFK mode is now ON. Autoreply \"This player is AFK.\"";
+        match parse_log_line(line) {
+            Some(ZoneEvent::Afk(true, _)) => {}
+            other => panic!("Expected AFK, got {:?}", other),
+        }
+        let line = "2018/11/22 05:45:00 123456789 9c4 [INFO Client 1234] : AFK mode is now OFF.";
+        match parse_log_line(line) {
+            Some(ZoneEvent::Afk(false, _)) => {}
+            other => panic!("Expected back from AFK, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_parse_level_ups() {
+        let line = "2018/11/22 05:32:00 123456789 9c4 [INFO Client 1234] : Nightblade (Assassin) is now level 91";
+        match parse_log_line(line) {
+            Some(ZoneEvent::LevelUp(character, class, level, _)) => {
+                assert_eq!(character, "Nightblade");
+                assert_eq!(class, "Assassin");
+                assert_eq!(level, 91);
+            }
+            other => panic!("Expected level up, got {:?}", other),
+        }
+    }
 
-    Ok((last_line, new_offset))
+    #[test]
+    fn should_parse_whispers_and_trades() {
+        let line = "2018/11/22 05:50:00 123456789 9c4 [INFO Client 1234] @From <GGG> Buyer: Hi, I would like to buy your Tabula Rasa listed for 8 chaos in Betrayal";
+        match parse_log_line(line) {
+            Some(ZoneEvent::Whisper(whisper, _)) => {
+                assert_eq!(whisper.direction, Direction::Incoming);
+                assert_eq!(whisper.player, "Buyer");
+                assert!(whisper.message.starts_with("Hi, I would like"));
+            }
+            other => panic!("Expected whisper, got {:?}", other),
+        }
+        let line = "2018/11/22 05:50:10 123456789 9c4 [INFO Client 1234] @To Seller: ty";
+        match parse_log_line(line) {
+            Some(ZoneEvent::Whisper(whisper, _)) => {
+                assert_eq!(whisper.direction, Direction::Outgoing)
+            }
+            other => panic!("Expected whisper, got {:?}", other),
+        }
+        let line = "2018/11/22 05:51:00 123456789 9c4 [INFO Client 1234] : Buyer has joined the area.";
+        match parse_log_line(line) {
+            Some(ZoneEvent::JoinedArea(player, _)) => assert_eq!(player, "Buyer"),
+            other => panic!("Expected somebody joining, got {:?}", other),
+        }
+        let line = "2018/11/22 05:51:30 123456789 9c4 [INFO Client 1234] : Trade accepted.";
+        match parse_log_line(line) {
+            Some(ZoneEvent::TradeAccepted(_)) => {}
+            other => panic!("Expected trade, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_handle_windows_line_endings() {
+        let line =
+            "2018/11/22 05:30:12 123456789 9c4 [INFO Client 1234] : You have entered Hideout.\r";
+        assert!(parse_log_line(line).is_some());
+    }
+
+    #[test]
+    fn should_ignore_other_lines() {
+        assert!(parse_log_line("").is_none());
+        assert!(parse_log_line("***** LOG FILE OPENING *****").is_none());
+        assert!(
+            parse_log_line(
+                "2018/11/22 05:30:12 123456789 9c4 [INFO Client 1234] Connecting to instance server at 1.2.3.4:6112"
+            ).is_none()
+        );
+    }
+
+    #[test]
+    fn should_read_new_lines_that_are_not_utf8() {
+        use std::fs;
+
+        let file = env::temp_dir().join(format!(
+            "atlas-of-beancounting-log-test-{}.txt",
+            ::std::process::id()
+        ));
+        let mut log = b"old line\n".to_vec();
+        log.extend_from_slice(b"@From Buyer: caf\xe9?\nnew line\nhalf a li");
+        fs::write(&file, &log).unwrap();
+
+        let (lines, offset) = get_new_lines_of_log(&file, 9).unwrap();
+        let _ = fs::remove_file(&file);
+
+        assert_eq!(lines, vec!["@From Buyer: caf\u{fffd}?", "new line"]);
+        assert_eq!(offset, log.len() as u64 - "half a li".len() as u64);
+    }
 }
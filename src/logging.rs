@@ -0,0 +1,304 @@
+//! Everything logs through the `log` macros. This decides where it ends up:
+//! stderr, a log file in the data directory that's moved aside when it gets
+//! big, and the log viewer in the frontend once one is attached. How much gets
+//! logged comes from the config, and changes with it, see `configure`.
+use chrono::prelude::*;
+use dirs;
+use fern;
+use log::{self, LevelFilter, Metadata, Record};
+use std::cmp::Reverse;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Mutex, RwLock};
+
+use constants::{APP_DIR_NAME, LOG_FILE, LOG_FILES_KEPT, LOG_FILE_MAX_BYTES};
+use types::config::Config;
+use types::frontend_communication::FrontendMessage;
+use types::logging::LogEntry;
+
+/// Our own modules log with targets starting with this. `log_filters` in the
+/// config can leave it off.
+const CRATE_PREFIX: &str = "atlas_of_beancounting::";
+
+lazy_static! {
+    static ref LEVELS: RwLock<Levels> = RwLock::new(Levels::default());
+    static ref FRONTEND: Mutex<Option<Sender<FrontendMessage>>> = Mutex::new(None);
+}
+
+/// The log level of every module, as configured.
+#[derive(Debug, PartialEq)]
+struct Levels {
+    default: LevelFilter,
+    /// Most specific module first, so the first match wins.
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Default for Levels {
+    fn default() -> Levels {
+        Levels {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+        }
+    }
+}
+
+impl Levels {
+    /// Levels that don't parse are left out, `config::validate` complains
+    /// about those.
+    fn from_config(config: &Config) -> Levels {
+        let mut modules: Vec<(String, LevelFilter)> = config
+            .log_filters
+            .iter()
+            .filter_map(|(module, level)| level.parse().ok().map(|level| (module.clone(), level)))
+            .collect();
+        modules.sort_by_key(|(module, _)| Reverse(module.len()));
+        Levels {
+            default: config.log_level.parse().unwrap_or(LevelFilter::Info),
+            modules,
+        }
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .find(|&(module, _)| {
+                covers(module, target) || covers(&format!("{}{}", CRATE_PREFIX, module), target)
+            })
+            .map_or(self.default, |&(_, level)| level)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|&(_, level)| level)
+            .fold(
+                self.default,
+                |max, level| if level > max { level } else { max },
+            )
+    }
+}
+
+/// Whether `target` is `module`, or one of its submodules.
+fn covers(module: &str, target: &str) -> bool {
+    target.starts_with(module)
+        && (target.len() == module.len() || target[module.len()..].starts_with("::"))
+}
+
+/// Where the log file goes.
+pub fn log_path() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(APP_DIR_NAME)
+        .join(LOG_FILE)
+}
+
+/// Start logging to stderr and, if given, to `file`. Levels are taken from
+/// `config`. Panics are logged too, so they make it into the file.
+pub fn init(config: &Config, file: Option<&Path>) -> Result<(), fern::InitError> {
+    let mut output = fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "{} {:<5} {}: {}",
+                Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                record.target(),
+                message
+            ))
+        })
+        .chain(io::stderr());
+    if let Some(file) = file {
+        let file = RotatingFile::open(file, LOG_FILE_MAX_BYTES, LOG_FILES_KEPT)?;
+        output = output.chain(fern::Output::writer(Box::new(file), "\n"));
+    }
+
+    fern::Dispatch::new()
+        .filter(enabled)
+        .chain(output)
+        .chain(fern::Output::call(send_to_frontend))
+        .apply()?;
+    configure(config);
+
+    // Whatever hook was there before still gets its say, backtraces included.
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        error!(target: "panic", "{}", info);
+        previous(info);
+    }));
+    Ok(())
+}
+
+/// Use the log levels in `config` from now on.
+pub fn configure(config: &Config) -> () {
+    let levels = Levels::from_config(config);
+    log::set_max_level(levels.max());
+    match LEVELS.write() {
+        Ok(mut current) => *current = levels,
+        Err(poisoned) => *poisoned.into_inner() = levels,
+    }
+}
+
+/// Send everything that's logged to the log viewer too, until `detach_frontend`.
+pub fn attach_frontend(frontend: Sender<FrontendMessage>) -> () {
+    if let Ok(mut current) = FRONTEND.lock() {
+        *current = Some(frontend);
+    }
+}
+
+/// Let go of the frontend, so it can tell that everybody hung up.
+pub fn detach_frontend() -> () {
+    if let Ok(mut current) = FRONTEND.lock() {
+        *current = None;
+    }
+}
+
+fn enabled(metadata: &Metadata) -> bool {
+    match LEVELS.read() {
+        Ok(levels) => metadata.level() <= levels.level_for(metadata.target()),
+        Err(_) => true,
+    }
+}
+
+fn send_to_frontend(record: &Record) -> () {
+    let frontend = match FRONTEND.lock() {
+        Ok(frontend) => frontend,
+        Err(_) => return,
+    };
+    if let Some(ref frontend) = *frontend {
+        // The window being gone is no reason to stop logging elsewhere.
+        let _ = frontend.send(FrontendMessage::Log {
+            entry: LogEntry {
+                timestamp: Local::now().timestamp(),
+                level: record.level().to_string(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            },
+        });
+    }
+}
+
+/// A file that's appended to until it's grown past `max_bytes`, then moved to
+/// `<path>.1`, with the older ones moving up to `<path>.<kept>`.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_bytes: u64,
+    kept: u32,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_bytes: u64, kept: u32) -> io::Result<RotatingFile> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file,
+            written,
+            max_bytes,
+            kept,
+        })
+    }
+
+    fn rotated_path(&self, generation: u32) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(format!(".{}", generation));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for generation in (1..self.kept).rev() {
+            let from = self.rotated_path(generation);
+            if from.exists() {
+                fs::rename(from, self.rotated_path(generation + 1))?;
+            }
+        }
+        if self.kept > 0 {
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        self.file = File::create(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    /// fern flushes after every line, so rotating here never splits one.
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.written >= self.max_bytes {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn should_pick_most_specific_level() {
+        let config = Config {
+            log_level: "warn".to_string(),
+            log_filters: vec![
+                ("web_client".to_string(), "debug".to_string()),
+                ("reqwest".to_string(), "error".to_string()),
+                ("reqwest::async_impl".to_string(), "trace".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            ..Config::default()
+        };
+        let levels = Levels::from_config(&config);
+
+        assert_eq!(
+            levels.level_for("atlas_of_beancounting::web_client"),
+            LevelFilter::Debug
+        );
+        assert_eq!(
+            levels.level_for("atlas_of_beancounting::mastermind"),
+            LevelFilter::Warn
+        );
+        assert_eq!(levels.level_for("reqwest::connect"), LevelFilter::Error);
+        assert_eq!(
+            levels.level_for("reqwest::async_impl::client"),
+            LevelFilter::Trace
+        );
+        // Only whole module names count.
+        assert_eq!(levels.level_for("reqwestish"), LevelFilter::Warn);
+        assert_eq!(levels.max(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn should_rotate_full_files() {
+        let directory = env::temp_dir().join(format!("beancounting-log-{}", ::std::process::id()));
+        let path = directory.join("test.log");
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+
+        for line in &["first line", "second line", "third line", "fourth"] {
+            writeln!(file, "{}", line).unwrap();
+            file.flush().unwrap();
+        }
+
+        let read = |path: PathBuf| fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "fourth\n");
+        assert_eq!(read(file.rotated_path(1)), "third line\n");
+        assert_eq!(read(file.rotated_path(2)), "second line\n");
+        assert!(!file.rotated_path(3).exists());
+        let _ = fs::remove_dir_all(directory);
+    }
+}
@@ -0,0 +1,779 @@
+//! Loot filters, the `.filter` files that decide how the game shows drops.
+//! NeverSink's and the ones made like it sort everything into tiers, which
+//! say more about a drop than its name. This reads the Show and Hide blocks of
+//! one and finds the block a copied `Item` falls into. Conditions on what the
+//! tooltip parser doesn't know about, like influence or drop level, never
+//! match, so such items end up in the next block down that doesn't ask.
+//!
+//! The tooltip parser doesn't make `Item::Gear` yet, so classes by base type,
+//! sockets and links only ever come into it for uniques, and for gear once
+//! that's parsed.
+use std::fs;
+use std::path::Path;
+
+use price_key::resolve_price_key;
+use types::item::{Item, ItemRarity};
+use types::loot_filter::FilterTier;
+use types::pricing::ItemKind;
+
+/// In the order `Rarity >= Magic` and the like go by.
+const RARITIES: &[&str] = &["Normal", "Magic", "Rare", "Unique"];
+
+/// Longest first, so `<=` isn't read as `<`.
+const OPERATORS: &[(&str, Operator)] = &[
+    ("==", Operator::Exact),
+    ("!=", Operator::NotEqual),
+    ("<=", Operator::LessOrEqual),
+    (">=", Operator::GreaterOrEqual),
+    ("=", Operator::Equal),
+    ("!", Operator::NotEqual),
+    ("<", Operator::Less),
+    (">", Operator::Greater),
+];
+
+/// Lines that change how a drop looks or sounds rather than which ones a
+/// block is for. The colours are kept, the rest doesn't matter here.
+const ACTIONS: &[&str] = &[
+    "Set",
+    "Play",
+    "MinimapIcon",
+    "CustomAlertSound",
+    "DisableDropSound",
+    "EnableDropSound",
+];
+
+/// Item classes by how their base types end, more specific endings first.
+/// One- and two-handed weapons share theirs, so those are left out.
+const CLASSES: &[(&str, &str)] = &[
+    ("Eye Jewel", "Abyss Jewels"),
+    ("Jewel", "Jewels"),
+    ("Life Flask", "Life Flasks"),
+    ("Mana Flask", "Mana Flasks"),
+    ("Hybrid Flask", "Hybrid Flasks"),
+    ("Flask", "Utility Flasks"),
+    ("Ring", "Rings"),
+    ("Amulet", "Amulets"),
+    ("Talisman", "Amulets"),
+    ("Belt", "Belts"),
+    ("Sash", "Belts"),
+    ("Vise", "Belts"),
+    ("Quiver", "Quivers"),
+    ("Circlet", "Helmets"),
+    ("Helmet", "Helmets"),
+    ("Helm", "Helmets"),
+    ("Hood", "Helmets"),
+    ("Mask", "Helmets"),
+    ("Crown", "Helmets"),
+    ("Cap", "Helmets"),
+    ("Hat", "Helmets"),
+    ("Tricorne", "Helmets"),
+    ("Bascinet", "Helmets"),
+    ("Burgonet", "Helmets"),
+    ("Gloves", "Gloves"),
+    ("Gauntlets", "Gloves"),
+    ("Mitts", "Gloves"),
+    ("Boots", "Boots"),
+    ("Greaves", "Boots"),
+    ("Slippers", "Boots"),
+    ("Shoes", "Boots"),
+    ("Shield", "Shields"),
+    ("Buckler", "Shields"),
+    ("Plate", "Body Armours"),
+    ("Regalia", "Body Armours"),
+    ("Robe", "Body Armours"),
+    ("Vestment", "Body Armours"),
+    ("Garb", "Body Armours"),
+    ("Vest", "Body Armours"),
+    ("Coat", "Body Armours"),
+    ("Jerkin", "Body Armours"),
+    ("Leather", "Body Armours"),
+    ("Brigandine", "Body Armours"),
+    ("Hauberk", "Body Armours"),
+    ("Chainmail", "Body Armours"),
+    ("Ringmail", "Body Armours"),
+    ("Raiment", "Body Armours"),
+    ("Silks", "Body Armours"),
+    ("Wrap", "Body Armours"),
+    ("Bow", "Bows"),
+    ("Wand", "Wands"),
+    ("Staff", "Staves"),
+    ("Claw", "Claws"),
+    ("Dagger", "Daggers"),
+    ("Sceptre", "Sceptres"),
+];
+
+pub struct LootFilter {
+    blocks: Vec<Block>,
+}
+
+struct Block {
+    hidden: bool,
+    /// From NeverSink's comment on the `Show` line.
+    tier: Option<String>,
+    conditions: Vec<Condition>,
+    text_colour: Option<String>,
+    border_colour: Option<String>,
+    background_colour: Option<String>,
+    /// Matching goes on with the blocks below, which can change the look.
+    continues: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    /// For text, that a value is part of it.
+    Equal,
+    /// For text, that a value is all of it.
+    Exact,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Attribute {
+    Class,
+    BaseType,
+    Rarity,
+    ItemLevel,
+    StackSize,
+    LinkedSockets,
+    Sockets,
+    Quality,
+    MapTier,
+    /// Anything else, which never matches.
+    Unknown,
+}
+
+struct Condition {
+    attribute: Attribute,
+    operator: Operator,
+    values: Vec<String>,
+}
+
+/// What the tooltip says about an item, as far as filters are concerned.
+/// `None` is for what it doesn't say.
+#[derive(Default)]
+struct Facts {
+    class: Option<&'static str>,
+    base_type: Option<String>,
+    rarity: Option<u32>,
+    item_level: Option<u32>,
+    stack_size: u32,
+    linked_sockets: Option<u32>,
+    sockets: Option<u32>,
+    quality: Option<u32>,
+    map_tier: Option<u32>,
+}
+
+impl LootFilter {
+    pub fn load(path: &Path) -> Result<LootFilter, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Can't open {}: {}", path.display(), e))?;
+        LootFilter::parse(&text).map_err(|e| format!("Can't parse {}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<LootFilter, String> {
+        let mut blocks: Vec<Block> = Vec::new();
+        for (number, line) in text.trim_start_matches('\u{feff}').lines().enumerate() {
+            let (code, comment) = match line.find('#') {
+                Some(start) => (&line[..start], &line[start + 1..]),
+                None => (line, ""),
+            };
+            let words = words(code);
+            let keyword = match words.first() {
+                Some(keyword) => keyword.as_str(),
+                None => continue,
+            };
+            match keyword {
+                "Show" | "Hide" | "Minimal" => blocks.push(Block::new(keyword == "Hide", comment)),
+                _ => blocks
+                    .last_mut()
+                    .ok_or_else(|| {
+                        format!(
+                            "line {}: {} outside of a Show or Hide block",
+                            number + 1,
+                            keyword
+                        )
+                    })?
+                    .add(keyword, &words[1..]),
+            }
+        }
+        if blocks.is_empty() {
+            return Err("there are no Show or Hide blocks in it".to_string());
+        }
+        Ok(LootFilter { blocks })
+    }
+
+    /// How the game would show `item`, or `None` if no block is for it, in
+    /// which case it's shown plain.
+    pub fn classify(&self, item: &Item) -> Option<FilterTier> {
+        let facts = Facts::of(item);
+        let mut shown: Option<FilterTier> = None;
+        for block in &self.blocks {
+            if !block.conditions.iter().all(|c| c.matches(&facts)) {
+                continue;
+            }
+            let mut tier = shown.take().unwrap_or_else(|| FilterTier {
+                tier: String::new(),
+                hidden: false,
+                text_colour: None,
+                border_colour: None,
+                background_colour: None,
+            });
+            block.apply(&mut tier);
+            if !block.continues {
+                return Some(name_tier(tier));
+            }
+            shown = Some(tier);
+        }
+        shown.map(name_tier)
+    }
+}
+
+/// Blocks without a NeverSink tier go by their colour.
+fn name_tier(mut tier: FilterTier) -> FilterTier {
+    if tier.tier.is_empty() {
+        tier.tier = match tier.text_colour {
+            Some(ref colour) => colour.clone(),
+            None if tier.hidden => "Hide".to_string(),
+            None => "Show".to_string(),
+        };
+    }
+    tier
+}
+
+impl Block {
+    /// NeverSink puts the tier on the `Show` line, as in
+    /// `Show # %D5 $type->currency $tier->t1`.
+    fn new(hidden: bool, comment: &str) -> Block {
+        let tags: Vec<&str> = comment
+            .split_whitespace()
+            .filter_map(|word| {
+                word.strip_prefix("$type->")
+                    .or_else(|| word.strip_prefix("$tier->"))
+            })
+            .collect();
+        Block {
+            hidden,
+            tier: if tags.is_empty() {
+                None
+            } else {
+                Some(tags.join(" "))
+            },
+            conditions: Vec::new(),
+            text_colour: None,
+            border_colour: None,
+            background_colour: None,
+            continues: false,
+        }
+    }
+
+    fn add(&mut self, keyword: &str, arguments: &[String]) -> () {
+        match keyword {
+            "Continue" => self.continues = true,
+            "SetTextColor" => self.text_colour = colour(arguments),
+            "SetBorderColor" => self.border_colour = colour(arguments),
+            "SetBackgroundColor" => self.background_colour = colour(arguments),
+            _ if ACTIONS.iter().any(|action| keyword.starts_with(action)) => {}
+            _ => self.conditions.push(Condition::new(keyword, arguments)),
+        }
+    }
+
+    fn apply(&self, tier: &mut FilterTier) -> () {
+        tier.hidden = self.hidden;
+        if let Some(ref name) = self.tier {
+            tier.tier = name.clone();
+        }
+        if self.text_colour.is_some() {
+            tier.text_colour = self.text_colour.clone();
+        }
+        if self.border_colour.is_some() {
+            tier.border_colour = self.border_colour.clone();
+        }
+        if self.background_colour.is_some() {
+            tier.background_colour = self.background_colour.clone();
+        }
+    }
+}
+
+/// `255 0 0` or `255 0 0 200` as CSS.
+fn colour(arguments: &[String]) -> Option<String> {
+    let channels: Vec<u8> = arguments
+        .iter()
+        .map(|channel| channel.parse().ok())
+        .collect::<Option<_>>()?;
+    match channels.as_slice() {
+        &[r, g, b] => Some(format!("rgba({}, {}, {}, 1.00)", r, g, b)),
+        &[r, g, b, a] => Some(format!(
+            "rgba({}, {}, {}, {:.2})",
+            r,
+            g,
+            b,
+            f32::from(a) / 255.0
+        )),
+        _ => None,
+    }
+}
+
+/// Split on whitespace, except inside quotes, which go.
+fn words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(word.clone());
+                    word.clear();
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+impl Condition {
+    fn new(keyword: &str, arguments: &[String]) -> Condition {
+        let attribute = match keyword {
+            "Class" => Attribute::Class,
+            "BaseType" => Attribute::BaseType,
+            "Rarity" => Attribute::Rarity,
+            "ItemLevel" => Attribute::ItemLevel,
+            "StackSize" => Attribute::StackSize,
+            "LinkedSockets" => Attribute::LinkedSockets,
+            "Sockets" => Attribute::Sockets,
+            "Quality" => Attribute::Quality,
+            "MapTier" => Attribute::MapTier,
+            _ => Attribute::Unknown,
+        };
+        let mut values = arguments.to_vec();
+        let mut operator = Operator::Equal;
+        if let Some(first) = values.first().cloned() {
+            // Written either on its own or stuck to the value, as in `>=75`.
+            if let Some(&(symbol, found)) = OPERATORS.iter().find(|&&(s, _)| first.starts_with(s)) {
+                operator = found;
+                if first == symbol {
+                    values.remove(0);
+                } else {
+                    values[0] = first[symbol.len()..].to_string();
+                }
+            }
+        }
+        Condition {
+            attribute,
+            operator,
+            values,
+        }
+    }
+
+    fn matches(&self, facts: &Facts) -> bool {
+        let number = |value: &String| value.parse().ok();
+        match self.attribute {
+            Attribute::Class => facts.class.map_or(false, |class| self.matches_text(class)),
+            Attribute::BaseType => facts
+                .base_type
+                .as_ref()
+                .map_or(false, |base_type| self.matches_text(base_type)),
+            Attribute::Rarity => facts.rarity.map_or(false, |rarity| {
+                self.matches_number(rarity, |value| {
+                    RARITIES.iter().position(|r| r == value).map(|i| i as u32)
+                })
+            }),
+            Attribute::ItemLevel => facts
+                .item_level
+                .map_or(false, |level| self.matches_number(level, number)),
+            Attribute::StackSize => self.matches_number(facts.stack_size, number),
+            Attribute::LinkedSockets => facts
+                .linked_sockets
+                .map_or(false, |links| self.matches_number(links, number)),
+            Attribute::Sockets => facts
+                .sockets
+                .map_or(false, |sockets| self.matches_number(sockets, number)),
+            Attribute::Quality => facts
+                .quality
+                .map_or(false, |quality| self.matches_number(quality, number)),
+            Attribute::MapTier => facts
+                .map_tier
+                .map_or(false, |tier| self.matches_number(tier, number)),
+            Attribute::Unknown => false,
+        }
+    }
+
+    /// The game doesn't care about case.
+    fn matches_text(&self, text: &str) -> bool {
+        let text = text.to_lowercase();
+        let found = self.values.iter().any(|value| {
+            let value = value.to_lowercase();
+            match self.operator {
+                Operator::Exact => text == value,
+                _ => text.contains(&value),
+            }
+        });
+        (self.operator == Operator::NotEqual) != found
+    }
+
+    /// Values that don't read as numbers, like the colours in `Sockets 5RGB`,
+    /// make it not match.
+    fn matches_number<F>(&self, actual: u32, parse: F) -> bool
+    where
+        F: Fn(&String) -> Option<u32>,
+    {
+        let values: Option<Vec<u32>> = self.values.iter().map(parse).collect();
+        let values = match values {
+            Some(ref values) if !values.is_empty() => values,
+            _ => return false,
+        };
+        match self.operator {
+            Operator::Equal | Operator::Exact => values.contains(&actual),
+            Operator::NotEqual => !values.contains(&actual),
+            Operator::Less => actual < values[0],
+            Operator::LessOrEqual => actual <= values[0],
+            Operator::Greater => actual > values[0],
+            Operator::GreaterOrEqual => actual >= values[0],
+        }
+    }
+}
+
+fn rarity(rarity: &ItemRarity) -> u32 {
+    match rarity {
+        ItemRarity::Magical => 1,
+        ItemRarity::Rare => 2,
+        ItemRarity::Unique => 3,
+        _ => 0,
+    }
+}
+
+fn class_of(base_type: &str) -> Option<&'static str> {
+    CLASSES
+        .iter()
+        .find(|(ending, _)| base_type.ends_with(ending))
+        .map(|(_, class)| *class)
+}
+
+/// Fragments, scarabs and the like copy as currency, what they're priced as
+/// says which class they're in.
+fn currency_class(kind: Option<ItemKind>) -> &'static str {
+    match kind {
+        Some(ItemKind::Fragment) => "Map Fragments",
+        Some(ItemKind::Scarab) => "Scarabs",
+        Some(ItemKind::Incubator) => "Incubators",
+        // Oils are priced apart, but they're currency to the game.
+        _ => "Stackable Currency",
+    }
+}
+
+impl Facts {
+    fn of(item: &Item) -> Facts {
+        let unsocketed = Facts {
+            stack_size: item.count(),
+            linked_sockets: Some(0),
+            sockets: Some(0),
+            quality: Some(0),
+            ..Facts::default()
+        };
+        match item {
+            Item::Currency(c) => Facts {
+                class: Some(currency_class(resolve_price_key(item).kind)),
+                base_type: Some(c.name.clone()),
+                rarity: Some(0),
+                ..unsocketed
+            },
+            Item::DivinationCard(d) => Facts {
+                class: Some("Divination Card"),
+                base_type: Some(d.name.clone()),
+                rarity: Some(0),
+                ..unsocketed
+            },
+            Item::Map(m) => Facts {
+                class: Some("Maps"),
+                base_type: Some(m.kind.clone()),
+                rarity: Some(rarity(&m.rarity)),
+                item_level: Some(m.item_level),
+                quality: Some(m.quality),
+                map_tier: Some(m.tier),
+                ..unsocketed
+            },
+            // Sockets copy as `R-G-B B`, linked ones joined by dashes.
+            Item::Gear(g) => Facts {
+                class: class_of(&g.base_type),
+                base_type: Some(g.base_type.clone()),
+                rarity: Some(rarity(&g.rarity)),
+                item_level: Some(g.item_level),
+                stack_size: 1,
+                linked_sockets: g
+                    .sockets
+                    .split_whitespace()
+                    .map(|group| group.split('-').count() as u32)
+                    .max()
+                    .or(Some(0)),
+                sockets: Some(g.sockets.chars().filter(|c| c.is_alphabetic()).count() as u32),
+                ..Facts::default()
+            },
+            Item::UniqueStub(u) => Facts {
+                class: class_of(&u.base_type),
+                base_type: Some(u.base_type.clone()),
+                rarity: Some(rarity(&ItemRarity::Unique)),
+                stack_size: 1,
+                ..Facts::default()
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use types::item::{Currency, DivinationCard, Gear, Map, Requirements, StackSize, UniqueStub};
+
+    const FILTER: &str = "\u{feff}#=====
+# A filter, shortened
+#=====
+
+Show # %D5 $type->currency $tier->t1exalted
+    Class \"Currency\"
+    BaseType == \"Exalted Orb\" \"Mirror of Kalandra\"
+    SetTextColor 255 0 0 255
+    SetBackgroundColor 255 255 255
+    PlayAlertSound 6 300
+
+Show # $type->currency $tier->chaosstack
+    Class Currency
+    BaseType \"Chaos\"
+    StackSize >=5
+    SetTextColor 200 200 200
+
+Show # $type->influenced
+    Class Currency
+    HasInfluence Shaper
+    SetTextColor 1 1 1
+
+Hide # $type->divination $tier->t5
+    Class \"Divination\"
+    BaseType \"Rain of Chaos\"
+
+Show # Every map gets a border, the blocks below pick the rest
+    Class Maps
+    SetBorderColor 0 0 255 128
+    Continue
+
+Show # $type->maps $tier->high
+    Class Maps
+    MapTier >= 11
+    Rarity <= Rare
+
+Show # %D5 $type->exoticbases $tier->t1
+    ItemLevel >= 86
+    Rarity <= Rare
+    Class \"Helmets\"
+    BaseType == \"Hubris Circlet\" \"Royal Burgonet\"
+    SetTextColor 255 255 255
+    SetBorderColor 0 0 0
+
+Show
+    LinkedSockets 6
+    SetTextColor 0 255 0
+
+Show # $type->uniques $tier->t1
+    Rarity Unique
+    Class \"Belts\"
+    BaseType == \"Leather Belt\"
+
+Show # $type->uniques $tier->rest
+    Rarity Unique
+";
+
+    fn currency(name: &str, count: u32) -> Item {
+        Item::Currency(Currency {
+            name: name.to_string(),
+            stack_size: StackSize {
+                current: count,
+                max: 10,
+            },
+            affixes: Vec::new(),
+            description: String::new(),
+        })
+    }
+
+    fn map(tier: u32) -> Item {
+        Item::Map(Map {
+            name: None,
+            kind: "Shaped Cage Map".to_string(),
+            tier,
+            item_quantity: 0,
+            item_rarity: 0,
+            quality: 0,
+            pack_size: 0,
+            affixes: Vec::new(),
+            item_level: 78,
+            rarity: ItemRarity::Rare,
+        })
+    }
+
+    fn tier(filter: &LootFilter, item: &Item) -> Option<String> {
+        filter.classify(item).map(|shown| shown.tier)
+    }
+
+    #[test]
+    fn should_put_drops_into_the_first_block_for_them() {
+        let filter = LootFilter::parse(FILTER).unwrap();
+
+        let exalted = filter.classify(&currency("Exalted Orb", 1)).unwrap();
+        assert_eq!(exalted.tier, "currency t1exalted");
+        assert!(!exalted.hidden);
+        assert_eq!(
+            exalted.text_colour,
+            Some("rgba(255, 0, 0, 1.00)".to_string())
+        );
+        assert_eq!(
+            exalted.background_colour,
+            Some("rgba(255, 255, 255, 1.00)".to_string())
+        );
+
+        assert_eq!(
+            tier(&filter, &currency("Chaos Orb", 7)),
+            Some("currency chaosstack".to_string())
+        );
+        // Too few for that, and influence is nothing we'd know about.
+        assert_eq!(tier(&filter, &currency("Chaos Orb", 2)), None);
+
+        let card = filter
+            .classify(&Item::DivinationCard(DivinationCard {
+                name: "Rain of Chaos".to_string(),
+                stack_size: StackSize { current: 1, max: 8 },
+                reward: String::new(),
+                description: String::new(),
+            }))
+            .unwrap();
+        assert_eq!(card.tier, "divination t5");
+        assert!(card.hidden);
+
+        let unique = |name: &str, base_type: &str| {
+            Item::UniqueStub(UniqueStub {
+                name: name.to_string(),
+                base_type: base_type.to_string(),
+            })
+        };
+        assert_eq!(
+            tier(&filter, &unique("Headhunter", "Leather Belt")),
+            Some("uniques t1".to_string())
+        );
+        assert_eq!(
+            tier(&filter, &unique("Inpulsa's Broken Heart", "Sadist Garb")),
+            Some("uniques rest".to_string())
+        );
+
+        // No NeverSink tier on that block, so it goes by colour.
+        assert_eq!(
+            tier(&filter, &circlet(ItemRarity::Normal, "B-B-B-B-B-B", 84)),
+            Some("rgba(0, 255, 0, 1.00)".to_string())
+        );
+    }
+
+    fn circlet(rarity: ItemRarity, sockets: &str, item_level: u32) -> Item {
+        Item::Gear(Gear {
+            name: "Hubris Circlet".to_string(),
+            base_type: "Hubris Circlet".to_string(),
+            rarity,
+            affixes: Vec::new(),
+            sockets: sockets.to_string(),
+            item_level,
+            requirements: Requirements {
+                level: 69,
+                strength: 0,
+                intelligence: 154,
+                dexterity: 0,
+            },
+        })
+    }
+
+    #[test]
+    fn should_match_gear_by_class_and_base_type() {
+        let filter = LootFilter::parse(FILTER).unwrap();
+
+        let exotic = filter
+            .classify(&circlet(ItemRarity::Rare, "B-B B", 86))
+            .unwrap();
+        assert_eq!(exotic.tier, "exoticbases t1");
+        assert_eq!(
+            exotic.border_colour,
+            Some("rgba(0, 0, 0, 1.00)".to_string())
+        );
+
+        // Too low a level for it.
+        assert_eq!(tier(&filter, &circlet(ItemRarity::Rare, "B-B B", 85)), None);
+    }
+
+    #[test]
+    fn should_tell_the_class_from_the_base_type() {
+        assert_eq!(class_of("Hubris Circlet"), Some("Helmets"));
+        assert_eq!(class_of("Vaal Regalia"), Some("Body Armours"));
+        assert_eq!(class_of("Full Ringmail"), Some("Body Armours"));
+        assert_eq!(class_of("Two-Stone Ring"), Some("Rings"));
+        assert_eq!(class_of("Murderous Eye Jewel"), Some("Abyss Jewels"));
+        assert_eq!(class_of("Cobalt Jewel"), Some("Jewels"));
+        assert_eq!(class_of("Divine Life Flask"), Some("Life Flasks"));
+        assert_eq!(class_of("Quicksilver Flask"), Some("Utility Flasks"));
+        // Could be a one- or a two-handed sword.
+        assert_eq!(class_of("Jewelled Foil"), None);
+    }
+
+    #[test]
+    fn should_tell_fragments_from_currency() {
+        let filter = LootFilter::parse(
+            "Show # $type->fragments $tier->t1
+    Class \"Map Fragments\"
+    BaseType \"Mortal\"
+
+Show # $type->scarabs $tier->t2
+    Class Scarabs
+
+Show # $type->currency $tier->rest
+    Class Currency
+",
+        )
+        .unwrap();
+        assert_eq!(
+            tier(&filter, &currency("Mortal Hope", 1)),
+            Some("fragments t1".to_string())
+        );
+        assert_eq!(
+            tier(&filter, &currency("Gilded Ambush Scarab", 1)),
+            Some("scarabs t2".to_string())
+        );
+        assert_eq!(
+            tier(&filter, &currency("Golden Oil", 1)),
+            Some("currency rest".to_string())
+        );
+    }
+
+    #[test]
+    fn should_keep_the_look_of_blocks_that_continue() {
+        let filter = LootFilter::parse(FILTER).unwrap();
+
+        let high = filter.classify(&map(14)).unwrap();
+        assert_eq!(high.tier, "maps high");
+        assert_eq!(
+            high.border_colour,
+            Some("rgba(0, 0, 255, 0.50)".to_string())
+        );
+
+        // Only the block that continues was for it.
+        let low = filter.classify(&map(3)).unwrap();
+        assert_eq!(low.tier, "Show");
+        assert!(low.border_colour.is_some());
+    }
+
+    #[test]
+    fn should_reject_what_is_not_a_filter() {
+        assert!(LootFilter::parse("# Just a comment\n").is_err());
+        assert!(LootFilter::parse("BaseType \"Exalted Orb\"\nShow\n").is_err());
+    }
+}
@@ -1,43 +1,262 @@
-#![allow(dead_code)]
+//! The application: wires the bots of the library together, or runs one of the
+//! one-shot subcommands.
+extern crate atlas_of_beancounting;
 extern crate chrono;
-extern crate clipboard;
-extern crate notify;
-extern crate regex;
-extern crate reqwest;
-extern crate web_view;
+extern crate clap;
+#[macro_use] extern crate log;
 extern crate rusqlite;
 extern crate serde;
 extern crate serde_json;
-#[macro_use] extern crate lazy_static;
 #[macro_use] extern crate serde_derive;
 
-mod clipboard_poller;
-mod log_watcher;
-mod tooltip_parser;
-mod types;
-mod web_client;
-mod frontend;
-mod constants;
+mod cli;
+mod commands;
 
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::mpsc;
-use std::thread;
+use std::time::Duration;
+
+use atlas_of_beancounting::config::{self, ConfigBot, Subscriber};
+use atlas_of_beancounting::database::{self, DatabaseBot};
+use atlas_of_beancounting::mastermind::{forward, Mastermind};
+use atlas_of_beancounting::supervisor::Supervisor;
+use atlas_of_beancounting::types::clipboard_event::ClipboardRequest;
+use atlas_of_beancounting::types::config::{Config, ConfigOverrides};
+use atlas_of_beancounting::types::mastermind::MastermindMessage;
+use atlas_of_beancounting::types::pricing::PriceMessage;
+use atlas_of_beancounting::web_client::PriceBot;
+use atlas_of_beancounting::{
+    clipboard_source, frontend, hotkeys, log_watcher, logging, tooltip_parser,
+};
+use cli::Command;
 
 fn main() {
+    let matches = cli::app().get_matches();
+    let config_path = cli::config_path(&matches);
+    let overrides = cli::overrides(&matches);
+    let load_config = || {
+        config::startup_config(&config_path, &overrides).unwrap_or_else(|e| {
+            eprintln!("{}: {}", config_path.display(), e);
+            process::exit(1)
+        })
+    };
+
+    // Only a running session keeps a log file, the subcommands are over
+    // before anyone would look at it.
+    let start_logging = |config: &Config, file: Option<&Path>| {
+        if let Err(e) = logging::init(config, file) {
+            eprintln!("Can't start logging: {}", e);
+        }
+    };
+
+    let exit_code = match cli::command(&matches) {
+        Command::Run { headless } => {
+            let config = load_config();
+            let log_path = logging::log_path();
+            start_logging(&config, Some(&log_path));
+            info!("Logging to {}", log_path.display());
+            run(config, config_path.clone(), overrides.clone(), headless);
+            0
+        }
+        Command::Parse { files } => {
+            // Parsing doesn't need a config file, only the log level.
+            let mut config = Config::default();
+            overrides.apply(&mut config);
+            start_logging(&config, None);
+            commands::parse(&files)
+        }
+        Command::Price { name } => {
+            let config = load_config();
+            start_logging(&config, None);
+            commands::price(&name, &config)
+        }
+        Command::Replay { log } => {
+            let config = load_config();
+            start_logging(&config, None);
+            commands::replay(&log, &config)
+        }
+        Command::Import {
+            files,
+            filter,
+            dry_run,
+        } => {
+            let config = load_config();
+            start_logging(&config, None);
+            commands::import(&files, &filter, dry_run, &config)
+        }
+        Command::Export {
+            output,
+            table,
+            format,
+            filter,
+            log,
+            columns,
+        } => {
+            if columns {
+                commands::columns(table)
+            } else {
+                let config = load_config();
+                start_logging(&config, None);
+                commands::export(
+                    output.as_deref(),
+                    table,
+                    format,
+                    &filter,
+                    log.as_deref(),
+                    &config,
+                )
+            }
+        }
+    };
+    process::exit(exit_code);
+}
+
+/// Track the session until the window is closed, or, when `headless`, until
+/// stdin is. Then everything is shut down, so the last map run gets saved.
+fn run(config: Config, config_path: PathBuf, overrides: ConfigOverrides, headless: bool) -> () {
+    let (mastermind_sender, mastermind_receiver) = mpsc::channel();
+    let (frontend_sender, frontend_receiver) = mpsc::channel();
+    let mut supervisor = Supervisor::new(frontend_sender.clone());
+    logging::attach_frontend(frontend_sender.clone());
+    let shutdown = supervisor.shutdown_flag();
+
+    // Sources first, so they're stopped before anything they feed.
     let (clipboard_sender, clipboard_receiver) = mpsc::channel();
-    thread::spawn(move || {
-        clipboard_poller::watch_clipboard(clipboard_sender);
+    let (clipboard_request_sender, clipboard_request_receiver) = mpsc::channel();
+    let mut poll_interval = Duration::from_millis(config.clipboard_poll_interval_ms);
+    let clipboard_backend = config.clipboard_backend.clone();
+    let clipboard_shutdown = shutdown.clone();
+    supervisor.spawn("ClipboardWatcher", move || {
+        let mut source = clipboard_source::open(&clipboard_backend);
+        clipboard_source::watch_clipboard(
+            &mut *source,
+            &clipboard_sender,
+            &mut poll_interval,
+            &clipboard_request_receiver,
+            &clipboard_shutdown,
+        )
     });
 
-    let (log_sender, _log_receiver) = mpsc::channel();
-    thread::spawn(move || {
-        log_watcher::watch_zone_log(log_sender);
+    // Hotkeys say the same things the frontend's buttons do.
+    let (command_sender, command_receiver) = mpsc::channel();
+    forward(command_receiver, mastermind_sender.clone(), MastermindMessage::Command);
+    let (binding_sender, binding_receiver) = mpsc::channel();
+    let mut bindings = hotkeys::bindings(&config.hotkeys).unwrap_or_default();
+    let hotkey_commands = command_sender.clone();
+    let hotkey_shutdown = shutdown.clone();
+    supervisor.spawn("HotkeyListener", move || {
+        if let Some(mut hotkeys) = hotkeys::open() {
+            // Crash, so the supervisor tries again after a while.
+            if let Err(e) = hotkeys::watch_hotkeys(
+                &mut *hotkeys,
+                &mut bindings,
+                &binding_receiver,
+                &hotkey_commands,
+                &hotkey_shutdown,
+            ) {
+                panic!("Hotkeys stopped working: {}", e);
+            }
+        }
     });
 
-    let (tooltip_sender, _tooltip_receiver) = mpsc::channel();
-    thread::spawn(move || {
-        tooltip_parser::spawn_tooltip_parser(clipboard_receiver, tooltip_sender);
+    let (log_sender, log_receiver) = mpsc::channel();
+    supervisor.spawn("LogWatcher", move || {
+        log_watcher::watch_zone_log(&log_sender, &shutdown)
     });
+    forward(log_receiver, mastermind_sender.clone(), MastermindMessage::Zone);
+
+    // Stops once the clipboard watcher has.
+    let (tooltip_sender, tooltip_receiver) = mpsc::channel();
+    supervisor.spawn("TooltipParser", move || {
+        tooltip_parser::spawn_tooltip_parser(&clipboard_receiver, &tooltip_sender)
+    });
+    forward(tooltip_receiver, mastermind_sender.clone(), MastermindMessage::Item);
+
+    let (price_request_sender, price_request_receiver) = mpsc::channel();
+    let (price_response_sender, price_response_receiver) = mpsc::channel();
+    forward(price_response_receiver, mastermind_sender.clone(), MastermindMessage::Price);
+
+    let (database_request_sender, database_request_receiver) = mpsc::channel();
+    let (database_response_sender, database_response_receiver) = mpsc::channel();
+    forward(database_response_receiver, mastermind_sender.clone(), MastermindMessage::Database);
+
+    let (config_request_sender, config_request_receiver) = mpsc::channel();
+    let subscribers: Vec<Subscriber> = {
+        let prices = price_request_sender.clone();
+        let clipboard = clipboard_request_sender.clone();
+        let mastermind = mastermind_sender.clone();
+        vec![
+            Box::new(move |c| {
+                prices
+                    .send(PriceMessage::Configure(config::price_bot_config(c)))
+                    .is_ok()
+            }),
+            Box::new(move |c| {
+                let interval = Duration::from_millis(c.clipboard_poll_interval_ms);
+                clipboard
+                    .send(ClipboardRequest::PollInterval(interval))
+                    .is_ok()
+            }),
+            Box::new(move |c| mastermind.send(MastermindMessage::Config(c.clone())).is_ok()),
+            Box::new(|c: &Config| {
+                logging::configure(c);
+                true
+            }),
+            Box::new(move |c| match hotkeys::bindings(&c.hotkeys) {
+                Ok(bindings) => binding_sender.send(bindings).is_ok(),
+                // Can't happen, the config bot only hands out valid configs.
+                Err(_) => true,
+            }),
+        ]
+    };
+
+    // The mastermind goes before the database bot, so its last map run is in
+    // the database's inbox by the time that's told to shut down.
+    let debug = config.debug;
+    Mastermind::new(
+        mastermind_receiver,
+        price_request_sender.clone(),
+        database_request_sender.clone(),
+        config_request_sender.clone(),
+        clipboard_request_sender,
+        frontend_sender,
+        config.clone(),
+    )
+    .supervise(&mut supervisor, mastermind_sender);
+    DatabaseBot::supervise(
+        &mut supervisor,
+        database_request_receiver,
+        database_response_sender,
+        database_request_sender,
+        database::database_path(&config),
+    );
+    PriceBot::supervise(
+        &mut supervisor,
+        price_response_sender,
+        price_request_receiver,
+        price_request_sender,
+        config::price_bot_config(&config),
+    );
+    ConfigBot::supervise(
+        &mut supervisor,
+        config_request_receiver,
+        config_request_sender,
+        config_path,
+        overrides,
+        config,
+        subscribers,
+    );
 
-    let (_frontend_sender, frontend_receiver) = mpsc::channel();
-    frontend::spawn_frontend(frontend_receiver);
+    if headless {
+        let printer = frontend::run_headless(frontend_receiver, command_sender);
+        supervisor.shut_down();
+        // Everything that could still say something is gone now.
+        logging::detach_frontend();
+        let _ = printer.join();
+    } else {
+        frontend::spawn_frontend(frontend_receiver, command_sender, debug);
+        supervisor.shut_down();
+        logging::detach_frontend();
+    }
 }
@@ -0,0 +1,1570 @@
+//! The mastermind is the centre of the calculations. It consumes zone changes,
+//! drops and prices, keeps track of which map drops belong to, and tells the
+//! frontend about all of it. Finished maps go to the database bot, which also
+//! answers the history screens. Everything it listens to comes in through a single
+//! `MastermindMessage` channel, use `forward` to plug other actors into it.
+use chrono::prelude::*;
+use chrono::Duration;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use clock::Clock;
+use config::{validate, ConfigError};
+use constants::{START_MAP_COPY_TIMEOUT_SECS, TOWN_ZONES};
+use dedup::{DropFilter, Verdict};
+use loot_filter::LootFilter;
+use price_key::resolve_price_key;
+use supervisor::Supervisor;
+use trade::{currency_name, is_chaos, Ledger};
+use types::clipboard_event::ClipboardRequest;
+use types::config::{Config, ConfigMessage};
+use types::database::DatabaseMessage;
+use types::frontend_communication::{FrontendCommand, FrontendMessage};
+use types::item::{Fingerprint, Item, Map};
+use types::mastermind::MastermindMessage;
+use types::pricing::{ItemKind, MatchQuality, PriceKey, PriceMessage};
+use types::session::{Character, Drop, MapRun, Session};
+use types::trade::Trade;
+use types::zone_event::ZoneEvent;
+
+/// What a price request was for.
+enum Awaiting {
+    Drop(u64),
+    /// An accepted trade paid in something other than chaos.
+    Trade(Trade),
+}
+
+pub struct Mastermind {
+    inbox: Receiver<MastermindMessage>,
+    price_requests: Sender<PriceMessage>,
+    database: Sender<DatabaseMessage>,
+    config_requests: Sender<ConfigMessage>,
+    clipboard_requests: Sender<ClipboardRequest>,
+    frontend: Sender<FrontendMessage>,
+    /// The config as of the last time the config bot told us about it.
+    config: Config,
+    session: Session,
+    next_drop_id: u64,
+    /// Drops and trades waiting for a price, by the id of the request that
+    /// asked for it.
+    awaiting_price: HashMap<u64, Awaiting>,
+    next_price_request: u64,
+    /// Maps that are over, with their league, but still have drops waiting
+    /// for a price. They're saved once the last one is in.
+    unsaved: Vec<(String, MapRun)>,
+    drops_seen: DropFilter,
+    /// Drops `UndoLastDrop` can take back, newest last, with the fingerprint
+    /// they were counted by. Emptied when a map ends, what's in the history
+    /// stays there.
+    undoable: Vec<(u64, Fingerprint)>,
+    /// When `StartMapFromCopy` came in, until the next item does.
+    start_from_copy: Option<DateTime<Local>>,
+    paused: bool,
+    /// Where the player is, as far as the log says.
+    zone: Option<String>,
+    /// The instance seed of the area being loaded, until it's entered.
+    loading_seed: Option<u64>,
+    /// The instance seed of the current map, once it's been entered.
+    map_seed: Option<u64>,
+    /// Who joined the area since we entered it. The log doesn't say that
+    /// about us, so whoever levels up or dies in here and is on this list is
+    /// somebody else in the party.
+    others_here: HashSet<String>,
+    /// The map copied last outside of a map. The next run of its area gets
+    /// its tier, mods and rolls.
+    queued_map: Option<Map>,
+    clock: Clock,
+    ledger: Ledger,
+    /// From `loot_filter_file`, read again with every new config.
+    loot_filter: Option<LootFilter>,
+}
+
+impl Mastermind {
+    pub fn new(
+        inbox: Receiver<MastermindMessage>,
+        price_requests: Sender<PriceMessage>,
+        database: Sender<DatabaseMessage>,
+        config_requests: Sender<ConfigMessage>,
+        clipboard_requests: Sender<ClipboardRequest>,
+        frontend: Sender<FrontendMessage>,
+        config: Config,
+    ) -> Mastermind {
+        let mut session = Session::new(Local::now());
+        if let Some(character) = named_character(&config) {
+            session.switch_character(character);
+        }
+        Mastermind {
+            inbox,
+            price_requests,
+            database,
+            config_requests,
+            clipboard_requests,
+            frontend,
+            drops_seen: DropFilter::new(Duration::minutes(config.duplicate_window_minutes)),
+            loot_filter: load_loot_filter(&config),
+            config,
+            session,
+            next_drop_id: 0,
+            awaiting_price: HashMap::new(),
+            next_price_request: 0,
+            unsaved: Vec::new(),
+            undoable: Vec::new(),
+            start_from_copy: None,
+            paused: false,
+            zone: None,
+            loading_seed: None,
+            map_seed: None,
+            others_here: HashSet::new(),
+            queued_map: None,
+            clock: Clock::new(),
+            ledger: Ledger::new(),
+        }
+    }
+
+    /// Run the mastermind under `supervisor`, which shuts it down through
+    /// `requests`. A restarted mastermind picks up the session where it was.
+    pub fn supervise(
+        mut self,
+        supervisor: &mut Supervisor,
+        requests: Sender<MastermindMessage>,
+    ) -> () {
+        supervisor.spawn_actor(
+            "Mastermind",
+            move || self.run(),
+            move || {
+                let _ = requests.send(MastermindMessage::ShutDown);
+            },
+        );
+    }
+
+    pub fn run(&mut self) -> () {
+        loop {
+            match self.inbox.recv() {
+                Ok(MastermindMessage::ShutDown) => {
+                    info!("Shutting down...");
+                    self.shut_down(Local::now());
+                    break;
+                }
+                Ok(message) => self.handle(message, Local::now()),
+                Err(_) => {
+                    info!("Inbox closed, exiting");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// The map being run when the tool is closed ends then, so it makes it
+    /// into the history.
+    /// Maps still waiting for prices are saved as they are, the answers
+    /// won't come anymore.
+    fn shut_down(&mut self, now: DateTime<Local>) -> () {
+        self.end_map(now);
+        for (league, run) in mem::replace(&mut self.unsaved, Vec::new()) {
+            self.send_to_database(DatabaseMessage::SaveMapRun { league, run });
+        }
+        self.send_to_database(DatabaseMessage::SaveSession {
+            league: self.config.league.clone(),
+            session: self.session.record(now),
+        });
+    }
+
+    fn handle(&mut self, message: MastermindMessage, now: DateTime<Local>) -> () {
+        match message {
+            MastermindMessage::Item(item) => self.receive_item(item, now),
+            MastermindMessage::Zone(ZoneEvent::ZoneChange(zone, timestamp)) => {
+                self.clock.entered(!is_safe_zone(&zone), timestamp);
+                self.zone = Some(zone.clone());
+                self.others_here.clear();
+                let seed = self.loading_seed.take();
+                // Runs still start and end while paused, only drops aren't counted.
+                self.change_zone(zone, seed, timestamp)
+            }
+            MastermindMessage::Zone(ZoneEvent::Death(character, _)) => self.count_death(&character),
+            MastermindMessage::Zone(ZoneEvent::LoadingStarted(seed, timestamp)) => {
+                self.loading_seed = seed;
+                self.clock.loading(timestamp)
+            }
+            MastermindMessage::Zone(ZoneEvent::Afk(afk, timestamp)) => {
+                self.clock.set_afk(afk, timestamp)
+            }
+            MastermindMessage::Zone(ZoneEvent::LevelUp(name, class, level, _)) => {
+                self.level_up(name, class, level)
+            }
+            MastermindMessage::Zone(ZoneEvent::Whisper(whisper, timestamp)) => {
+                if let Some(trade) = self.ledger.whisper(&whisper, timestamp) {
+                    self.save_trade(trade);
+                }
+            }
+            MastermindMessage::Zone(ZoneEvent::JoinedArea(player, _)) => {
+                self.ledger.joined(&player);
+                self.others_here.insert(player);
+            }
+            MastermindMessage::Zone(ZoneEvent::TradeAccepted(timestamp)) => {
+                match self.ledger.accepted(timestamp) {
+                    Some(trade) => self.complete_trade(trade),
+                    None => info!("Accepted a trade nobody whispered about"),
+                }
+            }
+            MastermindMessage::Price(PriceMessage::Response {
+                id, price, quality, ..
+            }) => {
+                let id = match self.awaiting_price.remove(&id) {
+                    Some(Awaiting::Drop(id)) => id,
+                    Some(Awaiting::Trade(trade)) => {
+                        match quality {
+                            // A guess at the currency is worse than no value.
+                            MatchQuality::Unpriced | MatchQuality::Fuzzy(_) => {
+                                warn!("No price for {}", trade.request.currency)
+                            }
+                            _ => self.price_trade(trade, price.chaos_equivalent),
+                        }
+                        return;
+                    }
+                    None => {
+                        warn!("Got a price nobody asked for: {:?}", price);
+                        return;
+                    }
+                };
+                self.price_drop(id, price.chaos_equivalent, quality, now);
+            }
+            MastermindMessage::Price(other) => {
+                warn!(
+                    "Unexpected message from price bot: {:?}",
+                    other
+                )
+            }
+            MastermindMessage::Command(command) => self.handle_command(command, now),
+            MastermindMessage::Database(DatabaseMessage::History(result)) => {
+                self.send(FrontendMessage::History { result })
+            }
+            MastermindMessage::Database(DatabaseMessage::Exported { path, records }) => {
+                self.send(FrontendMessage::Exported { path, records })
+            }
+            MastermindMessage::Database(DatabaseMessage::Error { reason }) => {
+                self.send(FrontendMessage::Error { message: reason })
+            }
+            MastermindMessage::Database(DatabaseMessage::Panic { reason }) => {
+                self.send(FrontendMessage::Error {
+                    message: format!("History is unavailable: {}", reason),
+                })
+            }
+            MastermindMessage::Database(other) => {
+                warn!("Unexpected message from database: {:?}", other)
+            }
+            MastermindMessage::Config(config) => {
+                self.drops_seen
+                    .set_window(Duration::minutes(config.duplicate_window_minutes));
+                let new_name = config.character_name != self.config.character_name;
+                self.loot_filter = load_loot_filter(&config);
+                self.config = config;
+                if let (true, Some(character)) = (new_name, named_character(&self.config)) {
+                    self.switch_character(character);
+                }
+                self.send(FrontendMessage::Settings {
+                    config: self.config.clone(),
+                });
+            }
+            MastermindMessage::ShutDown => {}
+        }
+    }
+
+    fn handle_command(&mut self, command: FrontendCommand, now: DateTime<Local>) -> () {
+        match command {
+            FrontendCommand::StartMap { name } => {
+                let name = name.unwrap_or_else(|| "Unknown Map".to_string());
+                self.end_map(now);
+                self.start_map(name, now);
+            }
+            FrontendCommand::StartMapFromCopy => {
+                self.start_from_copy = Some(now);
+                // The map may well be what was copied last.
+                if let Err(e) = self.clipboard_requests.send(ClipboardRequest::ExpectCopy) {
+                    warn!("Can't reach clipboard watcher: {}", e);
+                }
+            }
+            FrontendCommand::EndMap => self.end_map(now),
+            FrontendCommand::UndoLastDrop => self.undo_last_drop(now),
+            FrontendCommand::TagLastDrop => self.tag_last_drop(),
+            FrontendCommand::TogglePause => {
+                self.paused = !self.paused;
+                self.clock.set_paused(self.paused, now);
+                info!("{}", if self.paused { "Paused" } else { "Unpaused" });
+                self.send(FrontendMessage::Paused {
+                    paused: self.paused,
+                });
+            }
+            FrontendCommand::RefreshPrices => {
+                self.send_to_prices(PriceMessage::InvalidateCache);
+            }
+            FrontendCommand::QueryHistory { query, filter } => {
+                self.send_to_database(DatabaseMessage::Query { query, filter })
+            }
+            FrontendCommand::Export {
+                table,
+                format,
+                filter,
+            } => self.send_to_database(DatabaseMessage::Export {
+                table,
+                format,
+                filter,
+            }),
+            FrontendCommand::ChangeLeague { league } => {
+                let mut config = self.config.clone();
+                config.league = league;
+                self.save_config(config);
+            }
+            FrontendCommand::OpenSettings => self.send(FrontendMessage::Settings {
+                config: self.config.clone(),
+            }),
+            FrontendCommand::SaveSettings { config } => self.save_config(config),
+        }
+    }
+
+    /// Copied items are drops, unless a map run was asked to be started from
+    /// the next one. Maps copied anywhere but in a map are about to be run,
+    /// not loot.
+    fn receive_item(&mut self, item: Item, now: DateTime<Local>) -> () {
+        if let Some(asked_at) = self.start_from_copy.take() {
+            if now - asked_at <= Duration::seconds(START_MAP_COPY_TIMEOUT_SECS) {
+                return self.start_map_from(item, now);
+            }
+        }
+        let in_map = match self.zone {
+            Some(ref zone) => !is_safe_zone(zone),
+            None => false,
+        };
+        let item = match item {
+            Item::Map(map) if !in_map => return self.queue_map(map),
+            other => other,
+        };
+        if self.paused {
+            info!("Paused, not counting {}", item.display_name());
+            return;
+        }
+        self.record_drop(item, now);
+    }
+
+    fn start_map_from(&mut self, item: Item, now: DateTime<Local>) -> () {
+        let map = match item {
+            Item::Map(map) => map,
+            other => {
+                info!("{} isn't a map, not starting one", other.display_name());
+                return self.receive_item(other, now);
+            }
+        };
+        self.end_map(now);
+        self.start_map(map_zone(&map.kind), now);
+        if let Some(ref mut run) = self.session.current_map {
+            take_rolls(run, map);
+        }
+    }
+
+    fn queue_map(&mut self, map: Map) -> () {
+        let name = map_zone(&map.kind);
+        info!("Next run of {} is the tier {} one just copied", name, map.tier);
+        self.send(FrontendMessage::MapQueued {
+            name,
+            tier: map.tier,
+        });
+        self.queued_map = Some(map);
+    }
+
+    /// Hand the queued map to the current run, if that's where it goes.
+    fn bind_queued_map(&mut self) -> () {
+        let map = match self.queued_map.take() {
+            Some(map) => map,
+            None => return,
+        };
+        match self.session.current_map {
+            Some(ref mut run) if run.name == map_zone(&map.kind) => take_rolls(run, map),
+            _ => self.queued_map = Some(map),
+        }
+    }
+
+    fn record_drop(&mut self, item: Item, now: DateTime<Local>) -> () {
+        let map_started_at = self.session.current_map.as_ref().map(|m| m.started_at);
+        let fingerprint = item.fingerprint();
+        match self.drops_seen.check(fingerprint.clone(), map_started_at, now) {
+            Verdict::New => {}
+            Verdict::Duplicate => {
+                info!("Not counting {} again, it was copied just now", item.display_name());
+                return;
+            }
+            Verdict::SeenBeforeMap => {
+                info!("Not counting {}, it was around before this map", item.display_name());
+                return;
+            }
+        }
+
+        let id = self.next_drop_id;
+        self.next_drop_id += 1;
+
+        let filter_tier = self
+            .loot_filter
+            .as_ref()
+            .and_then(|filter| filter.classify(&item));
+        let drop = Drop {
+            id,
+            name: item.display_name(),
+            count: item.count(),
+            chaos_value: None,
+            dropped_at: now,
+            tagged: false,
+            filter_tier: filter_tier.as_ref().map(|shown| shown.tier.clone()),
+        };
+        self.send(FrontendMessage::ItemDropped {
+            id,
+            name: drop.name.clone(),
+            filter_tier,
+        });
+        match self.session.current_map {
+            Some(ref mut map) => map.drops.push(drop),
+            None => self.session.unassigned_drops.push(drop),
+        }
+        self.undoable.push((id, fingerprint));
+
+        let key = resolve_price_key(&item);
+        self.ask_for_price(key, Awaiting::Drop(id));
+    }
+
+    fn price_drop(
+        &mut self,
+        id: u64,
+        chaos_equivalent: f32,
+        quality: MatchQuality,
+        now: DateTime<Local>,
+    ) -> () {
+        let mut chaos_value = self.session.price_drop(id, chaos_equivalent);
+        let unsaved = self
+            .unsaved
+            .iter_mut()
+            .flat_map(|&mut (_, ref mut run)| run.drops.iter_mut())
+            .find(|d| d.id == id);
+        if let Some(drop) = unsaved {
+            let value = chaos_equivalent * drop.count as f32;
+            drop.chaos_value = Some(value);
+            // Older than the previous map, the session doesn't have it.
+            if chaos_value.is_none() {
+                self.session.finished_chaos_value += value;
+                chaos_value = Some(value);
+            }
+        }
+        self.save_priced_maps();
+        // The drop went away in the meantime, nothing to update.
+        let chaos_value = match chaos_value {
+            Some(value) => value,
+            None => return,
+        };
+        self.send(FrontendMessage::PriceUpdated {
+            id,
+            chaos_equivalent: chaos_value,
+            quality,
+        });
+        self.send_running_total(now);
+    }
+
+    fn ask_for_price(&mut self, item: PriceKey, awaiting: Awaiting) -> () {
+        let id = self.next_price_request;
+        self.next_price_request += 1;
+        if self.send_to_prices(PriceMessage::Get { id, item }) {
+            self.awaiting_price.insert(id, awaiting);
+        }
+    }
+
+    fn is_awaiting_price(&self, id: u64) -> bool {
+        self.awaiting_price
+            .values()
+            .any(|awaiting| match *awaiting {
+                Awaiting::Drop(drop) => drop == id,
+                Awaiting::Trade(_) => false,
+            })
+    }
+
+    fn save_priced_maps(&mut self) -> () {
+        let unsaved = mem::replace(&mut self.unsaved, Vec::new());
+        let (waiting, priced): (Vec<_>, Vec<_>) = unsaved
+            .into_iter()
+            .partition(|&(_, ref run)| run.drops.iter().any(|d| self.is_awaiting_price(d.id)));
+        self.unsaved = waiting;
+        for (league, run) in priced {
+            self.send_to_database(DatabaseMessage::SaveMapRun { league, run });
+        }
+    }
+
+    /// Keep a trade that went through. What it was worth in chaos has to be
+    /// asked for unless it was paid in chaos.
+    fn complete_trade(&mut self, trade: Trade) -> () {
+        info!(
+            "Traded {} with {} for {} {}",
+            trade.request.item, trade.player, trade.request.price, trade.request.currency
+        );
+        if is_chaos(&trade.request.currency) {
+            return self.price_trade(trade, 1.0);
+        }
+
+        self.save_trade(trade.clone());
+        let currency = PriceKey {
+            kind: Some(ItemKind::Currency),
+            name: currency_name(&trade.request.currency),
+        };
+        self.ask_for_price(currency, Awaiting::Trade(trade));
+    }
+
+    /// `chaos_equivalent` is that of one of the currency the trade was paid in.
+    fn price_trade(&mut self, mut trade: Trade, chaos_equivalent: f32) -> () {
+        trade.chaos_value = Some(chaos_equivalent * trade.request.price);
+        self.save_trade(trade);
+    }
+
+    fn save_trade(&self, trade: Trade) -> () {
+        self.send_to_database(DatabaseMessage::SaveTrade {
+            trade: trade.clone(),
+        });
+        self.send(FrontendMessage::TradeUpdated { trade });
+    }
+
+    fn undo_last_drop(&mut self, now: DateTime<Local>) -> () {
+        let undone = self.undoable.pop().and_then(|(id, fingerprint)| {
+            // Copying it again after taking it back is counting it again.
+            self.drops_seen.forget(&fingerprint);
+            self.session.remove_drop(id)
+        });
+        match undone {
+            Some(drop) => {
+                info!("Took back {}", drop.name);
+                self.send(FrontendMessage::DropRemoved { id: drop.id });
+                self.send_running_total(now);
+            }
+            None => self.send(FrontendMessage::Error {
+                message: "No drop to take back".to_string(),
+            }),
+        }
+    }
+
+    fn tag_last_drop(&mut self) -> () {
+        let tagged = match self.undoable.last() {
+            Some(&(id, _)) => self.session.drop_mut(id).map(|drop| {
+                drop.tagged = !drop.tagged;
+                FrontendMessage::DropTagged {
+                    id,
+                    tagged: drop.tagged,
+                }
+            }),
+            None => None,
+        };
+        self.send(tagged.unwrap_or_else(|| FrontendMessage::Error {
+            message: "No drop to tag".to_string(),
+        }));
+    }
+
+    /// Dying doesn't end the map. Coming back into it through a portal is
+    /// still the same run, see `change_zone`.
+    /// Only deaths of whoever runs the current map count, or of the one
+    /// playing when not in a map.
+    fn count_death(&mut self, character: &str) -> () {
+        let runner = self
+            .session
+            .current_map
+            .as_ref()
+            .and_then(|run| run.character.as_ref())
+            .or_else(|| self.session.character.as_ref())
+            .map(|runner| runner.name.clone());
+        match runner {
+            Some(ref ours) if ours != character => return,
+            Some(_) => {}
+            None if self.others_here.contains(character) => return,
+            // Not knowing who's playing, it's most likely the one dying.
+            None => self.switch_character(Character {
+                name: character.to_string(),
+                class: None,
+                level: None,
+            }),
+        }
+        info!("{} died", character);
+        self.session.deaths += 1;
+        let map_deaths = match self.session.current_map {
+            Some(ref mut run) => {
+                run.deaths += 1;
+                run.deaths
+            }
+            None => 0,
+        };
+        self.send(FrontendMessage::Died {
+            map_deaths,
+            session_deaths: self.session.deaths,
+        });
+    }
+
+    /// Without a name in the config, a level up is the best sign of who's
+    /// playing, unless it's somebody who joined the area after us. In a map
+    /// it's only the one running it, switching characters means leaving it.
+    fn level_up(&mut self, name: String, class: String, level: u32) -> () {
+        if !self.config.character_name.is_empty() && self.config.character_name != name {
+            return;
+        }
+        let runner = self
+            .session
+            .current_map
+            .as_ref()
+            .and_then(|run| run.character.as_ref());
+        let someone_else = match runner {
+            Some(runner) => runner.name != name,
+            None => self.others_here.contains(&name),
+        };
+        if someone_else && self.config.character_name.is_empty() {
+            info!("{} ({}) in the party is now level {}", name, class, level);
+            return;
+        }
+        info!("{} ({}) is now level {}", name, class, level);
+        self.switch_character(Character {
+            name,
+            class: Some(class),
+            level: Some(level),
+        });
+    }
+
+    /// The current map goes to the new character too, unless somebody else
+    /// was already running it.
+    fn switch_character(&mut self, character: Character) -> () {
+        if let Some(ref mut run) = self.session.current_map {
+            let someone_else = match run.character {
+                Some(ref runner) => runner.name != character.name,
+                None => false,
+            };
+            if !someone_else {
+                run.character = Some(character.clone());
+            }
+        }
+        self.send(FrontendMessage::CharacterChanged {
+            character: character.clone(),
+        });
+        self.session.switch_character(character);
+    }
+
+    /// Entering a new map ends the current one. Going to town or the hideout
+    /// and back into the same instance doesn't, that's just dumping loot or
+    /// coming back after dying. A fresh instance of the same map is another
+    /// run, when the log has the seeds to tell them apart.
+    fn change_zone(&mut self, zone: String, seed: Option<u64>, timestamp: DateTime<Local>) -> () {
+        if is_safe_zone(&zone) {
+            return;
+        }
+        let same_run = match self.session.current_map {
+            Some(ref map) => {
+                map.name == zone
+                    && (self.map_seed.is_none() || seed.is_none() || self.map_seed == seed)
+            }
+            None => false,
+        };
+        if same_run {
+            // A map started by hand only learns its instance on the way in.
+            self.map_seed = self.map_seed.or(seed);
+            return;
+        }
+
+        self.end_map(timestamp);
+        self.start_map(zone, timestamp);
+        self.map_seed = seed;
+        self.bind_queued_map();
+    }
+
+    fn start_map(&mut self, name: String, now: DateTime<Local>) -> () {
+        self.send(FrontendMessage::MapStarted {
+            name: name.clone(),
+            started_at: now.timestamp(),
+        });
+        let mut run = MapRun::new(name, now);
+        run.character = self.session.character.clone();
+        self.session.current_map = Some(run);
+        self.map_seed = None;
+        self.send_running_total(now);
+    }
+
+    fn end_map(&mut self, now: DateTime<Local>) -> () {
+        let mut map = match self.session.current_map.take() {
+            Some(map) => map,
+            None => return,
+        };
+        map.ended_at = Some(now);
+        self.undoable.clear();
+
+        self.session.maps_run += 1;
+        self.session.finished_chaos_value += map.chaos_value();
+        self.send(FrontendMessage::MapEnded {
+            name: map.name.clone(),
+            ended_at: now.timestamp(),
+            chaos_value: map.chaos_value(),
+        });
+        let league = self.config.league.clone();
+        if map.drops.iter().any(|d| self.is_awaiting_price(d.id)) {
+            self.unsaved.push((league, map.clone()));
+        } else {
+            self.send_to_database(DatabaseMessage::SaveMapRun {
+                league,
+                run: map.clone(),
+            });
+        }
+        self.session.previous_map = Some(map);
+        let time = self.clock.spent(now);
+        self.send(FrontendMessage::SessionSummary {
+            maps_run: self.session.maps_run,
+            chaos_value: self.session.chaos_value(),
+            chaos_per_hour: self.session.chaos_per_hour(now),
+            active_chaos_per_hour: self.session.chaos_per_active_hour(&time),
+            time,
+            deaths: self.session.deaths,
+            experience_lost: self.session.experience_lost(),
+        });
+    }
+
+    fn send_running_total(&self, now: DateTime<Local>) -> () {
+        let (map_chaos_value, map_chaos_per_hour) = match self.session.current_map {
+            Some(ref map) => (map.chaos_value(), map.chaos_per_hour(now)),
+            None => (0.0, 0.0),
+        };
+        self.send(FrontendMessage::RunningTotal {
+            map_chaos_value,
+            map_chaos_per_hour,
+            previous_map_chaos_value: self.session.previous_map.as_ref().map(|m| m.chaos_value()),
+        });
+    }
+
+    /// Hand a changed config to the config bot, which writes it and tells
+    /// everybody, including us. Nonsense goes straight back to the frontend.
+    fn save_config(&self, config: Config) -> () {
+        match validate(&config) {
+            Ok(()) => {
+                if let Err(e) = self.config_requests.send(ConfigMessage::Save(config)) {
+                    warn!("Can't reach config bot: {}", e);
+                }
+            }
+            Err(ConfigError::Invalid(problems)) => {
+                self.send(FrontendMessage::SettingsInvalid { problems })
+            }
+            Err(e) => self.send(FrontendMessage::SettingsInvalid {
+                problems: vec![e.to_string()],
+            }),
+        }
+    }
+
+    /// Whether the price bot got the message. Drops stay unpriced if not.
+    fn send_to_prices(&self, message: PriceMessage) -> bool {
+        match self.price_requests.send(message) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Can't reach price bot: {}", e);
+                false
+            }
+        }
+    }
+
+    fn send_to_database(&self, message: DatabaseMessage) -> () {
+        // Losing history is bad, but not worth losing the session over.
+        if let Err(e) = self.database.send(message) {
+            warn!("Can't reach database: {}", e);
+        }
+    }
+
+    fn send(&self, message: FrontendMessage) -> () {
+        // The window being closed is no reason to stop counting.
+        if let Err(e) = self.frontend.send(message) {
+            warn!("Can't reach frontend: {}", e);
+        }
+    }
+}
+
+/// Work out the map runs from zone changes alone, the same way they'd have
+/// been recorded live. Used to rebuild history from an old Client.txt, which
+/// knows nothing about drops. The last map is left out, since there's no
+/// telling when it ended.
+pub fn replay<I>(events: I, config: Config) -> Vec<MapRun>
+where
+    I: IntoIterator<Item = ZoneEvent>,
+{
+    let (_inbox_sender, inbox) = channel();
+    let (price_sender, _price_receiver) = channel();
+    let (database_sender, database_receiver) = channel();
+    let (config_sender, _config_receiver) = channel();
+    let (clipboard_sender, _clipboard_receiver) = channel();
+    let (frontend_sender, _frontend_receiver) = channel();
+    let mut mastermind = Mastermind::new(
+        inbox,
+        price_sender,
+        database_sender,
+        config_sender,
+        clipboard_sender,
+        frontend_sender,
+        config,
+    );
+
+    for event in events {
+        let timestamp = event.timestamp();
+        mastermind.handle(MastermindMessage::Zone(event), timestamp);
+    }
+
+    database_receiver
+        .try_iter()
+        .filter_map(|message| match message {
+            DatabaseMessage::SaveMapRun { run, .. } => Some(run),
+            _ => None,
+        })
+        .collect()
+}
+
+fn is_safe_zone(zone: &str) -> bool {
+    zone.ends_with("Hideout") || TOWN_ZONES.contains(&zone)
+}
+
+/// A filter that can't be read leaves drops unsorted. Saving it through the
+/// settings says what's wrong with it.
+fn load_loot_filter(config: &Config) -> Option<LootFilter> {
+    let file = config.loot_filter_file.as_ref()?;
+    match LootFilter::load(file) {
+        Ok(filter) => Some(filter),
+        Err(e) => {
+            warn!("Not sorting drops by loot filter: {}", e);
+            None
+        }
+    }
+}
+
+/// The character named in the config, before the log says more about them.
+fn named_character(config: &Config) -> Option<Character> {
+    if config.character_name.is_empty() {
+        return None;
+    }
+    Some(Character {
+        name: config.character_name.clone(),
+        class: None,
+        level: None,
+    })
+}
+
+/// The area a map item opens, "Shaped Cage Map" goes to "Shaped Cage".
+fn map_zone(kind: &str) -> String {
+    kind.trim_end_matches(" Map").to_string()
+}
+
+/// What the map item knows about the run that the zone doesn't.
+fn take_rolls(run: &mut MapRun, map: Map) -> () {
+    run.tier = Some(map.tier);
+    run.mods = map.affixes;
+    run.item_quantity = Some(map.item_quantity);
+    run.item_rarity = Some(map.item_rarity);
+    run.pack_size = Some(map.pack_size);
+}
+
+/// Pass everything coming out of `receiver` on to the mastermind, wrapped up
+/// by `wrap`. Runs until either side hangs up.
+pub fn forward<T, F>(receiver: Receiver<T>, mastermind: Sender<MastermindMessage>, wrap: F)
+where
+    T: Send + 'static,
+    F: Fn(T) -> MastermindMessage + Send + 'static,
+{
+    thread::spawn(move || {
+        for message in receiver.iter() {
+            if mastermind.send(wrap(message)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+    use types::history::{HistoryFilter, HistoryQuery, HistoryResult};
+    use types::item::{Currency, ItemRarity, Map, StackSize};
+    use types::pricing::{MatchQuality, Price};
+    use types::trade::{Direction, Whisper};
+
+    fn mastermind() -> (
+        Mastermind,
+        Receiver<PriceMessage>,
+        Receiver<DatabaseMessage>,
+        Receiver<FrontendMessage>,
+    ) {
+        let (_inbox_sender, inbox) = mpsc::channel();
+        let (price_sender, price_receiver) = mpsc::channel();
+        let (database_sender, database_receiver) = mpsc::channel();
+        let (config_sender, _config_receiver) = mpsc::channel();
+        let (clipboard_sender, _clipboard_receiver) = mpsc::channel();
+        let (frontend_sender, frontend_receiver) = mpsc::channel();
+        (
+            Mastermind::new(
+                inbox,
+                price_sender,
+                database_sender,
+                config_sender,
+                clipboard_sender,
+                frontend_sender,
+                Config::default(),
+            ),
+            price_receiver,
+            database_receiver,
+            frontend_receiver,
+        )
+    }
+
+    fn chaos_orbs(count: u32) -> Item {
+        Item::Currency(Currency {
+            name: "Chaos Orb".to_string(),
+            stack_size: StackSize {
+                current: count,
+                max: 10,
+            },
+            affixes: Vec::new(),
+            description: String::new(),
+        })
+    }
+
+    fn shaped_cage_map() -> Item {
+        Item::Map(Map {
+            name: None,
+            kind: "Shaped Cage Map".to_string(),
+            tier: 11,
+            item_quantity: 64,
+            item_rarity: 38,
+            quality: 0,
+            pack_size: 22,
+            affixes: vec!["Area is inhabited by Goatmen".to_string()],
+            item_level: 78,
+            rarity: ItemRarity::Magical,
+        })
+    }
+
+    fn price_response(id: u64, chaos: f32) -> MastermindMessage {
+        MastermindMessage::Price(PriceMessage::Response {
+            id,
+            item: "Chaos Orb".to_string(),
+            price: Price {
+                name: "Chaos Orb".to_string(),
+                chaos_equivalent: chaos,
+                variant: None,
+            },
+            quality: MatchQuality::Exact,
+        })
+    }
+
+    fn enter(zone: &str, at: DateTime<Local>) -> MastermindMessage {
+        MastermindMessage::Zone(ZoneEvent::ZoneChange(zone.to_string(), at))
+    }
+
+    fn load(seed: u64, at: DateTime<Local>) -> MastermindMessage {
+        MastermindMessage::Zone(ZoneEvent::LoadingStarted(Some(seed), at))
+    }
+
+    #[test]
+    fn should_show_drops_before_their_price() {
+        let (mut mastermind, prices, _database, frontend) = mastermind();
+        let now = Local::now();
+
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(3)), now);
+        assert_eq!(
+            frontend.try_recv().unwrap(),
+            FrontendMessage::ItemDropped {
+                id: 0,
+                name: "Chaos Orb".to_string(),
+                filter_tier: None,
+            }
+        );
+        match prices.try_recv().unwrap() {
+            PriceMessage::Get { id, item } => {
+                assert_eq!(id, 0);
+                assert_eq!(item.name, "Chaos Orb".to_string())
+            }
+            other => panic!("Expected price request, got {:?}", other),
+        }
+
+        mastermind.handle(price_response(0, 1.0), now);
+        assert_eq!(
+            frontend.try_recv().unwrap(),
+            FrontendMessage::PriceUpdated {
+                id: 0,
+                chaos_equivalent: 3.0,
+                quality: MatchQuality::Exact,
+            }
+        );
+    }
+
+    #[test]
+    fn should_not_shift_prices_when_an_answer_is_lost() {
+        let (mut mastermind, _prices, _database, frontend) = mastermind();
+        let now = Local::now();
+
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(3)), now);
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(5)), now);
+        // The answer for the first one never comes.
+        mastermind.handle(price_response(1, 2.0), now);
+        mastermind.handle(price_response(1, 2.0), now);
+
+        let updates: Vec<_> = frontend
+            .try_iter()
+            .filter(|m| match m {
+                FrontendMessage::PriceUpdated { .. } => true,
+                _ => false,
+            })
+            .collect();
+        assert_eq!(
+            updates,
+            vec![FrontendMessage::PriceUpdated {
+                id: 1,
+                chaos_equivalent: 10.0,
+                quality: MatchQuality::Exact,
+            }]
+        );
+        assert!(mastermind.is_awaiting_price(0));
+    }
+
+    #[test]
+    fn should_sort_drops_by_loot_filter() {
+        let (mut mastermind, _prices, _database, frontend) = mastermind();
+        mastermind.loot_filter = Some(
+            LootFilter::parse("Show # $type->currency $tier->t5\n    BaseType \"Chaos Orb\"\n")
+                .unwrap(),
+        );
+        let now = Local::now();
+
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(3)), now);
+        match frontend.try_recv().unwrap() {
+            FrontendMessage::ItemDropped { filter_tier, .. } => {
+                assert_eq!(filter_tier.unwrap().tier, "currency t5")
+            }
+            other => panic!("Expected drop, got {:?}", other),
+        }
+        assert_eq!(
+            mastermind.session.unassigned_drops[0].filter_tier,
+            Some("currency t5".to_string())
+        );
+    }
+
+    #[test]
+    fn should_count_each_drop_once() {
+        let (mut mastermind, _prices, _database, frontend) = mastermind();
+        let at = |minute| Local.ymd(2018, 11, 22).and_hms(5, minute, 0);
+
+        mastermind.handle(enter("Shaped Cage", at(0)), at(0));
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(3)), at(1));
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(7)), at(2));
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(3)), at(3));
+        mastermind.handle(enter("Dunes", at(10)), at(10));
+        // Still in the inventory from the last map.
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(7)), at(11));
+        // But the same stack dropping well after that is loot again.
+        let later = Local.ymd(2018, 11, 22).and_hms(7, 0, 0);
+        mastermind.handle(enter("Strand", later), later);
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(7)), later);
+
+        let dropped = frontend
+            .try_iter()
+            .filter(|m| match m {
+                FrontendMessage::ItemDropped { .. } => true,
+                _ => false,
+            })
+            .count();
+        assert_eq!(dropped, 3);
+    }
+
+    #[test]
+    fn should_take_back_last_drop() {
+        let (mut mastermind, _prices, _database, frontend) = mastermind();
+        let now = Local::now();
+
+        mastermind.handle(enter("Shaped Cage", now), now);
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(3)), now);
+        mastermind.handle(price_response(0, 1.0), now);
+        let undo = || MastermindMessage::Command(FrontendCommand::UndoLastDrop);
+        mastermind.handle(undo(), now);
+        mastermind.handle(undo(), now);
+
+        let messages: Vec<_> = frontend.try_iter().collect();
+        assert!(messages.contains(&FrontendMessage::DropRemoved { id: 0 }));
+        assert_eq!(
+            messages.last(),
+            Some(&FrontendMessage::Error {
+                message: "No drop to take back".to_string()
+            })
+        );
+        assert_eq!(mastermind.session.chaos_value(), 0.0);
+    }
+
+    #[test]
+    fn should_count_drops_copied_again_after_taking_them_back() {
+        let (mut mastermind, _prices, _database, frontend) = mastermind();
+        let now = Local::now();
+
+        mastermind.handle(enter("Shaped Cage", now), now);
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(3)), now);
+        mastermind.handle(
+            MastermindMessage::Command(FrontendCommand::UndoLastDrop),
+            now,
+        );
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(3)), now);
+
+        let dropped: Vec<u64> = frontend
+            .try_iter()
+            .filter_map(|m| match m {
+                FrontendMessage::ItemDropped { id, .. } => Some(id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(dropped, vec![0, 1]);
+    }
+
+    #[test]
+    fn should_start_map_from_copied_map() {
+        let (mut mastermind, _prices, _database, _frontend) = mastermind();
+        let at = |second| Local.ymd(2018, 11, 22).and_hms(5, 0, second);
+
+        mastermind.handle(MastermindMessage::Command(FrontendCommand::StartMapFromCopy), at(0));
+        mastermind.handle(MastermindMessage::Item(shaped_cage_map()), at(1));
+
+        let run = mastermind.session.current_map.as_ref().unwrap();
+        assert_eq!(run.name, "Shaped Cage");
+        assert_eq!(run.tier, Some(11));
+        assert_eq!(run.mods.len(), 1);
+        assert!(run.drops.is_empty());
+    }
+
+    #[test]
+    fn should_count_drops_copied_instead_of_a_map() {
+        let (mut mastermind, _prices, _database, _frontend) = mastermind();
+        let at = |second| Local.ymd(2018, 11, 22).and_hms(5, 0, second);
+
+        mastermind.handle(enter("Shaped Cage", at(0)), at(0));
+        mastermind.handle(MastermindMessage::Command(FrontendCommand::StartMapFromCopy), at(1));
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(3)), at(2));
+
+        let run = mastermind.session.current_map.as_ref().unwrap();
+        assert_eq!(run.name, "Shaped Cage");
+        assert_eq!(run.drops.len(), 1);
+    }
+
+    #[test]
+    fn should_give_queued_map_to_next_run_of_its_area() {
+        let (mut mastermind, _prices, _database, frontend) = mastermind();
+        let now = Local::now();
+
+        mastermind.handle(enter("Celestial Hideout", now), now);
+        mastermind.handle(MastermindMessage::Item(shaped_cage_map()), now);
+        mastermind.handle(enter("Dunes", now), now);
+        assert_eq!(mastermind.session.current_map.as_ref().unwrap().tier, None);
+        mastermind.handle(enter("Shaped Cage", now), now);
+        // Dropped in there, so it's loot.
+        mastermind.handle(MastermindMessage::Item(shaped_cage_map()), now);
+
+        let run = mastermind.session.current_map.as_ref().unwrap();
+        assert_eq!(run.tier, Some(11));
+        assert_eq!(run.item_quantity, Some(64));
+        assert_eq!(run.drops.len(), 1);
+        assert!(mastermind.queued_map.is_none());
+        assert!(frontend.try_iter().any(|m| m
+            == FrontendMessage::MapQueued {
+                name: "Shaped Cage".to_string(),
+                tier: 11
+            }));
+    }
+
+    #[test]
+    fn should_not_count_drops_while_paused() {
+        let (mut mastermind, _prices, _database, frontend) = mastermind();
+        let now = Local::now();
+        let pause = || MastermindMessage::Command(FrontendCommand::TogglePause);
+
+        mastermind.handle(enter("Shaped Cage", now), now);
+        mastermind.handle(pause(), now);
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(3)), now);
+        mastermind.handle(enter("Dunes", now), now);
+        mastermind.handle(pause(), now);
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(4)), now);
+        mastermind.handle(MastermindMessage::Command(FrontendCommand::TagLastDrop), now);
+
+        assert_eq!(mastermind.session.maps_run, 1);
+        let run = mastermind.session.current_map.as_ref().unwrap();
+        assert_eq!(run.name, "Dunes");
+        assert_eq!(run.drops.len(), 1);
+        assert!(run.drops[0].tagged);
+        let messages: Vec<_> = frontend.try_iter().collect();
+        assert!(messages.contains(&FrontendMessage::Paused { paused: true }));
+        assert!(messages.contains(&FrontendMessage::Paused { paused: false }));
+        assert!(messages.contains(&FrontendMessage::DropTagged {
+            id: 0,
+            tagged: true
+        }));
+    }
+
+    #[test]
+    fn should_keep_map_across_hideout_visits() {
+        let (mut mastermind, _prices, _database, frontend) = mastermind();
+        let start = Local.ymd(2018, 11, 22).and_hms(5, 0, 0);
+
+        mastermind.handle(load(1, start), start);
+        mastermind.handle(enter("Shaped Cage", start), start);
+        mastermind.handle(load(2, start), start);
+        mastermind.handle(enter("Celestial Hideout", start), start);
+        mastermind.handle(load(1, start), start);
+        mastermind.handle(enter("Shaped Cage", start), start);
+
+        let started: Vec<_> = frontend
+            .try_iter()
+            .filter(|m| match m {
+                FrontendMessage::MapStarted { .. } => true,
+                _ => false,
+            })
+            .collect();
+        assert_eq!(started.len(), 1);
+        assert_eq!(mastermind.session.maps_run, 0);
+    }
+
+    #[test]
+    fn should_start_new_run_in_new_instance_of_same_map() {
+        let (mut mastermind, _prices, _database, _frontend) = mastermind();
+        let now = Local::now();
+
+        mastermind.handle(load(1, now), now);
+        mastermind.handle(enter("Shaped Cage", now), now);
+        mastermind.handle(load(2, now), now);
+        mastermind.handle(enter("Celestial Hideout", now), now);
+        mastermind.handle(MastermindMessage::Item(shaped_cage_map()), now);
+        mastermind.handle(load(3, now), now);
+        mastermind.handle(enter("Shaped Cage", now), now);
+
+        assert_eq!(mastermind.session.maps_run, 1);
+        let run = mastermind.session.current_map.as_ref().unwrap();
+        assert_eq!(run.name, "Shaped Cage");
+        assert_eq!(run.tier, Some(11));
+        assert!(mastermind.queued_map.is_none());
+    }
+
+    #[test]
+    fn should_compare_running_total_to_previous_map() {
+        let (mut mastermind, _prices, _database, frontend) = mastermind();
+        let start = Local.ymd(2018, 11, 22).and_hms(5, 0, 0);
+        let later = Local.ymd(2018, 11, 22).and_hms(5, 10, 0);
+
+        mastermind.handle(enter("Shaped Cage", start), start);
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(10)), start);
+        mastermind.handle(price_response(0, 1.0), start);
+        mastermind.handle(enter("Shaped Underground Sea", later), later);
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(2)), later);
+        mastermind.handle(
+            price_response(1, 1.0),
+            later + ::chrono::Duration::minutes(6),
+        );
+
+        let last_total = frontend
+            .try_iter()
+            .filter(|m| match m {
+                FrontendMessage::RunningTotal { .. } => true,
+                _ => false,
+            })
+            .last();
+        assert_eq!(
+            last_total,
+            Some(FrontendMessage::RunningTotal {
+                map_chaos_value: 2.0,
+                map_chaos_per_hour: 20.0,
+                previous_map_chaos_value: Some(10.0),
+            })
+        );
+        assert_eq!(mastermind.session.maps_run, 1);
+        assert_eq!(mastermind.session.chaos_value(), 12.0);
+    }
+
+    #[test]
+    fn should_price_trades_once_they_go_through() {
+        let (mut mastermind, prices, database, _frontend) = mastermind();
+        let now = Local.ymd(2018, 11, 22).and_hms(5, 0, 0);
+        let whisper = Whisper {
+            direction: Direction::Incoming,
+            player: "Buyer".to_string(),
+            message: "Hi, I would like to buy your Headhunter listed for 2 exa in Betrayal"
+                .to_string(),
+        };
+
+        mastermind.handle(MastermindMessage::Zone(ZoneEvent::Whisper(whisper, now)), now);
+        mastermind.handle(
+            MastermindMessage::Zone(ZoneEvent::JoinedArea("Buyer".to_string(), now)),
+            now,
+        );
+        mastermind.handle(MastermindMessage::Zone(ZoneEvent::TradeAccepted(now)), now);
+
+        match prices.try_recv().unwrap() {
+            PriceMessage::Get { item, .. } => assert_eq!(item.name, "Exalted Orb"),
+            other => panic!("Expected price request, got {:?}", other),
+        }
+        mastermind.handle(price_response(0, 150.0), now);
+        let saved: Vec<_> = database
+            .try_iter()
+            .map(|message| match message {
+                DatabaseMessage::SaveTrade { trade } => trade,
+                other => panic!("Expected a trade to save, got {:?}", other),
+            })
+            .collect();
+        // Once when whispered, once when accepted and once when priced.
+        assert_eq!(saved.len(), 3);
+        assert_eq!(saved[0].completed_at, None);
+        assert_eq!(saved[1].chaos_value, None);
+        assert_eq!(saved[2].chaos_value, Some(300.0));
+        assert_eq!(saved[2].completed_at, Some(now.timestamp()));
+    }
+
+    #[test]
+    fn should_not_value_trades_in_unknown_currency() {
+        let (mut mastermind, _prices, database, _frontend) = mastermind();
+        let now = Local.ymd(2018, 11, 22).and_hms(5, 0, 0);
+        let whisper = Whisper {
+            direction: Direction::Incoming,
+            player: "Buyer".to_string(),
+            message: "Hi, I would like to buy your Headhunter listed for 2 shiny in Betrayal"
+                .to_string(),
+        };
+
+        mastermind.handle(MastermindMessage::Zone(ZoneEvent::Whisper(whisper, now)), now);
+        mastermind.handle(
+            MastermindMessage::Zone(ZoneEvent::JoinedArea("Buyer".to_string(), now)),
+            now,
+        );
+        mastermind.handle(MastermindMessage::Zone(ZoneEvent::TradeAccepted(now)), now);
+        mastermind.handle(
+            MastermindMessage::Price(PriceMessage::Response {
+                id: 0,
+                item: "shiny".to_string(),
+                price: Price {
+                    name: "Chaos Orb".to_string(),
+                    chaos_equivalent: 1.0,
+                    variant: None,
+                },
+                quality: MatchQuality::Unpriced,
+            }),
+            now,
+        );
+
+        let saved: Vec<_> = database
+            .try_iter()
+            .map(|message| match message {
+                DatabaseMessage::SaveTrade { trade } => trade,
+                other => panic!("Expected a trade to save, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(saved.len(), 2);
+        assert!(saved.iter().all(|trade| trade.chaos_value.is_none()));
+    }
+
+    #[test]
+    fn should_save_finished_maps() {
+        let (mut mastermind, _prices, database, _frontend) = mastermind();
+        let start = Local.ymd(2018, 11, 22).and_hms(5, 0, 0);
+        let later = Local.ymd(2018, 11, 22).and_hms(5, 10, 0);
+
+        mastermind.handle(enter("Shaped Cage", start), start);
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(10)), start);
+        mastermind.handle(price_response(0, 1.0), start);
+        mastermind.handle(enter("Dunes", later), later);
+
+        match database.try_recv().unwrap() {
+            DatabaseMessage::SaveMapRun { league, run } => {
+                assert_eq!(league, Config::default().league);
+                assert_eq!(run.name, "Shaped Cage".to_string());
+                assert_eq!(run.ended_at, Some(later));
+                assert_eq!(run.chaos_value(), 10.0);
+            }
+            other => panic!("Expected a map run to save, got {:?}", other),
+        }
+        assert!(database.try_recv().is_err());
+    }
+
+    #[test]
+    fn should_save_maps_once_their_prices_are_in() {
+        let (mut mastermind, _prices, database, _frontend) = mastermind();
+        let start = Local.ymd(2018, 11, 22).and_hms(5, 0, 0);
+        let later = Local.ymd(2018, 11, 22).and_hms(5, 10, 0);
+
+        mastermind.handle(enter("Shaped Cage", start), start);
+        mastermind.handle(MastermindMessage::Item(chaos_orbs(10)), start);
+        mastermind.handle(enter("Dunes", later), later);
+        // poe.ninja is slow today.
+        assert!(database.try_recv().is_err());
+
+        mastermind.handle(price_response(0, 1.0), later);
+        match database.try_recv().unwrap() {
+            DatabaseMessage::SaveMapRun { run, .. } => {
+                assert_eq!(run.name, "Shaped Cage".to_string());
+                assert_eq!(run.chaos_value(), 10.0);
+            }
+            other => panic!("Expected a map run to save, got {:?}", other),
+        }
+        assert_eq!(mastermind.session.chaos_value(), 10.0);
+    }
+
+    #[test]
+    fn should_save_current_map_on_shutdown() {
+        let (mut mastermind, _prices, database, _frontend) = mastermind();
+        let start = Local.ymd(2018, 11, 22).and_hms(5, 0, 0);
+        let later = Local.ymd(2018, 11, 22).and_hms(5, 10, 0);
+
+        mastermind.handle(enter("Shaped Cage", start), start);
+        mastermind.shut_down(later);
+
+        match database.try_recv().unwrap() {
+            DatabaseMessage::SaveMapRun { run, .. } => {
+                assert_eq!(run.name, "Shaped Cage".to_string());
+                assert_eq!(run.ended_at, Some(later));
+            }
+            other => panic!("Expected a map run to save, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_pass_history_between_frontend_and_database() {
+        let (mut mastermind, _prices, database, frontend) = mastermind();
+        let now = Local::now();
+
+        let query = FrontendCommand::QueryHistory {
+            query: HistoryQuery::MapRuns,
+            filter: HistoryFilter::default(),
+        };
+        mastermind.handle(MastermindMessage::Command(query), now);
+        match database.try_recv().unwrap() {
+            DatabaseMessage::Query { query, .. } => assert_eq!(query, HistoryQuery::MapRuns),
+            other => panic!("Expected a history query, got {:?}", other),
+        }
+
+        let result = HistoryResult::MapRuns(Vec::new());
+        mastermind.handle(
+            MastermindMessage::Database(DatabaseMessage::History(result.clone())),
+            now,
+        );
+        assert_eq!(
+            frontend.try_recv().unwrap(),
+            FrontendMessage::History { result }
+        );
+    }
+
+    #[test]
+    fn should_send_invalid_settings_back() {
+        let (mut mastermind, _prices, _database, frontend) = mastermind();
+        let config = Config {
+            league: String::new(),
+            ..Config::default()
+        };
+
+        mastermind.handle(
+            MastermindMessage::Command(FrontendCommand::SaveSettings { config }),
+            Local::now(),
+        );
+        match frontend.try_recv().unwrap() {
+            FrontendMessage::SettingsInvalid { problems } => assert_eq!(problems.len(), 1),
+            other => panic!("Expected complaints, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_replay_zone_changes_into_map_runs() {
+        let at = |minute| Local.ymd(2018, 11, 22).and_hms(5, minute, 0);
+        let events = vec![
+            ZoneEvent::ZoneChange("Celestial Hideout".to_string(), at(0)),
+            ZoneEvent::ZoneChange("Shaped Cage".to_string(), at(1)),
+            ZoneEvent::ZoneChange("Celestial Hideout".to_string(), at(8)),
+            ZoneEvent::ZoneChange("Shaped Cage".to_string(), at(9)),
+            ZoneEvent::ZoneChange("Celestial Hideout".to_string(), at(12)),
+            ZoneEvent::ZoneChange("Dunes".to_string(), at(15)),
+        ];
+
+        let runs = replay(events, Config::default());
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].name, "Shaped Cage".to_string());
+        assert_eq!(runs[0].started_at, at(1));
+        assert_eq!(runs[0].ended_at, Some(at(15)));
+    }
+
+    #[test]
+    fn should_count_our_deaths_in_the_map_they_happened_in() {
+        let at = |minute| Local.ymd(2018, 11, 22).and_hms(5, minute, 0);
+        let events = vec![
+            ZoneEvent::ZoneChange("Shaped Cage".to_string(), at(1)),
+            ZoneEvent::Death("Nightblade".to_string(), at(4)),
+            ZoneEvent::Death("SomebodyElse".to_string(), at(5)),
+            ZoneEvent::ZoneChange("Celestial Hideout".to_string(), at(5)),
+            ZoneEvent::ZoneChange("Shaped Cage".to_string(), at(6)),
+            ZoneEvent::Death("Nightblade".to_string(), at(7)),
+            ZoneEvent::ZoneChange("Dunes".to_string(), at(15)),
+        ];
+        let config = Config {
+            character_name: "Nightblade".to_string(),
+            ..Config::default()
+        };
+
+        let runs = replay(events, config);
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].deaths, 2);
+    }
+
+    #[test]
+    fn should_put_the_character_on_their_runs() {
+        let at = |minute| Local.ymd(2018, 11, 22).and_hms(5, minute, 0);
+        let level_up = |name: &str, level, minute| {
+            ZoneEvent::LevelUp(name.to_string(), "Assassin".to_string(), level, at(minute))
+        };
+        let events = vec![
+            ZoneEvent::ZoneChange("Shaped Cage".to_string(), at(1)),
+            level_up("Nightblade", 91, 4),
+            ZoneEvent::ZoneChange("Dunes".to_string(), at(10)),
+            // Somebody in the party, it's still Nightblade's run.
+            level_up("Daybreak", 70, 12),
+            ZoneEvent::Death("Nightblade".to_string(), at(13)),
+            ZoneEvent::ZoneChange("Nightblade's Hideout".to_string(), at(20)),
+            ZoneEvent::JoinedArea("Daybreak".to_string(), at(21)),
+            // Visiting, so not who's playing either.
+            level_up("Daybreak", 71, 22),
+            ZoneEvent::Death("Daybreak".to_string(), at(23)),
+            ZoneEvent::ZoneChange("Arcade".to_string(), at(30)),
+            ZoneEvent::ZoneChange("Port".to_string(), at(40)),
+        ];
+
+        let runs = replay(events, Config::default());
+        let runners: Vec<_> = runs
+            .iter()
+            .map(|run| run.character.as_ref().map(|c| (c.name.as_str(), c.level)))
+            .collect();
+        assert_eq!(
+            runners,
+            vec![
+                Some(("Nightblade", Some(91))),
+                Some(("Nightblade", Some(91))),
+                Some(("Nightblade", Some(91)))
+            ]
+        );
+        assert_eq!(runs[1].deaths, 1);
+        assert_eq!(runs[2].deaths, 0);
+    }
+
+    #[test]
+    fn should_save_the_session_with_who_played() {
+        let (mut mastermind, _prices, database, _frontend) = mastermind();
+        let now = Local.ymd(2018, 11, 22).and_hms(5, 0, 0);
+
+        mastermind.handle(
+            MastermindMessage::Zone(ZoneEvent::LevelUp(
+                "Nightblade".to_string(),
+                "Assassin".to_string(),
+                91,
+                now,
+            )),
+            now,
+        );
+        mastermind.shut_down(now);
+        match database.try_recv().unwrap() {
+            DatabaseMessage::SaveSession { session, .. } => {
+                assert_eq!(session.characters.len(), 1);
+                assert_eq!(session.characters[0].name, "Nightblade");
+                assert_eq!(session.characters[0].level, Some(91));
+            }
+            other => panic!("Expected the session to save, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,454 @@
+//! Resolve parsed items to the name poe.ninja knows them by. Tooltip names are
+//! decorated in ways the price data isn't -- "Superior" on quality gear, map
+//! qualifiers like "Shaped", affixes around the base type of magic items -- so
+//! an exact name lookup misses a lot. The cache here normalises names on the
+//! way in and out, and falls back to base types and the closest known name,
+//! reporting how good the match was.
+use std::collections::HashMap;
+
+use types::item::{Item, ItemRarity};
+use types::pricing::{ItemKind, MatchQuality, Price, PriceKey};
+
+/// Prefixes that the game adds to a name but poe.ninja doesn't list.
+const NAME_QUALIFIERS: &[&str] = &["Superior "];
+
+/// Map qualifiers that don't change which map it is, price wise.
+const MAP_QUALIFIERS: &[&str] = &["Shaped ", "Elder ", "Shaper "];
+
+/// Map qualifiers that poe.ninja prices in their own category.
+const BLIGHTED_MAP_QUALIFIERS: &[&str] = &["Blighted ", "Blight-ravaged "];
+
+/// How fragments and splinters are named, besides Breachstones.
+const FRAGMENT_PREFIXES: &[&str] = &[
+    "Sacrifice at ",
+    "Mortal ",
+    "Fragment of ",
+    "Splinter of ",
+    "Timeless ",
+    "Offering to the Goddess",
+    "Divine Vessel",
+    "Simulacrum",
+];
+
+/// Kinds that all copy as currency. A name can't always tell them apart, so
+/// a price looked up as one of them is also looked for in the others.
+const CURRENCY_KINDS: &[ItemKind] = &[
+    ItemKind::Currency,
+    ItemKind::Fragment,
+    ItemKind::Scarab,
+    ItemKind::Incubator,
+    ItemKind::Oil,
+];
+
+/// How similar two names have to be before a fuzzy match is taken seriously.
+const FUZZY_THRESHOLD: f32 = 0.85;
+
+/// Build the key to look up the price of an item with.
+pub fn resolve_price_key(item: &Item) -> PriceKey {
+    match item {
+        Item::Currency(c) => PriceKey {
+            kind: Some(currency_kind(&c.name)),
+            name: c.name.clone(),
+        },
+        Item::DivinationCard(d) => PriceKey {
+            kind: Some(ItemKind::DivinationCard),
+            name: d.name.clone(),
+        },
+        Item::UniqueStub(u) => PriceKey {
+            kind: Some(ItemKind::Unique),
+            name: u.name.clone(),
+        },
+        Item::Map(m) => match (&m.rarity, &m.name) {
+            (ItemRarity::Unique, Some(name)) => PriceKey {
+                kind: Some(ItemKind::UniqueMap),
+                name: name.clone(),
+            },
+            // Rare and magic maps carry a random name, only the kind is useful.
+            _ => PriceKey {
+                kind: Some(
+                    if BLIGHTED_MAP_QUALIFIERS
+                        .iter()
+                        .any(|q| m.kind.starts_with(q))
+                    {
+                        ItemKind::BlightedMap
+                    } else {
+                        ItemKind::Map
+                    },
+                ),
+                name: m.kind.clone(),
+            },
+        },
+        Item::Gear(g) => PriceKey {
+            kind: Some(match g.rarity {
+                ItemRarity::Unique => ItemKind::Unique,
+                _ => ItemKind::BaseType,
+            }),
+            name: g.name.clone(),
+        },
+    }
+}
+
+/// Which of the `CURRENCY_KINDS` something copied as currency is.
+fn currency_kind(name: &str) -> ItemKind {
+    if name.ends_with(" Scarab") {
+        ItemKind::Scarab
+    } else if name.ends_with(" Incubator") {
+        ItemKind::Incubator
+    } else if name.ends_with(" Oil") {
+        ItemKind::Oil
+    } else if name.ends_with(" Breachstone")
+        || FRAGMENT_PREFIXES.iter().any(|p| name.starts_with(p))
+    {
+        ItemKind::Fragment
+    } else {
+        ItemKind::Currency
+    }
+}
+
+/// Bring a name into the shape the cache is keyed on: no qualifiers, single
+/// spaces, lower case.
+pub fn normalise_name(name: &str, kind: Option<ItemKind>) -> String {
+    let mut name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let qualifiers = match kind {
+        Some(ItemKind::Map) => [NAME_QUALIFIERS, MAP_QUALIFIERS].concat(),
+        _ => NAME_QUALIFIERS.to_vec(),
+    };
+    // Qualifiers can stack ("Superior Shaped ..."), so keep going until none match.
+    while let Some(q) = qualifiers.iter().find(|q| name.starts_with(*q)) {
+        name = name[q.len()..].to_string();
+    }
+
+    name.to_lowercase()
+}
+
+/// Prices by kind and normalised name.
+#[derive(Debug, Default)]
+pub struct PriceCache {
+    prices: HashMap<ItemKind, HashMap<String, Price>>,
+}
+
+impl PriceCache {
+    pub fn new() -> PriceCache {
+        PriceCache {
+            prices: HashMap::new(),
+        }
+    }
+
+    /// Lines for one version of an item are also kept as "name (variant)".
+    /// Tooltips don't say which version they are, so the plain name goes to
+    /// the line without a variant, or to the first version until there is one.
+    pub fn insert(&mut self, kind: ItemKind, price: Price) {
+        let key = normalise_name(&price.name, Some(kind));
+        let prices = self.prices.entry(kind).or_insert_with(HashMap::new);
+        if let Some(ref variant) = price.variant {
+            prices.insert(
+                format!("{} ({})", key, variant.to_lowercase()),
+                price.clone(),
+            );
+            if prices.contains_key(&key) {
+                return;
+            }
+        }
+        prices.insert(key, price);
+    }
+
+    pub fn len(&self) -> usize {
+        self.prices.values().map(|p| p.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Find the best price for `key`, trying in order: the normalised name,
+    /// a known base type contained in the name, and the closest name of the
+    /// same kind.
+    pub fn lookup(&self, key: &PriceKey) -> Option<(Price, MatchQuality)> {
+        self.lookup_normalised(key)
+            .or_else(|| self.lookup_base_type(key))
+            .or_else(|| self.lookup_fuzzy(key))
+    }
+
+    fn kinds_for(&self, key: &PriceKey) -> Vec<ItemKind> {
+        match key.kind {
+            Some(kind) if CURRENCY_KINDS.contains(&kind) => {
+                let mut kinds = vec![kind];
+                kinds.extend(CURRENCY_KINDS.iter().cloned().filter(|&k| k != kind));
+                kinds
+            }
+            Some(kind) => vec![kind],
+            None => self.prices.keys().cloned().collect(),
+        }
+    }
+
+    fn lookup_normalised(&self, key: &PriceKey) -> Option<(Price, MatchQuality)> {
+        self.kinds_for(key)
+            .into_iter()
+            .filter_map(|kind| {
+                let normalised = normalise_name(&key.name, Some(kind));
+                self.prices.get(&kind).and_then(|p| p.get(&normalised))
+            })
+            .next()
+            .map(|price| {
+                let quality = if price.name == key.name {
+                    MatchQuality::Exact
+                } else {
+                    MatchQuality::Normalised
+                };
+                (price.clone(), quality)
+            })
+    }
+
+    /// Magic items are named "<prefix> <base type> <suffix>", so the longest
+    /// base type that appears as whole words in the name is the item's base.
+    fn lookup_base_type(&self, key: &PriceKey) -> Option<(Price, MatchQuality)> {
+        match key.kind {
+            Some(ItemKind::BaseType) | None => {}
+            _ => return None,
+        }
+
+        let name = format!(" {} ", normalise_name(&key.name, key.kind));
+        self.prices
+            .get(&ItemKind::BaseType)?
+            .iter()
+            .filter(|(base, _)| name.contains(&format!(" {} ", base)))
+            .max_by_key(|(base, _)| base.len())
+            .map(|(_, price)| (price.clone(), MatchQuality::BaseType))
+    }
+
+    fn lookup_fuzzy(&self, key: &PriceKey) -> Option<(Price, MatchQuality)> {
+        let mut best: Option<(&Price, f32)> = None;
+        for kind in self.kinds_for(key) {
+            let name = normalise_name(&key.name, Some(kind));
+            let candidates = match self.prices.get(&kind) {
+                Some(c) => c,
+                None => continue,
+            };
+            for (candidate, price) in candidates {
+                let score = similarity(&name, candidate);
+                if best.map_or(true, |(_, s)| score > s) {
+                    best = Some((price, score));
+                }
+            }
+        }
+
+        match best {
+            Some((price, score)) if score >= FUZZY_THRESHOLD => {
+                Some((price.clone(), MatchQuality::Fuzzy(score)))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Similarity of two strings between 0 and 1, based on their edit distance.
+fn similarity(a: &str, b: &str) -> f32 {
+    let longest = a.chars().count().max(b.chars().count());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - (levenshtein(a, b) as f32 / longest as f32)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use types::item::{Currency, Map, StackSize};
+
+    fn price(name: &str, chaos: f32) -> Price {
+        Price {
+            name: name.to_string(),
+            chaos_equivalent: chaos,
+            variant: None,
+        }
+    }
+
+    fn cache() -> PriceCache {
+        let mut cache = PriceCache::new();
+        cache.insert(ItemKind::Currency, price("Exalted Orb", 80.0));
+        cache.insert(ItemKind::Map, price("Cage Map", 3.0));
+        cache.insert(ItemKind::BaseType, price("Heavy Belt", 1.0));
+        cache.insert(ItemKind::BaseType, price("Belt", 0.5));
+        cache.insert(ItemKind::Unique, price("Inpulsa's Broken Heart", 40.0));
+        cache
+    }
+
+    #[test]
+    fn should_match_exact_names() {
+        let (price, quality) = cache().lookup(&PriceKey::named("Exalted Orb")).unwrap();
+        assert_eq!(price.chaos_equivalent, 80.0);
+        assert_eq!(quality, MatchQuality::Exact);
+    }
+
+    #[test]
+    fn should_strip_map_qualifiers() {
+        let item = Item::Map(Map {
+            name: None,
+            kind: "Shaped Cage Map".to_string(),
+            tier: 8,
+            item_quantity: 0,
+            item_rarity: 0,
+            quality: 0,
+            pack_size: 0,
+            affixes: Vec::new(),
+            item_level: 75,
+            rarity: ItemRarity::Normal,
+        });
+        let (price, quality) = cache().lookup(&resolve_price_key(&item)).unwrap();
+        assert_eq!(price.name, "Cage Map".to_string());
+        assert_eq!(quality, MatchQuality::Normalised);
+    }
+
+    #[test]
+    fn should_price_blighted_maps_separately() {
+        let item = Item::Map(Map {
+            name: None,
+            kind: "Blighted Cage Map".to_string(),
+            tier: 8,
+            item_quantity: 0,
+            item_rarity: 0,
+            quality: 0,
+            pack_size: 0,
+            affixes: Vec::new(),
+            item_level: 75,
+            rarity: ItemRarity::Normal,
+        });
+        let mut cache = cache();
+        cache.insert(ItemKind::BlightedMap, price("Blighted Cage Map", 12.0));
+
+        let (price, quality) = cache.lookup(&resolve_price_key(&item)).unwrap();
+        assert_eq!(price.chaos_equivalent, 12.0);
+        assert_eq!(quality, MatchQuality::Exact);
+    }
+
+    #[test]
+    fn should_price_fragments_as_fragments() {
+        let orbs = |name: &str| {
+            Item::Currency(Currency {
+                name: name.to_string(),
+                stack_size: StackSize {
+                    current: 1,
+                    max: 10,
+                },
+                affixes: Vec::new(),
+                description: String::new(),
+            })
+        };
+        let kind = |name: &str| resolve_price_key(&orbs(name)).kind;
+        assert_eq!(kind("Mortal Hope"), Some(ItemKind::Fragment));
+        assert_eq!(kind("Xoph's Breachstone"), Some(ItemKind::Fragment));
+        assert_eq!(kind("Gilded Ambush Scarab"), Some(ItemKind::Scarab));
+        assert_eq!(kind("Diviner's Incubator"), Some(ItemKind::Incubator));
+        assert_eq!(kind("Golden Oil"), Some(ItemKind::Oil));
+        assert_eq!(kind("Chaos Orb"), Some(ItemKind::Currency));
+
+        // Priced as currency by a list of categories that doesn't know better.
+        let mut cache = cache();
+        cache.insert(ItemKind::Currency, price("Mortal Hope", 60.0));
+        let (price, quality) = cache
+            .lookup(&resolve_price_key(&orbs("Mortal Hope")))
+            .unwrap();
+        assert_eq!(price.chaos_equivalent, 60.0);
+        assert_eq!(quality, MatchQuality::Exact);
+    }
+
+    #[test]
+    fn should_strip_superior() {
+        let key = PriceKey {
+            kind: Some(ItemKind::Unique),
+            name: "Superior Inpulsa's Broken Heart".to_string(),
+        };
+        let (_, quality) = cache().lookup(&key).unwrap();
+        assert_eq!(quality, MatchQuality::Normalised);
+    }
+
+    #[test]
+    fn should_fall_back_to_longest_base_type() {
+        let key = PriceKey {
+            kind: Some(ItemKind::BaseType),
+            name: "Rotund Heavy Belt of the Whale".to_string(),
+        };
+        let (price, quality) = cache().lookup(&key).unwrap();
+        assert_eq!(price.name, "Heavy Belt".to_string());
+        assert_eq!(quality, MatchQuality::BaseType);
+    }
+
+    #[test]
+    fn should_fall_back_to_closest_match() {
+        let key = PriceKey {
+            kind: Some(ItemKind::Unique),
+            name: "Inpulsas Broken Heart".to_string(),
+        };
+        match cache().lookup(&key) {
+            Some((price, MatchQuality::Fuzzy(score))) => {
+                assert_eq!(price.name, "Inpulsa's Broken Heart".to_string());
+                assert!(score > FUZZY_THRESHOLD);
+            }
+            other => panic!("Expected fuzzy match, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn should_keep_variants_apart() {
+        let mut cache = cache();
+        let shaper = Price {
+            variant: Some("Shaper".to_string()),
+            ..price("Heavy Belt", 5.0)
+        };
+        cache.insert(ItemKind::BaseType, shaper);
+        let atlas = Price {
+            variant: Some("Atlas2".to_string()),
+            ..price("Doryani's Machinarium", 20.0)
+        };
+        cache.insert(ItemKind::UniqueMap, atlas);
+
+        let (plain, _) = cache.lookup(&PriceKey::named("Heavy Belt")).unwrap();
+        assert_eq!(plain.chaos_equivalent, 1.0);
+        let (shaper, quality) = cache
+            .lookup(&PriceKey::named("Heavy Belt (Shaper)"))
+            .unwrap();
+        assert_eq!(shaper.chaos_equivalent, 5.0);
+        assert_eq!(quality, MatchQuality::Normalised);
+        let (map, _) = cache
+            .lookup(&PriceKey::named("Doryani's Machinarium"))
+            .unwrap();
+        assert_eq!(map.variant, Some("Atlas2".to_string()));
+
+        cache.insert(ItemKind::UniqueMap, price("Doryani's Machinarium", 15.0));
+        let (map, _) = cache
+            .lookup(&PriceKey::named("Doryani's Machinarium"))
+            .unwrap();
+        assert_eq!(map.chaos_equivalent, 15.0);
+    }
+
+    #[test]
+    fn should_not_match_unrelated_names() {
+        assert!(cache()
+            .lookup(&PriceKey::named("Mirror of Kalandra"))
+            .is_none());
+    }
+
+    #[test]
+    fn should_compute_edit_distance() {
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("abc", "abc"), 0);
+    }
+}
@@ -0,0 +1,352 @@
+//! The supervisor owns the threads of all the workers: the clipboard watcher,
+//! the log watcher, the tooltip parser and the bots. A worker that panics is
+//! started again after a backoff, with the same channels, so nobody talking to
+//! it notices more than a delay. How every worker is doing goes to the
+//! frontend as `Health` messages.
+//!
+//! Shutting down happens in the order the workers were added: the sources
+//! stop first, then each actor gets told to shut down and is waited for before
+//! the next one is. That way the mastermind's last map run is in the
+//! database's inbox before the database bot is told to stop.
+use std::any::Any;
+use std::cmp;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use constants::{
+    SUPERVISOR_INITIAL_BACKOFF_MS, SUPERVISOR_MAX_BACKOFF_MS, SUPERVISOR_MAX_RESTARTS,
+    SUPERVISOR_STABLE_AFTER_SECS,
+};
+use types::frontend_communication::FrontendMessage;
+use types::supervisor::WorkerStatus;
+
+/// How often a sleeping worker checks whether it should stop instead.
+const SHUTDOWN_POLL_MS: u64 = 50;
+
+/// Set once the supervisor shuts down. Workers that don't have an inbox to be
+/// told through, like the clipboard watcher, check this instead.
+#[derive(Clone, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    pub fn request(&self) -> () {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Sleep for `duration`, or less if shutdown is requested meanwhile.
+    /// Returns whether the worker should keep going.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let until = Instant::now() + duration;
+        while !self.is_requested() {
+            let now = Instant::now();
+            if now >= until {
+                return true;
+            }
+            thread::sleep(cmp::min(
+                until - now,
+                Duration::from_millis(SHUTDOWN_POLL_MS),
+            ));
+        }
+        false
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RestartPolicy {
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Crashes in a row before a worker is given up on.
+    pub max_restarts: u32,
+    /// A worker that ran this long before crashing gets a clean slate.
+    pub stable_after: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> RestartPolicy {
+        RestartPolicy {
+            initial_backoff: Duration::from_millis(SUPERVISOR_INITIAL_BACKOFF_MS),
+            max_backoff: Duration::from_millis(SUPERVISOR_MAX_BACKOFF_MS),
+            max_restarts: SUPERVISOR_MAX_RESTARTS,
+            stable_after: Duration::from_secs(SUPERVISOR_STABLE_AFTER_SECS),
+        }
+    }
+}
+
+struct Worker {
+    name: &'static str,
+    /// Tells the worker to finish up, for actors that listen on an inbox.
+    stop: Option<Box<dyn Fn() + Send>>,
+    handle: JoinHandle<()>,
+}
+
+pub struct Supervisor {
+    workers: Vec<Worker>,
+    shutdown: Shutdown,
+    health: Sender<FrontendMessage>,
+    policy: RestartPolicy,
+}
+
+impl Supervisor {
+    pub fn new(health: Sender<FrontendMessage>) -> Supervisor {
+        Supervisor::with_policy(health, RestartPolicy::default())
+    }
+
+    pub fn with_policy(health: Sender<FrontendMessage>, policy: RestartPolicy) -> Supervisor {
+        Supervisor {
+            workers: Vec::new(),
+            shutdown: Shutdown::new(),
+            health,
+            policy,
+        }
+    }
+
+    /// The flag that workers added with `spawn` have to watch.
+    pub fn shutdown_flag(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Run `body` in its own thread until it returns. It has to return once
+    /// `shutdown_flag` is set, or once its inputs hang up.
+    pub fn spawn<F>(&mut self, name: &'static str, body: F) -> ()
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.add(name, body, None);
+    }
+
+    /// Like `spawn`, for actors that shut down on a message. `stop` sends it.
+    pub fn spawn_actor<F, S>(&mut self, name: &'static str, body: F, stop: S) -> ()
+    where
+        F: FnMut() + Send + 'static,
+        S: Fn() + Send + 'static,
+    {
+        self.add(name, body, Some(Box::new(stop)));
+    }
+
+    fn add<F>(&mut self, name: &'static str, body: F, stop: Option<Box<dyn Fn() + Send>>) -> ()
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let shutdown = self.shutdown.clone();
+        let health = self.health.clone();
+        let policy = self.policy.clone();
+        let handle = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || supervise(name, body, &shutdown, &health, &policy))
+            .expect("Can't spawn worker thread");
+        self.workers.push(Worker { name, stop, handle });
+    }
+
+    /// Stop every worker, in the order they were added, and wait for them.
+    pub fn shut_down(self) -> () {
+        info!("Shutting down...");
+        self.shutdown.request();
+        for worker in self.workers {
+            if let Some(ref stop) = worker.stop {
+                stop();
+            }
+            if worker.handle.join().is_err() {
+                warn!("{} didn't stop cleanly", worker.name);
+            }
+        }
+    }
+}
+
+/// Run `body` over and over for as long as it panics, backing off in between.
+fn supervise<F>(
+    name: &'static str,
+    mut body: F,
+    shutdown: &Shutdown,
+    health: &Sender<FrontendMessage>,
+    policy: &RestartPolicy,
+) -> ()
+where
+    F: FnMut(),
+{
+    let report = |status: WorkerStatus| {
+        // The frontend goes away before we're done shutting down.
+        let _ = health.send(FrontendMessage::Health {
+            worker: name.to_string(),
+            status,
+        });
+    };
+
+    let mut backoff = policy.initial_backoff;
+    let mut crashes = 0;
+    loop {
+        report(WorkerStatus::Running);
+        let started = Instant::now();
+        let cause = match panic::catch_unwind(AssertUnwindSafe(&mut body)) {
+            Ok(()) => break,
+            Err(cause) => cause,
+        };
+        let reason = panic_reason(&*cause);
+        error!("{} crashed: {}", name, reason);
+        if shutdown.is_requested() {
+            break;
+        }
+
+        if started.elapsed() >= policy.stable_after {
+            crashes = 0;
+            backoff = policy.initial_backoff;
+        }
+        crashes += 1;
+        if crashes > policy.max_restarts {
+            error!("Giving up on {}", name);
+            report(WorkerStatus::Failed { reason });
+            return;
+        }
+
+        report(WorkerStatus::Restarting {
+            reason,
+            retry_in_ms: backoff.as_secs() * 1000 + u64::from(backoff.subsec_millis()),
+        });
+        if !shutdown.sleep(backoff) {
+            break;
+        }
+        backoff = cmp::min(backoff * 2, policy.max_backoff);
+    }
+    report(WorkerStatus::Stopped);
+}
+
+/// Panics carry whatever was given to `panic!`, which is almost always a
+/// string of some sort.
+fn panic_reason(cause: &(dyn Any + Send)) -> String {
+    if let Some(reason) = cause.downcast_ref::<&str>() {
+        reason.to_string()
+    } else if let Some(reason) = cause.downcast_ref::<String>() {
+        reason.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc::{channel, Receiver};
+    use std::sync::Mutex;
+
+    fn quick_policy() -> RestartPolicy {
+        RestartPolicy {
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+            max_restarts: 2,
+            stable_after: Duration::from_secs(60),
+        }
+    }
+
+    fn statuses(health: &Receiver<FrontendMessage>) -> Vec<WorkerStatus> {
+        health
+            .try_iter()
+            .map(|message| match message {
+                FrontendMessage::Health { status, .. } => status,
+                other => panic!("Expected health, got {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_restart_crashed_workers() {
+        let (health_sender, health) = channel();
+        let mut supervisor = Supervisor::with_policy(health_sender, quick_policy());
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        supervisor.spawn("Flaky", move || {
+            if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("first time's unlucky");
+            }
+        });
+        while runs.load(Ordering::SeqCst) < 2 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        supervisor.shut_down();
+
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            statuses(&health),
+            vec![
+                WorkerStatus::Running,
+                WorkerStatus::Restarting {
+                    reason: "first time's unlucky".to_string(),
+                    retry_in_ms: 1,
+                },
+                WorkerStatus::Running,
+                WorkerStatus::Stopped,
+            ]
+        );
+    }
+
+    #[test]
+    fn should_give_up_on_workers_that_keep_crashing() {
+        let (health_sender, health) = channel();
+        let mut supervisor = Supervisor::with_policy(health_sender, quick_policy());
+        supervisor.spawn("Broken", || panic!("{}", "never works"));
+
+        let failed = WorkerStatus::Failed {
+            reason: "never works".to_string(),
+        };
+        let mut seen = Vec::new();
+        while let Ok(FrontendMessage::Health { status, .. }) =
+            health.recv_timeout(Duration::from_secs(5))
+        {
+            seen.push(status.clone());
+            if status == failed {
+                break;
+            }
+        }
+        supervisor.shut_down();
+
+        // Two restarts, and the crash after them is one too many.
+        assert_eq!(
+            seen.iter().filter(|s| **s == WorkerStatus::Running).count(),
+            3
+        );
+        assert_eq!(seen.last(), Some(&failed));
+        assert!(statuses(&health).is_empty());
+    }
+
+    #[test]
+    fn should_stop_workers_in_order() {
+        let (health_sender, _health) = channel();
+        let mut supervisor = Supervisor::with_policy(health_sender, quick_policy());
+        let stopped = Arc::new(Mutex::new(Vec::new()));
+
+        let shutdown = supervisor.shutdown_flag();
+        let log = stopped.clone();
+        supervisor.spawn("Source", move || {
+            while shutdown.sleep(Duration::from_millis(10)) {}
+            log.lock().unwrap().push("Source");
+        });
+        for name in ["First", "Second"].iter().cloned() {
+            let (inbox_sender, inbox) = channel::<()>();
+            let log = stopped.clone();
+            supervisor.spawn_actor(
+                name,
+                move || {
+                    let _ = inbox.recv();
+                    log.lock().unwrap().push(name);
+                },
+                move || {
+                    let _ = inbox_sender.send(());
+                },
+            );
+        }
+        supervisor.shut_down();
+
+        assert_eq!(*stopped.lock().unwrap(), vec!["Source", "First", "Second"]);
+    }
+}
@@ -15,30 +15,29 @@ use types::item::Rest;
 use types::item::StackSize;
 use types::item::UniqueStub;
 
+/// Parse everything that comes off the clipboard, and pass on what turned out
+/// to be an item. Returns once either side hangs up.
 pub fn spawn_tooltip_parser(
-    clipboard_receiver: Receiver<ClipboardEvent>,
-    parser_sender: Sender<Item>,
-) -> ! {
-    loop {
-        match clipboard_receiver.recv() {
-            Ok(ClipboardEvent { content }) => {
-                match parse_tooltip(&content) {
-                    Ok(item) => match parser_sender.send(item) {
-                        Ok(()) => {}
-                        Err(e) => panic!("Can't send over parser sender, error: {}", e),
-                    },
-                    // Log in the future, NOP for now.
-                    Err(_) => unreachable!(),
+    clipboard_receiver: &Receiver<ClipboardEvent>,
+    parser_sender: &Sender<Item>,
+) -> () {
+    for ClipboardEvent { content } in clipboard_receiver.iter() {
+        match parse_tooltip(&content) {
+            Ok(item) => {
+                if parser_sender.send(item).is_err() {
+                    return;
                 }
             }
-            Err(e) => {
-                panic!("Can't receive from clipboard thread, Error: {}", e);
-            }
+            // Most of what gets copied isn't a tooltip at all.
+            Err(e) => debug!("Not a tooltip: {:?}", e),
         }
     }
 }
 
-fn parse_tooltip(content: &str) -> Result<Item, Error> {
+/// Turn the text the game puts on the clipboard into an item. Fails with
+/// `ErrorKind::InvalidData` for text that isn't a tooltip, or a broken one,
+/// and with `ErrorKind::InvalidInput` for tooltips of items we can't parse yet.
+pub fn parse_tooltip(content: &str) -> Result<Item, Error> {
     let (rarity, rest) = parse_rarity(&content)?;
 
     match rarity {
@@ -56,7 +55,7 @@ fn parse_common_item(rest: &str) -> Result<Item, Error> {
     if kind.contains("Map") {
         parse_common_map(kind, ItemRarity::Normal, &rest)
     } else {
-        unimplemented!()
+        Err(unsupported(format!("Normal {} isn't supported yet", kind)))
     }
 }
 
@@ -68,10 +67,16 @@ fn parse_uncommon_item(rest: &str, rarity: ItemRarity) -> Result<Item, Error> {
     }
 
     if rarity == ItemRarity::Unique {
-        return Ok(Item::UniqueStub(UniqueStub { name }));
+        return Ok(Item::UniqueStub(UniqueStub {
+            name,
+            base_type: kind,
+        }));
     }
 
-    unimplemented!()
+    Err(unsupported(format!(
+        "{:?} {} isn't supported yet",
+        rarity, kind
+    )))
 }
 
 fn parse_common_map(kind: String, rarity: ItemRarity, rest: &str) -> Result<Item, Error> {
@@ -132,8 +137,13 @@ fn parse_uncommon_map(
 fn parse_currency(rest: &str) -> Result<Item, Error> {
     let (name, name_rest) = parse_name(&rest)?;
     let first_divider = parse_divider(&name_rest)?;
-    let (stack_size, stack_rest) = parse_stack_size(&first_divider)?;
-    let second_div = parse_divider(&stack_rest)?;
+    // Fossils and resonators copy without a stack size when there's only one.
+    let (stack_size, second_div) = if first_divider.starts_with("Stack Size: ") {
+        let (stack_size, stack_rest) = parse_stack_size(&first_divider)?;
+        (stack_size, parse_divider(&stack_rest)?)
+    } else {
+        (StackSize { current: 1, max: 1 }, first_divider)
+    };
     let (affixes, affixes_rest) = parse_affixes(&second_div)?;
     let third_div = parse_divider(&affixes_rest)?;
     let desc = parse_description(third_div)?;
@@ -154,6 +164,12 @@ fn parse_divination_cards(item: &str) -> Result<Item, Error> {
     let (mut affixes, rest) = parse_affixes(&rest)?;
     let rest = parse_divider(&rest)?;
     let description = parse_description(rest)?;
+    if affixes.is_empty() {
+        return Err(generate_error(format!(
+            "Divination card {} doesn't say what it grants.",
+            name
+        )));
+    }
 
     Ok(Item::DivinationCard(DivinationCard {
         name,
@@ -192,9 +208,10 @@ fn capture_key_line(item: &str, key: &str) -> Result<KeyCapture, Error> {
         }
     };
 
-    if first_line.starts_with(key) {
-        // Keys are specified in form of "Key: Value", so we can add 2 to drop colon and space.
-        let value_part = first_line[(key.len() + 2)..].to_string();
+    // Keys are specified in form of "Key: Value".
+    let prefix = format!("{}: ", key);
+    if first_line.starts_with(&prefix) {
+        let value_part = first_line[prefix.len()..].to_string();
         Ok(Capture(value_part, gather(lines)))
     } else {
         Ok(NoCapture(item.to_string()))
@@ -261,7 +278,7 @@ fn parse_tier(item: &str) -> Result<(u32, Rest), Error> {
         Capture(tier_string, rest) => {
             // Tier strings might include an "(augmented)", which would mess up parsing.
             // I also highly doubt tiers are going to reach higher than 99.
-            let relevant_parts = &tier_string[0..2].trim();
+            let relevant_parts = tier_string.split_whitespace().next().unwrap_or("");
             match relevant_parts.parse::<u32>() {
                 Ok(tier) => Ok((tier, rest)),
                 Err(e) => Err(generate_error(format!(
@@ -454,6 +471,13 @@ fn generate_error(reason: String) -> Error {
     Error::new(ErrorKind::InvalidData, reason)
 }
 
+/// For tooltips that are fine, but describe something we can't parse yet.
+/// Kept apart from `generate_error` so callers can tell the two apart by
+/// `ErrorKind`.
+fn unsupported(reason: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, reason)
+}
+
 fn gather(mut t: Lines) -> String {
     let first_line = match t.next() {
         Some(x) => x.to_string(),
@@ -652,6 +676,18 @@ mod test {
         }
     }
 
+    #[test]
+    fn should_parse_single_fossils() {
+        match parse_tooltip(include_str!("../resources/fossil")) {
+            Ok(Item::Currency(c)) => {
+                assert_eq!(c.name, "Aetheric Fossil".to_string());
+                assert_eq!(c.stack_size, StackSize { current: 1, max: 1 });
+                assert_eq!(c.affixes.len(), 2);
+            }
+            other => panic!("Expected a fossil, got {:?}", other),
+        }
+    }
+
     #[test]
     fn should_parse_maps() {
         let cage = include_str!("../resources/shaped-cage");
@@ -723,6 +759,40 @@ mod test {
         }
     }
 
+    #[test]
+    fn should_not_panic_on_cut_off_tooltips() {
+        let tooltips = [
+            include_str!("../resources/chaos-orb"),
+            include_str!("../resources/essence-of-spite"),
+            include_str!("../resources/fossil"),
+            include_str!("../resources/heterochromia-card"),
+            include_str!("../resources/honour-arch"),
+            include_str!("../resources/inpulsas-broken-heart"),
+            include_str!("../resources/shaped-cage"),
+            include_str!("../resources/shaped-underground-sea"),
+            include_str!("../resources/the-wise-oak"),
+        ];
+        for tooltip in tooltips.iter() {
+            let lines: Vec<_> = tooltip.lines().collect();
+            for end in 0..lines.len() {
+                let _ = parse_tooltip(&lines[..end].join("\n"));
+            }
+        }
+    }
+
+    #[test]
+    fn should_parse_single_digit_tiers() {
+        match parse_tier("Map Tier: 5\nItem Level: 70") {
+            Ok((tier, rest)) => {
+                assert_eq!(tier, 5);
+                assert_eq!(rest, "Item Level: 70");
+            }
+            Err(_) => assert!(false),
+        }
+        assert!(parse_tier("Map Tier: 14 (augmented)").is_ok());
+        assert!(parse_tier("Map Tier").is_err());
+    }
+
     #[test]
     fn should_preserve_newlines() {
         let test_string = String::from("a\nb\nc\nd");
@@ -0,0 +1,237 @@
+//! Trading happens over whispers, and the trade site writes those for
+//! everybody, so they all look the same. This reads them and keeps the
+//! `Ledger` of who wanted what, and which of those went through.
+use chrono::prelude::*;
+use chrono::Duration;
+use regex::Regex;
+
+use constants::TRADE_REQUEST_TIMEOUT_MINUTES;
+use types::trade::{Direction, Trade, TradeKind, TradeRequest, Whisper};
+
+/// What the trade site calls currencies in whispers, and what they're called
+/// on poe.ninja.
+const CURRENCY_NAMES: &[(&str, &str)] = &[
+    ("chaos", "Chaos Orb"),
+    ("exa", "Exalted Orb"),
+    ("exalted", "Exalted Orb"),
+    ("divine", "Divine Orb"),
+    ("alch", "Orb of Alchemy"),
+    ("alt", "Orb of Alteration"),
+    ("fusing", "Orb of Fusing"),
+    ("chrom", "Chromatic Orb"),
+    ("jewellers", "Jeweller's Orb"),
+    ("chance", "Orb of Chance"),
+    ("chisel", "Cartographer's Chisel"),
+    ("scour", "Orb of Scouring"),
+    ("blessed", "Blessed Orb"),
+    ("regret", "Orb of Regret"),
+    ("regal", "Regal Orb"),
+    ("gcp", "Gemcutter's Prism"),
+    ("vaal", "Vaal Orb"),
+    ("mirror", "Mirror of Kalandra"),
+];
+
+/// The full name of a currency as whispered, or what was whispered if it's
+/// not one we know, which it is when it's spelled out already.
+pub fn currency_name(whispered: &str) -> String {
+    CURRENCY_NAMES
+        .iter()
+        .find(|&&(short, _)| short.eq_ignore_ascii_case(whispered))
+        .map_or_else(|| whispered.to_string(), |&(_, name)| name.to_string())
+}
+
+/// Whether `currency` is what prices are in anyway.
+pub fn is_chaos(currency: &str) -> bool {
+    currency_name(currency) == "Chaos Orb"
+}
+
+/// Read one of the trade site's whispers. These come in two kinds:
+///
+/// `Hi, I would like to buy your Tabula Rasa listed for 8 chaos in Betrayal (stash tab "~price"; position: left 3, top 5)`
+/// `Hi, I'd like to buy your 20 Exalted Orb for my 1500 Chaos Orb in Betrayal.`
+pub fn parse_trade_request(message: &str) -> Option<TradeRequest> {
+    lazy_static! {
+        static ref LISTED: Regex = Regex::new(
+            r"^Hi, I would like to buy your (.+) listed for ([\d.]+) (.+?) in ([^(]+?)\.?(?: \(.*)?$"
+        )
+        .unwrap();
+        static ref BULK: Regex =
+            Regex::new(r"^Hi, I'd like to buy your (\d+) (.+) for my ([\d.]+) (.+?) in ([^.(]+)")
+                .unwrap();
+    }
+
+    let message = message.trim();
+    if let Some(listed) = LISTED.captures(message) {
+        return Some(TradeRequest {
+            item: listed[1].to_string(),
+            quantity: 1,
+            price: listed[2].parse().ok()?,
+            currency: listed[3].to_string(),
+            league: listed[4].trim().to_string(),
+        });
+    }
+    let bulk = BULK.captures(message)?;
+    Some(TradeRequest {
+        item: bulk[2].to_string(),
+        quantity: bulk[1].parse().ok()?,
+        price: bulk[3].parse().ok()?,
+        currency: bulk[4].to_string(),
+        league: bulk[5].trim().to_string(),
+    })
+}
+
+/// Trade requests waiting to go through, and who's come over to trade.
+pub struct Ledger {
+    pending: Vec<Trade>,
+    /// Who joined our area last. When a trade is accepted, it's most likely
+    /// with them.
+    last_joined: Option<String>,
+    next_id: u64,
+}
+
+impl Ledger {
+    pub fn new() -> Ledger {
+        Ledger {
+            pending: Vec::new(),
+            last_joined: None,
+            next_id: 0,
+        }
+    }
+
+    /// The trade a whisper asks for, if it's a trade whisper. Asking again for
+    /// the same thing is the same trade.
+    pub fn whisper(&mut self, whisper: &Whisper, at: DateTime<Local>) -> Option<Trade> {
+        let request = parse_trade_request(&whisper.message)?;
+        let kind = match whisper.direction {
+            Direction::Incoming => TradeKind::Sale,
+            Direction::Outgoing => TradeKind::Purchase,
+        };
+        if let Some(trade) = self
+            .pending
+            .iter()
+            .find(|t| t.player == whisper.player && t.request == request)
+        {
+            return Some(trade.clone());
+        }
+
+        let trade = Trade {
+            id: self.next_id,
+            kind,
+            player: whisper.player.clone(),
+            request,
+            requested_at: at.timestamp(),
+            completed_at: None,
+            chaos_value: None,
+        };
+        self.next_id += 1;
+        self.pending.push(trade.clone());
+        Some(trade)
+    }
+
+    pub fn joined(&mut self, player: &str) -> () {
+        self.last_joined = Some(player.to_string());
+    }
+
+    /// The trade that was just accepted: the newest one with whoever joined
+    /// last, or the newest one at all if nobody did. Someone who came over
+    /// without whispering traded for something else. Requests too old to
+    /// still be about anything are forgotten.
+    pub fn accepted(&mut self, at: DateTime<Local>) -> Option<Trade> {
+        let oldest = (at - Duration::minutes(TRADE_REQUEST_TIMEOUT_MINUTES)).timestamp();
+        self.pending.retain(|t| t.requested_at >= oldest);
+
+        let index = match self.last_joined.take() {
+            Some(player) => self.pending.iter().rposition(|t| t.player == player),
+            None => self.pending.len().checked_sub(1),
+        }?;
+        let mut trade = self.pending.remove(index);
+        trade.completed_at = Some(at.timestamp());
+        Some(trade)
+    }
+}
+
+impl Default for Ledger {
+    fn default() -> Ledger {
+        Ledger::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_parse_trade_whispers() {
+        let listed = parse_trade_request(
+            "Hi, I would like to buy your Tabula Rasa Simple Robe listed for 8.5 chaos in Betrayal (stash tab \"~price\"; position: left 3, top 5)",
+        )
+        .unwrap();
+        assert_eq!(
+            listed,
+            TradeRequest {
+                item: "Tabula Rasa Simple Robe".to_string(),
+                quantity: 1,
+                price: 8.5,
+                currency: "chaos".to_string(),
+                league: "Betrayal".to_string(),
+            }
+        );
+
+        let bulk = parse_trade_request(
+            "Hi, I'd like to buy your 20 Exalted Orb for my 1500 Chaos Orb in Hardcore Betrayal.",
+        )
+        .unwrap();
+        assert_eq!(bulk.quantity, 20);
+        assert_eq!(bulk.item, "Exalted Orb");
+        assert_eq!(bulk.price, 1500.0);
+        assert_eq!(bulk.league, "Hardcore Betrayal");
+        assert!(is_chaos(&bulk.currency));
+
+        assert!(parse_trade_request("wtb your tabula 5c").is_none());
+        assert_eq!(currency_name("exa"), "Exalted Orb");
+    }
+
+    #[test]
+    fn should_complete_trade_with_whoever_came_over() {
+        let mut ledger = Ledger::new();
+        let at = |minute| Local.ymd(2018, 11, 22).and_hms(5, minute, 0);
+        let whisper = |player: &str, item: &str| Whisper {
+            direction: Direction::Incoming,
+            player: player.to_string(),
+            message: format!(
+                "Hi, I would like to buy your {} listed for 1 exalted in Betrayal",
+                item
+            ),
+        };
+
+        let first = ledger
+            .whisper(&whisper("Buyer", "Kaom's Heart"), at(0))
+            .unwrap();
+        ledger
+            .whisper(&whisper("Other", "Headhunter"), at(1))
+            .unwrap();
+        // Asking twice doesn't make two trades.
+        let again = ledger
+            .whisper(&whisper("Buyer", "Kaom's Heart"), at(2))
+            .unwrap();
+        assert_eq!(again.id, first.id);
+        assert_eq!(first.kind, TradeKind::Sale);
+
+        ledger.joined("Buyer");
+        let trade = ledger.accepted(at(3)).unwrap();
+        assert_eq!(trade.player, "Buyer");
+        assert_eq!(trade.completed_at, Some(at(3).timestamp()));
+
+        // Nobody whispered about this one.
+        ledger.joined("Stranger");
+        assert!(ledger.accepted(at(4)).is_none());
+        // Whoever it was didn't come over, but the request is all there is.
+        assert_eq!(ledger.accepted(at(5)).unwrap().player, "Other");
+
+        ledger
+            .whisper(&whisper("Other", "Headhunter"), at(6))
+            .unwrap();
+        // Way too late for it.
+        assert!(ledger.accepted(at(59)).is_none());
+    }
+}
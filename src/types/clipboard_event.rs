@@ -1,4 +1,15 @@
+use std::time::Duration;
+
 #[derive(Debug)]
 pub struct ClipboardEvent {
     pub content: String,
 }
+
+/// What the clipboard watcher can be told, see `watch_clipboard`.
+#[derive(Debug, PartialEq)]
+pub enum ClipboardRequest {
+    PollInterval(Duration),
+    /// A copy is coming that counts even if it's the same as the last one,
+    /// like a map for `StartMapFromCopy`.
+    ExpectCopy,
+}
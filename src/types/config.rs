@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Everything the user can change about the tool. Lives as TOML in the
+/// platform config directory, see `config::default_config_path`. Fields left
+/// out of the file take their default, so an empty file is a valid config.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The league prices are fetched for and map runs are recorded under.
+    /// TODO: Get the list of these from the POE API:
+    /// <https://www.pathofexile.com/developer/docs/api-resource-leagues>
+    pub league: String,
+    /// Who's playing. Left empty, that's whoever levels up, or failing that
+    /// dies, which can be wrong in a party.
+    pub character_name: String,
+    /// Enables the web inspector. Only takes effect on restart.
+    pub debug: bool,
+    /// How often the clipboard is checked for new tooltips, when it has to be
+    /// polled.
+    pub clipboard_poll_interval_ms: u64,
+    /// How to notice new tooltips: `x11`, `wayland`, `polling`, or `auto` to
+    /// go by the session. Only takes effect on restart.
+    pub clipboard_backend: String,
+    /// How long fetched prices stay valid before the price bot refreshes them.
+    pub price_cache_ttl_minutes: i64,
+    /// How long before the cache expires the price bot starts refreshing it
+    /// in the background, so requests never have to wait for poe.ninja.
+    pub price_refresh_lead_minutes: i64,
+    /// A poe.ninja endpoint list to use instead of the built-in one. When a
+    /// league adds a new category, point this at a copy of
+    /// `resources/poe-ninja-endpoints.json` with the new entry instead of
+    /// waiting for a release.
+    pub endpoints_file: Option<PathBuf>,
+    /// Copying the same item again within this long doesn't count it again.
+    pub duplicate_window_minutes: i64,
+    /// Where map runs are kept. Only takes effect on restart.
+    pub database_file: Option<PathBuf>,
+    /// A `.filter` file, like NeverSink's, to sort drops into its tiers.
+    /// Read again whenever the config is saved.
+    pub loot_filter_file: Option<PathBuf>,
+    /// How much to log: one of `off`, `error`, `warn`, `info`, `debug` or
+    /// `trace`.
+    pub log_level: String,
+    /// Log levels for single modules, overriding `log_level`. Keys are module
+    /// paths, with or without the leading `atlas_of_beancounting::`, so
+    /// `web_client = "debug"` works. Other crates, like `reqwest`, too.
+    /// Tables stay last, TOML wants them after plain values.
+    pub log_filters: BTreeMap<String, String>,
+    /// Keys that do things without leaving the game, by what they do:
+    /// `start_map`, `end_map`, `pause_session`, `tag_last_drop` and
+    /// `undo_last_drop`. Combos look like `ctrl+shift+F5`, empty unbinds.
+    pub hotkeys: BTreeMap<String, String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            league: "Standard".to_string(),
+            character_name: String::new(),
+            debug: false,
+            clipboard_poll_interval_ms: 200,
+            clipboard_backend: "auto".to_string(),
+            price_cache_ttl_minutes: 60,
+            price_refresh_lead_minutes: 5,
+            endpoints_file: None,
+            duplicate_window_minutes: 60,
+            database_file: None,
+            loot_filter_file: None,
+            log_level: "info".to_string(),
+            log_filters: BTreeMap::new(),
+            hotkeys: vec![
+                ("start_map", "ctrl+F5"),
+                ("end_map", "ctrl+F6"),
+                ("pause_session", "ctrl+F7"),
+                ("tag_last_drop", "ctrl+F8"),
+                ("undo_last_drop", "ctrl+F9"),
+            ]
+            .into_iter()
+            .map(|(action, combo)| (action.to_string(), combo.to_string()))
+            .collect(),
+        }
+    }
+}
+
+/// Values given on the command line. They win over the config file, including
+/// after it's reloaded.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigOverrides {
+    pub league: Option<String>,
+    pub debug: Option<bool>,
+    pub clipboard_poll_interval_ms: Option<u64>,
+    pub price_cache_ttl_minutes: Option<i64>,
+    pub endpoints_file: Option<PathBuf>,
+    pub database_file: Option<PathBuf>,
+    pub log_level: Option<String>,
+}
+
+impl ConfigOverrides {
+    pub fn apply(&self, config: &mut Config) -> () {
+        if let Some(ref league) = self.league {
+            config.league = league.clone();
+        }
+        if let Some(debug) = self.debug {
+            config.debug = debug;
+        }
+        if let Some(interval) = self.clipboard_poll_interval_ms {
+            config.clipboard_poll_interval_ms = interval;
+        }
+        if let Some(ttl) = self.price_cache_ttl_minutes {
+            config.price_cache_ttl_minutes = ttl;
+        }
+        if let Some(ref file) = self.endpoints_file {
+            config.endpoints_file = Some(file.clone());
+        }
+        if let Some(ref file) = self.database_file {
+            config.database_file = Some(file.clone());
+        }
+        if let Some(ref level) = self.log_level {
+            config.log_level = level.clone();
+        }
+    }
+
+    /// What goes in the file when `edited`, a config with these applied, is
+    /// saved over `file`. Fields left at their overridden value keep what the
+    /// file has. Fields changed anyway, like the league from the league
+    /// picker, are saved and not overridden any more.
+    pub fn unapply(&mut self, edited: &Config, file: &Config) -> Config {
+        let mut saved = edited.clone();
+        unapply(&mut self.league, &mut saved.league, &file.league);
+        unapply(&mut self.debug, &mut saved.debug, &file.debug);
+        unapply(
+            &mut self.clipboard_poll_interval_ms,
+            &mut saved.clipboard_poll_interval_ms,
+            &file.clipboard_poll_interval_ms,
+        );
+        unapply(
+            &mut self.price_cache_ttl_minutes,
+            &mut saved.price_cache_ttl_minutes,
+            &file.price_cache_ttl_minutes,
+        );
+        unapply_file(
+            &mut self.endpoints_file,
+            &mut saved.endpoints_file,
+            &file.endpoints_file,
+        );
+        unapply_file(
+            &mut self.database_file,
+            &mut saved.database_file,
+            &file.database_file,
+        );
+        unapply(&mut self.log_level, &mut saved.log_level, &file.log_level);
+        saved
+    }
+}
+
+/// Files are overridden with a file, not with none.
+fn unapply_file(
+    overridden: &mut Option<PathBuf>,
+    saved: &mut Option<PathBuf>,
+    file: &Option<PathBuf>,
+) -> () {
+    let mut overridden_with = overridden.take().map(Some);
+    unapply(&mut overridden_with, saved, file);
+    *overridden = overridden_with.and_then(|file| file);
+}
+
+fn unapply<T: Clone + PartialEq>(overridden: &mut Option<T>, saved: &mut T, file: &T) -> () {
+    let unchanged = match *overridden {
+        Some(ref value) => value == saved,
+        None => return,
+    };
+    if unchanged {
+        *saved = file.clone();
+    } else {
+        *overridden = None;
+    }
+}
+
+/// Communicate with the config actor.
+#[derive(Debug)]
+pub enum ConfigMessage {
+    /// The file changed on disk, read it again.
+    Reload,
+    /// Replace the config with this one and write it to disk.
+    Save(Config),
+    ShutDown,
+}
@@ -1,8 +1,50 @@
-use types::item::Item;
+use std::path::PathBuf;
+
+use types::export::{ExportFormat, ExportTable};
+use types::history::{HistoryFilter, HistoryQuery, HistoryResult};
+use types::session::{MapRun, SessionRecord};
+use types::trade::Trade;
 
 /// Communicate with the database actor.
+#[derive(Debug)]
 pub enum DatabaseMessage {
-    Save(Item),
+    /// Persist a finished map run and its drops.
+    SaveMapRun {
+        league: String,
+        run: MapRun,
+    },
+    /// Persist a session that's over, with who played in it.
+    SaveSession {
+        league: String,
+        session: SessionRecord,
+    },
+    /// Persist a trade, or what's new about one saved before.
+    SaveTrade {
+        trade: Trade,
+    },
+    Query {
+        query: HistoryQuery,
+        filter: HistoryFilter,
+    },
+    /// The answer to a `Query`.
+    History(HistoryResult),
+    /// Write `table` to a file in the exports directory.
+    Export {
+        table: ExportTable,
+        format: ExportFormat,
+        filter: HistoryFilter,
+    },
+    /// The answer to an `Export`.
+    Exported {
+        path: PathBuf,
+        records: usize,
+    },
+    /// Something went wrong that the user should hear about.
+    Error {
+        reason: String,
+    },
     ShutDown,
-    Panic{reason: String},
+    Panic {
+        reason: String,
+    },
 }
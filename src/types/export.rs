@@ -0,0 +1,65 @@
+/// What an export is of. The columns of each are in `export::columns`, and
+/// the README.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ExportTable {
+    Runs,
+    Drops,
+    Trades,
+    /// What Client.txt says happened, read from the log rather than the
+    /// database.
+    Events,
+    /// Sessions that are over, with everybody who played in them.
+    Sessions,
+}
+
+impl ExportTable {
+    pub const ALL: &'static [ExportTable] = &[
+        ExportTable::Runs,
+        ExportTable::Drops,
+        ExportTable::Trades,
+        ExportTable::Events,
+        ExportTable::Sessions,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ExportTable::Runs => "runs",
+            ExportTable::Drops => "drops",
+            ExportTable::Trades => "trades",
+            ExportTable::Events => "events",
+            ExportTable::Sessions => "sessions",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ExportTable> {
+        ExportTable::ALL.iter().cloned().find(|t| t.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ExportFormat {
+    /// One document. Runs come with their drops in it, as they always have.
+    Json,
+    /// With a header line of the column names.
+    Csv,
+    /// One JSON object per line, keyed by column name.
+    Ndjson,
+}
+
+impl ExportFormat {
+    pub const ALL: &'static [ExportFormat] =
+        &[ExportFormat::Json, ExportFormat::Csv, ExportFormat::Ndjson];
+
+    /// Also the file extension.
+    pub fn name(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ExportFormat> {
+        ExportFormat::ALL.iter().cloned().find(|f| f.name() == name)
+    }
+}
@@ -1,4 +1,420 @@
+//! The protocol between the backend and the web-view frontend. Both directions
+//! are JSON with the variant name in `type` and its fields in `payload`, e.g.
+//! `{"type": "ItemDropped", "payload": {"id": 3, "name": "Chaos Orb", ...}}`.
+//! `index.js` dispatches on `type`, so renaming a variant here means renaming
+//! it there.
+use std::path::PathBuf;
+
+use types::config::Config;
+use types::export::{ExportFormat, ExportTable};
+use types::history::{HistoryFilter, HistoryQuery, HistoryResult};
+use types::logging::LogEntry;
+use types::loot_filter::FilterTier;
+use types::pricing::MatchQuality;
+use types::session::{Character, TimeSpent};
+use types::supervisor::WorkerStatus;
+use types::trade::Trade;
+
+/// Events the backend pushes into the frontend via `window.dispatch`.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
 pub enum FrontendMessage {
-    Placeholder
+    /// A freshly parsed drop. Its price follows in a `PriceUpdated` with the
+    /// same `id`.
+    ItemDropped {
+        id: u64,
+        name: String,
+        /// How the loot filter showed it, if there is one.
+        filter_tier: Option<FilterTier>,
+    },
+    PriceUpdated {
+        id: u64,
+        chaos_equivalent: f32,
+        quality: MatchQuality,
+    },
+    /// A drop was taken back, see `UndoLastDrop`.
+    DropRemoved {
+        id: u64,
+    },
+    /// A drop was tagged or untagged, see `TagLastDrop`.
+    DropTagged {
+        id: u64,
+        tagged: bool,
+    },
+    /// Drops are ignored while paused, see `TogglePause`. Runs still start
+    /// and end with the zone.
+    Paused {
+        paused: bool,
+    },
+    /// A map was copied, and the next run of its area gets its mods.
+    MapQueued {
+        name: String,
+        tier: u32,
+    },
+    /// Timestamps are seconds since the unix epoch.
+    MapStarted {
+        name: String,
+        started_at: i64,
+    },
+    MapEnded {
+        name: String,
+        ended_at: i64,
+        chaos_value: f32,
+    },
+    /// How the current map is going, sent whenever that changes.
+    RunningTotal {
+        map_chaos_value: f32,
+        map_chaos_per_hour: f32,
+        previous_map_chaos_value: Option<f32>,
+    },
+    /// Somebody else is playing now, or the same character levelled up.
+    CharacterChanged {
+        character: Character,
+    },
+    /// The character died, see `Config::character_name`.
+    Died {
+        map_deaths: u32,
+        session_deaths: u32,
+    },
+    /// A trade was whispered about, went through, or got its price. Same
+    /// `id`, same trade.
+    TradeUpdated {
+        trade: Trade,
+    },
+    /// An `Export` was written.
+    Exported {
+        path: PathBuf,
+        records: usize,
+    },
+    SessionSummary {
+        maps_run: u32,
+        chaos_value: f32,
+        chaos_per_hour: f32,
+        /// Leaving out time spent AFK or paused.
+        active_chaos_per_hour: f32,
+        time: TimeSpent,
+        deaths: u32,
+        /// In levels, see `Session::experience_lost`.
+        experience_lost: f32,
+    },
+    /// The current settings, in answer to `OpenSettings` and whenever they
+    /// change.
+    Settings {
+        config: Config,
+    },
+    /// Why a `SaveSettings` was refused.
+    SettingsInvalid {
+        problems: Vec<String>,
+    },
+    /// The answer to a `QueryHistory`.
+    History {
+        result: HistoryResult,
+    },
+    /// Something was logged, for the log viewer.
+    Log {
+        entry: LogEntry,
+    },
+    /// How one of the workers in the backend is doing, whenever that changes.
+    Health {
+        worker: String,
+        status: WorkerStatus,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Commands the frontend sends to the backend via `window.external.invoke`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum FrontendCommand {
+    /// Start a new map run. Without a name, the backend uses whatever it
+    /// knows about the current zone.
+    StartMap {
+        name: Option<String>,
+    },
+    /// Start a new map run from the next copied map item, which the
+    /// `start_map` hotkey copies right after sending this. Whatever else gets
+    /// copied instead is ignored.
+    StartMapFromCopy,
+    EndMap,
+    /// Take back the newest drop of the current map, for when something got
+    /// counted that didn't drop. Answered with `DropRemoved`.
+    UndoLastDrop,
+    /// Mark the newest drop of the current map, or unmark it. Answered with
+    /// `DropTagged`.
+    TagLastDrop,
+    /// Stop counting drops, or start again. Answered with `Paused`.
+    TogglePause,
+    RefreshPrices,
+    ChangeLeague {
+        league: String,
+    },
+    OpenSettings,
+    /// Replace the settings. Answered with `Settings` once they're in use, or
+    /// `SettingsInvalid`.
+    SaveSettings {
+        config: Config,
+    },
+    /// Ask for one of the history views, answered with a `History` message.
+    QueryHistory {
+        query: HistoryQuery,
+        #[serde(default)]
+        filter: HistoryFilter,
+    },
+    /// Write history to a file, answered with `Exported`. Takes the same
+    /// filter as the history views.
+    Export {
+        table: ExportTable,
+        format: ExportFormat,
+        #[serde(default)]
+        filter: HistoryFilter,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json;
+    use types::config::Config;
+    use types::history::MapRunSummary;
+    use types::trade::{TradeKind, TradeRequest};
+
+    fn round_trip_message(message: FrontendMessage) {
+        let json = serde_json::to_string(&message).unwrap();
+        let parsed: FrontendMessage = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, message);
+    }
+
+    fn round_trip_command(command: FrontendCommand) {
+        let json = serde_json::to_string(&command).unwrap();
+        let parsed: FrontendCommand = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, command);
+    }
+
+    #[test]
+    fn should_round_trip_messages() {
+        round_trip_message(FrontendMessage::ItemDropped {
+            id: 1,
+            name: "Chaos Orb".to_string(),
+            filter_tier: Some(FilterTier {
+                tier: "currency t5".to_string(),
+                hidden: false,
+                text_colour: Some("rgba(170, 158, 130, 1.00)".to_string()),
+                border_colour: None,
+                background_colour: None,
+            }),
+        });
+        round_trip_message(FrontendMessage::PriceUpdated {
+            id: 1,
+            chaos_equivalent: 1.0,
+            quality: MatchQuality::Exact,
+        });
+        round_trip_message(FrontendMessage::PriceUpdated {
+            id: 2,
+            chaos_equivalent: 3.5,
+            quality: MatchQuality::Fuzzy(0.9),
+        });
+        round_trip_message(FrontendMessage::DropRemoved { id: 2 });
+        round_trip_message(FrontendMessage::DropTagged {
+            id: 2,
+            tagged: true,
+        });
+        round_trip_message(FrontendMessage::Paused { paused: true });
+        round_trip_message(FrontendMessage::MapQueued {
+            name: "Shaped Cage".to_string(),
+            tier: 11,
+        });
+        round_trip_message(FrontendMessage::MapStarted {
+            name: "Shaped Cage Map".to_string(),
+            started_at: 1542864000,
+        });
+        round_trip_message(FrontendMessage::MapEnded {
+            name: "Shaped Cage Map".to_string(),
+            ended_at: 1542864600,
+            chaos_value: 42.0,
+        });
+        round_trip_message(FrontendMessage::RunningTotal {
+            map_chaos_value: 12.0,
+            map_chaos_per_hour: 144.0,
+            previous_map_chaos_value: None,
+        });
+        round_trip_message(FrontendMessage::RunningTotal {
+            map_chaos_value: 12.0,
+            map_chaos_per_hour: 144.0,
+            previous_map_chaos_value: Some(30.5),
+        });
+        round_trip_message(FrontendMessage::CharacterChanged {
+            character: Character {
+                name: "Nightblade".to_string(),
+                class: Some("Assassin".to_string()),
+                level: None,
+            },
+        });
+        round_trip_message(FrontendMessage::Died {
+            map_deaths: 1,
+            session_deaths: 2,
+        });
+        round_trip_message(FrontendMessage::TradeUpdated {
+            trade: Trade {
+                id: 0,
+                kind: TradeKind::Sale,
+                player: "Buyer".to_string(),
+                request: TradeRequest {
+                    item: "Tabula Rasa".to_string(),
+                    quantity: 1,
+                    price: 8.0,
+                    currency: "chaos".to_string(),
+                    league: "Betrayal".to_string(),
+                },
+                requested_at: 1542864000,
+                completed_at: Some(1542864060),
+                chaos_value: Some(8.0),
+            },
+        });
+        round_trip_message(FrontendMessage::Exported {
+            path: PathBuf::from("/tmp/drops-20181122-050000.csv"),
+            records: 12,
+        });
+        round_trip_message(FrontendMessage::SessionSummary {
+            maps_run: 3,
+            chaos_value: 120.0,
+            chaos_per_hour: 240.0,
+            active_chaos_per_hour: 300.0,
+            time: TimeSpent {
+                map_secs: 1200,
+                hideout_secs: 300,
+                loading_secs: 60,
+                afk_secs: 240,
+            },
+            deaths: 2,
+            experience_lost: 0.2,
+        });
+        round_trip_message(FrontendMessage::Settings {
+            config: Config::default(),
+        });
+        round_trip_message(FrontendMessage::SettingsInvalid {
+            problems: vec!["league can't be empty".to_string()],
+        });
+        round_trip_message(FrontendMessage::History {
+            result: HistoryResult::MapRuns(vec![MapRunSummary {
+                id: 1,
+                league: "Betrayal".to_string(),
+                name: "Shaped Cage".to_string(),
+                tier: Some(11),
+                mods: vec!["Area is inhabited by Goatmen".to_string()],
+                item_quantity: Some(64),
+                item_rarity: Some(38),
+                pack_size: Some(22),
+                character: Some("Nightblade".to_string()),
+                character_class: Some("Assassin".to_string()),
+                character_level: Some(91),
+                started_at: 1542864000,
+                duration_secs: 600,
+                deaths: 1,
+                chaos_value: 42.0,
+                uuid: Some("5f0c1a2e-8b7d-4c3a-9e21-6d4f0b8a7c13".to_string()),
+            }]),
+        });
+        round_trip_message(FrontendMessage::Log {
+            entry: LogEntry {
+                timestamp: 1542864000,
+                level: "WARN".to_string(),
+                target: "atlas_of_beancounting::web_client".to_string(),
+                message: "Something broke".to_string(),
+            },
+        });
+        round_trip_message(FrontendMessage::Health {
+            worker: "PriceBot".to_string(),
+            status: WorkerStatus::Restarting {
+                reason: "Something broke".to_string(),
+                retry_in_ms: 500,
+            },
+        });
+        round_trip_message(FrontendMessage::Error {
+            message: "Something broke".to_string(),
+        });
+    }
+
+    #[test]
+    fn should_round_trip_commands() {
+        round_trip_command(FrontendCommand::StartMap { name: None });
+        round_trip_command(FrontendCommand::StartMap {
+            name: Some("Shaped Cage Map".to_string()),
+        });
+        round_trip_command(FrontendCommand::EndMap);
+        round_trip_command(FrontendCommand::StartMapFromCopy);
+        round_trip_command(FrontendCommand::UndoLastDrop);
+        round_trip_command(FrontendCommand::TagLastDrop);
+        round_trip_command(FrontendCommand::TogglePause);
+        round_trip_command(FrontendCommand::RefreshPrices);
+        round_trip_command(FrontendCommand::ChangeLeague {
+            league: "Betrayal".to_string(),
+        });
+        round_trip_command(FrontendCommand::OpenSettings);
+        round_trip_command(FrontendCommand::SaveSettings {
+            config: Config {
+                league: "Betrayal".to_string(),
+                ..Config::default()
+            },
+        });
+        round_trip_command(FrontendCommand::QueryHistory {
+            query: HistoryQuery::BestDrops { limit: 10 },
+            filter: HistoryFilter {
+                league: Some("Betrayal".to_string()),
+                min_tier: Some(11),
+                mods: vec!["Elemental Weakness".to_string()],
+                ..HistoryFilter::default()
+            },
+        });
+        round_trip_command(FrontendCommand::Export {
+            table: ExportTable::Drops,
+            format: ExportFormat::Csv,
+            filter: HistoryFilter {
+                character: Some("Nightblade".to_string()),
+                ..HistoryFilter::default()
+            },
+        });
+    }
+
+    #[test]
+    fn should_tag_messages_the_way_the_frontend_expects() {
+        let message = FrontendMessage::ItemDropped {
+            id: 3,
+            name: "Chaos Orb".to_string(),
+            filter_tier: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"type":"ItemDropped","payload":{"id":3,"name":"Chaos Orb","filter_tier":null}}"#
+        );
+    }
+
+    #[test]
+    fn should_parse_commands_the_way_the_frontend_sends_them() {
+        assert_eq!(
+            serde_json::from_str::<FrontendCommand>(r#"{"type":"EndMap"}"#).unwrap(),
+            FrontendCommand::EndMap
+        );
+        assert_eq!(
+            serde_json::from_str::<FrontendCommand>(
+                r#"{"type":"ChangeLeague","payload":{"league":"Standard"}}"#
+            )
+            .unwrap(),
+            FrontendCommand::ChangeLeague {
+                league: "Standard".to_string()
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<FrontendCommand>(
+                r#"{"type":"QueryHistory","payload":{"query":{"type":"MapRuns"}}}"#
+            )
+            .unwrap(),
+            FrontendCommand::QueryHistory {
+                query: HistoryQuery::MapRuns,
+                filter: HistoryFilter::default(),
+            }
+        );
+        assert!(serde_json::from_str::<FrontendCommand>(r#"{"type":"Explode"}"#).is_err());
+    }
 }
@@ -0,0 +1,157 @@
+//! What the history screens ask the database for, and what they get back.
+//! Everything here travels through the frontend protocol, so it's all JSON,
+//! and timestamps are seconds since the unix epoch.
+use types::trade::Trade;
+
+/// Narrows down which map runs a history query looks at. Everything left out
+/// matches everything.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryFilter {
+    pub league: Option<String>,
+    /// Runs started at or after this.
+    pub from: Option<i64>,
+    /// Runs started before this.
+    pub to: Option<i64>,
+    pub min_tier: Option<u32>,
+    pub max_tier: Option<u32>,
+    /// Runs that rolled all of these mods. Matching is on substrings, so
+    /// "Elemental Weakness" finds "Players are Cursed with Elemental Weakness".
+    pub mods: Vec<String>,
+    /// Runs by the character of this name.
+    pub character: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum HistoryQuery {
+    /// Every run, newest first.
+    MapRuns,
+    /// How each map does on average, best chaos per hour first.
+    MapKindAverages,
+    BestDrops {
+        limit: u32,
+    },
+    /// Chaos per hour for each day something was run.
+    ChaosPerHourOverTime,
+    /// How deadly each map mod is, deadliest first. Only counts runs where
+    /// the map item was seen.
+    DeathsPerMod,
+    /// Trades that went through, newest first. Only `league`, `from` and
+    /// `to` filter these.
+    Trades,
+    /// Loot, sales and purchases for each day, in chaos.
+    Profit,
+    /// Drops by the loot filter tier they showed as, most valuable first.
+    FilterTiers,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "payload")]
+pub enum HistoryResult {
+    MapRuns(Vec<MapRunSummary>),
+    MapKindAverages(Vec<MapKindAverage>),
+    BestDrops(Vec<BestDrop>),
+    ChaosPerHourOverTime(Vec<ChaosPerHourPoint>),
+    DeathsPerMod(Vec<ModDeaths>),
+    Trades(Vec<Trade>),
+    Profit(Vec<ProfitPoint>),
+    FilterTiers(Vec<FilterTierTotal>),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MapRunSummary {
+    pub id: i64,
+    pub league: String,
+    pub name: String,
+    pub tier: Option<u32>,
+    pub mods: Vec<String>,
+    pub item_quantity: Option<u32>,
+    pub item_rarity: Option<u32>,
+    pub pack_size: Option<u32>,
+    pub character: Option<String>,
+    pub character_class: Option<String>,
+    pub character_level: Option<u32>,
+    pub started_at: i64,
+    pub duration_secs: i64,
+    pub deaths: u32,
+    pub chaos_value: f32,
+    /// The same on every machine the run is imported to. Exports from before
+    /// it don't have it.
+    #[serde(default)]
+    pub uuid: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MapKindAverage {
+    pub name: String,
+    pub runs: u32,
+    pub average_chaos_value: f32,
+    pub average_duration_secs: f32,
+    pub chaos_per_hour: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BestDrop {
+    pub name: String,
+    pub count: u32,
+    pub chaos_value: f32,
+    pub map_name: String,
+    pub dropped_at: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChaosPerHourPoint {
+    /// Local date, `YYYY-MM-DD`.
+    pub day: String,
+    pub maps_run: u32,
+    pub chaos_per_hour: f32,
+}
+
+/// How often maps with a given mod killed the character.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModDeaths {
+    pub map_mod: String,
+    pub runs: u32,
+    pub deaths: u32,
+    pub deaths_per_run: f32,
+}
+
+/// `tier` is `None` for drops that weren't sorted by a loot filter.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FilterTierTotal {
+    pub tier: Option<String>,
+    pub drops: u32,
+    pub chaos_value: f32,
+}
+
+/// Loot is what the drops were estimated to be worth, sales and purchases
+/// what trades actually went for. Loot that was sold counts in both.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProfitPoint {
+    /// Local date, `YYYY-MM-DD`.
+    pub day: String,
+    pub loot: f32,
+    pub sales: f32,
+    pub purchases: f32,
+}
+
+/// A map run with everything that dropped in it, as written by `export`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MapRunExport {
+    #[serde(flatten)]
+    pub run: MapRunSummary,
+    pub drops: Vec<DropSummary>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DropSummary {
+    pub name: String,
+    pub count: u32,
+    pub chaos_value: Option<f32>,
+    pub dropped_at: i64,
+    pub tagged: bool,
+    /// Exports from before loot filters don't have it.
+    #[serde(default)]
+    pub filter_tier: Option<String>,
+}
@@ -0,0 +1,48 @@
+use types::frontend_communication::FrontendCommand;
+
+/// What a hotkey can be bound to. The config names them like `name` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotkeyAction {
+    /// Copy the map under the mouse and start a run of it.
+    StartMap,
+    EndMap,
+    PauseSession,
+    TagLastDrop,
+    UndoLastDrop,
+}
+
+impl HotkeyAction {
+    pub const ALL: &'static [HotkeyAction] = &[
+        HotkeyAction::StartMap,
+        HotkeyAction::EndMap,
+        HotkeyAction::PauseSession,
+        HotkeyAction::TagLastDrop,
+        HotkeyAction::UndoLastDrop,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HotkeyAction::StartMap => "start_map",
+            HotkeyAction::EndMap => "end_map",
+            HotkeyAction::PauseSession => "pause_session",
+            HotkeyAction::TagLastDrop => "tag_last_drop",
+            HotkeyAction::UndoLastDrop => "undo_last_drop",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<HotkeyAction> {
+        HotkeyAction::ALL.iter().cloned().find(|a| a.name() == name)
+    }
+
+    /// What the mastermind gets told, the same as if the button in the
+    /// frontend had been clicked.
+    pub fn command(self) -> FrontendCommand {
+        match self {
+            HotkeyAction::StartMap => FrontendCommand::StartMapFromCopy,
+            HotkeyAction::EndMap => FrontendCommand::EndMap,
+            HotkeyAction::PauseSession => FrontendCommand::TogglePause,
+            HotkeyAction::TagLastDrop => FrontendCommand::TagLastDrop,
+            HotkeyAction::UndoLastDrop => FrontendCommand::UndoLastDrop,
+        }
+    }
+}
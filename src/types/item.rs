@@ -1,5 +1,8 @@
 
-#[derive(Debug)]
+/// Something copied out of the game, see `parse_tooltip`. Serializes as the
+/// item's fields, with the kind of item in `type`.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum Item {
     Gear(Gear),
     UniqueStub(UniqueStub),
@@ -8,9 +11,67 @@ pub enum Item {
     Map(Map),
 }
 
-#[derive(Debug, PartialEq)]
+impl Item {
+    /// The name to show for the item: the proper name if it has one, the base
+    /// type otherwise.
+    pub fn display_name(&self) -> String {
+        match self {
+            Item::Gear(g) => g.name.clone(),
+            Item::UniqueStub(u) => u.name.clone(),
+            Item::Currency(c) => c.name.clone(),
+            Item::DivinationCard(d) => d.name.clone(),
+            Item::Map(m) => match m.name {
+                Some(ref name) => format!("{} ({})", name, m.kind),
+                None => m.kind.clone(),
+            },
+        }
+    }
+
+    /// How many of the item there are. Prices are per item, so this is what
+    /// they get multiplied with.
+    pub fn count(&self) -> u32 {
+        match self {
+            Item::Currency(c) => c.stack_size.current,
+            Item::DivinationCard(d) => d.stack_size.current,
+            _ => 1,
+        }
+    }
+
+    /// What tells this item apart from others, as far as copying it again is
+    /// concerned.
+    pub fn fingerprint(&self) -> Fingerprint {
+        let (mods, item_level) = match self {
+            Item::Gear(g) => (g.affixes.clone(), Some(g.item_level)),
+            Item::Currency(c) => (c.affixes.clone(), None),
+            Item::Map(m) => (m.affixes.clone(), Some(m.item_level)),
+            Item::UniqueStub(_) | Item::DivinationCard(_) => (Vec::new(), None),
+        };
+        Fingerprint {
+            name: self.display_name(),
+            mods,
+            stack_size: self.count(),
+            item_level,
+        }
+    }
+}
+
+/// Two copies of the same item have the same one. So do two items that only
+/// differ in what the tooltip doesn't show, but that's as good as it gets.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Fingerprint {
+    pub name: String,
+    pub mods: Vec<String>,
+    pub stack_size: u32,
+    pub item_level: Option<u32>,
+}
+
+/// Weapons, armour, jewellery and the like. Not parsed yet.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Gear {
     pub name: String,
+    /// Normal and magic items carry it in `name` too, rares and uniques have
+    /// it on a line of its own.
+    pub base_type: String,
     pub rarity: ItemRarity,
     pub affixes: Vec<String>,
     pub sockets: String,
@@ -22,12 +83,15 @@ pub struct Gear {
 /// reasons, for which a name is plenty. The full parsing of items, gear, and
 /// affix rolls is to be done, but a huge time sink, so stubbed out for the
 /// moment.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct UniqueStub {
     pub name: String,
+    pub base_type: String,
 }
 
-#[derive(Debug, PartialEq)]
+/// Anything that copies as `Rarity: Currency`, so fossils, essences and
+/// fragments too.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Currency {
     pub name: String,
     pub stack_size: StackSize,
@@ -35,19 +99,23 @@ pub struct Currency {
     pub description: String,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct DivinationCard {
     pub name: String,
     pub stack_size: StackSize,
+    /// What a full set is exchanged for.
     pub reward: String,
     pub description: String,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Map {
+    /// Only magic, rare and unique maps have one.
     pub name: Option<String>,
+    /// The base type, e.g. "Shaped Cage Map".
     pub kind: String,
     pub tier: u32,
+    /// The rolls below are percentages, 0 if the map doesn't have them.
     pub item_quantity: u32,
     pub item_rarity: u32,
     pub quality: u32,
@@ -57,7 +125,7 @@ pub struct Map {
     pub rarity: ItemRarity,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Requirements {
     pub level: u32,
     pub strength: u32,
@@ -65,13 +133,13 @@ pub struct Requirements {
     pub dexterity: u32,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct StackSize {
     pub current: u32,
     pub max: u32,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum ItemRarity {
     Currency,
     DivinationCard,
@@ -81,8 +149,11 @@ pub enum ItemRarity {
     Unique,
 }
 
+/// What's left of a tooltip for the next step of the parser.
+#[doc(hidden)]
 pub type Rest = String;
 
+#[doc(hidden)]
 #[derive(Debug, PartialEq)]
 pub enum KeyCapture {
     Capture(String, Rest),
@@ -0,0 +1,11 @@
+/// A line of the log, as shown in the log viewer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    /// Seconds since the unix epoch.
+    pub timestamp: i64,
+    /// `ERROR`, `WARN`, `INFO`, `DEBUG` or `TRACE`.
+    pub level: String,
+    /// The module that logged it, e.g. `atlas_of_beancounting::web_client`.
+    pub target: String,
+    pub message: String,
+}
@@ -0,0 +1,13 @@
+/// How the loot filter would show a drop, see `LootFilter::classify`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FilterTier {
+    /// NeverSink's `$type->currency $tier->t1` comes out as `currency t1`.
+    /// Blocks without one go by their text colour, or just `Show`/`Hide`.
+    pub tier: String,
+    /// `Hide` blocks still match, the drop just wouldn't have been seen.
+    pub hidden: bool,
+    /// As CSS, `rgba(255, 0, 0, 1.00)`.
+    pub text_colour: Option<String>,
+    pub border_colour: Option<String>,
+    pub background_colour: Option<String>,
+}
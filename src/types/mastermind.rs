@@ -0,0 +1,19 @@
+use types::config::Config;
+use types::database::DatabaseMessage;
+use types::frontend_communication::FrontendCommand;
+use types::item::Item;
+use types::pricing::PriceMessage;
+use types::zone_event::ZoneEvent;
+
+/// Everything the mastermind listens to, funneled into a single channel.
+#[derive(Debug)]
+pub enum MastermindMessage {
+    Item(Item),
+    Zone(ZoneEvent),
+    Price(PriceMessage),
+    Command(FrontendCommand),
+    Database(DatabaseMessage),
+    /// The config changed.
+    Config(Config),
+    ShutDown,
+}
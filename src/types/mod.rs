@@ -5,3 +5,13 @@ pub mod pricing;
 pub mod poe_ninja;
 pub mod database;
 pub mod frontend_communication;
+pub mod mastermind;
+pub mod session;
+pub mod history;
+pub mod config;
+pub mod supervisor;
+pub mod logging;
+pub mod hotkeys;
+pub mod trade;
+pub mod export;
+pub mod loot_filter;
@@ -2,15 +2,57 @@
 //! Poe.ninja. They're not necessarily the types used here, but needed as
 //! deserialization target. Snake case is ignored because the names matter for
 //! deserialization.
+use constants::POE_NINJA_API_BASE;
+use serde_json::Value;
+use types::pricing::ItemKind;
 
+/// poe.ninja serves two shapes of overview: currency-like things priced by
+/// exchange rate, and everything else priced by listings.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum NinjaEndpointType {
+    CurrencyOverview,
+    ItemOverview,
+}
+
+/// One category of prices on poe.ninja, and what we use it for.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NinjaEndpoint {
+    /// The `type` poe.ninja knows the category by, e.g. "Fossil".
+    pub category: String,
+    pub endpoint: NinjaEndpointType,
+    /// The kind of item the category prices.
+    pub prices: ItemKind,
+}
+
+impl NinjaEndpoint {
+    pub fn url(&self, league: &str) -> String {
+        let endpoint = match self.endpoint {
+            NinjaEndpointType::CurrencyOverview => "currencyoverview",
+            NinjaEndpointType::ItemOverview => "itemoverview",
+        };
+        format!(
+            "{}/{}?league={}&type={}",
+            POE_NINJA_API_BASE, endpoint, league, self.category
+        )
+    }
+}
+
+/// The envelope of both overview endpoints. Lines are kept as raw JSON here so
+/// a single line poe.ninja changed under our feet only costs us that line, see
+/// `NinjaLineResponse` for what we make of them.
 #[derive(Serialize, Deserialize, Debug)]
 #[allow(non_snake_case)]
 pub struct NinjaCurrencyOverviewResponse {
-    pub lines: Vec<NinjaLineResponse>,
+    pub lines: Vec<Value>,
+    #[serde(default)]
     pub currencyDetails: Option<Vec<NinjaDetails>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// A single priced thing. Everything is optional: poe.ninja adds, drops and
+/// renames fields between leagues, and all we really need is a name and a
+/// value. Unknown fields are ignored.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 #[allow(non_snake_case)]
 pub struct NinjaLineResponse {
     // One of the two has to be present, one for currencies, one for the rest.
@@ -23,27 +65,33 @@ pub struct NinjaLineResponse {
     // First is for currencies, latter for items.
     pub chaosEquivalent: Option<f32>,
     pub chaosValue: Option<f32>,
+
+    // Items that come in several versions, like unique maps or Shaper and
+    // Elder bases, say which one.
+    pub variant: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 #[allow(non_snake_case)]
 pub struct NinjaPrice {
-    pub id: u32,
-    pub league_id: u32,
-    pub pay_currency_id: u32,
-    pub get_currency_id: u32,
-    pub sample_time_utc: String,
-    pub count: u32,
-    pub value: f64,
-    pub data_point_count: u32,
-    pub includes_secondary: bool,
+    pub id: Option<u32>,
+    pub league_id: Option<u32>,
+    pub pay_currency_id: Option<u32>,
+    pub get_currency_id: Option<u32>,
+    pub sample_time_utc: Option<String>,
+    pub count: Option<u32>,
+    pub value: Option<f64>,
+    pub data_point_count: Option<u32>,
+    pub includes_secondary: Option<bool>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
 #[allow(non_snake_case)]
 pub struct NinjaDetails {
-    pub id: u32,
-    pub icon: String,
-    pub poeTradeId: i32,
-    pub name: String,
+    pub id: Option<u32>,
+    pub icon: Option<String>,
+    pub poeTradeId: Option<i32>,
+    pub name: Option<String>,
 }
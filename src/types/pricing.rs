@@ -1,4 +1,5 @@
-use types::poe_ninja::NinjaLineResponse;
+use chrono::Duration;
+use types::poe_ninja::{NinjaEndpoint, NinjaLineResponse};
 
 /// A price for an Item, relative to a currency, for example chaos orbs.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
@@ -6,33 +7,127 @@ pub struct Price {
     /// The name of the item that is being priced.
     pub name: String,
     pub chaos_equivalent: f32,
+    /// Which version of the item, for items poe.ninja lists several times
+    /// under the same name.
+    #[serde(default)]
+    pub variant: Option<String>,
 }
 
+/// The broad kind of item a poe.ninja category prices. Several categories can
+/// price the same kind -- Fossils, Essences and Resonators are all "Currency"
+/// as far as the tooltip is concerned, since they all show up as `Rarity:
+/// Currency` when copied. Fragments, Scarabs, Incubators and Oils copy like
+/// that too, but loot filters tell them apart, so they get their own.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum ItemKind {
+    Currency,
+    DivinationCard,
+    Map,
+    BlightedMap,
+    UniqueMap,
+    Unique,
+    BaseType,
+    SkillGem,
+    Prophecy,
+    HelmetEnchant,
+    /// Nothing copied is priced as these yet, they're only here so lists of
+    /// categories that have them still load.
+    ClusterJewel,
+    Beast,
+    Fragment,
+    Scarab,
+    Incubator,
+    Oil,
+}
+
+/// What to look up in the price cache for a given item. `kind` is `None` when
+/// all we have is a name, in which case every kind is searched.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PriceKey {
+    pub kind: Option<ItemKind>,
+    pub name: String,
+}
+
+impl PriceKey {
+    pub fn named(name: &str) -> PriceKey {
+        PriceKey {
+            kind: None,
+            name: name.to_string(),
+        }
+    }
+}
+
+/// How well the price we found fits the item we were asked about, from best to
+/// worst.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum MatchQuality {
+    /// The name matched character for character.
+    Exact,
+    /// The name matched after dropping qualifiers like "Superior" or "Shaped".
+    Normalised,
+    /// Only the base type of the item had a price, e.g. for magic items.
+    BaseType,
+    /// The closest name in the cache, with a similarity between 0 and 1.
+    Fuzzy(f32),
+    /// Nothing matched, the price is a placeholder.
+    Unpriced,
+}
 
-impl From<NinjaLineResponse> for Price {
-    fn from(t: NinjaLineResponse) -> Price {
-        match t.currencyTypeName {
-            Some(x) => Price {
-                name: x,
-                chaos_equivalent: t.chaosEquivalent.unwrap_or(0.0),
-            },
-            None => match t.name {
-                Some(x) => Price {
-                    name: x,
-                    chaos_equivalent: t.chaosValue.unwrap_or(0.0),
-                },
-                None => panic!(
-                    "Poe.ninja delivered bad JSON, neither name nor currency_name are defined"
-                ),
-            },
+impl Price {
+    /// Make a price out of a poe.ninja line. Currencies and items keep their
+    /// names and values in different fields, a line with neither is useless.
+    pub fn from_ninja_line(t: NinjaLineResponse) -> Result<Price, String> {
+        let (name, value) = match (t.currencyTypeName, t.name) {
+            (Some(name), _) => (name, t.chaosEquivalent.or(t.chaosValue)),
+            (None, Some(name)) => (name, t.chaosValue.or(t.chaosEquivalent)),
+            (None, None) => return Err("Line has neither name nor currencyTypeName".to_string()),
+        };
+
+        match value {
+            Some(chaos_equivalent) => Ok(Price {
+                name,
+                chaos_equivalent,
+                variant: t.variant.filter(|v| !v.trim().is_empty()),
+            }),
+            None => Err(format!("Line for {:?} has no chaos value", name)),
         }
     }
 }
 
+/// Tunables for the price bot's cache, derived from the user's `Config` by
+/// `config::price_bot_config`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceBotConfig {
+    /// Whose prices to fetch.
+    pub league: String,
+    /// The poe.ninja categories to fetch.
+    pub endpoints: Vec<NinjaEndpoint>,
+    /// How long a freshly fetched cache is considered valid.
+    pub ttl: Duration,
+    /// How long before the cache expires the background refresh kicks in.
+    pub refresh_lead: Duration,
+    /// How long to wait after the first failed refresh. Doubles with each
+    /// further failure.
+    pub initial_backoff: Duration,
+    /// Upper bound for the wait between failed refreshes.
+    pub max_backoff: Duration,
+}
+
 #[derive(Debug)]
 pub enum PriceMessage {
-    Get { item: String },
-    Response { item: String, price: Price },
+    /// `id` comes back with the `Response`, so whoever asked can tell which
+    /// request it answers.
+    Get {
+        id: u64,
+        item: PriceKey,
+    },
+    Response {
+        id: u64,
+        item: String,
+        price: Price,
+        quality: MatchQuality,
+    },
     InvalidateCache,
-    ShutDown
+    Configure(PriceBotConfig),
+    ShutDown,
 }
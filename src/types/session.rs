@@ -0,0 +1,252 @@
+use chrono::prelude::*;
+use chrono::Duration;
+
+use constants::DEATH_EXPERIENCE_PENALTY;
+
+/// Something that dropped, as far as bookkeeping is concerned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drop {
+    pub id: u64,
+    pub name: String,
+    pub count: u32,
+    /// Value of the whole stack. `None` until the price bot has answered.
+    pub chaos_value: Option<f32>,
+    pub dropped_at: DateTime<Local>,
+    /// Marked with `TagLastDrop`, to find it again in the history.
+    pub tagged: bool,
+    /// The loot filter tier it would show as, see `FilterTier`. `None` without
+    /// a loot filter, or when no block of it is for the drop.
+    pub filter_tier: Option<String>,
+}
+
+/// Who's playing. Only the name is known until they level up, which is when
+/// the log says the rest.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Character {
+    pub name: String,
+    pub class: Option<String>,
+    pub level: Option<u32>,
+}
+
+/// One run through a map, from entering it to entering the next one (or
+/// being told it's over).
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapRun {
+    pub name: String,
+    pub started_at: DateTime<Local>,
+    pub ended_at: Option<DateTime<Local>>,
+    /// Only known when the map item itself was seen, not just the zone.
+    pub tier: Option<u32>,
+    pub mods: Vec<String>,
+    /// The map's rolls, in percent. Also only known from the map item.
+    pub item_quantity: Option<u32>,
+    pub item_rarity: Option<u32>,
+    pub pack_size: Option<u32>,
+    pub deaths: u32,
+    /// Who ran it, if anybody was known to be playing.
+    pub character: Option<Character>,
+    pub drops: Vec<Drop>,
+    /// Tells the run apart on every machine it's imported to. Given when it's
+    /// first saved.
+    pub uuid: Option<String>,
+}
+
+impl MapRun {
+    pub fn new(name: String, started_at: DateTime<Local>) -> MapRun {
+        MapRun {
+            name,
+            started_at,
+            ended_at: None,
+            tier: None,
+            mods: Vec::new(),
+            item_quantity: None,
+            item_rarity: None,
+            pack_size: None,
+            deaths: 0,
+            character: None,
+            drops: Vec::new(),
+            uuid: None,
+        }
+    }
+
+    /// The value of all drops priced so far.
+    pub fn chaos_value(&self) -> f32 {
+        self.drops.iter().filter_map(|d| d.chaos_value).sum()
+    }
+
+    /// How long the map took, or has taken so far if it's still running.
+    pub fn duration(&self, now: DateTime<Local>) -> Duration {
+        self.ended_at.unwrap_or(now) - self.started_at
+    }
+
+    pub fn chaos_per_hour(&self, now: DateTime<Local>) -> f32 {
+        chaos_per_hour(self.chaos_value(), self.duration(now))
+    }
+}
+
+/// Where the session's time went, in seconds. See `clock::Clock`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TimeSpent {
+    pub map_secs: i64,
+    /// In town or the hideout.
+    pub hideout_secs: i64,
+    pub loading_secs: i64,
+    /// AFK, or paused.
+    pub afk_secs: i64,
+}
+
+impl TimeSpent {
+    /// Everything but being away.
+    pub fn active(&self) -> Duration {
+        Duration::seconds(self.map_secs + self.hideout_secs + self.loading_secs)
+    }
+}
+
+/// Everything since the tool was started.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub started_at: DateTime<Local>,
+    pub current_map: Option<MapRun>,
+    pub previous_map: Option<MapRun>,
+    pub maps_run: u32,
+    /// Value of all maps that are over.
+    pub finished_chaos_value: f32,
+    /// Things picked up while not in a map.
+    pub unassigned_drops: Vec<Drop>,
+    pub deaths: u32,
+    /// The character being played right now, as far as we know.
+    pub character: Option<Character>,
+    /// Everybody played this session, as they were last seen.
+    pub characters: Vec<Character>,
+}
+
+/// What's kept of a session once it's over.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionRecord {
+    pub started_at: DateTime<Local>,
+    pub ended_at: DateTime<Local>,
+    pub maps_run: u32,
+    pub chaos_value: f32,
+    pub deaths: u32,
+    pub characters: Vec<Character>,
+}
+
+/// A saved session with the league it was saved under, as `export` reads it
+/// back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionExport {
+    pub id: i64,
+    pub league: String,
+    pub session: SessionRecord,
+}
+
+impl Session {
+    pub fn new(started_at: DateTime<Local>) -> Session {
+        Session {
+            started_at,
+            current_map: None,
+            previous_map: None,
+            maps_run: 0,
+            finished_chaos_value: 0.0,
+            unassigned_drops: Vec::new(),
+            deaths: 0,
+            character: None,
+            characters: Vec::new(),
+        }
+    }
+
+    /// Make `character` the one playing.
+    pub fn switch_character(&mut self, character: Character) -> () {
+        match self.characters.iter().position(|c| c.name == character.name) {
+            Some(index) => self.characters[index] = character.clone(),
+            None => self.characters.push(character.clone()),
+        }
+        self.character = Some(character);
+    }
+
+    pub fn record(&self, ended_at: DateTime<Local>) -> SessionRecord {
+        SessionRecord {
+            started_at: self.started_at,
+            ended_at,
+            maps_run: self.maps_run,
+            chaos_value: self.chaos_value(),
+            deaths: self.deaths,
+            characters: self.characters.clone(),
+        }
+    }
+
+    pub fn chaos_value(&self) -> f32 {
+        let current = self.current_map.as_ref().map_or(0.0, |m| m.chaos_value());
+        let unassigned: f32 = self
+            .unassigned_drops
+            .iter()
+            .filter_map(|d| d.chaos_value)
+            .sum();
+        self.finished_chaos_value + current + unassigned
+    }
+
+    pub fn chaos_per_hour(&self, now: DateTime<Local>) -> f32 {
+        chaos_per_hour(self.chaos_value(), now - self.started_at)
+    }
+
+    /// Like `chaos_per_hour`, leaving out the time nobody was playing.
+    pub fn chaos_per_active_hour(&self, time: &TimeSpent) -> f32 {
+        chaos_per_hour(self.chaos_value(), time.active())
+    }
+
+    /// The experience the deaths cost, in levels. That's at most, dying can't
+    /// take away a level, so it's less when they came early in one.
+    pub fn experience_lost(&self) -> f32 {
+        self.deaths as f32 * DEATH_EXPERIENCE_PENALTY
+    }
+
+    /// Take a drop back out of the current map, or out of the unassigned
+    /// ones. Maps that are over stay as they are.
+    pub fn remove_drop(&mut self, id: u64) -> Option<Drop> {
+        if let Some(ref mut map) = self.current_map {
+            if let Some(index) = map.drops.iter().position(|d| d.id == id) {
+                return Some(map.drops.remove(index));
+            }
+        }
+        let index = self.unassigned_drops.iter().position(|d| d.id == id)?;
+        Some(self.unassigned_drops.remove(index))
+    }
+
+    pub fn drop_mut(&mut self, id: u64) -> Option<&mut Drop> {
+        let in_map = self
+            .current_map
+            .as_mut()
+            .and_then(|m| m.drops.iter_mut().find(|d| d.id == id));
+        match in_map {
+            Some(d) => Some(d),
+            None => self.unassigned_drops.iter_mut().find(|d| d.id == id),
+        }
+    }
+
+    /// Price a drop at `chaos_equivalent` apiece, wherever it is. Prices can
+    /// come in after the map is over, those count towards the finished maps.
+    /// The value of the whole stack, `None` if the drop is gone.
+    pub fn price_drop(&mut self, id: u64, chaos_equivalent: f32) -> Option<f32> {
+        if let Some(drop) = self.drop_mut(id) {
+            let value = chaos_equivalent * drop.count as f32;
+            drop.chaos_value = Some(value);
+            return Some(value);
+        }
+        let drop = self
+            .previous_map
+            .as_mut()
+            .and_then(|m| m.drops.iter_mut().find(|d| d.id == id))?;
+        let value = chaos_equivalent * drop.count as f32;
+        drop.chaos_value = Some(value);
+        self.finished_chaos_value += value;
+        Some(value)
+    }
+}
+
+fn chaos_per_hour(chaos_value: f32, duration: Duration) -> f32 {
+    let seconds = duration.num_seconds();
+    if seconds <= 0 {
+        return 0.0;
+    }
+    chaos_value * 3600.0 / seconds as f32
+}
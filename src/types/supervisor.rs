@@ -0,0 +1,17 @@
+/// How one of the supervised workers is doing, as shown to the frontend.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state")]
+pub enum WorkerStatus {
+    Running,
+    /// It crashed with `reason`, and gets another go in `retry_in_ms`.
+    Restarting {
+        reason: String,
+        retry_in_ms: u64,
+    },
+    /// It crashed too often in a row, and stays down until restart.
+    Failed {
+        reason: String,
+    },
+    /// It finished, usually because we're shutting down.
+    Stopped,
+}
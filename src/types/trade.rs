@@ -0,0 +1,69 @@
+/// Whose whisper it was. `@From` lines are incoming, `@To` ones outgoing.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Whisper {
+    pub direction: Direction,
+    /// The character on the other end, without their guild tag.
+    pub player: String,
+    pub message: String,
+}
+
+/// What one of the trade site's canned whispers asks for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TradeRequest {
+    pub item: String,
+    /// More than one only for bulk trades.
+    pub quantity: u32,
+    /// For all of them, in `currency`.
+    pub price: f32,
+    /// As the whisper has it, like "chaos" or "exalted".
+    pub currency: String,
+    pub league: String,
+}
+
+/// Which way the item goes. Somebody whispering us wants to buy, so that's
+/// a sale.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TradeKind {
+    Sale,
+    Purchase,
+}
+
+impl TradeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            TradeKind::Sale => "sale",
+            TradeKind::Purchase => "purchase",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TradeKind> {
+        match name {
+            "sale" => Some(TradeKind::Sale),
+            "purchase" => Some(TradeKind::Purchase),
+            _ => None,
+        }
+    }
+}
+
+/// A line in the trade ledger, from the first whisper on. Timestamps are
+/// seconds since the unix epoch.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Trade {
+    /// Only unique within a session.
+    pub id: u64,
+    pub kind: TradeKind,
+    pub player: String,
+    pub request: TradeRequest,
+    pub requested_at: i64,
+    /// `None` while it's just a request.
+    pub completed_at: Option<i64>,
+    /// What the price was worth when the trade went through, once the price
+    /// bot has said.
+    pub chaos_value: Option<f32>,
+}
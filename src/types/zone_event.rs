@@ -1,6 +1,36 @@
 use chrono::prelude::*;
 
+use types::trade::Whisper;
+
 #[derive(Debug)]
 pub enum ZoneEvent {
     ZoneChange(String, DateTime<Local>),
+    /// A character in the same area died, by name.
+    Death(String, DateTime<Local>),
+    /// The game started generating an area, with the seed of its instance if
+    /// the log says. The loading screen lasts until the next `ZoneChange`.
+    LoadingStarted(Option<u64>, DateTime<Local>),
+    /// AFK mode went on or off, whether by hand or by standing around.
+    Afk(bool, DateTime<Local>),
+    /// A character in the same area levelled up: name, class and new level.
+    LevelUp(String, String, u32, DateTime<Local>),
+    Whisper(Whisper, DateTime<Local>),
+    /// Somebody came into our area, by name. Mostly to trade.
+    JoinedArea(String, DateTime<Local>),
+    TradeAccepted(DateTime<Local>),
+}
+
+impl ZoneEvent {
+    pub fn timestamp(&self) -> DateTime<Local> {
+        match *self {
+            ZoneEvent::ZoneChange(_, timestamp)
+            | ZoneEvent::Death(_, timestamp)
+            | ZoneEvent::LoadingStarted(_, timestamp)
+            | ZoneEvent::Afk(_, timestamp)
+            | ZoneEvent::LevelUp(_, _, _, timestamp)
+            | ZoneEvent::Whisper(_, timestamp)
+            | ZoneEvent::JoinedArea(_, timestamp)
+            | ZoneEvent::TradeAccepted(timestamp) => timestamp,
+        }
+    }
 }
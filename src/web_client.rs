@@ -1,44 +1,116 @@
 use chrono::prelude::*;
 use chrono::Duration;
-use reqwest::{Client, Error};
+use reqwest::header::{
+    HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RETRY_AFTER,
+    USER_AGENT,
+};
+use reqwest::{Client, Error, StatusCode};
 
+use serde_json;
+
+use std::cmp;
 use std::collections::HashMap;
-use std::sync::mpsc::{Receiver, Sender};
+use std::fmt;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time;
+use std::time::Instant;
 
-use constants::{CURRENT_LEAGUE, POE_NINJA_ENDPOINT_TEMPLATES};
-use types::poe_ninja::NinjaCurrencyOverviewResponse;
-use types::pricing::{Price, PriceMessage};
+use config::price_bot_config;
+use constants::{
+    NINJA_DEFAULT_RETRY_AFTER_SECS, NINJA_MAX_BACKOFF_SECS, NINJA_MIN_REFRESH_INTERVAL_SECS,
+    NINJA_USER_AGENT,
+};
+use price_key::PriceCache;
+use supervisor::Supervisor;
+use types::config::Config;
+use types::poe_ninja::{NinjaCurrencyOverviewResponse, NinjaEndpoint, NinjaLineResponse};
+use types::pricing::{MatchQuality, Price, PriceBotConfig, PriceKey, PriceMessage};
 
-type PriceCache = HashMap<String, Price>;
+/// How often the bot wakes up to check on its refresh schedule when no
+/// requests come in.
+const SCHEDULER_TICK_MS: u64 = 1000;
 
 pub struct PriceBot {
     response_channel: Sender<PriceMessage>,
     request_channel: Receiver<PriceMessage>,
     price_cache: PriceCache,
     cache_expiration: DateTime<Local>,
+    config: PriceBotConfig,
+    refresh: RefreshSchedule,
+    client: Arc<Mutex<NinjaClient>>,
+}
+
+/// Bookkeeping for the background refresh. Only one refresh runs at a time,
+/// and its result comes back over `in_flight`.
+struct RefreshSchedule {
+    in_flight: Option<InFlight>,
+    next_attempt: DateTime<Local>,
+    consecutive_failures: u32,
+}
+
+/// A background refresh, with what it was started for. The config can change
+/// while it runs, and then its prices are no good.
+struct InFlight {
+    league: String,
+    endpoints: Vec<NinjaEndpoint>,
+    result: Receiver<Result<PriceCache, FetchError>>,
+}
+
+impl Default for PriceBotConfig {
+    fn default() -> PriceBotConfig {
+        price_bot_config(&Config::default())
+    }
 }
 
 impl PriceBot {
     pub fn new(sender: Sender<PriceMessage>, receiver: Receiver<PriceMessage>) -> PriceBot {
-        // Will be invalid immediately, because the cache is empty, and get
-        // fixed on first request.
+        PriceBot::with_config(sender, receiver, PriceBotConfig::default())
+    }
+
+    pub fn with_config(
+        sender: Sender<PriceMessage>,
+        receiver: Receiver<PriceMessage>,
+        config: PriceBotConfig,
+    ) -> PriceBot {
+        // Will be invalid immediately, because the cache is empty, so the
+        // scheduler seeds it on the first tick.
         let cache_expiration = Local::now();
-        let price_cache = HashMap::new();
+        let price_cache = PriceCache::new();
 
         PriceBot {
             response_channel: sender,
             request_channel: receiver,
             price_cache,
             cache_expiration,
+            config,
+            refresh: RefreshSchedule {
+                in_flight: None,
+                next_attempt: cache_expiration,
+                consecutive_failures: 0,
+            },
+            client: Arc::new(Mutex::new(NinjaClient::new())),
         }
     }
 
-    pub fn spawn(
+    /// Run the bot under `supervisor`, which shuts it down through
+    /// `requests`. A restarted bot keeps its cache.
+    pub fn supervise(
+        supervisor: &mut Supervisor,
         sender: Sender<PriceMessage>,
         receiver: Receiver<PriceMessage>,
-    ) -> thread::JoinHandle<()> {
-        thread::spawn(move || PriceBot::new(sender, receiver).run())
+        requests: Sender<PriceMessage>,
+        config: PriceBotConfig,
+    ) -> () {
+        let mut price_bot = PriceBot::with_config(sender, receiver, config);
+        supervisor.spawn_actor(
+            "PriceBot",
+            move || price_bot.run(),
+            move || {
+                let _ = requests.send(PriceMessage::ShutDown);
+            },
+        );
     }
 
     /// Run the price bot. This will lock in an endless loop, so do it in a
@@ -47,189 +119,870 @@ impl PriceBot {
     /// possilble messages are Enum variants of `PriceMessage`, and obviously
     /// constructing a Response variant and sending it to the bot will panic the
     /// bot. Don't be a smartass. :)
+    ///
+    /// Between requests the bot keeps the cache fresh on its own: it starts a
+    /// refresh in the background shortly before the cache expires, and keeps
+    /// answering from the old cache until the new one arrives.
     pub fn run(&mut self) -> () {
+        let tick = time::Duration::from_millis(SCHEDULER_TICK_MS);
         loop {
-            match self.request_channel.recv() {
+            match self.request_channel.recv_timeout(tick) {
                 Ok(o) => match o {
-                    PriceMessage::Get { item } => self.respond_to_price_request(item),
+                    PriceMessage::Get { id, item } => self.send_price_response(id, item),
                     PriceMessage::InvalidateCache => self.invalidate_cache(),
+                    PriceMessage::Configure(config) => self.configure(config),
                     PriceMessage::Response { .. } => {
                         panic!("How is a Response on the request channel?");
                     }
                     PriceMessage::ShutDown => {
-                        println!("[PriceBot] Shutting down...");
+                        info!("Shutting down...");
                         break;
                     }
                 },
-                Err(e) => panic!("Error when receiving price request: {}", e),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    info!("Nobody is asking for prices anymore, exiting");
+                    break;
+                }
             }
-        }
-    }
 
-    fn respond_to_price_request(&mut self, item: String) -> () {
-        if self.cache_expiration > Local::now() {
-            self.send_price_response(item)
-        } else {
-            match refresh_price_cache() {
-                Ok(cache) => {
-                    self.price_cache = cache;
-                    self.send_price_response(item)
-                }
-                Err(e) => {
-                    println!(
-                        "[PriceBot] Can't update cache, contuing with old. Error: {}",
-                        e
-                    );
-                    self.send_price_response(item)
-                }
+            self.collect_refresh();
+            if self.refresh_due(Local::now()) {
+                self.start_refresh();
             }
         }
     }
 
-    fn send_price_response(&self, item: String) -> () {
-        let price = match self.price_cache.get(&item) {
-            Some(price) => (*price).clone(),
+    fn send_price_response(&self, id: u64, item: PriceKey) -> () {
+        let (price, quality) = match self.price_cache.lookup(&item) {
+            Some(found) => found,
             // Send back dummy for display purposes. It still will appear, we
             // just don't have a price for it.
-            None => Price {
-                name: item.clone(),
-                chaos_equivalent: 0.0,
-            },
+            None => (
+                Price {
+                    name: item.name.clone(),
+                    chaos_equivalent: 0.0,
+                    variant: None,
+                },
+                MatchQuality::Unpriced,
+            ),
         };
 
-        match self
-            .response_channel
-            .send(PriceMessage::Response { item, price })
-        {
-            Ok(()) => {}
-            Err(e) => panic!(
-                "[PriceBot] Can't send pricing response,\
-                 error: {}",
-                e
-            ),
+        if let Err(e) = self.response_channel.send(PriceMessage::Response {
+            id,
+            item: item.name,
+            price,
+            quality,
+        }) {
+            warn!("Can't send pricing response, error: {}", e);
         }
     }
 
+    /// Throw away the schedule and refresh right now. The old cache stays in
+    /// use until the refresh comes back.
     fn invalidate_cache(&mut self) -> () {
-        match refresh_price_cache() {
-            Ok(c) => {
-                self.price_cache = c;
-                self.cache_expiration = calculate_expiration_date(Local::now());
-                println!("[PriceBot] Invalidated and refreshed cache");
-            }
-            Err(e) => println!(
-                "Can't refresh cache while invalidating, using old\
-                 cache instead.\nError: {}",
-                e
+        self.refresh.consecutive_failures = 0;
+        self.refresh.next_attempt = Local::now();
+        self.start_refresh();
+    }
+
+    fn configure(&mut self, config: PriceBotConfig) -> () {
+        // Prices for another league, or from other places, are no good.
+        let stale = config.league != self.config.league || config.endpoints != self.config.endpoints;
+        // Expiry is relative to when the cache was fetched, so move it by the
+        // difference between the old and the new TTL.
+        self.cache_expiration = self.cache_expiration - self.config.ttl + config.ttl;
+        // Nothing fetched yet means the first refresh is still due right away.
+        if self.refresh.consecutive_failures == 0 && !self.price_cache.is_empty() {
+            self.refresh.next_attempt = self.cache_expiration - config.refresh_lead;
+        }
+        self.config = config;
+        info!("Reconfigured: {:?}", self.config);
+        if stale {
+            self.invalidate_cache();
+        }
+    }
+
+    fn refresh_due(&self, now: DateTime<Local>) -> bool {
+        self.refresh.in_flight.is_none() && self.refresh.next_attempt <= now
+    }
+
+    fn start_refresh(&mut self) -> () {
+        if self.refresh.in_flight.is_some() {
+            return;
+        }
+
+        let (sender, receiver) = channel();
+        let endpoints = self.config.endpoints.clone();
+        let league = self.config.league.clone();
+        let client = self.client.clone();
+        thread::spawn(move || {
+            // A refresh that panicked halfway leaves nothing broken behind,
+            // so a poisoned client is still good to use.
+            let mut client = match client.lock() {
+                Ok(client) => client,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            // If the bot is gone by the time we're done, nobody cares anymore.
+            let _ = sender.send(client.refresh_price_cache(&endpoints, &league));
+        });
+        self.refresh.in_flight = Some(self.in_flight(receiver));
+        info!("Refreshing price cache in the background...");
+    }
+
+    /// A refresh of what's configured right now, answering on `result`.
+    fn in_flight(&self, result: Receiver<Result<PriceCache, FetchError>>) -> InFlight {
+        InFlight {
+            league: self.config.league.clone(),
+            endpoints: self.config.endpoints.clone(),
+            result,
+        }
+    }
+
+    /// Swap in the refreshed cache if the background refresh is done, or
+    /// schedule the next attempt if it failed. A refresh for a league or
+    /// endpoints we've moved on from is thrown away, and done again.
+    fn collect_refresh(&mut self) -> () {
+        let (received, current) = match self.refresh.in_flight {
+            Some(ref in_flight) => (
+                in_flight.result.try_recv(),
+                in_flight.league == self.config.league
+                    && in_flight.endpoints == self.config.endpoints,
             ),
+            None => return,
+        };
+        let now = Local::now();
+        let result = match received {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => {
+                self.refresh.in_flight = None;
+                self.schedule_retry(now);
+                warn!("Refresh thread died without an answer.");
+                return;
+            }
+        };
+        self.refresh.in_flight = None;
+
+        match result {
+            Ok(_) if !current => {
+                info!("Throwing away prices for the old config, refreshing again");
+                self.refresh.next_attempt = now;
+            }
+            Ok(cache) => {
+                self.price_cache = cache;
+                self.cache_expiration = calculate_expiration_date(now, self.config.ttl);
+                self.refresh.consecutive_failures = 0;
+                self.refresh.next_attempt = self.cache_expiration - self.config.refresh_lead;
+                info!(
+                    "Refreshed cache, valid until {}",
+                    self.cache_expiration
+                );
+            }
+            // Not a failure, we just asked too early. Come back when allowed.
+            Err(FetchError::TooSoon(wait)) => {
+                self.refresh.next_attempt = now + to_chrono(wait);
+            }
+            Err(e) => {
+                self.schedule_retry(now);
+                if let FetchError::RateLimited(retry_after) = e {
+                    let allowed = now + cmp::min(to_chrono(retry_after), self.config.max_backoff);
+                    if allowed > self.refresh.next_attempt {
+                        self.refresh.next_attempt = allowed;
+                    }
+                }
+                warn!(
+                    "Can't update cache, continuing with old, retrying at {}. Error: {}",
+                    self.refresh.next_attempt, e
+                );
+            }
+        }
+    }
+
+    fn schedule_retry(&mut self, now: DateTime<Local>) -> () {
+        self.refresh.consecutive_failures += 1;
+        let backoff = calculate_backoff(&self.config, self.refresh.consecutive_failures);
+        self.refresh.next_attempt = now + backoff;
+    }
+}
+
+/// Why a refresh didn't produce a new cache.
+#[derive(Debug)]
+pub enum FetchError {
+    Request(Error),
+    /// poe.ninja answered with something other than success.
+    Status(StatusCode),
+    /// A category came back as something other than an overview, like an
+    /// error page, and why it couldn't be read.
+    NotAnOverview(String, String),
+    /// poe.ninja told us to back off, and for how long.
+    RateLimited(time::Duration),
+    /// The last refresh was too recent, try again after the given wait.
+    TooSoon(time::Duration),
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FetchError::Request(e) => write!(f, "Request failed: {}", e),
+            FetchError::Status(status) => write!(f, "Unexpected status {}", status),
+            FetchError::NotAnOverview(category, reason) => write!(f, "{}: {}", category, reason),
+            FetchError::RateLimited(wait) => {
+                write!(f, "Rate limited, retry in {}s", wait.as_secs())
+            }
+            FetchError::TooSoon(wait) => {
+                write!(f, "Refreshed too recently, retry in {}s", wait.as_secs())
+            }
         }
     }
 }
 
-/// Refresh the passed cache with new poe.ninja data.  This will hit up all the
-/// endpoints in poe.ninja and move all the response data into the cache --
-/// currently sequentially. This would benefit greatly from throwing a rayon
-/// par_iter() on there, even though I'm not sure how well Reqwest handles
-/// that. Definitely needs to be made either asynchronous or parallel.
-fn refresh_price_cache() -> Result<PriceCache, Error> {
-    let client = Client::new();
-    let prices: Vec<Price> = POE_NINJA_ENDPOINT_TEMPLATES
-        .to_vec()
-        .iter()
-        .map(|url| url.replace("{}", CURRENT_LEAGUE))
-        .map(|url| client.get(&url).send())
-        .map(|res| res.expect("Can't unwrap Request when refreshing gear cache"))
-        .map(
-            |mut resp| match resp.json::<NinjaCurrencyOverviewResponse>() {
-                Ok(result) => result,
-                Err(e) => panic!(
-                    "Can't parse response into poe.ninja type, got {:?} instead, error: {}",
-                    resp, e
-                ),
+impl From<Error> for FetchError {
+    fn from(e: Error) -> FetchError {
+        FetchError::Request(e)
+    }
+}
+
+/// The last good response for a URL, with what poe.ninja told us to identify
+/// it by, so we can ask "has this changed?" instead of fetching it again.
+struct CachedResponse {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: String,
+}
+
+/// A poe.ninja client that tries not to get us blocked: it identifies itself,
+/// keeps a single connection pool, only downloads what changed, backs off when
+/// told to, and refuses to refresh more often than every
+/// `NINJA_MIN_REFRESH_INTERVAL_SECS`, no matter how often it's asked.
+pub struct NinjaClient {
+    client: Client,
+    responses: HashMap<String, CachedResponse>,
+    min_refresh_interval: time::Duration,
+    last_refresh: Option<Instant>,
+    blocked_until: Option<Instant>,
+}
+
+impl NinjaClient {
+    pub fn new() -> NinjaClient {
+        let mut headers = HeaderMap::new();
+        headers.insert(USER_AGENT, HeaderValue::from_static(NINJA_USER_AGENT));
+        let client = Client::builder()
+            .default_headers(headers)
+            .build()
+            .expect("Can't set up HTTP client");
+
+        NinjaClient {
+            client,
+            responses: HashMap::new(),
+            min_refresh_interval: time::Duration::from_secs(NINJA_MIN_REFRESH_INTERVAL_SECS),
+            last_refresh: None,
+            blocked_until: None,
+        }
+    }
+
+    /// Refresh the passed cache with new poe.ninja data.  This will hit up all
+    /// the endpoints in poe.ninja and move all the response data into the
+    /// cache -- currently sequentially. This would benefit greatly from
+    /// throwing a rayon par_iter() on there, even though I'm not sure how well
+    /// Reqwest handles that. Since the bot runs this in the background it's
+    /// not blocking anybody, but it's still slow.
+    pub fn refresh_price_cache(
+        &mut self,
+        endpoints: &[NinjaEndpoint],
+        league: &str,
+    ) -> Result<PriceCache, FetchError> {
+        self.check_allowed(Instant::now())?;
+
+        let mut cache = PriceCache::new();
+        for endpoint in endpoints {
+            let url = endpoint.url(league);
+            let body = self.fetch(&url)?;
+            let overview = match parse_overview(&body) {
+                Ok(overview) => overview,
+                Err(e) => {
+                    // Don't let a 304 bring it back next time.
+                    self.responses.remove(&url);
+                    return Err(FetchError::NotAnOverview(endpoint.category.clone(), e));
+                }
+            };
+
+            for warning in &overview.warnings {
+                warn!("Skipping part of {}: {}", endpoint.category, warning);
+            }
+            overview
+                .prices
+                .into_iter()
+                .for_each(|price| cache.insert(endpoint.prices, price));
+        }
+
+        // Failed refreshes are retried with backoff, only a full one counts
+        // against the interval.
+        self.last_refresh = Some(Instant::now());
+        info!("Fetched {} prices.", cache.len());
+        Ok(cache)
+    }
+
+    fn check_allowed(&self, now: Instant) -> Result<(), FetchError> {
+        if let Some(until) = self.blocked_until {
+            if until > now {
+                return Err(FetchError::RateLimited(until - now));
+            }
+        }
+        if let Some(last) = self.last_refresh {
+            let allowed = last + self.min_refresh_interval;
+            if allowed > now {
+                return Err(FetchError::TooSoon(allowed - now));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the body behind `url`, from our own cache if poe.ninja says it
+    /// hasn't changed.
+    fn fetch(&mut self, url: &str) -> Result<String, FetchError> {
+        let mut request = self.client.get(url);
+        if let Some(cached) = self.responses.get(url) {
+            if let Some(ref etag) = cached.etag {
+                request = request.header(IF_NONE_MATCH, etag.as_str());
+            }
+            if let Some(ref last_modified) = cached.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+            }
+        }
+
+        let mut response = request.send()?;
+        let status = response.status();
+
+        if status == StatusCode::NOT_MODIFIED {
+            if let Some(cached) = self.responses.get(url) {
+                return Ok(cached.body.clone());
+            }
+        }
+
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok());
+            let wait = retry_wait(retry_after, Utc::now());
+            self.blocked_until = Some(Instant::now() + wait);
+            return Err(FetchError::RateLimited(wait));
+        }
+
+        if !status.is_success() {
+            return Err(FetchError::Status(status));
+        }
+
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v: &HeaderValue| v.to_str().ok())
+                .map(|v| v.to_string())
+        };
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+        let body = response.text()?;
+
+        self.responses.insert(
+            url.to_string(),
+            CachedResponse {
+                etag,
+                last_modified,
+                body: body.clone(),
             },
-        ).flat_map(|resp| resp.lines.into_iter().map(Price::from))
-        .collect();
+        );
+        Ok(body)
+    }
+}
+
+/// How long a 429 has us wait, no longer than we'd back off anyway.
+fn retry_wait(retry_after: Option<&str>, now: DateTime<Utc>) -> time::Duration {
+    let wait = retry_after
+        .and_then(|v| parse_retry_after(v, now))
+        .unwrap_or_else(|| time::Duration::from_secs(NINJA_DEFAULT_RETRY_AFTER_SECS));
+    cmp::min(wait, time::Duration::from_secs(NINJA_MAX_BACKOFF_SECS))
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<time::Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(time::Duration::from_secs(seconds));
+    }
+
+    DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .map(|date| date.with_timezone(&Utc) - now)
+        .map(|wait| wait.to_std().unwrap_or_else(|_| time::Duration::from_secs(0)))
+}
+
+fn to_chrono(duration: time::Duration) -> Duration {
+    Duration::from_std(duration).unwrap_or_else(|_| Duration::max_value())
+}
+
+/// The prices in a poe.ninja overview, and the complaints about everything we
+/// couldn't make sense of.
+#[derive(Debug)]
+pub struct ParsedOverview {
+    pub prices: Vec<Price>,
+    pub warnings: Vec<String>,
+}
+
+/// Parse an overview response as leniently as we can. Lines that don't make
+/// sense are skipped with a warning instead of failing the whole response. A
+/// response that isn't an overview at all, like an error page, is an error:
+/// no prices would leave the whole category out of the cache.
+pub fn parse_overview(body: &str) -> Result<ParsedOverview, String> {
+    let mut overview = ParsedOverview {
+        prices: Vec::new(),
+        warnings: Vec::new(),
+    };
 
-    println!(
-        "[PriceBot] Fetched {} prices, updating cache...",
-        prices.len()
-    );
-    let mut cache = PriceCache::new();
-    prices.into_iter().for_each(|price| {
-        cache.insert(price.name.clone(), price);
-    });
+    let response: NinjaCurrencyOverviewResponse =
+        serde_json::from_str(body).map_err(|e| format!("Response is not an overview: {}", e))?;
+
+    for (index, line) in response.lines.into_iter().enumerate() {
+        let price = serde_json::from_value::<NinjaLineResponse>(line)
+            .map_err(|e| e.to_string())
+            .and_then(Price::from_ninja_line);
+        match price {
+            Ok(price) => overview.prices.push(price),
+            Err(e) => overview.warnings.push(format!("Line {}: {}", index, e)),
+        }
+    }
 
-    Ok(cache)
+    Ok(overview)
 }
 
-fn calculate_expiration_date(now: DateTime<Local>) -> DateTime<Local> {
-    let offset = Duration::hours(1);
-    now.checked_add_signed(offset)
+fn calculate_expiration_date(now: DateTime<Local>, ttl: Duration) -> DateTime<Local> {
+    now.checked_add_signed(ttl)
         .expect("The heat death of universe is near, date addition would overflow")
 }
 
+/// Exponential backoff: the initial backoff doubles with every consecutive
+/// failure, up to the configured maximum.
+fn calculate_backoff(config: &PriceBotConfig, failures: u32) -> Duration {
+    let factor = 2i32.saturating_pow(failures.saturating_sub(1));
+    let backoff = config.initial_backoff * factor;
+    if backoff > config.max_backoff {
+        config.max_backoff
+    } else {
+        backoff
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use types::pricing::ItemKind;
 
     #[test]
     #[ignore]
     fn should_update_cache() {
-        let cache = refresh_price_cache().unwrap();
+        let config = PriceBotConfig::default();
+        let cache = NinjaClient::new()
+            .refresh_price_cache(&config.endpoints, &config.league)
+            .unwrap();
 
         println!("{:?}", cache);
-        assert!(cache.len() > 0;)
+        assert!(cache.len() > 0);
     }
 
-    #[test]
-    fn should_update_cache_expiry() {
+    /// Build a bot around deliberately invalid starting data. If the cache is
+    /// replaced, the outside world will never see this.
+    fn bot_with_stale_cache() -> (PriceBot, Sender<PriceMessage>, Receiver<PriceMessage>) {
         use std::sync::mpsc;
-        use std::thread;
 
-        let mut cache: PriceCache = HashMap::new();
-        // Create deliberately invalid starting data.  If the cache is
-        // invalidated correctly, the outside world will never see this.
-        cache.insert(
-            "Exalted Orb".to_string(),
+        let (sender, receiver_bot) = mpsc::channel();
+        let (sender_bot, receiver) = mpsc::channel();
+        let mut price_bot = PriceBot::new(sender_bot, receiver_bot);
+        price_bot.price_cache.insert(
+            ItemKind::Currency,
             Price {
                 name: "Exalted Orb".to_string(),
                 chaos_equivalent: -111111.0,
+                variant: None,
             },
         );
 
-        let (sender, receiver_bot) = mpsc::channel();
-        let (sender_bot, receiver) = mpsc::channel();
-        thread::spawn(move || {
-            let mut price_bot = PriceBot {
-                response_channel: sender_bot,
-                request_channel: receiver_bot,
-                cache_expiration: Local::now(),
-                price_cache: cache,
-            };
-            price_bot.run()
-        });
+        (price_bot, sender, receiver)
+    }
 
+    fn ask_for_exalts(
+        sender: &Sender<PriceMessage>,
+        receiver: &Receiver<PriceMessage>,
+    ) -> Price {
         match sender.send(PriceMessage::Get {
-            item: "Exalted Orb".to_string(),
+            id: 7,
+            item: PriceKey::named("Exalted Orb"),
         }) {
             Ok(()) => {}
             Err(e) => panic!("Can't send Price/Get Message: {}", e),
         };
 
         match receiver.recv() {
-            Ok(o) => match o {
-                PriceMessage::Response { item, price } => {
-                    assert_eq!(item, "Exalted Orb".to_string());
-                    assert!(price.chaos_equivalent > 0.0);
-                }
-                _ => panic!("Not a response"),
-            },
-            _ => panic!("Can't read from channel after updating Price and asking again."),
+            Ok(PriceMessage::Response {
+                id, item, price, ..
+            }) => {
+                assert_eq!(id, 7);
+                assert_eq!(item, "Exalted Orb".to_string());
+                price
+            }
+            Ok(_) => panic!("Not a response"),
+            Err(e) => panic!("Can't read from price bot: {}", e),
         }
+    }
+
+    #[test]
+    #[ignore]
+    fn should_update_cache_expiry() {
+        use std::thread;
+        use std::time;
+
+        let (mut price_bot, sender, receiver) = bot_with_stale_cache();
+        thread::spawn(move || price_bot.run());
+
+        // The bot refreshes in the background, so keep asking until the
+        // refreshed price shows up.
+        let mut price = ask_for_exalts(&sender, &receiver);
+        for _ in 0..60 {
+            if price.chaos_equivalent > 0.0 {
+                break;
+            }
+            thread::sleep(time::Duration::from_secs(1));
+            price = ask_for_exalts(&sender, &receiver);
+        }
+        assert!(price.chaos_equivalent > 0.0);
 
         match sender.send(PriceMessage::ShutDown) {
             Ok(()) => {}
             Err(e) => panic!("Can't shut down price bot: {}", e),
         }
     }
+
+    #[test]
+    fn should_answer_from_old_cache_while_refreshing() {
+        use std::sync::mpsc;
+        use std::thread;
+
+        let (mut price_bot, sender, receiver) = bot_with_stale_cache();
+        // A refresh that never finishes.
+        let (_refresh_sender, refresh_receiver) = mpsc::channel();
+        price_bot.refresh.in_flight = Some(price_bot.in_flight(refresh_receiver));
+        let handle = thread::spawn(move || price_bot.run());
+
+        let price = ask_for_exalts(&sender, &receiver);
+        assert_eq!(price.chaos_equivalent, -111111.0);
+
+        sender.send(PriceMessage::ShutDown).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn should_schedule_retry_when_refresh_fails() {
+        use std::sync::mpsc;
+
+        let (mut price_bot, _sender, _receiver) = bot_with_stale_cache();
+        let (refresh_sender, refresh_receiver) = mpsc::channel();
+        price_bot.refresh.in_flight = Some(price_bot.in_flight(refresh_receiver));
+        drop(refresh_sender);
+
+        let before = Local::now();
+        price_bot.collect_refresh();
+
+        assert!(price_bot.refresh.in_flight.is_none());
+        assert_eq!(price_bot.refresh.consecutive_failures, 1);
+        assert!(price_bot.refresh.next_attempt >= before + price_bot.config.initial_backoff);
+        assert!(!price_bot.refresh_due(Local::now()));
+    }
+
+    #[test]
+    fn should_back_off_exponentially() {
+        let config = PriceBotConfig {
+            league: "Standard".to_string(),
+            endpoints: Vec::new(),
+            ttl: Duration::minutes(60),
+            refresh_lead: Duration::minutes(5),
+            initial_backoff: Duration::seconds(30),
+            max_backoff: Duration::minutes(5),
+        };
+
+        assert_eq!(calculate_backoff(&config, 1), Duration::seconds(30));
+        assert_eq!(calculate_backoff(&config, 2), Duration::seconds(60));
+        assert_eq!(calculate_backoff(&config, 3), Duration::seconds(120));
+        assert_eq!(calculate_backoff(&config, 5), Duration::minutes(5));
+        assert_eq!(calculate_backoff(&config, 100), Duration::minutes(5));
+    }
+
+    mod overview_test {
+        use super::*;
+
+        fn price_of(overview: &ParsedOverview, name: &str) -> f32 {
+            overview
+                .prices
+                .iter()
+                .find(|p| p.name == name)
+                .unwrap_or_else(|| panic!("No price for {}", name))
+                .chaos_equivalent
+        }
+
+        #[test]
+        fn should_parse_currency_overviews() {
+            let overview = parse_overview(include_str!(
+                "../resources/poe-ninja/currency-overview.json"
+            ))
+            .unwrap();
+
+            assert!(overview.warnings.is_empty());
+            assert_eq!(overview.prices.len(), 3);
+            assert_eq!(price_of(&overview, "Exalted Orb"), 150.22);
+            // Only ever sold, never bought, so `pay` is null.
+            assert_eq!(price_of(&overview, "Mirror of Kalandra"), 37000.0);
+        }
+
+        #[test]
+        fn should_parse_divination_card_overviews() {
+            let overview = parse_overview(include_str!(
+                "../resources/poe-ninja/item-overview-divination-card.json"
+            ))
+            .unwrap();
+
+            assert!(overview.warnings.is_empty());
+            assert_eq!(overview.prices.len(), 2);
+            assert_eq!(price_of(&overview, "The Doctor"), 2500.0);
+        }
+
+        #[test]
+        fn should_parse_unique_map_overviews() {
+            let overview = parse_overview(include_str!(
+                "../resources/poe-ninja/item-overview-unique-map.json"
+            ))
+            .unwrap();
+
+            assert!(overview.warnings.is_empty());
+            assert_eq!(overview.prices.len(), 2);
+            assert_eq!(price_of(&overview, "Hall of Grandmasters"), 12.0);
+        }
+
+        #[test]
+        fn should_parse_unique_weapon_overviews() {
+            let overview = parse_overview(include_str!(
+                "../resources/poe-ninja/item-overview-unique-weapon.json"
+            ))
+            .unwrap();
+
+            assert!(overview.warnings.is_empty());
+            assert_eq!(overview.prices.len(), 2);
+        }
+
+        #[test]
+        fn should_skip_malformed_lines_with_warnings() {
+            let overview = parse_overview(include_str!(
+                "../resources/poe-ninja/malformed-overview.json"
+            ))
+            .unwrap();
+
+            assert_eq!(overview.prices.len(), 2);
+            assert_eq!(price_of(&overview, "Chromatic Orb"), 0.2);
+            assert_eq!(price_of(&overview, "Orb of Fusing"), 0.5);
+            assert_eq!(overview.warnings.len(), 4);
+        }
+
+        #[test]
+        fn should_refuse_responses_that_are_not_overviews() {
+            assert!(parse_overview("<html>502 Bad Gateway</html>").is_err());
+            assert!(parse_overview("").is_err());
+            assert!(parse_overview(r#"{"error": "Try again later"}"#).is_err());
+        }
+
+        #[test]
+        fn should_tolerate_empty_overviews() {
+            let overview = parse_overview(r#"{"lines": []}"#).unwrap();
+
+            assert!(overview.prices.is_empty());
+            assert!(overview.warnings.is_empty());
+        }
+    }
+
+    #[test]
+    fn should_refuse_refreshing_too_often() {
+        let mut client = NinjaClient::new();
+        client.last_refresh = Some(Instant::now());
+
+        match client.refresh_price_cache(&[], "Standard") {
+            Err(FetchError::TooSoon(wait)) => assert!(wait <= client.min_refresh_interval),
+            other => panic!("Expected refusal, got {:?}", other.map(|c| c.len())),
+        }
+    }
+
+    #[test]
+    fn should_refuse_refreshing_while_rate_limited() {
+        let mut client = NinjaClient::new();
+        client.blocked_until = Some(Instant::now() + time::Duration::from_secs(60));
+
+        match client.refresh_price_cache(&[], "Standard") {
+            Err(FetchError::RateLimited(_)) => {}
+            other => panic!("Expected refusal, got {:?}", other.map(|c| c.len())),
+        }
+    }
+
+    #[test]
+    fn should_allow_first_refresh() {
+        let client = NinjaClient::new();
+        assert!(client.check_allowed(Instant::now()).is_ok());
+    }
+
+    #[test]
+    fn should_only_count_refreshes_that_went_through() {
+        let mut client = NinjaClient::new();
+        client.blocked_until = Some(Instant::now() + time::Duration::from_secs(60));
+        assert!(client.refresh_price_cache(&[], "Standard").is_err());
+        assert!(client.last_refresh.is_none());
+
+        client.blocked_until = None;
+        assert!(client.refresh_price_cache(&[], "Standard").is_ok());
+        assert!(client.last_refresh.is_some());
+    }
+
+    #[test]
+    fn should_parse_retry_after() {
+        let now = Utc.ymd(2018, 11, 22).and_hms(5, 0, 0);
+
+        assert_eq!(
+            parse_retry_after("120", now),
+            Some(time::Duration::from_secs(120))
+        );
+        assert_eq!(
+            parse_retry_after("Thu, 22 Nov 2018 05:01:30 GMT", now),
+            Some(time::Duration::from_secs(90))
+        );
+        // Dates in the past mean "go ahead".
+        assert_eq!(
+            parse_retry_after("Thu, 22 Nov 2018 04:00:00 GMT", now),
+            Some(time::Duration::from_secs(0))
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+
+        assert_eq!(
+            retry_wait(Some("soon"), now),
+            time::Duration::from_secs(NINJA_DEFAULT_RETRY_AFTER_SECS)
+        );
+        assert_eq!(
+            retry_wait(Some("18446744073709551615"), now),
+            time::Duration::from_secs(NINJA_MAX_BACKOFF_SECS)
+        );
+    }
+
+    #[test]
+    fn should_wait_for_rate_limit_before_retrying() {
+        use std::sync::mpsc;
+
+        let (mut price_bot, _sender, _receiver) = bot_with_stale_cache();
+        let (refresh_sender, refresh_receiver) = mpsc::channel();
+        price_bot.refresh.in_flight = Some(price_bot.in_flight(refresh_receiver));
+        refresh_sender
+            .send(Err(FetchError::RateLimited(time::Duration::from_secs(1200))))
+            .unwrap();
+
+        let before = Local::now();
+        price_bot.collect_refresh();
+
+        assert_eq!(price_bot.refresh.consecutive_failures, 1);
+        assert!(price_bot.refresh.next_attempt >= before + Duration::minutes(19));
+    }
+
+    #[test]
+    fn should_wait_no_longer_than_the_longest_backoff() {
+        use std::sync::mpsc;
+
+        let (mut price_bot, _sender, _receiver) = bot_with_stale_cache();
+        let (refresh_sender, refresh_receiver) = mpsc::channel();
+        price_bot.refresh.in_flight = Some(price_bot.in_flight(refresh_receiver));
+        refresh_sender
+            .send(Err(FetchError::RateLimited(time::Duration::from_secs(
+                u64::max_value(),
+            ))))
+            .unwrap();
+
+        price_bot.collect_refresh();
+
+        let latest = Local::now() + price_bot.config.max_backoff;
+        assert!(price_bot.refresh.next_attempt <= latest);
+    }
+
+    #[test]
+    fn should_move_expiry_with_new_ttl() {
+        let (mut price_bot, _sender, _receiver) = bot_with_stale_cache();
+        let expiration = price_bot.cache_expiration;
+        let mut config = price_bot.config.clone();
+        config.ttl = config.ttl + Duration::minutes(30);
+
+        price_bot.configure(config);
+
+        assert_eq!(price_bot.cache_expiration, expiration + Duration::minutes(30));
+    }
+
+    #[test]
+    fn should_throw_away_refresh_for_old_league() {
+        use std::sync::mpsc;
+
+        let (mut price_bot, _sender, _receiver) = bot_with_stale_cache();
+        price_bot.client.lock().unwrap().blocked_until =
+            Some(Instant::now() + time::Duration::from_secs(3600));
+        let (refresh_sender, refresh_receiver) = mpsc::channel();
+        price_bot.refresh.in_flight = Some(price_bot.in_flight(refresh_receiver));
+        let mut config = price_bot.config.clone();
+        config.league = "Hardcore".to_string();
+        price_bot.configure(config);
+
+        let mut old_league = PriceCache::new();
+        old_league.insert(
+            ItemKind::Currency,
+            Price {
+                name: "Exalted Orb".to_string(),
+                chaos_equivalent: 80.0,
+                variant: None,
+            },
+        );
+        refresh_sender.send(Ok(old_league)).unwrap();
+        price_bot.collect_refresh();
+
+        let (price, _) = price_bot
+            .price_cache
+            .lookup(&PriceKey::named("Exalted Orb"))
+            .unwrap();
+        assert_eq!(price.chaos_equivalent, -111111.0);
+        assert_eq!(price_bot.refresh.consecutive_failures, 0);
+        assert!(price_bot.refresh_due(Local::now()));
+    }
+
+    #[test]
+    fn should_keep_first_refresh_due_when_reconfigured() {
+        let (sender, _receiver) = channel();
+        let (_requests, receiver) = channel();
+        let mut price_bot = PriceBot::new(sender, receiver);
+        let mut config = price_bot.config.clone();
+        config.ttl = config.ttl + Duration::minutes(30);
+
+        price_bot.configure(config);
+
+        assert!(price_bot.refresh_due(Local::now()));
+    }
+
+    #[test]
+    fn should_refresh_when_league_changes() {
+        let (mut price_bot, _sender, _receiver) = bot_with_stale_cache();
+        // The refresh is turned away before it gets to poe.ninja.
+        price_bot.client.lock().unwrap().blocked_until =
+            Some(Instant::now() + time::Duration::from_secs(3600));
+        let mut config = price_bot.config.clone();
+        config.ttl = config.ttl + Duration::minutes(30);
+        price_bot.configure(config.clone());
+        assert!(price_bot.refresh.in_flight.is_none());
+
+        config.league = "Hardcore".to_string();
+        price_bot.configure(config);
+        assert!(price_bot.refresh.in_flight.is_some());
+    }
 }